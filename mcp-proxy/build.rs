@@ -0,0 +1,69 @@
+// Copyright AGNTCY Contributors (https://github.com/agntcy)
+// SPDX-License-Identifier: Apache-2.0
+
+//! Record the build information served on the `/version` endpoint
+
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const UNKNOWN: &str = "unknown";
+
+fn main() {
+    // GIT_COMMIT is set by the builds without the git history, e.g. in Docker
+    println!("cargo:rerun-if-env-changed=GIT_COMMIT");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+    println!("cargo:rerun-if-changed=../.git/refs");
+    println!("cargo:rerun-if-changed=../Cargo.lock");
+
+    let commit = std::env::var("GIT_COMMIT")
+        .ok()
+        .or_else(git_commit)
+        .unwrap_or_else(|| UNKNOWN.to_string());
+    println!("cargo:rustc-env=BUILD_GIT_COMMIT={}", commit);
+
+    // reproducible builds pin the timestamp
+    let timestamp = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default()
+        });
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", timestamp);
+
+    let lock = std::fs::read_to_string("../Cargo.lock").unwrap_or_default();
+    for (var, package) in [
+        ("BUILD_RMCP_VERSION", "rmcp"),
+        ("BUILD_SLIM_VERSION", "agntcy-slim"),
+        ("BUILD_SLIM_SERVICE_VERSION", "agntcy-slim-service"),
+    ] {
+        let version = locked_version(&lock, package).unwrap_or(UNKNOWN);
+        println!("cargo:rustc-env={}={}", var, version);
+    }
+}
+
+fn git_commit() -> Option<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let commit = String::from_utf8(output.stdout).ok()?;
+    Some(commit.trim().to_string())
+}
+
+/// Version of a package in the lock file, the first one if it is there twice
+fn locked_version<'a>(lock: &'a str, package: &str) -> Option<&'a str> {
+    let name = format!("name = \"{}\"", package);
+    let mut lines = lock.lines();
+    lines.find(|line| *line == name)?;
+    lines
+        .next()?
+        .strip_prefix("version = \"")?
+        .strip_suffix('"')
+}
@@ -0,0 +1,1010 @@
+// Copyright AGNTCY Contributors (https://github.com/agntcy)
+// SPDX-License-Identifier: Apache-2.0
+
+use axum::{
+    Router,
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode, Uri, header},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use parking_lot::Mutex;
+use pprof::protos::Message;
+use rmcp::model::InitializeResult;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    io,
+    net::SocketAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    net::TcpListener,
+    sync::{Notify, oneshot},
+    task::JoinHandle,
+};
+use tracing::{error, info, warn};
+
+use crate::events::Events;
+use crate::keepalive::PingHealth;
+use crate::memory::SessionActivity;
+
+/// Consecutive connection failures after which an MCP server is reported down
+pub const DEFAULT_BACKEND_DOWN_FAILURES: u32 = 3;
+
+/// Duration of the CPU profiles taken without a `seconds` parameter
+const DEFAULT_PROFILE_DURATION: Duration = Duration::from_secs(30);
+const MAX_PROFILE_DURATION: Duration = Duration::from_secs(300);
+/// Sampling frequency of the CPU profiles, in Hz
+const PROFILE_FREQUENCY: i32 = 99;
+/// Bound of the requests exchanged by two instances during a handover
+const HANDOVER_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Attempts of the new instance to hand over from the previous one, the
+/// wait between two attempts doubling from the first one
+const HANDOVER_ATTEMPTS: u32 = 5;
+const HANDOVER_RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Role of an MCP server for the sessions
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackendRole {
+    Primary,
+    Fallback,
+    /// MCP server selected by name by the clients
+    Logical,
+}
+
+impl BackendRole {
+    fn as_str(self) -> &'static str {
+        match self {
+            BackendRole::Primary => "primary",
+            BackendRole::Fallback => "fallback",
+            BackendRole::Logical => "logical",
+        }
+    }
+}
+
+/// Connections of the sessions to an MCP server and their outcome
+#[derive(Debug)]
+struct Backend {
+    role: BackendRole,
+    /// sessions connected to the server
+    connections: u64,
+    last_connected: Option<SystemTime>,
+    last_error: Option<(SystemTime, String)>,
+    /// failures since the last successful handshake
+    consecutive_failures: u32,
+}
+
+/// State shared by the proxy and its admin endpoint
+#[derive(Debug)]
+pub struct AdminState {
+    /// the proxy accepts new sessions
+    ready: AtomicBool,
+    /// a drain was requested on the endpoint
+    drain: Notify,
+    /// the drain was requested by a handover to another instance
+    handover: AtomicBool,
+    /// last handshake result seen from each MCP server, by address
+    capabilities: Mutex<BTreeMap<String, serde_json::Value>>,
+    /// health of each MCP server, by address
+    backends: Mutex<BTreeMap<String, Backend>>,
+    /// consecutive failures after which an MCP server is down
+    backend_down_failures: u32,
+    /// SHA-256 of the bearer token required to take a CPU profile, the
+    /// profiling endpoint is not served if `None`
+    profiling_token: Option<[u8; 32]>,
+    /// SHA-256 of the bearer token required to hand over, anyone can ask for
+    /// a handover without a successor if `None`
+    handover_token: Option<[u8; 32]>,
+    /// a CPU profile is being taken, only one can be at a time
+    profiling: Arc<AtomicBool>,
+    /// publishes the changes of health of the MCP servers
+    events: Events,
+    /// activity of the open sessions, by client name and session id, the
+    /// sessions endpoint is not served if `None`
+    sessions: Option<Mutex<BTreeMap<(String, u32), Arc<SessionActivity>>>>,
+}
+
+/// Ping health of an open session
+#[derive(Clone, Debug)]
+pub struct SessionHealth {
+    pub session_id: u32,
+    pub client: String,
+    pub pending_pings: usize,
+    pub last_ping_rtt: Option<Duration>,
+    pub health: PingHealth,
+}
+
+impl AdminState {
+    /// State of a proxy using the given MCP servers, by address, serving CPU
+    /// profiles to the holders of the profiling token if any, and handing
+    /// over only to the holders of the handover token if any
+    pub fn new(
+        backends: impl IntoIterator<Item = (String, BackendRole)>,
+        backend_down_failures: u32,
+        profiling_token: Option<&str>,
+        handover_token: Option<&str>,
+        events: Events,
+        expose_sessions: bool,
+    ) -> Self {
+        let backends = backends
+            .into_iter()
+            .map(|(address, role)| {
+                let backend = Backend {
+                    role,
+                    connections: 0,
+                    last_connected: None,
+                    last_error: None,
+                    consecutive_failures: 0,
+                };
+                (address, backend)
+            })
+            .collect();
+        Self {
+            ready: AtomicBool::new(false),
+            drain: Notify::new(),
+            handover: AtomicBool::new(false),
+            capabilities: Mutex::new(BTreeMap::new()),
+            backends: Mutex::new(backends),
+            backend_down_failures,
+            profiling_token: profiling_token.map(|token| Sha256::digest(token).into()),
+            handover_token: handover_token.map(|token| Sha256::digest(token).into()),
+            profiling: Arc::new(AtomicBool::new(false)),
+            events,
+            sessions: expose_sessions.then(|| Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    pub fn set_ready(&self, ready: bool) {
+        self.ready.store(ready, Ordering::Relaxed);
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Relaxed)
+    }
+
+    /// Start draining the proxy, failing the readiness right away since the
+    /// proxy may take a while to notice
+    pub fn request_drain(&self) {
+        self.set_ready(false);
+        self.drain.notify_one();
+    }
+
+    /// Record the capabilities advertised by an MCP server in a handshake
+    pub fn record_capabilities(&self, backend: String, result: &InitializeResult) {
+        let seen = serde_json::json!({
+            "protocolVersion": result.protocol_version,
+            "capabilities": result.capabilities,
+            "serverInfo": result.server_info,
+        });
+        self.capabilities.lock().insert(backend, seen);
+    }
+
+    /// Record a successful handshake with an MCP server
+    pub fn record_backend_success(&self, url: &str) {
+        if let Some(backend) = self.backends.lock().get_mut(url) {
+            backend.last_connected = Some(SystemTime::now());
+            if backend.consecutive_failures > 0 {
+                self.events.emit(
+                    "backend_recovered",
+                    serde_json::json!({ "url": url, "role": backend.role.as_str() }),
+                );
+            }
+            backend.consecutive_failures = 0;
+        }
+    }
+
+    /// Record a failed connection or handshake with an MCP server
+    pub fn record_backend_failure(&self, url: &str, error: String) {
+        if let Some(backend) = self.backends.lock().get_mut(url) {
+            backend.consecutive_failures += 1;
+            self.events.emit(
+                "backend_failure",
+                serde_json::json!({
+                    "url": url,
+                    "role": backend.role.as_str(),
+                    "health": self.health(backend.consecutive_failures),
+                    "error": error,
+                    "consecutiveFailures": backend.consecutive_failures,
+                }),
+            );
+            backend.last_error = Some((SystemTime::now(), error));
+        }
+    }
+
+    fn health(&self, consecutive_failures: u32) -> &'static str {
+        match consecutive_failures {
+            0 => "healthy",
+            n if n < self.backend_down_failures => "degraded",
+            _ => "down",
+        }
+    }
+
+    /// Count a session connected to an MCP server, or no longer connected
+    pub fn record_backend_connection(&self, backend: &str, connected: bool) {
+        if let Some(backend) = self.backends.lock().get_mut(backend) {
+            backend.connections = if connected {
+                backend.connections + 1
+            } else {
+                backend.connections.saturating_sub(1)
+            };
+        }
+    }
+
+    fn backends_json(&self) -> serde_json::Value {
+        let millis =
+            |t: &SystemTime| t.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+        let backends = self.backends.lock();
+        let backends = backends
+            .iter()
+            .map(|(url, backend)| {
+                serde_json::json!({
+                    "url": url,
+                    "role": backend.role.as_str(),
+                    "health": self.health(backend.consecutive_failures),
+                    "connections": backend.connections,
+                    "lastConnected": backend.last_connected.as_ref().map(millis),
+                    "lastError": backend.last_error.as_ref().map(|(at, message)| serde_json::json!({
+                        "at": millis(at),
+                        "message": message,
+                    })),
+                    "consecutiveFailures": backend.consecutive_failures,
+                })
+            })
+            .collect();
+        serde_json::Value::Array(backends)
+    }
+
+    pub fn register_session(
+        &self,
+        client: String,
+        session_id: u32,
+        activity: Arc<SessionActivity>,
+    ) {
+        if let Some(sessions) = &self.sessions {
+            sessions.lock().insert((client, session_id), activity);
+        }
+    }
+
+    pub fn unregister_session(&self, client: String, session_id: u32) {
+        if let Some(sessions) = &self.sessions {
+            sessions.lock().remove(&(client, session_id));
+        }
+    }
+
+    /// Ping health of the open sessions, by client name and session id
+    pub fn active_sessions(&self) -> Vec<SessionHealth> {
+        let Some(sessions) = &self.sessions else {
+            return Vec::new();
+        };
+        sessions
+            .lock()
+            .iter()
+            .map(|((client, session_id), activity)| {
+                let pings = activity.pings();
+                SessionHealth {
+                    session_id: *session_id,
+                    client: client.clone(),
+                    pending_pings: pings.pending(),
+                    last_ping_rtt: pings.last_rtt(),
+                    health: if activity.is_shed() {
+                        PingHealth::Closing
+                    } else {
+                        pings.health()
+                    },
+                }
+            })
+            .collect()
+    }
+
+    fn sessions_json(&self) -> serde_json::Value {
+        let sessions = self
+            .active_sessions()
+            .into_iter()
+            .map(|session| {
+                serde_json::json!({
+                    "sessionId": session.session_id,
+                    "client": session.client,
+                    "pendingPings": session.pending_pings,
+                    "lastPingRttMs": session.last_ping_rtt.map(|rtt| rtt.as_secs_f64() * 1000.0),
+                    "health": session.health.as_str(),
+                })
+            })
+            .collect();
+        serde_json::Value::Array(sessions)
+    }
+
+    /// Wait until a drain is requested, and tell whether it was a plain drain
+    /// or a handover. A request made while nobody waits is kept for the next
+    /// call.
+    pub async fn drain_requested(&self) -> &'static str {
+        self.drain.notified().await;
+        if self.handover.load(Ordering::Relaxed) {
+            "handover"
+        } else {
+            "admin"
+        }
+    }
+}
+
+/// Admin endpoint running in the background. The endpoint is aborted when
+/// dropped, so that its port is released even if the proxy stops on an error.
+#[derive(Debug)]
+pub struct AdminServer {
+    shutdown: Option<oneshot::Sender<()>>,
+    task: JoinHandle<()>,
+}
+
+impl AdminServer {
+    pub fn spawn(addr: SocketAddr, state: Arc<AdminState>) -> Self {
+        let (shutdown, stopped) = oneshot::channel();
+        let task = tokio::spawn(async move {
+            let stopped = async {
+                let _ = stopped.await;
+            };
+            if let Err(e) = serve(addr, state, stopped).await {
+                error!("admin endpoint stopped: {}", e);
+            }
+        });
+        Self {
+            shutdown: Some(shutdown),
+            task,
+        }
+    }
+
+    /// Stop accepting connections and wait for the open ones to end, at most
+    /// for the timeout, after which they are closed
+    pub async fn stop(mut self, timeout: Duration) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if tokio::time::timeout(timeout, &mut self.task).await.is_err() {
+            warn!(?timeout, "admin connections still open, closing them");
+            self.task.abort();
+            let _ = (&mut self.task).await;
+        }
+        info!("admin endpoint stopped");
+    }
+}
+
+impl Drop for AdminServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Serve the admin endpoint until `shutdown` completes:
+/// - `GET /readyz` answers 200 while the proxy accepts new sessions, 503
+///   while it starts, recovers or drains;
+/// - `POST /drain` starts draining the proxy;
+/// - `POST /handover` leaves the name to a successor and drains the proxy,
+///   once the successor, if given, is ready, and only with the handover
+///   token if any;
+/// - `GET /version` describes the build of the proxy in JSON;
+/// - `GET /capabilities` returns the capabilities last advertised by each
+///   MCP server in JSON;
+/// - `GET /backends` reports the health of each MCP server in JSON;
+/// - `GET /sessions` reports the ping health of each session in JSON, only
+///   if enabled;
+/// - `GET /debug/pprof/profile?seconds=<n>` takes a CPU profile in the pprof
+///   format, only with a profiling token.
+async fn serve(
+    addr: SocketAddr,
+    state: Arc<AdminState>,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!(%addr, "serving admin endpoint on /readyz, /drain, /handover, /version, /capabilities and /backends");
+    let mut app = Router::new()
+        .route("/readyz", get(readyz_handler))
+        .route("/drain", post(drain_handler))
+        .route("/handover", post(handover_handler))
+        .route("/version", get(version_handler))
+        .route("/capabilities", get(capabilities_handler))
+        .route("/backends", get(backends_handler));
+    if state.sessions.is_some() {
+        info!(%addr, "serving the health of the sessions on /sessions");
+        app = app.route("/sessions", get(sessions_handler));
+    }
+    if state.profiling_token.is_some() {
+        warn!(%addr, "serving CPU profiles on /debug/pprof/profile");
+        app = app.route("/debug/pprof/profile", get(profile_handler));
+    }
+    let app = app.with_state(state);
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown)
+        .await
+}
+
+async fn readyz_handler(State(state): State<Arc<AdminState>>) -> (StatusCode, &'static str) {
+    if state.is_ready() {
+        (StatusCode::OK, "ready\n")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "not ready\n")
+    }
+}
+
+/// Build information, recorded by the build script
+fn version_info() -> serde_json::Value {
+    serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "gitCommit": env!("BUILD_GIT_COMMIT"),
+        "buildTimestamp": env!("BUILD_TIMESTAMP").parse::<u64>().unwrap_or_default(),
+        "dependencies": {
+            "rmcp": env!("BUILD_RMCP_VERSION"),
+            "agntcy-slim": env!("BUILD_SLIM_VERSION"),
+            "agntcy-slim-service": env!("BUILD_SLIM_SERVICE_VERSION"),
+        },
+    })
+}
+
+async fn version_handler() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "application/json")],
+        version_info().to_string(),
+    )
+}
+
+async fn capabilities_handler(State(state): State<Arc<AdminState>>) -> impl IntoResponse {
+    let capabilities = serde_json::to_string(&*state.capabilities.lock()).unwrap_or_default();
+    ([(header::CONTENT_TYPE, "application/json")], capabilities)
+}
+
+async fn backends_handler(State(state): State<Arc<AdminState>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "application/json")],
+        state.backends_json().to_string(),
+    )
+}
+
+async fn sessions_handler(State(state): State<Arc<AdminState>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "application/json")],
+        state.sessions_json().to_string(),
+    )
+}
+
+/// Duration of the CPU profile requested by the query, `None` if invalid
+fn profile_duration(query: Option<&str>) -> Option<Duration> {
+    let Some(seconds) = query
+        .into_iter()
+        .flat_map(|q| q.split('&'))
+        .find_map(|param| param.strip_prefix("seconds="))
+    else {
+        return Some(DEFAULT_PROFILE_DURATION);
+    };
+    let duration = Duration::from_secs(seconds.parse().ok()?);
+    (!duration.is_zero() && duration <= MAX_PROFILE_DURATION).then_some(duration)
+}
+
+/// Sample the CPU of the proxy for the duration, blocking the thread, and
+/// encode the profile in the pprof format
+fn cpu_profile(duration: Duration) -> pprof::Result<Vec<u8>> {
+    let guard = pprof::ProfilerGuardBuilder::default()
+        .frequency(PROFILE_FREQUENCY)
+        .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+        .build()?;
+    std::thread::sleep(duration);
+    let profile = guard.report().build()?.pprof()?;
+    Ok(profile.encode_to_vec())
+}
+
+/// Whether the request bears the token with the given SHA-256, as
+/// `Authorization: Bearer <token>`
+fn bears_token(headers: &HeaderMap, expected: Option<&[u8; 32]>) -> bool {
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match (token, expected) {
+        (Some(token), Some(expected)) => Sha256::digest(token).as_slice() == expected,
+        _ => false,
+    }
+}
+
+async fn profile_handler(
+    State(state): State<Arc<AdminState>>,
+    headers: HeaderMap,
+    uri: Uri,
+) -> Response {
+    if !bears_token(&headers, state.profiling_token.as_ref()) {
+        warn!("CPU profile requested without the profiling token");
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let Some(duration) = profile_duration(uri.query()) else {
+        let msg = format!(
+            "seconds must be between 1 and {}\n",
+            MAX_PROFILE_DURATION.as_secs()
+        );
+        return (StatusCode::BAD_REQUEST, msg).into_response();
+    };
+    if state.profiling.swap(true, Ordering::AcqRel) {
+        return (
+            StatusCode::CONFLICT,
+            "a CPU profile is already being taken\n",
+        )
+            .into_response();
+    }
+    info!(?duration, "taking a CPU profile");
+    // the flag is cleared by the task, which goes on if the request is dropped
+    let profiling = state.profiling.clone();
+    let profile = tokio::task::spawn_blocking(move || {
+        let profile = cpu_profile(duration);
+        profiling.store(false, Ordering::Release);
+        profile
+    })
+    .await;
+    match profile {
+        Ok(Ok(profile)) => (
+            [(header::CONTENT_TYPE, "application/octet-stream")],
+            profile,
+        )
+            .into_response(),
+        Ok(Err(e)) => {
+            error!("error taking a CPU profile: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+        Err(e) => {
+            error!("CPU profiling task failed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn drain_handler(State(state): State<Arc<AdminState>>) -> StatusCode {
+    info!("drain requested on the admin endpoint");
+    state.request_drain();
+    StatusCode::ACCEPTED
+}
+
+/// Whether the instance with the admin endpoint at the given address is ready
+async fn successor_ready(readyz: reqwest::Url) -> Result<bool, reqwest::Error> {
+    let response = reqwest::Client::new()
+        .get(readyz)
+        .timeout(HANDOVER_REQUEST_TIMEOUT)
+        .send()
+        .await?;
+    Ok(response.status().is_success())
+}
+
+/// Drain the proxy for the instance taking over its name, which stops
+/// subscribing the name. With a handover token, the request must bear it.
+/// With a successor in the body, `{"successor": "<admin address>"}`, the
+/// proxy only drains once the successor is ready, so that the name is never
+/// left without an instance accepting the new sessions. The proxy requests
+/// the successor, so it must be named by a holder of the handover token.
+async fn handover_handler(
+    State(state): State<Arc<AdminState>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    if state.handover_token.is_some() && !bears_token(&headers, state.handover_token.as_ref()) {
+        warn!("handover requested without the handover token");
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let successor = if body.is_empty() {
+        None
+    } else if state.handover_token.is_none() {
+        warn!("handover to a successor refused without a handover token");
+        return (
+            StatusCode::FORBIDDEN,
+            "a successor requires a handover token\n",
+        )
+            .into_response();
+    } else {
+        let successor = serde_json::from_slice::<serde_json::Value>(&body)
+            .ok()
+            .and_then(|body| body.get("successor")?.as_str().map(str::to_string))
+            .and_then(|successor| reqwest::Url::parse(&successor).ok())
+            .filter(|successor| matches!(successor.scheme(), "http" | "https"));
+        match successor {
+            Some(successor) => Some(successor),
+            None => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    "expected {\"successor\": \"<admin address>\"}\n",
+                )
+                    .into_response();
+            }
+        }
+    };
+    if let Some(successor) = &successor {
+        let ready = match successor.join("readyz") {
+            Ok(readyz) => successor_ready(readyz).await,
+            Err(_) => Ok(false),
+        };
+        match ready {
+            Ok(true) => {}
+            Ok(false) => {
+                warn!(%successor, "handover refused, the successor is not ready");
+                return (StatusCode::CONFLICT, "successor not ready\n").into_response();
+            }
+            Err(e) => {
+                warn!(%successor, "handover refused, cannot reach the successor: {}", e);
+                return (StatusCode::BAD_GATEWAY, "cannot reach the successor\n").into_response();
+            }
+        }
+    }
+    info!(successor = ?successor, "handover requested on the admin endpoint");
+    state.handover.store(true, Ordering::Relaxed);
+    state.set_ready(false);
+    state.drain.notify_one();
+    StatusCode::ACCEPTED.into_response()
+}
+
+/// Ask the instance with the admin endpoint at the given address to hand
+/// over its name, once this one is ready, presenting the handover token if
+/// any. The instance is retried a few times before giving up, in which case
+/// both keep serving the name.
+pub async fn request_handover(previous: reqwest::Url, token: Option<String>) {
+    let Ok(handover) = previous.join("handover") else {
+        error!(%previous, "invalid admin address of the previous instance");
+        return;
+    };
+    let client = reqwest::Client::new();
+    let mut backoff = HANDOVER_RETRY_BACKOFF;
+    for attempt in 1..=HANDOVER_ATTEMPTS {
+        let mut request = client
+            .post(handover.clone())
+            .timeout(HANDOVER_REQUEST_TIMEOUT);
+        if let Some(token) = &token {
+            request = request.bearer_auth(token);
+        }
+        match request.send().await {
+            Ok(response) if response.status() == StatusCode::ACCEPTED => {
+                info!(%previous, "previous instance handed over, it drains its sessions");
+                return;
+            }
+            Ok(response) => {
+                warn!(%previous, attempt, status = %response.status(), "previous instance refused the handover")
+            }
+            Err(e) => warn!(%previous, attempt, "error requesting the handover: {}", e),
+        }
+        if attempt < HANDOVER_ATTEMPTS {
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+    error!(%previous, "handover failed, the previous instance keeps serving the name");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const BACKEND: &str = "http://mcp.example.com/mcp";
+
+    fn state() -> Arc<AdminState> {
+        Arc::new(AdminState::new(
+            [(BACKEND.to_string(), BackendRole::Primary)],
+            DEFAULT_BACKEND_DOWN_FAILURES,
+            None,
+            None,
+            Events::default(),
+            false,
+        ))
+    }
+
+    /// Local address free at the time of the call
+    fn free_addr() -> SocketAddr {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    }
+
+    /// GET a path of the admin endpoint, waiting for it to listen
+    async fn get_json(addr: SocketAddr, path: &str) -> serde_json::Value {
+        let url = format!("http://{addr}{path}");
+        for _ in 0..100 {
+            if let Ok(response) = reqwest::get(&url).await {
+                assert_eq!(response.status(), StatusCode::OK);
+                return response.json().await.unwrap();
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("admin endpoint not listening on {addr}");
+    }
+
+    fn initialize_result(name: &str, tools: bool) -> InitializeResult {
+        let capabilities = if tools {
+            json!({"tools": {"listChanged": true}})
+        } else {
+            json!({})
+        };
+        serde_json::from_value(json!({
+            "protocolVersion": "2025-06-18",
+            "capabilities": capabilities,
+            "serverInfo": {"name": name, "version": "1.0.0"},
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn capabilities_recorded_and_served() {
+        let state = state();
+        let addr = free_addr();
+        let server = AdminServer::spawn(addr, state.clone());
+        assert_eq!(get_json(addr, "/capabilities").await, json!({}));
+
+        state.record_capabilities(BACKEND.into(), &initialize_result("mcp", false));
+        state.record_capabilities(
+            "http://other/mcp".into(),
+            &initialize_result("other", false),
+        );
+        // the last handshake with a server replaces its capabilities
+        state.record_capabilities(BACKEND.into(), &initialize_result("mcp", true));
+        assert_eq!(
+            get_json(addr, "/capabilities").await,
+            json!({
+                BACKEND: {
+                    "protocolVersion": "2025-06-18",
+                    "capabilities": {"tools": {"listChanged": true}},
+                    "serverInfo": {"name": "mcp", "version": "1.0.0"},
+                },
+                "http://other/mcp": {
+                    "protocolVersion": "2025-06-18",
+                    "capabilities": {},
+                    "serverInfo": {"name": "other", "version": "1.0.0"},
+                },
+            })
+        );
+        server.stop(Duration::from_secs(1)).await;
+    }
+
+    #[tokio::test]
+    async fn version_served() {
+        let addr = free_addr();
+        let server = AdminServer::spawn(addr, state());
+        let version = get_json(addr, "/version").await;
+        assert_eq!(version["version"], env!("CARGO_PKG_VERSION"));
+        let commit = version["gitCommit"].as_str().unwrap();
+        assert!(commit == "unknown" || commit.len() == 40, "{commit}");
+        assert!(version["buildTimestamp"].as_u64().unwrap() > 0);
+
+        // the versions locked in the workspace
+        let lock = include_str!("../../Cargo.lock");
+        let dependencies = version["dependencies"].as_object().unwrap();
+        assert_eq!(dependencies.len(), 3);
+        for package in ["rmcp", "agntcy-slim", "agntcy-slim-service"] {
+            let locked = format!(
+                "name = \"{package}\"\nversion = \"{}\"\n",
+                dependencies[package].as_str().unwrap()
+            );
+            assert!(
+                lock.contains(&locked),
+                "{package} not locked at its version"
+            );
+        }
+        server.stop(Duration::from_secs(1)).await;
+    }
+
+    #[tokio::test]
+    async fn port_released_on_stop_and_drop() {
+        let state = state();
+        let addr = free_addr();
+        // a keep-alive connection stays open across the restarts
+        let client = reqwest::Client::new();
+        for _ in 0..5 {
+            let server = AdminServer::spawn(addr, state.clone());
+            get_json(addr, "/version").await;
+            let response = client
+                .get(format!("http://{addr}/readyz"))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+            server.stop(Duration::from_millis(100)).await;
+            std::net::TcpListener::bind(addr).unwrap();
+        }
+
+        let server = AdminServer::spawn(addr, state.clone());
+        get_json(addr, "/version").await;
+        drop(server);
+        // the aborted task drops its listener when the runtime next polls it
+        for attempt in 0.. {
+            match std::net::TcpListener::bind(addr) {
+                Ok(_) => break,
+                Err(e) if attempt == 100 => panic!("port still bound: {e}"),
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn backend_health_transitions_served() {
+        let state = state();
+        let addr = free_addr();
+        let server = AdminServer::spawn(addr, state.clone());
+        let backend = || async { get_json(addr, "/backends").await[0].clone() };
+        let initial = backend().await;
+        assert_eq!(initial["health"], "healthy");
+        assert_eq!(initial["lastConnected"], serde_json::Value::Null);
+        assert_eq!(initial["lastError"], serde_json::Value::Null);
+
+        state.record_backend_success(BACKEND);
+        state.record_backend_connection(BACKEND, true);
+        state.record_backend_connection(BACKEND, true);
+        let connected = backend().await;
+        assert!(connected["lastConnected"].is_u64());
+        assert_eq!(connected["connections"], 2);
+
+        // degraded until the configured number of consecutive failures
+        for (failures, health) in [(1, "degraded"), (2, "degraded"), (3, "down"), (4, "down")] {
+            state.record_backend_failure(BACKEND, format!("failure {failures}"));
+            let failing = backend().await;
+            assert_eq!(failing["health"], health, "{failures} failures");
+            assert_eq!(failing["consecutiveFailures"], failures);
+            assert_eq!(
+                failing["lastError"]["message"],
+                format!("failure {failures}")
+            );
+        }
+
+        // a single handshake recovers it, the last error stays for inspection
+        state.record_backend_success(BACKEND);
+        let recovered = backend().await;
+        assert_eq!(recovered["health"], "healthy");
+        assert_eq!(recovered["consecutiveFailures"], 0);
+        assert_eq!(recovered["lastError"]["message"], "failure 4");
+
+        // connections never go below zero, unknown servers are ignored
+        for _ in 0..3 {
+            state.record_backend_connection(BACKEND, false);
+        }
+        state.record_backend_failure("http://unknown/mcp", "failure".into());
+        let backends = get_json(addr, "/backends").await;
+        assert_eq!(backends.as_array().unwrap().len(), 1);
+        assert_eq!(backends[0]["connections"], 0);
+        server.stop(Duration::from_secs(1)).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn valid_cpu_profile_served_to_the_token_holders() {
+        let state = Arc::new(AdminState::new(
+            [(BACKEND.to_string(), BackendRole::Primary)],
+            DEFAULT_BACKEND_DOWN_FAILURES,
+            Some("secret"),
+            None,
+            Events::default(),
+            false,
+        ));
+        let addr = free_addr();
+        let server = AdminServer::spawn(addr, state);
+        get_json(addr, "/version").await;
+        let client = reqwest::Client::new();
+        let profile = |token: &'static str, query: &'static str| {
+            client
+                .get(format!("http://{addr}/debug/pprof/profile{query}"))
+                .bearer_auth(token)
+                .send()
+        };
+
+        assert_eq!(
+            profile("wrong", "?seconds=1").await.unwrap().status(),
+            StatusCode::UNAUTHORIZED
+        );
+        for query in [
+            "?seconds=0",
+            "?seconds=-1",
+            "?seconds=3600",
+            "?seconds=soon",
+        ] {
+            assert_eq!(
+                profile("secret", query).await.unwrap().status(),
+                StatusCode::BAD_REQUEST,
+                "{query}"
+            );
+        }
+
+        let (first, second) = tokio::join!(profile("secret", "?seconds=1"), async {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            profile("secret", "?seconds=1").await
+        });
+        // one profile at a time
+        assert_eq!(second.unwrap().status(), StatusCode::CONFLICT);
+        let first = first.unwrap();
+        assert_eq!(first.status(), StatusCode::OK);
+        let profile = pprof::protos::Profile::decode(first.bytes().await.unwrap()).unwrap();
+        let string = |index: i64| profile.string_table[index as usize].as_str();
+        let sample_types: Vec<_> = profile
+            .sample_type
+            .iter()
+            .map(|t| (string(t.ty), string(t.unit)))
+            .collect();
+        assert!(
+            sample_types.contains(&("cpu", "nanoseconds")),
+            "{sample_types:?}"
+        );
+        assert!(profile.duration_nanos >= 1_000_000_000);
+        assert!(profile.period > 0);
+        server.stop(Duration::from_secs(1)).await;
+    }
+
+    #[tokio::test]
+    async fn handover_restricted_to_the_token_holders() {
+        let client = reqwest::Client::new();
+        let handover = |addr: SocketAddr, token: Option<&'static str>, body: &str| {
+            let mut request = client
+                .post(format!("http://{addr}/handover"))
+                .body(body.to_string());
+            if let Some(token) = token {
+                request = request.bearer_auth(token);
+            }
+            request.send()
+        };
+
+        // without a token, anyone can ask for a handover but not name a successor
+        let open = state();
+        open.set_ready(true);
+        let open_addr = free_addr();
+        let open_server = AdminServer::spawn(open_addr, open.clone());
+        get_json(open_addr, "/version").await;
+        let successor = format!(r#"{{"successor": "http://{open_addr}/"}}"#);
+        assert_eq!(
+            handover(open_addr, None, &successor)
+                .await
+                .unwrap()
+                .status(),
+            StatusCode::FORBIDDEN
+        );
+        assert!(open.is_ready());
+
+        let guarded = Arc::new(AdminState::new(
+            [(BACKEND.to_string(), BackendRole::Primary)],
+            DEFAULT_BACKEND_DOWN_FAILURES,
+            None,
+            Some("secret"),
+            Events::default(),
+            false,
+        ));
+        guarded.set_ready(true);
+        let addr = free_addr();
+        let server = AdminServer::spawn(addr, guarded.clone());
+        get_json(addr, "/version").await;
+        for token in [None, Some("wrong")] {
+            assert_eq!(
+                handover(addr, token, "").await.unwrap().status(),
+                StatusCode::UNAUTHORIZED,
+                "{token:?}"
+            );
+        }
+        // the successor is only requested over HTTP
+        assert_eq!(
+            handover(
+                addr,
+                Some("secret"),
+                r#"{"successor": "file:///etc/passwd"}"#
+            )
+            .await
+            .unwrap()
+            .status(),
+            StatusCode::BAD_REQUEST
+        );
+        assert!(guarded.is_ready());
+
+        assert_eq!(
+            handover(addr, Some("secret"), &successor)
+                .await
+                .unwrap()
+                .status(),
+            StatusCode::ACCEPTED
+        );
+        assert!(!guarded.is_ready());
+        assert_eq!(guarded.drain_requested().await, "handover");
+
+        assert_eq!(
+            handover(open_addr, None, "").await.unwrap().status(),
+            StatusCode::ACCEPTED
+        );
+        assert_eq!(open.drain_requested().await, "handover");
+        server.stop(Duration::from_secs(1)).await;
+        open_server.stop(Duration::from_secs(1)).await;
+    }
+}
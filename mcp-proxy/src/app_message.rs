@@ -0,0 +1,117 @@
+// Copyright AGNTCY Contributors (https://github.com/agntcy)
+// SPDX-License-Identifier: Apache-2.0
+
+use async_trait::async_trait;
+use slim_datapath::api::ProtoMessage as Message;
+use tracing::{debug, info};
+
+/// Handles the messages received by the proxy outside of any session.
+///
+/// The proxy serves every client through a session of its own, so the SLIM app
+/// only notifies a message when a peer publishes to the proxy name without
+/// opening a session, e.g. a control plane component or a misconfigured
+/// client. The handler runs in the loop accepting the sessions and must not
+/// block it.
+#[async_trait]
+pub trait AppMessageHandler: Send + Sync {
+    async fn on_message(&self, msg: Box<Message>);
+}
+
+/// Ignore the messages, logging them at debug level
+pub struct IgnoreAppMessages;
+
+#[async_trait]
+impl AppMessageHandler for IgnoreAppMessages {
+    async fn on_message(&self, msg: Box<Message>) {
+        debug!(
+            session_id = msg.get_session_header().get_session_id(),
+            "received unexpected NewMessage at app level"
+        );
+    }
+}
+
+/// Log the source and size of the messages at info level
+pub struct LogAppMessages;
+
+#[async_trait]
+impl AppMessageHandler for LogAppMessages {
+    async fn on_message(&self, msg: Box<Message>) {
+        let size = msg
+            .get_payload()
+            .and_then(|p| p.as_application_payload().ok())
+            .map_or(0, |p| p.blob.len());
+        info!(
+            source = %msg.get_source(),
+            session_id = msg.get_session_header().get_session_id(),
+            %size,
+            "received message outside of a session"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+    use slim_datapath::messages::Name;
+    use std::sync::Arc;
+
+    fn message(payload: Option<&[u8]>) -> Box<Message> {
+        let builder = Message::builder()
+            .source(Name::from_strings(["org", "ns", "peer"]))
+            .destination(Name::from_strings(["org", "ns", "mcp"]));
+        let builder = match payload {
+            Some(blob) => builder.application_payload("msg", blob.to_vec()),
+            None => builder,
+        };
+        Box::new(builder.build_publish().unwrap())
+    }
+
+    /// Handler keeping the messages it receives
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<Message>>>);
+
+    #[async_trait]
+    impl AppMessageHandler for Recorder {
+        async fn on_message(&self, msg: Box<Message>) {
+            self.0.lock().push(*msg);
+        }
+    }
+
+    #[tokio::test]
+    async fn custom_handler_receives_the_message() {
+        let recorder = Recorder::default();
+        let handler: Box<dyn AppMessageHandler> = Box::new(recorder.clone());
+        handler.on_message(message(Some(b"hello"))).await;
+
+        let received = recorder.0.lock();
+        assert_eq!(received.len(), 1);
+        assert_eq!(
+            received[0].get_source(),
+            Name::from_strings(["org", "ns", "peer"])
+        );
+        let payload = received[0]
+            .get_payload()
+            .unwrap()
+            .as_application_payload()
+            .unwrap();
+        assert_eq!(payload.blob, b"hello");
+    }
+
+    #[tokio::test]
+    #[tracing_test::traced_test]
+    async fn messages_logged_with_their_size() {
+        LogAppMessages.on_message(message(Some(b"hello"))).await;
+        assert!(logs_contain("received message outside of a session"));
+        assert!(logs_contain("size=5"));
+    }
+
+    #[tokio::test]
+    #[tracing_test::traced_test]
+    async fn messages_without_payload_handled() {
+        LogAppMessages.on_message(message(None)).await;
+        assert!(logs_contain("size=0"));
+        IgnoreAppMessages.on_message(message(None)).await;
+        assert!(logs_contain("received unexpected NewMessage at app level"));
+    }
+}
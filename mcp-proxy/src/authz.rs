@@ -0,0 +1,254 @@
+// Copyright AGNTCY Contributors (https://github.com/agntcy)
+// SPDX-License-Identifier: Apache-2.0
+
+use slim_datapath::messages::Name;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::pattern::{NamePattern, NamePatternError};
+
+#[derive(Debug, Error)]
+pub enum AllowlistError {
+    #[error("cannot read source allowlist {path}: {source}")]
+    Read {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("{path}:{line}: {source}")]
+    Pattern {
+        path: PathBuf,
+        line: usize,
+        #[source]
+        source: NamePatternError,
+    },
+}
+
+/// SLIM names of the clients allowed to open a session with the proxy.
+///
+/// The filtering is advisory: the identity verifier of the SLIM app checks
+/// the token of a client, not that the token was issued for the name the
+/// client claims, so any authenticated client can pass the filter by
+/// claiming an allowed name. The file holds one name pattern per line, e.g.
+/// `org/tenant-a/*`. Empty lines and lines starting with `#` are ignored.
+#[derive(Clone, Debug)]
+pub struct Allowlist {
+    patterns: Vec<NamePattern>,
+}
+
+impl Allowlist {
+    pub fn load(path: &Path) -> Result<Self, AllowlistError> {
+        let content = std::fs::read_to_string(path).map_err(|source| AllowlistError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+        let mut patterns = Vec::new();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            patterns.push(line.parse().map_err(|source| AllowlistError::Pattern {
+                path: path.to_path_buf(),
+                line: i + 1,
+                source,
+            })?);
+        }
+        Ok(Self { patterns })
+    }
+
+    pub fn allows(&self, name: &Name) -> bool {
+        self.patterns.iter().any(|p| p.matches(name))
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SourceLimitError {
+    #[error("expected <pattern>=<sessions>, found {0}")]
+    Format(String),
+    #[error("{0}")]
+    Pattern(#[from] NamePatternError),
+    #[error("invalid session limit: {0}")]
+    Limit(#[from] std::num::ParseIntError),
+    #[error("session limit must be greater than zero")]
+    Zero,
+}
+
+/// Maximum number of concurrent sessions of the clients whose name matches a
+/// pattern, e.g. `org/tenant-a/*=10`
+#[derive(Clone, Debug)]
+pub struct SourceLimit {
+    pattern: NamePattern,
+    limit: usize,
+}
+
+impl FromStr for SourceLimit {
+    type Err = SourceLimitError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (pattern, limit) = s
+            .rsplit_once('=')
+            .ok_or_else(|| SourceLimitError::Format(s.to_string()))?;
+        let limit = limit.trim().parse()?;
+        if limit == 0 {
+            return Err(SourceLimitError::Zero);
+        }
+        Ok(Self {
+            pattern: pattern.parse()?,
+            limit,
+        })
+    }
+}
+
+/// Concurrent session limit of a client: the last matching override wins over
+/// the global limit
+pub fn session_limit_for(
+    global: Option<usize>,
+    overrides: &[SourceLimit],
+    name: &Name,
+) -> Option<usize> {
+    overrides
+        .iter()
+        .rev()
+        .find(|o| o.pattern.matches(name))
+        .map(|o| o.limit)
+        .or(global)
+}
+
+#[derive(Debug, Error)]
+pub enum SourcePriorityError {
+    #[error("expected <pattern>=<priority>, found {0}")]
+    Format(String),
+    #[error("{0}")]
+    Pattern(#[from] NamePatternError),
+    #[error("invalid priority: {0}")]
+    Priority(#[from] std::num::ParseIntError),
+}
+
+/// Priority of the clients whose name matches a pattern, e.g.
+/// `org/tenant-a/*=10`: when the proxy is full, the sessions of a client
+/// close those of clients with a lower priority
+#[derive(Clone, Debug)]
+pub struct SourcePriority {
+    pattern: NamePattern,
+    priority: u32,
+}
+
+impl FromStr for SourcePriority {
+    type Err = SourcePriorityError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (pattern, priority) = s
+            .rsplit_once('=')
+            .ok_or_else(|| SourcePriorityError::Format(s.to_string()))?;
+        Ok(Self {
+            pattern: pattern.parse()?,
+            priority: priority.trim().parse()?,
+        })
+    }
+}
+
+/// Priority of a client: the last matching pattern wins, the clients matching
+/// no pattern have the lowest priority, 0
+pub fn priority_for(priorities: &[SourcePriority], name: &Name) -> u32 {
+    priorities
+        .iter()
+        .rev()
+        .find(|p| p.pattern.matches(name))
+        .map(|p| p.priority)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(components: [&str; 3]) -> Name {
+        Name::from_strings(components)
+    }
+
+    /// Allowlist file with the given content, removed once loaded
+    fn load(file: &str, content: &str) -> Result<Allowlist, AllowlistError> {
+        let path = std::env::temp_dir().join(format!("{file}-{}", std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        let allowlist = Allowlist::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        allowlist
+    }
+
+    #[test]
+    fn allowed_and_other_sources() {
+        let allowlist = load(
+            "allowlist-sources",
+            "# tenants\norg/tenant-a/*\n\n  org/tenant-b/agent/1f  \n",
+        )
+        .unwrap();
+        assert!(allowlist.allows(&name(["org", "tenant-a", "client"])));
+        assert!(allowlist.allows(&name(["org", "tenant-b", "agent"]).with_id(0x1f)));
+
+        assert!(!allowlist.allows(&name(["org", "tenant-b", "agent"]).with_id(0x2f)));
+        assert!(!allowlist.allows(&name(["org", "tenant-c", "client"])));
+        assert!(!allowlist.allows(&name(["other", "tenant-a", "client"])));
+    }
+
+    #[test]
+    fn empty_allowlist_allows_nobody() {
+        let allowlist = load("allowlist-empty", "# nobody yet\n").unwrap();
+        assert!(!allowlist.allows(&name(["org", "tenant-a", "client"])));
+    }
+
+    #[test]
+    fn invalid_allowlists_rejected() {
+        let error = load("allowlist-invalid", "org/tenant-a/*\norg/tenant-b\n").unwrap_err();
+        assert!(
+            matches!(error, AllowlistError::Pattern { line: 2, .. }),
+            "{error}"
+        );
+
+        let missing = std::env::temp_dir().join("allowlist-missing");
+        assert!(matches!(
+            Allowlist::load(&missing),
+            Err(AllowlistError::Read { .. })
+        ));
+    }
+
+    #[test]
+    fn session_limits_of_the_clients() {
+        let overrides: Vec<SourceLimit> = ["org/*/*=5", "org/tenant-a/*=1"]
+            .iter()
+            .map(|o| o.parse().unwrap())
+            .collect();
+        let limited = name(["org", "tenant-a", "client"]);
+        let other = name(["org", "tenant-b", "client"]);
+        let unmatched = name(["other", "tenant-a", "client"]);
+
+        // the last matching override wins
+        assert_eq!(session_limit_for(Some(2), &overrides, &limited), Some(1));
+        assert_eq!(session_limit_for(Some(2), &overrides, &other), Some(5));
+        assert_eq!(session_limit_for(Some(2), &overrides, &unmatched), Some(2));
+        assert_eq!(session_limit_for(None, &overrides, &unmatched), None);
+        assert_eq!(session_limit_for(None, &[], &limited), None);
+    }
+
+    #[test]
+    fn invalid_session_limits_rejected() {
+        assert!(matches!(
+            "org/tenant-a/*".parse::<SourceLimit>(),
+            Err(SourceLimitError::Format(_))
+        ));
+        assert!(matches!(
+            "org/tenant-a/*=0".parse::<SourceLimit>(),
+            Err(SourceLimitError::Zero)
+        ));
+        assert!(matches!(
+            "org/tenant-a/*=many".parse::<SourceLimit>(),
+            Err(SourceLimitError::Limit(_))
+        ));
+        assert!(matches!(
+            "org/tenant-a=1".parse::<SourceLimit>(),
+            Err(SourceLimitError::Pattern(_))
+        ));
+    }
+}
@@ -0,0 +1,556 @@
+// Copyright AGNTCY Contributors (https://github.com/agntcy)
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    borrow::Cow,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use futures::{StreamExt, stream::BoxStream};
+use reqwest::header::{ACCEPT, CONTENT_TYPE, WWW_AUTHENTICATE};
+use rmcp::model::{ClientJsonRpcMessage, ServerJsonRpcMessage};
+use rmcp::transport::common::http_header::{
+    EVENT_STREAM_MIME_TYPE, HEADER_LAST_EVENT_ID, HEADER_SESSION_ID, JSON_MIME_TYPE,
+};
+use rmcp::transport::streamable_http_client::{
+    AuthRequiredError, SseError, StreamableHttpClient, StreamableHttpError,
+    StreamableHttpPostResponse,
+};
+use sse_stream::{Sse, SseStream};
+use thiserror::Error;
+use tracing::{error, warn};
+
+/// Default size of the largest message read from the MCP server
+pub const DEFAULT_READ_BUFFER_BYTES: usize = 4 * 1024 * 1024;
+
+/// first event of the legacy HTTP+SSE transport, advertising where to POST
+const LEGACY_ENDPOINT_EVENT: &str = "endpoint";
+/// maximum time to wait for the `endpoint` event when probing the MCP server
+const LEGACY_SSE_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Error)]
+enum ReadError {
+    #[error("{0}")]
+    Http(#[from] reqwest::Error),
+    #[error("MCP server message exceeds the read buffer of {0} bytes")]
+    TooLarge(usize),
+    #[error(
+        "MCP server sent an endpoint event: it serves the legacy HTTP+SSE transport, not the streamable HTTP one"
+    )]
+    LegacySse,
+    #[error(
+        "MCP server sent a malformed endpoint event ({0}): it may serve the legacy HTTP+SSE transport, not the streamable HTTP one"
+    )]
+    MalformedEndpoint(&'static str),
+    #[error(
+        "MCP server does not accept POST and sent no endpoint event: it serves neither the streamable HTTP transport nor the legacy HTTP+SSE one"
+    )]
+    NoEndpoint,
+}
+
+/// What the MCP server answered to the GET probing for the legacy HTTP+SSE
+/// transport
+#[derive(Debug, PartialEq, Eq)]
+enum LegacyProbe {
+    /// an `endpoint` event with the address to POST to
+    Endpoint,
+    /// an `endpoint` event without a usable address
+    Malformed(&'static str),
+    /// no `endpoint` event as the first event, or no event in time
+    Missing,
+}
+
+/// Counts the bytes of the SSE event being received. An event ends with a
+/// blank line, lines end with CR, LF or CRLF.
+#[derive(Default)]
+struct EventSize {
+    bytes: usize,
+    at_line_start: bool,
+    last: u8,
+}
+
+impl EventSize {
+    /// Feed a chunk of the body, returning the size of the largest event
+    /// seen in it, complete or not
+    fn feed(&mut self, chunk: &[u8]) -> usize {
+        let mut largest = self.bytes;
+        for &b in chunk {
+            match b {
+                b'\n' if self.last == b'\r' => {}
+                b'\r' | b'\n' if self.at_line_start => self.bytes = 0,
+                b'\r' | b'\n' => self.at_line_start = true,
+                _ => {
+                    self.at_line_start = false;
+                    self.bytes += 1;
+                    largest = largest.max(self.bytes);
+                }
+            }
+            self.last = b;
+        }
+        largest
+    }
+}
+
+/// Body of a response of the MCP server, read without holding more than
+/// `max_message_bytes` of a single message.
+///
+/// The SSE parser buffers an event until it is complete, and a JSON response
+/// is read in full before being parsed: an event or a response larger than the
+/// limit is dropped as soon as the limit is crossed, ending the stream or
+/// failing the request.
+struct BoundedResponse {
+    response: reqwest::Response,
+    max_message_bytes: usize,
+}
+
+impl BoundedResponse {
+    /// Events of an SSE body, failing on the `endpoint` event of the legacy
+    /// HTTP+SSE transport
+    fn events(self, legacy_sse: Arc<AtomicBool>) -> BoxStream<'static, Result<Sse, SseError>> {
+        let max = self.max_message_bytes;
+        let bytes = self.response.bytes_stream().scan(
+            (EventSize::default(), false),
+            move |(size, exceeded), chunk| {
+                if *exceeded {
+                    return futures::future::ready(None);
+                }
+                let item = chunk.map_err(ReadError::from).and_then(|chunk| {
+                    if size.feed(&chunk) > max {
+                        warn!(
+                            max,
+                            "SSE event from MCP server too large, closing the stream"
+                        );
+                        *exceeded = true;
+                        Err(ReadError::TooLarge(max))
+                    } else {
+                        Ok(chunk)
+                    }
+                });
+                futures::future::ready(Some(item))
+            },
+        );
+        SseStream::from_byte_stream(bytes)
+            .map(move |event| match event {
+                Ok(sse) if sse.event.as_deref() == Some(LEGACY_ENDPOINT_EVENT) => {
+                    error!(
+                        "MCP server sent an endpoint event, it serves the legacy HTTP+SSE transport"
+                    );
+                    legacy_sse.store(true, Ordering::Relaxed);
+                    Err(SseError::Body(Box::new(ReadError::LegacySse)))
+                }
+                event => event,
+            })
+            .boxed()
+    }
+
+    /// Message of a JSON body
+    async fn json(mut self) -> Result<ServerJsonRpcMessage, StreamableHttpError<reqwest::Error>> {
+        let max = self.max_message_bytes;
+        let too_large = || {
+            warn!(max, "JSON response from MCP server too large");
+            StreamableHttpError::UnexpectedServerResponse(Cow::from(
+                ReadError::TooLarge(max).to_string(),
+            ))
+        };
+        if self
+            .response
+            .content_length()
+            .is_some_and(|len| len > max as u64)
+        {
+            return Err(too_large());
+        }
+        let mut body: Vec<u8> = Vec::new();
+        while let Some(chunk) = self.response.chunk().await? {
+            if body.len() + chunk.len() > max {
+                return Err(too_large());
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok(serde_json::from_slice(&body)?)
+    }
+}
+
+/// Whether a response of the MCP server has an SSE body rather than a JSON
+/// one, failing when it has neither
+fn is_event_stream(
+    response: &reqwest::Response,
+) -> Result<bool, StreamableHttpError<reqwest::Error>> {
+    match response.headers().get(CONTENT_TYPE) {
+        Some(ct) if ct.as_bytes().starts_with(EVENT_STREAM_MIME_TYPE.as_bytes()) => Ok(true),
+        Some(ct) if ct.as_bytes().starts_with(JSON_MIME_TYPE.as_bytes()) => Ok(false),
+        ct => {
+            Err(StreamableHttpError::UnexpectedContentType(ct.map(|ct| {
+                String::from_utf8_lossy(ct.as_bytes()).to_string()
+            })))
+        }
+    }
+}
+
+/// HTTP client of the MCP transport bounding the memory taken by a single
+/// message of the MCP server, see [`BoundedResponse`].
+///
+/// The client also recognizes the MCP servers serving the legacy HTTP+SSE
+/// transport, which the proxy does not support: they announce the endpoint to
+/// POST to in an `endpoint` event, where a streamable HTTP server answers the
+/// POST itself.
+#[derive(Clone)]
+pub struct BoundedClient {
+    inner: reqwest::Client,
+    max_message_bytes: usize,
+    /// the MCP server was found to serve the legacy HTTP+SSE transport
+    legacy_sse: Arc<AtomicBool>,
+}
+
+impl BoundedClient {
+    pub fn new(inner: reqwest::Client, max_message_bytes: usize) -> Self {
+        Self {
+            inner,
+            max_message_bytes,
+            legacy_sse: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn http(&self) -> &reqwest::Client {
+        &self.inner
+    }
+
+    /// The MCP server was found to serve the legacy HTTP+SSE transport, by
+    /// this client or one of its clones
+    pub fn legacy_sse(&self) -> bool {
+        self.legacy_sse.load(Ordering::Relaxed)
+    }
+
+    /// Request of the MCP transport, accepting both SSE and JSON bodies
+    fn request(
+        &self,
+        request: reqwest::RequestBuilder,
+        session_id: Option<&str>,
+        auth_token: Option<String>,
+    ) -> reqwest::RequestBuilder {
+        let mut request =
+            request.header(ACCEPT, [EVENT_STREAM_MIME_TYPE, JSON_MIME_TYPE].join(", "));
+        if let Some(session_id) = session_id {
+            request = request.header(HEADER_SESSION_ID, session_id);
+        }
+        if let Some(auth_token) = auth_token {
+            request = request.bearer_auth(auth_token);
+        }
+        request
+    }
+
+    fn bounded(&self, response: reqwest::Response) -> BoundedResponse {
+        BoundedResponse {
+            response,
+            max_message_bytes: self.max_message_bytes,
+        }
+    }
+
+    /// Whether the MCP server answers a GET with the `endpoint` event of the
+    /// legacy HTTP+SSE transport, and whether the event gives an address
+    async fn probe_legacy_sse(&self, uri: &str) -> LegacyProbe {
+        let probe = async {
+            let response = self
+                .inner
+                .get(uri)
+                .header(ACCEPT, EVENT_STREAM_MIME_TYPE)
+                .send()
+                .await
+                .ok()?;
+            let mut events = SseStream::from_byte_stream(response.bytes_stream());
+            loop {
+                let event = events.next().await?.ok()?;
+                if event.event.is_some() || event.data.is_some() {
+                    return Some(event);
+                }
+            }
+        };
+        let event = match tokio::time::timeout(LEGACY_SSE_PROBE_TIMEOUT, probe).await {
+            Ok(Some(event)) if event.event.as_deref() == Some(LEGACY_ENDPOINT_EVENT) => event,
+            _ => return LegacyProbe::Missing,
+        };
+        // the address is relative to the SSE endpoint, or absolute
+        match event.data.as_deref().map(str::trim) {
+            None | Some("") => LegacyProbe::Malformed("no address"),
+            Some(endpoint) => match reqwest::Url::parse(uri).and_then(|uri| uri.join(endpoint)) {
+                Ok(_) => LegacyProbe::Endpoint,
+                Err(_) => LegacyProbe::Malformed("invalid address"),
+            },
+        }
+    }
+}
+
+// The requests are built and checked as by the reqwest client of rmcp, only
+// the bodies are read through the bounded buffers
+impl StreamableHttpClient for BoundedClient {
+    type Error = reqwest::Error;
+
+    async fn get_stream(
+        &self,
+        uri: Arc<str>,
+        session_id: Arc<str>,
+        last_event_id: Option<String>,
+        auth_token: Option<String>,
+    ) -> Result<BoxStream<'static, Result<Sse, SseError>>, StreamableHttpError<Self::Error>> {
+        let mut request = self.request(
+            self.inner.get(uri.as_ref()),
+            Some(session_id.as_ref()),
+            auth_token,
+        );
+        if let Some(last_event_id) = last_event_id {
+            request = request.header(HEADER_LAST_EVENT_ID, last_event_id);
+        }
+        let response = request.send().await?;
+        if response.status() == reqwest::StatusCode::METHOD_NOT_ALLOWED {
+            return Err(StreamableHttpError::ServerDoesNotSupportSse);
+        }
+        let response = response.error_for_status()?;
+        is_event_stream(&response)?;
+        Ok(self.bounded(response).events(self.legacy_sse.clone()))
+    }
+
+    async fn delete_session(
+        &self,
+        uri: Arc<str>,
+        session_id: Arc<str>,
+        auth_token: Option<String>,
+    ) -> Result<(), StreamableHttpError<Self::Error>> {
+        self.inner.delete_session(uri, session_id, auth_token).await
+    }
+
+    async fn post_message(
+        &self,
+        uri: Arc<str>,
+        message: ClientJsonRpcMessage,
+        session_id: Option<Arc<str>>,
+        auth_token: Option<String>,
+    ) -> Result<StreamableHttpPostResponse, StreamableHttpError<Self::Error>> {
+        let response = self
+            .request(
+                self.inner.post(uri.as_ref()),
+                session_id.as_deref(),
+                auth_token,
+            )
+            .json(&message)
+            .send()
+            .await?;
+        if response.status() == reqwest::StatusCode::UNAUTHORIZED
+            && let Some(header) = response.headers().get(WWW_AUTHENTICATE)
+        {
+            let header = header.to_str().map_err(|_| {
+                StreamableHttpError::UnexpectedServerResponse(Cow::from(
+                    "invalid www-authenticate header value",
+                ))
+            })?;
+            return Err(StreamableHttpError::AuthRequired(AuthRequiredError {
+                www_authenticate_header: header.to_string(),
+            }));
+        }
+        // the SSE endpoint of the legacy transport only answers GET
+        if response.status() == reqwest::StatusCode::METHOD_NOT_ALLOWED {
+            let error = match self.probe_legacy_sse(uri.as_ref()).await {
+                LegacyProbe::Endpoint => {
+                    error!(
+                        "MCP server does not accept POST and sent an endpoint event, it serves the legacy HTTP+SSE transport"
+                    );
+                    self.legacy_sse.store(true, Ordering::Relaxed);
+                    ReadError::LegacySse
+                }
+                LegacyProbe::Malformed(reason) => {
+                    error!(
+                        reason,
+                        "MCP server does not accept POST and sent a malformed endpoint event"
+                    );
+                    self.legacy_sse.store(true, Ordering::Relaxed);
+                    ReadError::MalformedEndpoint(reason)
+                }
+                LegacyProbe::Missing => {
+                    error!("MCP server does not accept POST and sent no endpoint event");
+                    ReadError::NoEndpoint
+                }
+            };
+            return Err(StreamableHttpError::UnexpectedServerResponse(Cow::from(
+                error.to_string(),
+            )));
+        }
+        if matches!(
+            response.status(),
+            reqwest::StatusCode::ACCEPTED | reqwest::StatusCode::NO_CONTENT
+        ) {
+            return Ok(StreamableHttpPostResponse::Accepted);
+        }
+        let session_id = response
+            .headers()
+            .get(HEADER_SESSION_ID)
+            .and_then(|v| v.to_str().ok())
+            .map(|s| s.to_string());
+        let body = self.bounded(response);
+        if is_event_stream(&body.response)? {
+            Ok(StreamableHttpPostResponse::Sse(
+                body.events(self.legacy_sse.clone()),
+                session_id,
+            ))
+        } else {
+            Ok(StreamableHttpPostResponse::Json(
+                body.json().await?,
+                session_id,
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        Router,
+        http::{StatusCode, header},
+        routing::{get, post},
+    };
+
+    const MAX: usize = 64;
+
+    fn response(content_type: &'static str, body: String) -> impl axum::response::IntoResponse {
+        ([(header::CONTENT_TYPE, content_type)], body)
+    }
+
+    async fn mcp_server() -> String {
+        let small = r#"{"jsonrpc":"2.0","id":1,"result":{}}"#;
+        let large = format!(
+            r#"{{"jsonrpc":"2.0","id":1,"result":{{"pad":"{}"}}}}"#,
+            "x".repeat(MAX)
+        );
+        let app = Router::new()
+            .route(
+                "/small",
+                post(move || async move { response(JSON_MIME_TYPE, small.to_string()) }),
+            )
+            .route(
+                "/large",
+                post(move || async move { response(JSON_MIME_TYPE, large) }),
+            )
+            .route(
+                "/events",
+                post(move || async move {
+                    let body = format!("data: {small}\n\ndata: {}\n\n", "x".repeat(MAX + 1));
+                    response(EVENT_STREAM_MIME_TYPE, body)
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{addr}")
+    }
+
+    async fn post_ping(
+        client: &BoundedClient,
+        uri: String,
+    ) -> Result<StreamableHttpPostResponse, StreamableHttpError<reqwest::Error>> {
+        let ping: ClientJsonRpcMessage = serde_json::from_value(
+            serde_json::json!({"jsonrpc": "2.0", "id": 1, "method": "ping"}),
+        )
+        .unwrap();
+        client.post_message(uri.into(), ping, None, None).await
+    }
+
+    #[tokio::test]
+    async fn json_bounded() {
+        let server = mcp_server().await;
+        let client = BoundedClient::new(reqwest::Client::new(), MAX);
+
+        let small = post_ping(&client, format!("{server}/small")).await;
+        assert!(matches!(small, Ok(StreamableHttpPostResponse::Json(..))));
+
+        let large = post_ping(&client, format!("{server}/large")).await;
+        assert!(matches!(
+            large,
+            Err(StreamableHttpError::UnexpectedServerResponse(msg)) if msg.contains("read buffer")
+        ));
+    }
+
+    #[tokio::test]
+    async fn event_stream_ends_at_a_large_event() {
+        let server = mcp_server().await;
+        let client = BoundedClient::new(reqwest::Client::new(), MAX);
+
+        let Ok(StreamableHttpPostResponse::Sse(mut events, _)) =
+            post_ping(&client, format!("{server}/events")).await
+        else {
+            panic!("expected an event stream");
+        };
+        // the events read in the same chunk as the large one are dropped with it
+        let mut ended_in_error = false;
+        while let Some(event) = events.next().await {
+            match event {
+                Ok(sse) => assert!(sse.data.is_some_and(|data| data.len() <= MAX)),
+                Err(_) => ended_in_error = true,
+            }
+        }
+        assert!(ended_in_error);
+        assert!(!client.legacy_sse());
+    }
+
+    #[test]
+    fn event_size_resets_at_blank_lines() {
+        let mut size = EventSize::default();
+        assert_eq!(size.feed(b"data: abc\r\n"), 9);
+        // the event ended in the chunk still counts
+        assert_eq!(size.feed(b"data: de\n\n"), 17);
+        assert_eq!(size.bytes, 0);
+        assert_eq!(size.feed(b"data: f"), 7);
+    }
+
+    /// Local server refusing POST and answering GET with the given events
+    async fn sse_only_server(events: &'static str) -> String {
+        let app = Router::new().route(
+            "/sse",
+            get(move || async move { response(EVENT_STREAM_MIME_TYPE, events.to_string()) })
+                .post(|| async { StatusCode::METHOD_NOT_ALLOWED }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{addr}/sse")
+    }
+
+    async fn post_error(events: &'static str) -> (String, bool) {
+        let client = BoundedClient::new(reqwest::Client::new(), MAX);
+        match post_ping(&client, sse_only_server(events).await).await {
+            Err(StreamableHttpError::UnexpectedServerResponse(msg)) => {
+                (msg.into_owned(), client.legacy_sse())
+            }
+            Err(e) => panic!("unexpected error {e}"),
+            Ok(_) => panic!("POST refused by the server succeeded"),
+        }
+    }
+
+    #[tokio::test]
+    async fn endpoint_event_classified() {
+        let (error, legacy) = post_error("event: endpoint\ndata: /messages?id=1\n\n").await;
+        assert_eq!(error, ReadError::LegacySse.to_string());
+        assert!(legacy);
+
+        for events in [
+            "event: endpoint\n\n",
+            "event: endpoint\ndata:  \n\n",
+            "event: endpoint\ndata: http://[::1\n\n",
+        ] {
+            let (error, legacy) = post_error(events).await;
+            assert!(
+                error.contains("malformed endpoint event"),
+                "{events}: {error}"
+            );
+            assert!(legacy);
+        }
+
+        // another event first, or none at all
+        for events in ["event: message\ndata: {}\n\n", ": comment\n\n"] {
+            let (error, legacy) = post_error(events).await;
+            assert_eq!(error, ReadError::NoEndpoint.to_string(), "{events}");
+            assert!(!legacy);
+        }
+    }
+}
@@ -0,0 +1,77 @@
+// Copyright AGNTCY Contributors (https://github.com/agntcy)
+// SPDX-License-Identifier: Apache-2.0
+
+use std::time::Duration;
+
+/// Faults injected in the messages of the sessions, to test the resilience of
+/// the clients. Never meant for production.
+#[derive(Clone, Copy, Debug)]
+pub struct Chaos {
+    /// delay added to every message
+    pub latency: Duration,
+    /// share of the messages dropped, between 0 and 1
+    pub drop_rate: f64,
+}
+
+impl Chaos {
+    /// Delay a message, then tell whether it is dropped
+    pub async fn drops(&self) -> bool {
+        if !self.latency.is_zero() {
+            tokio::time::sleep(self.latency).await;
+        }
+        self.drop_rate > 0.0 && rand::random::<f64>() < self.drop_rate
+    }
+}
+
+/// Parse a drop rate, between 0 and 1
+pub fn drop_rate(s: &str) -> Result<f64, String> {
+    let rate: f64 = s.parse().map_err(|e| format!("{}", e))?;
+    if !(0.0..=1.0).contains(&rate) {
+        return Err("must be between 0 and 1".into());
+    }
+    Ok(rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn chaos(latency: Duration, drop_rate: f64) -> Chaos {
+        Chaos { latency, drop_rate }
+    }
+
+    #[tokio::test]
+    async fn messages_delayed_by_the_latency() {
+        let chaos = chaos(Duration::from_millis(50), 0.0);
+        let started = Instant::now();
+        assert!(!chaos.drops().await);
+        assert!(started.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn messages_dropped_at_the_rate() {
+        async fn dropped(drop_rate: f64) -> usize {
+            let chaos = chaos(Duration::ZERO, drop_rate);
+            let mut dropped = 0;
+            for _ in 0..10_000 {
+                dropped += usize::from(chaos.drops().await);
+            }
+            dropped
+        }
+        assert_eq!(dropped(0.0).await, 0);
+        assert_eq!(dropped(1.0).await, 10_000);
+        // more than 10 standard deviations away is not a coincidence
+        assert!((2_500..3_500).contains(&dropped(0.3).await));
+    }
+
+    #[test]
+    fn drop_rate_between_0_and_1() {
+        assert_eq!(drop_rate("0"), Ok(0.0));
+        assert_eq!(drop_rate("0.25"), Ok(0.25));
+        assert_eq!(drop_rate("1"), Ok(1.0));
+        for invalid in ["-0.1", "1.5", "half", "NaN"] {
+            assert!(drop_rate(invalid).is_err(), "{invalid}");
+        }
+    }
+}
@@ -0,0 +1,222 @@
+// Copyright AGNTCY Contributors (https://github.com/agntcy)
+// SPDX-License-Identifier: Apache-2.0
+
+use parking_lot::Mutex;
+use std::{sync::Arc, time::Duration};
+use tokio::time::Instant;
+use tracing::trace;
+
+use crate::metrics::Metrics;
+
+/// interval of the credit grants when not configured
+pub const DEFAULT_CREDIT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What a session that used its credits does
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum CreditPolicy {
+    /// Wait for the next grant
+    #[default]
+    Strict,
+    /// Keep publishing while no other session published in the interval,
+    /// waiting for the next grant only when the dataplane is shared
+    Share,
+}
+
+/// Credits granted to every session at each interval
+#[derive(Clone, Copy, Debug)]
+pub struct CreditConfig {
+    /// publications to the client, batches counting as one
+    pub messages: Option<u64>,
+    pub bytes: Option<u64>,
+    pub interval: Duration,
+    pub policy: CreditPolicy,
+}
+
+/// Flow control of the messages published to the SLIM clients, shared by the
+/// sessions. The grants of all the sessions are renewed together at the start
+/// of each interval, so that a session that used its credits yields the
+/// dataplane to the others until then.
+#[derive(Debug)]
+pub struct CreditScheduler {
+    config: CreditConfig,
+    start: Instant,
+    /// current interval, and the sessions that published during it
+    publishers: Mutex<(u64, usize)>,
+    metrics: Arc<Metrics>,
+}
+
+impl CreditScheduler {
+    pub fn new(config: CreditConfig, metrics: Arc<Metrics>) -> Self {
+        Self {
+            config,
+            start: Instant::now(),
+            publishers: Mutex::new((0, 0)),
+            metrics,
+        }
+    }
+
+    /// Credits of a new session
+    pub fn account(self: &Arc<Self>) -> SessionCredits {
+        SessionCredits {
+            scheduler: self.clone(),
+            // renewed at the first publication
+            state: Mutex::new(Credits {
+                interval: u64::MAX,
+                messages: 0,
+                bytes: 0,
+            }),
+        }
+    }
+
+    fn interval(&self, now: Instant) -> u64 {
+        (now.duration_since(self.start).as_nanos() / self.config.interval.as_nanos()) as u64
+    }
+
+    fn next_grant(&self, interval: u64) -> Instant {
+        let elapsed = self.config.interval.as_nanos() * (u128::from(interval) + 1);
+        self.start + Duration::from_nanos(elapsed as u64)
+    }
+
+    /// Count a session publishing for the first time in the interval
+    fn publishing(&self, interval: u64) {
+        let mut publishers = self.publishers.lock();
+        if publishers.0 != interval {
+            *publishers = (interval, 0);
+        }
+        publishers.1 += 1;
+    }
+
+    /// Whether other sessions than the calling one, already counted,
+    /// published in the interval
+    fn shared(&self, interval: u64) -> bool {
+        let publishers = self.publishers.lock();
+        publishers.0 != interval || publishers.1 > 1
+    }
+}
+
+#[derive(Debug)]
+struct Credits {
+    /// interval of the last grant
+    interval: u64,
+    messages: u64,
+    bytes: u64,
+}
+
+/// Credits left to a session in the current interval
+#[derive(Debug)]
+pub struct SessionCredits {
+    scheduler: Arc<CreditScheduler>,
+    state: Mutex<Credits>,
+}
+
+impl SessionCredits {
+    /// Wait until the session has the credits to publish a message of the
+    /// given size, and use them. A message larger than the byte grant is
+    /// published with a whole grant.
+    pub async fn acquire(&self, bytes: usize) {
+        let scheduler = &self.scheduler;
+        let config = &scheduler.config;
+        let bytes = bytes as u64;
+        let mut waited = false;
+        loop {
+            let now = Instant::now();
+            let interval = scheduler.interval(now);
+            {
+                let mut credits = self.state.lock();
+                if credits.interval != interval {
+                    scheduler.publishing(interval);
+                    *credits = Credits {
+                        interval,
+                        messages: config.messages.unwrap_or(u64::MAX),
+                        bytes: config.bytes.unwrap_or(u64::MAX),
+                    };
+                }
+                let full = config.bytes.is_some_and(|grant| credits.bytes == grant);
+                let available = credits.messages >= 1 && (credits.bytes >= bytes || full);
+                if available
+                    || (config.policy == CreditPolicy::Share && !scheduler.shared(interval))
+                {
+                    credits.messages = credits.messages.saturating_sub(1);
+                    credits.bytes = credits.bytes.saturating_sub(bytes);
+                    return;
+                }
+            }
+            if !waited {
+                waited = true;
+                scheduler.metrics.credit_waits.inc();
+            }
+            trace!(bytes, "session out of credits, waiting for the next grant");
+            tokio::time::sleep_until(scheduler.next_grant(interval)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::SourceLabels;
+
+    #[tokio::test]
+    async fn heavy_session_yields_to_the_light_ones() {
+        let interval = Duration::from_millis(100);
+        let metrics = Arc::new(Metrics::new(SourceLabels::Off));
+        let scheduler = Arc::new(CreditScheduler::new(
+            CreditConfig {
+                messages: Some(2),
+                bytes: None,
+                interval,
+                policy: CreditPolicy::Strict,
+            },
+            metrics.clone(),
+        ));
+        let started = Instant::now();
+        let publish = |count: usize| {
+            let credits = scheduler.account();
+            tokio::spawn(async move {
+                for _ in 0..count {
+                    credits.acquire(100).await;
+                }
+                started.elapsed()
+            })
+        };
+        let heavy = publish(10);
+        let light: Vec<_> = (0..3).map(|_| publish(2)).collect();
+
+        // the light sessions publish everything in the first interval, the
+        // heavy one 2 messages per interval
+        for session in light {
+            assert!(session.await.unwrap() < interval);
+        }
+        assert!(heavy.await.unwrap() >= interval * 4);
+        // only the heavy session waited, once for each grant
+        assert_eq!(metrics.credit_waits.get(), 4);
+    }
+
+    #[tokio::test]
+    async fn lone_session_keeps_publishing_when_sharing() {
+        let interval = Duration::from_millis(100);
+        let metrics = Arc::new(Metrics::new(SourceLabels::Off));
+        let scheduler = Arc::new(CreditScheduler::new(
+            CreditConfig {
+                messages: Some(2),
+                bytes: None,
+                interval,
+                policy: CreditPolicy::Share,
+            },
+            metrics.clone(),
+        ));
+        let started = Instant::now();
+        let heavy = scheduler.account();
+        for _ in 0..10 {
+            heavy.acquire(100).await;
+        }
+        assert!(started.elapsed() < interval);
+
+        // once another session published in the interval, the grant applies
+        let light = scheduler.account();
+        light.acquire(100).await;
+        heavy.acquire(100).await;
+        assert!(started.elapsed() >= interval);
+        assert_eq!(metrics.credit_waits.get(), 1);
+    }
+}
@@ -0,0 +1,167 @@
+// Copyright AGNTCY Contributors (https://github.com/agntcy)
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::redact::RedactedUrl;
+use hickory_resolver::{ResolveError, TokioResolver};
+use parking_lot::RwLock;
+use reqwest::{
+    Url,
+    dns::{Name, Resolve, Resolving},
+};
+use std::{collections::HashMap, fmt, net::SocketAddr, str::FromStr, time::Duration};
+use thiserror::Error;
+use tokio::net::TcpStream;
+use tracing::debug;
+
+const DNS_SRV_PREFIX: &str = "dns-srv:";
+/// time given to a discovered target to accept a TCP connection
+const TARGET_CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Error)]
+pub enum DiscoveryError {
+    #[error("unsupported discovery source {0}, expected dns-srv:<name>")]
+    UnsupportedSource(String),
+    #[error("error resolving {name}: {source}")]
+    Resolve {
+        name: String,
+        #[source]
+        source: ResolveError,
+    },
+    #[error("no reachable target found for {0}")]
+    NoTarget(String),
+    #[error("cannot use {host}:{port} as MCP server address")]
+    InvalidTarget { host: String, port: u16 },
+    #[error("error resolving the MCP server host {host}: {source}")]
+    Dns {
+        host: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("no address found for the MCP server host {0}")]
+    NoAddress(String),
+    #[error("resolving the MCP server host {host} took more than {timeout:?}")]
+    DnsTimeout { host: String, timeout: Duration },
+}
+
+/// Addresses of the MCP server hosts resolved ahead of the connections,
+/// given to the HTTP clients so that they do not resolve the hosts again. A
+/// host not resolved ahead is left to the system resolver.
+#[derive(Debug, Default)]
+pub struct ResolvedHosts(RwLock<HashMap<String, Vec<SocketAddr>>>);
+
+impl ResolvedHosts {
+    /// Record the last addresses of the host, replacing the previous ones
+    pub fn insert(&self, host: &str, addrs: Vec<SocketAddr>) {
+        self.0.write().insert(host.to_ascii_lowercase(), addrs);
+    }
+
+    pub fn get(&self, host: &str) -> Option<Vec<SocketAddr>> {
+        self.0.read().get(&host.to_ascii_lowercase()).cloned()
+    }
+}
+
+impl Resolve for ResolvedHosts {
+    fn resolve(&self, name: Name) -> Resolving {
+        let resolved = self.get(name.as_str());
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = match resolved {
+                Some(addrs) => addrs,
+                // the port is set by the HTTP client
+                None => tokio::net::lookup_host((name.as_str(), 0)).await?.collect(),
+            };
+            Ok(Box::new(addrs.into_iter()) as Box<dyn Iterator<Item = SocketAddr> + Send>)
+        })
+    }
+}
+
+/// Where the address of the MCP server is looked up
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DiscoverySource {
+    /// DNS SRV record name, e.g. `_mcp._tcp.example.com`
+    DnsSrv(String),
+}
+
+impl FromStr for DiscoverySource {
+    type Err = DiscoveryError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix(DNS_SRV_PREFIX) {
+            Some(name) if !name.trim().is_empty() => Ok(Self::DnsSrv(name.trim().to_string())),
+            _ => Err(DiscoveryError::UnsupportedSource(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for DiscoverySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DnsSrv(name) => write!(f, "{}{}", DNS_SRV_PREFIX, name),
+        }
+    }
+}
+
+/// Resolves the MCP server address every time a session connects
+#[derive(Clone)]
+pub struct Discovery {
+    source: DiscoverySource,
+    resolver: TokioResolver,
+}
+
+impl fmt::Debug for Discovery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Discovery")
+            .field("source", &self.source)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Discovery {
+    /// Create a discovery using the system DNS configuration
+    pub fn new(source: DiscoverySource) -> Result<Self, ResolveError> {
+        let resolver = TokioResolver::builder_tokio()?.build();
+        Ok(Self { source, resolver })
+    }
+
+    /// Replace host and port of the MCP server URL with the discovered target.
+    ///
+    /// SRV targets are tried by ascending priority and, for the same priority,
+    /// by descending weight. The first one accepting a TCP connection is used.
+    pub async fn resolve(&self, mcp_server: &Url) -> Result<Url, DiscoveryError> {
+        let DiscoverySource::DnsSrv(name) = &self.source;
+        let lookup = self
+            .resolver
+            .srv_lookup(name.as_str())
+            .await
+            .map_err(|source| DiscoveryError::Resolve {
+                name: name.clone(),
+                source,
+            })?;
+
+        let mut targets: Vec<_> = lookup
+            .iter()
+            .map(|srv| {
+                let host = srv.target().to_utf8();
+                let host = host.trim_end_matches('.').to_string();
+                (srv.priority(), srv.weight(), host, srv.port())
+            })
+            .collect();
+        targets.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+
+        for (priority, weight, host, port) in targets {
+            let connect = TcpStream::connect((host.as_str(), port));
+            match tokio::time::timeout(TARGET_CONNECT_TIMEOUT, connect).await {
+                Ok(Ok(_)) => {
+                    let mut url = mcp_server.clone();
+                    if url.set_host(Some(&host)).is_err() || url.set_port(Some(port)).is_err() {
+                        return Err(DiscoveryError::InvalidTarget { host, port });
+                    }
+                    debug!(%priority, %weight, url = %RedactedUrl(url.as_str()), "discovered MCP server");
+                    return Ok(url);
+                }
+                Ok(Err(e)) => debug!(%host, %port, "discovered target not reachable: {}", e),
+                Err(_) => debug!(%host, %port, "discovered target not reachable: timeout"),
+            }
+        }
+        Err(DiscoveryError::NoTarget(name.clone()))
+    }
+}
@@ -0,0 +1,49 @@
+// Copyright AGNTCY Contributors (https://github.com/agntcy)
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{io, path::PathBuf};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ProxyError {
+    #[error(
+        "no dataplane clients configured for the service: add an entry under \
+         services.<svc_name>.dataplane.clients in the configuration file"
+    )]
+    NoDataplaneClients,
+    #[error(
+        "no dataplane client with the endpoint {endpoint} to subscribe on, configured endpoints: {}",
+        if configured.is_empty() { "none".to_string() } else { configured.join(", ") }
+    )]
+    UnknownDataplaneEndpoint {
+        endpoint: String,
+        configured: Vec<String>,
+    },
+    #[error("invalid proxy configuration: {}", .0.join("; "))]
+    InvalidConfig(Vec<String>),
+    #[error("error running the SLIM service: {0}")]
+    ServiceRun(#[from] slim_service::ServiceError),
+    #[error("error creating the SLIM app: {0}")]
+    CreateApp(#[source] slim_service::ServiceError),
+    #[error(
+        "no connection to the dataplane endpoint {endpoint}, connected endpoints: {}",
+        if connected.is_empty() { "none".to_string() } else { connected.join(", ") }
+    )]
+    NotConnected {
+        endpoint: String,
+        /// endpoints the service is connected to, to spot a spelling mismatch
+        connected: Vec<String>,
+    },
+    #[error("error subscribing the proxy name after {attempts} attempts: {source}")]
+    Subscribe {
+        attempts: u32,
+        #[source]
+        source: slim_service::ServiceError,
+    },
+    #[error("cannot listen on the event socket {}: {source}", path.display())]
+    EventSocket {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+}
@@ -0,0 +1,75 @@
+// Copyright AGNTCY Contributors (https://github.com/agntcy)
+// SPDX-License-Identifier: Apache-2.0
+
+use clap::ValueEnum;
+use std::{ops::RangeInclusive, str::FromStr};
+use thiserror::Error;
+
+/// What the session does after forwarding an error of the MCP server
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ErrorAction {
+    /// Only forward the error to the client
+    #[default]
+    Forward,
+    /// Close the session
+    Close,
+    /// Reconnect to the MCP server, with a backoff, and replay the handshake
+    Reconnect,
+}
+
+#[derive(Debug, Error)]
+pub enum ErrorRuleError {
+    #[error("expected <code>=<action> or <first>..<last>=<action>, found {0}")]
+    Format(String),
+    #[error("invalid error code: {0}")]
+    Code(#[from] std::num::ParseIntError),
+    #[error("empty error code range {0}..{1}")]
+    Range(i32, i32),
+    #[error("invalid action {0}, expected forward, close or reconnect")]
+    Action(String),
+}
+
+/// Action for the MCP errors whose code is in a range, e.g. `-32002=reconnect`
+/// or `-32099..-32000=close`
+#[derive(Clone, Debug)]
+pub struct ErrorRule {
+    codes: RangeInclusive<i32>,
+    action: ErrorAction,
+}
+
+impl FromStr for ErrorRule {
+    type Err = ErrorRuleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (codes, action) = s
+            .rsplit_once('=')
+            .ok_or_else(|| ErrorRuleError::Format(s.to_string()))?;
+        let codes = match codes.split_once("..") {
+            Some((first, last)) => {
+                let (first, last) = (first.trim().parse()?, last.trim().parse()?);
+                if first > last {
+                    return Err(ErrorRuleError::Range(first, last));
+                }
+                first..=last
+            }
+            None => {
+                let code = codes.trim().parse()?;
+                code..=code
+            }
+        };
+        let action = ErrorAction::from_str(action.trim(), true)
+            .map_err(|_| ErrorRuleError::Action(action.trim().to_string()))?;
+        Ok(Self { codes, action })
+    }
+}
+
+/// Action for an error code: the last matching rule wins, errors matching no
+/// rule are only forwarded
+pub fn action_for(rules: &[ErrorRule], code: i32) -> ErrorAction {
+    rules
+        .iter()
+        .rev()
+        .find(|r| r.codes.contains(&code))
+        .map(|r| r.action)
+        .unwrap_or_default()
+}
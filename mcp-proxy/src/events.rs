@@ -0,0 +1,234 @@
+// Copyright AGNTCY Contributors (https://github.com/agntcy)
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    io,
+    os::unix::fs::FileTypeExt,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    io::AsyncWriteExt,
+    net::{UnixListener, UnixStream},
+    sync::{broadcast, watch},
+    task::{JoinHandle, JoinSet},
+};
+use tracing::{debug, info, warn};
+
+/// Events kept for a slow consumer of the socket, beyond which it misses the
+/// oldest ones
+const EVENT_QUEUE_CAPACITY: usize = 1024;
+
+/// Lifecycle and session events of the proxy, published as JSON lines to the
+/// consumers of the event socket. Events are only serialized while a
+/// consumer is connected.
+#[derive(Clone, Debug)]
+pub struct Events {
+    tx: broadcast::Sender<Arc<str>>,
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        Self {
+            tx: broadcast::channel(EVENT_QUEUE_CAPACITY).0,
+        }
+    }
+}
+
+impl Events {
+    /// Publish an event with the given fields, which must be a JSON object,
+    /// adding its name and time
+    pub fn emit(&self, event: &str, fields: serde_json::Value) {
+        if self.tx.receiver_count() == 0 {
+            return;
+        }
+        let mut line = serde_json::json!({
+            "event": event,
+            "time": SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64,
+        });
+        if let (Some(line), serde_json::Value::Object(fields)) = (line.as_object_mut(), fields) {
+            line.extend(fields);
+        }
+        let _ = self.tx.send(format!("{}\n", line).into());
+    }
+}
+
+/// Unix socket serving the events to every connected consumer
+pub struct EventSocket {
+    path: PathBuf,
+    shutdown: watch::Sender<bool>,
+    task: JoinHandle<()>,
+}
+
+impl EventSocket {
+    /// Listen on the socket, replacing a socket left by a previous run. Any
+    /// other file at the path is kept and fails the bind.
+    pub fn bind(path: PathBuf, events: Events) -> io::Result<Self> {
+        match std::fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(&path)?,
+            Ok(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "the path exists and is not a socket",
+                ));
+            }
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            Err(_) => {}
+        }
+        let listener = UnixListener::bind(&path)?;
+        info!(path = %path.display(), "publishing events on the event socket");
+        let (shutdown, stopped) = watch::channel(false);
+        let task = tokio::spawn(accept(listener, events, stopped));
+        Ok(Self {
+            path,
+            shutdown,
+            task,
+        })
+    }
+
+    /// Stop accepting consumers, and give the connected ones the time to
+    /// receive the events already published
+    pub async fn stop(mut self, timeout: Duration) {
+        let _ = self.shutdown.send(true);
+        if tokio::time::timeout(timeout, &mut self.task).await.is_err() {
+            warn!(?timeout, "event socket consumers too slow, closing them");
+            self.task.abort();
+        }
+    }
+}
+
+impl Drop for EventSocket {
+    fn drop(&mut self) {
+        self.task.abort();
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+async fn accept(listener: UnixListener, events: Events, mut stopped: watch::Receiver<bool>) {
+    let mut consumers = JoinSet::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => {
+                    debug!("event socket consumer connected");
+                    consumers.spawn(publish(stream, events.tx.subscribe(), stopped.clone()));
+                }
+                Err(e) => warn!("error accepting an event socket consumer: {}", e),
+            },
+            Some(_) = consumers.join_next(), if !consumers.is_empty() => {}
+            _ = stopped.changed() => break,
+        }
+    }
+    while consumers.join_next().await.is_some() {}
+}
+
+/// Write the events to a consumer until it disconnects or the socket stops,
+/// after writing the events already published
+async fn publish(
+    mut stream: UnixStream,
+    mut rx: broadcast::Receiver<Arc<str>>,
+    mut stopped: watch::Receiver<bool>,
+) {
+    loop {
+        let received = tokio::select! {
+            received = rx.recv() => received,
+            _ = stopped.changed() => break,
+        };
+        let line = match received {
+            Ok(line) => line,
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                warn!(missed, "event socket consumer too slow, events skipped");
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        if stream.write_all(line.as_bytes()).await.is_err() {
+            debug!("event socket consumer disconnected");
+            return;
+        }
+    }
+    while let Ok(line) = rx.try_recv() {
+        if stream.write_all(line.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, BufReader};
+
+    fn socket_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("events-{}-{}.sock", name, std::process::id()))
+    }
+
+    async fn next_event(lines: &mut tokio::io::Lines<BufReader<UnixStream>>) -> serde_json::Value {
+        let line = tokio::time::timeout(Duration::from_secs(5), lines.next_line())
+            .await
+            .expect("no event in time")
+            .unwrap()
+            .expect("event socket closed");
+        serde_json::from_str(&line).unwrap()
+    }
+
+    #[tokio::test]
+    async fn consumers_receive_the_events() {
+        let path = socket_path("consumers");
+        let events = Events::default();
+        // nobody listens yet, the event is not serialized
+        events.emit("proxy_starting", serde_json::json!({}));
+        let socket = EventSocket::bind(path.clone(), events.clone()).unwrap();
+
+        let mut consumers = Vec::new();
+        for _ in 0..2 {
+            let stream = UnixStream::connect(&path).await.unwrap();
+            consumers.push(BufReader::new(stream).lines());
+        }
+        // the consumers are subscribed once accepted
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while events.tx.receiver_count() < 2 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        events.emit("session_opened", serde_json::json!({ "sessionId": 7 }));
+        events.emit("proxy_stopped", serde_json::json!({}));
+        for lines in &mut consumers {
+            let opened = next_event(lines).await;
+            assert_eq!(opened["event"], "session_opened");
+            assert_eq!(opened["sessionId"], 7);
+            assert!(opened["time"].is_u64());
+            assert_eq!(next_event(lines).await["event"], "proxy_stopped");
+        }
+
+        // the consumers get the events published before the stop, then the end
+        socket.stop(Duration::from_secs(1)).await;
+        for lines in &mut consumers {
+            assert_eq!(lines.next_line().await.unwrap(), None);
+        }
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn only_a_stale_socket_replaced() {
+        let path = socket_path("stale");
+        // left by a previous run that did not stop cleanly
+        let stale = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        drop(stale);
+        let socket = EventSocket::bind(path.clone(), Events::default()).unwrap();
+        UnixStream::connect(&path).await.unwrap();
+        drop(socket);
+        assert!(!path.exists());
+
+        std::fs::write(&path, "keep me").unwrap();
+        let error = EventSocket::bind(path.clone(), Events::default())
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "keep me");
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,210 @@
+// Copyright AGNTCY Contributors (https://github.com/agntcy)
+// SPDX-License-Identifier: Apache-2.0
+
+use regex::Regex;
+use rmcp::model::{CustomResult, RawContent, RawEmbeddedResource, ResourceContents, ServerResult};
+use serde_json::Value;
+
+const REDACTED_CONTENT: &str = "[REDACTED]";
+
+/// What to do with a tool result matching the content filter
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum FilterAction {
+    /// Replace the matching text with [REDACTED]
+    #[default]
+    Redact,
+    /// Replace the whole result with an error
+    Block,
+}
+
+impl FilterAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FilterAction::Redact => "redact",
+            FilterAction::Block => "block",
+        }
+    }
+}
+
+/// Result of the content filter on a tool result
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FilterOutcome {
+    /// nothing matched
+    Clean,
+    /// the given number of matches were redacted
+    Redacted(usize),
+    /// the result must not reach the client
+    Blocked,
+}
+
+/// Scans the text of the `tools/call` results for sensitive patterns, e.g.
+/// credit card numbers, before they are sent to the clients
+#[derive(Clone, Debug)]
+pub struct ContentFilter {
+    patterns: Vec<Regex>,
+    action: FilterAction,
+}
+
+impl ContentFilter {
+    pub fn new<I, S>(patterns: I, action: FilterAction) -> Result<Self, regex::Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Ok(Self {
+            patterns: patterns
+                .into_iter()
+                .map(|p| Regex::new(p.as_ref()))
+                .collect::<Result<_, _>>()?,
+            action,
+        })
+    }
+
+    /// Apply the filter to the text contents, the embedded text resources and
+    /// the structured content of a tool result. Results not recognized as a
+    /// tool result are scanned as JSON.
+    pub fn apply(&self, result: &mut ServerResult) -> FilterOutcome {
+        let mut matches = 0;
+        match result {
+            ServerResult::CallToolResult(result) => {
+                for content in &mut result.content {
+                    match &mut content.raw {
+                        RawContent::Text(text) => matches += self.scan(&mut text.text),
+                        RawContent::Resource(RawEmbeddedResource {
+                            resource: ResourceContents::TextResourceContents { text, .. },
+                            ..
+                        }) => matches += self.scan(text),
+                        _ => {}
+                    }
+                }
+                if let Some(structured) = &mut result.structured_content {
+                    matches += self.scan_value(structured);
+                }
+            }
+            ServerResult::CustomResult(CustomResult(value)) => matches += self.scan_value(value),
+            _ => {}
+        }
+
+        match (matches, self.action) {
+            (0, _) => FilterOutcome::Clean,
+            (n, FilterAction::Redact) => FilterOutcome::Redacted(n),
+            (_, FilterAction::Block) => FilterOutcome::Blocked,
+        }
+    }
+
+    /// Count the matches in the text, redacting them if required
+    fn scan(&self, text: &mut String) -> usize {
+        let mut matches = 0;
+        for pattern in &self.patterns {
+            let n = pattern.find_iter(text).count();
+            if n > 0 && self.action == FilterAction::Redact {
+                *text = pattern.replace_all(text, REDACTED_CONTENT).into_owned();
+            }
+            matches += n;
+        }
+        matches
+    }
+
+    fn scan_value(&self, value: &mut Value) -> usize {
+        match value {
+            Value::String(text) => self.scan(text),
+            Value::Array(items) => items.iter_mut().map(|v| self.scan_value(v)).sum(),
+            Value::Object(map) => map.values_mut().map(|v| self.scan_value(v)).sum(),
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rmcp::model::{CallToolResult, Content};
+    use serde_json::json;
+
+    const CARD: &str = r"\b\d{4}-\d{4}-\d{4}-\d{4}\b";
+
+    fn tool_result(content: Vec<Content>) -> ServerResult {
+        ServerResult::CallToolResult(CallToolResult::success(content))
+    }
+
+    fn texts(result: &ServerResult) -> Vec<String> {
+        let ServerResult::CallToolResult(result) = result else {
+            panic!("not a tool result");
+        };
+        result
+            .content
+            .iter()
+            .map(|content| match &content.raw {
+                RawContent::Text(text) => text.text.clone(),
+                RawContent::Resource(RawEmbeddedResource {
+                    resource: ResourceContents::TextResourceContents { text, .. },
+                    ..
+                }) => text.clone(),
+                _ => panic!("unexpected content"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn clean_results_unchanged() {
+        let filter = ContentFilter::new([CARD], FilterAction::Block).unwrap();
+        let mut result = tool_result(vec![Content::text("order 1234 shipped")]);
+        assert_eq!(filter.apply(&mut result), FilterOutcome::Clean);
+        assert_eq!(texts(&result), ["order 1234 shipped"]);
+    }
+
+    #[test]
+    fn matches_redacted() {
+        let filter =
+            ContentFilter::new([CARD, r"\d{3}-\d{2}-\d{4}"], FilterAction::Redact).unwrap();
+        let mut result = tool_result(vec![
+            Content::text("card 1234-5678-9012-3456, ssn 123-45-6789"),
+            Content::embedded_text("file:///card", "1111-2222-3333-4444"),
+            Content::text("nothing to hide"),
+        ]);
+        assert_eq!(filter.apply(&mut result), FilterOutcome::Redacted(3));
+        assert_eq!(
+            texts(&result),
+            [
+                "card [REDACTED], ssn [REDACTED]",
+                "[REDACTED]",
+                "nothing to hide"
+            ]
+        );
+    }
+
+    #[test]
+    fn structured_and_unknown_results_scanned() {
+        let filter = ContentFilter::new([CARD], FilterAction::Redact).unwrap();
+        let mut result = ServerResult::CallToolResult(CallToolResult {
+            content: vec![],
+            structured_content: Some(json!({"cards": ["1234-5678-9012-3456"], "count": 1})),
+            is_error: None,
+            meta: None,
+        });
+        assert_eq!(filter.apply(&mut result), FilterOutcome::Redacted(1));
+        let ServerResult::CallToolResult(result) = result else {
+            unreachable!()
+        };
+        assert_eq!(
+            result.structured_content,
+            Some(json!({"cards": ["[REDACTED]"], "count": 1}))
+        );
+
+        let mut result =
+            ServerResult::CustomResult(CustomResult(json!({"card": "1234-5678-9012-3456"})));
+        assert_eq!(filter.apply(&mut result), FilterOutcome::Redacted(1));
+    }
+
+    #[test]
+    fn matches_blocked() {
+        let filter = ContentFilter::new([CARD], FilterAction::Block).unwrap();
+        let mut result = tool_result(vec![Content::text("card 1234-5678-9012-3456")]);
+        assert_eq!(filter.apply(&mut result), FilterOutcome::Blocked);
+    }
+
+    #[test]
+    fn invalid_patterns_rejected() {
+        assert!(ContentFilter::new(["(unclosed"], FilterAction::Redact).is_err());
+    }
+}
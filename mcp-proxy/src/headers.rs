@@ -0,0 +1,117 @@
+// Copyright AGNTCY Contributors (https://github.com/agntcy)
+// SPDX-License-Identifier: Apache-2.0
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, InvalidHeaderName, InvalidHeaderValue};
+use slim_datapath::messages::Name;
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::pattern::{NamePattern, NamePatternError};
+
+#[derive(Debug, Error)]
+pub enum SourceHeaderError {
+    #[error("expected <pattern>=<header>: <value>, found {0}")]
+    Format(String),
+    #[error("{0}")]
+    Pattern(#[from] NamePatternError),
+    #[error("invalid header name: {0}")]
+    HeaderName(#[from] InvalidHeaderName),
+    #[error("invalid header value: {0}")]
+    HeaderValue(#[from] InvalidHeaderValue),
+}
+
+/// HTTP header set on the MCP connection of the sessions whose client name
+/// matches a pattern, e.g. `org/tenant-a/*=X-Tenant: a`
+#[derive(Clone, Debug)]
+pub struct SourceHeader {
+    pattern: NamePattern,
+    name: HeaderName,
+    value: HeaderValue,
+}
+
+impl FromStr for SourceHeader {
+    type Err = SourceHeaderError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (pattern, header) = s
+            .split_once('=')
+            .ok_or_else(|| SourceHeaderError::Format(s.to_string()))?;
+        let (name, value) = header
+            .split_once(':')
+            .ok_or_else(|| SourceHeaderError::Format(s.to_string()))?;
+
+        Ok(Self {
+            pattern: pattern.parse()?,
+            name: HeaderName::from_str(name.trim())?,
+            value: HeaderValue::from_str(value.trim())?,
+        })
+    }
+}
+
+/// Headers of all the mappings matching the client name. When several
+/// mappings set the same header the last one wins.
+pub fn headers_for(mappings: &[SourceHeader], name: &Name) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for mapping in mappings.iter().filter(|m| m.pattern.matches(name)) {
+        headers.insert(mapping.name.clone(), mapping.value.clone());
+    }
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(components: [&str; 3]) -> Name {
+        Name::from_strings(components)
+    }
+
+    #[test]
+    fn valid_mappings_parsed() {
+        let mapping: SourceHeader = "org/tenant-a/*= X-Tenant : a ".parse().unwrap();
+        assert_eq!(mapping.pattern, "org/tenant-a/*".parse().unwrap());
+        assert_eq!(mapping.name, "x-tenant");
+        assert_eq!(mapping.value, "a");
+    }
+
+    #[test]
+    fn invalid_mappings_rejected() {
+        for (mapping, expected) in [
+            ("org/tenant-a/*", "expected <pattern>=<header>: <value>"),
+            (
+                "org/tenant-a/*=X-Tenant",
+                "expected <pattern>=<header>: <value>",
+            ),
+            ("org/tenant-a=X-Tenant: a", "invalid name pattern"),
+            ("org/tenant-a/*=X Tenant: a", "invalid header name"),
+            ("org/tenant-a/*=X-Tenant: a\u{7f}", "invalid header value"),
+        ] {
+            let error = mapping.parse::<SourceHeader>().unwrap_err().to_string();
+            assert!(error.starts_with(expected), "{mapping}: {error}");
+        }
+    }
+
+    #[test]
+    fn headers_of_matching_mappings() {
+        let mappings: Vec<SourceHeader> = [
+            "org/tenant-a/*=X-Tenant: a",
+            "org/*/*=X-Org: org",
+            "org/tenant-a/admin=X-Tenant: admin",
+        ]
+        .iter()
+        .map(|m| m.parse().unwrap())
+        .collect();
+
+        let headers = headers_for(&mappings, &name(["org", "tenant-a", "client"]));
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers["x-tenant"], "a");
+        assert_eq!(headers["x-org"], "org");
+
+        // the last mapping setting a header wins
+        let headers = headers_for(&mappings, &name(["org", "tenant-a", "admin"]));
+        assert_eq!(headers["x-tenant"], "admin");
+
+        let headers = headers_for(&mappings, &name(["other", "tenant-a", "client"]));
+        assert!(headers.is_empty());
+    }
+}
@@ -0,0 +1,192 @@
+// Copyright AGNTCY Contributors (https://github.com/agntcy)
+// SPDX-License-Identifier: Apache-2.0
+
+use rmcp::model::{
+    ClientJsonRpcMessage, ClientNotification, JsonRpcError, JsonRpcMessage, JsonRpcNotification,
+    JsonRpcRequest, JsonRpcResponse, NumberOrString, RequestId, ServerJsonRpcMessage,
+};
+
+const NUMBER_TAG: &str = "n/";
+const STRING_TAG: &str = "s/";
+/// Prefix of the ids of the requests sent by the proxy on its own to the MCP
+/// server, which the clients cannot use: their responses are handled by the
+/// proxy and never reach a client
+const INTERNAL_PREFIX: &str = "slim-proxy/";
+
+/// Id of a request sent by the proxy on its own to the MCP server
+pub fn internal_id(name: &str) -> RequestId {
+    NumberOrString::String(format!("{}{}", INTERNAL_PREFIX, name).into())
+}
+
+/// Whether the id is the one of a request sent by the proxy on its own
+pub fn is_internal(id: &RequestId) -> bool {
+    matches!(id, NumberOrString::String(s) if s.starts_with(INTERNAL_PREFIX))
+}
+
+/// Rewrites the ids of the client requests into a namespace of the session,
+/// so that the MCP server can tell apart the requests of different sessions
+/// using the same ids.
+///
+/// A request id `42` of session `7` is forwarded as `"slim-7/n/42"` and the
+/// string id `"abc"` as `"slim-7/s/abc"`. The ids of the responses are turned
+/// back into the original ones, so the transform is invisible to the client.
+#[derive(Clone, Debug)]
+pub struct IdNamespace {
+    prefix: String,
+}
+
+impl IdNamespace {
+    pub fn new(session_id: u32) -> Self {
+        Self {
+            prefix: format!("slim-{}/", session_id),
+        }
+    }
+
+    /// Namespace the ids of a message to the MCP server. Responses answer the
+    /// requests of the server and keep their id.
+    pub fn to_server(&self, msg: &mut ClientJsonRpcMessage) {
+        match msg {
+            JsonRpcMessage::Request(JsonRpcRequest { id, .. }) => *id = self.wrap(id),
+            JsonRpcMessage::Notification(JsonRpcNotification {
+                notification: ClientNotification::CancelledNotification(cancelled),
+                ..
+            }) => cancelled.params.request_id = self.wrap(&cancelled.params.request_id),
+            _ => {}
+        }
+    }
+
+    /// Restore the original ids of the responses to the client. Ids outside of
+    /// the namespace are left untouched.
+    pub fn to_client(&self, msg: &mut ServerJsonRpcMessage) {
+        if let JsonRpcMessage::Response(JsonRpcResponse { id, .. })
+        | JsonRpcMessage::Error(JsonRpcError { id, .. }) = msg
+            && let Some(original) = self.unwrap(id)
+        {
+            *id = original;
+        }
+    }
+
+    fn wrap(&self, id: &RequestId) -> RequestId {
+        let wrapped = match id {
+            NumberOrString::Number(n) => format!("{}{}{}", self.prefix, NUMBER_TAG, n),
+            NumberOrString::String(s) => format!("{}{}{}", self.prefix, STRING_TAG, s),
+        };
+        NumberOrString::String(wrapped.into())
+    }
+
+    fn unwrap(&self, id: &RequestId) -> Option<RequestId> {
+        let NumberOrString::String(s) = id else {
+            return None;
+        };
+        let tagged = s.strip_prefix(self.prefix.as_str())?;
+        if let Some(n) = tagged.strip_prefix(NUMBER_TAG) {
+            n.parse().ok().map(NumberOrString::Number)
+        } else {
+            tagged
+                .strip_prefix(STRING_TAG)
+                .map(|s| NumberOrString::String(s.into()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn request(id: serde_json::Value) -> ClientJsonRpcMessage {
+        serde_json::from_value(json!({ "jsonrpc": "2.0", "id": id, "method": "tools/list" }))
+            .unwrap()
+    }
+
+    fn response(id: RequestId) -> ServerJsonRpcMessage {
+        serde_json::from_value(json!({ "jsonrpc": "2.0", "id": id, "result": {} })).unwrap()
+    }
+
+    fn forwarded_id(msg: &ClientJsonRpcMessage) -> RequestId {
+        match msg {
+            JsonRpcMessage::Request(JsonRpcRequest { id, .. }) => id.clone(),
+            _ => panic!("not a request"),
+        }
+    }
+
+    fn response_id(msg: &ServerJsonRpcMessage) -> RequestId {
+        match msg {
+            JsonRpcMessage::Response(JsonRpcResponse { id, .. }) => id.clone(),
+            _ => panic!("not a response"),
+        }
+    }
+
+    #[test]
+    fn numeric_and_string_ids_round_trip() {
+        let namespace = IdNamespace::new(7);
+        for (id, wrapped) in [
+            (json!(42), "slim-7/n/42"),
+            (json!("abc"), "slim-7/s/abc"),
+            // a string looking like a number stays a string
+            (json!("42"), "slim-7/s/42"),
+        ] {
+            let mut msg = request(id.clone());
+            let original = forwarded_id(&msg);
+            namespace.to_server(&mut msg);
+            let forwarded = forwarded_id(&msg);
+            assert_eq!(forwarded, NumberOrString::String(wrapped.into()));
+
+            let mut resp = response(forwarded);
+            namespace.to_client(&mut resp);
+            assert_eq!(response_id(&resp), original);
+        }
+    }
+
+    #[test]
+    fn ids_of_other_sessions_untouched() {
+        let mut msg = request(json!(1));
+        IdNamespace::new(7).to_server(&mut msg);
+        let mut resp = response(forwarded_id(&msg));
+        IdNamespace::new(8).to_client(&mut resp);
+        assert_eq!(
+            response_id(&resp),
+            NumberOrString::String("slim-7/n/1".into())
+        );
+    }
+
+    #[test]
+    fn cancellation_follows_the_request() {
+        let namespace = IdNamespace::new(7);
+        let mut cancelled: ClientJsonRpcMessage = serde_json::from_value(json!({
+            "jsonrpc": "2.0",
+            "method": "notifications/cancelled",
+            "params": { "requestId": 42 },
+        }))
+        .unwrap();
+        namespace.to_server(&mut cancelled);
+        let JsonRpcMessage::Notification(JsonRpcNotification {
+            notification: ClientNotification::CancelledNotification(cancelled),
+            ..
+        }) = cancelled
+        else {
+            panic!("not a cancellation");
+        };
+        assert_eq!(
+            cancelled.params.request_id,
+            NumberOrString::String("slim-7/n/42".into())
+        );
+    }
+
+    #[test]
+    fn only_the_ids_of_the_proxy_internal() {
+        assert!(is_internal(&internal_id("reinitialize")));
+        for id in [
+            json!(1),
+            json!("reinitialize"),
+            json!("slim-proxy"),
+            json!("slim-7/s/x"),
+        ] {
+            assert!(!is_internal(&forwarded_id(&request(id.clone()))), "{id}");
+        }
+        // a namespaced client id never looks internal
+        let mut msg = request(json!("slim-proxy/reinitialize"));
+        IdNamespace::new(7).to_server(&mut msg);
+        assert!(!is_internal(&forwarded_id(&msg)));
+    }
+}
@@ -0,0 +1,261 @@
+// Copyright AGNTCY Contributors (https://github.com/agntcy)
+// SPDX-License-Identifier: Apache-2.0
+
+use rmcp::model::RequestId;
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+use tracing::debug;
+
+/// State of a session when its ping timer fires
+#[derive(Clone, Copy, Debug)]
+pub struct PingContext {
+    pub session_id: u32,
+    /// pings sent to the client and not answered yet
+    pub pending_pings: usize,
+    /// configured limit of unanswered pings
+    pub max_pending_pings: usize,
+    pub ping_interval: Duration,
+    /// time since the last message of the client
+    pub idle: Duration,
+}
+
+/// What to do when the ping timer of a session fires
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PingDecision {
+    Send,
+    Skip,
+    Close,
+}
+
+/// Decides, every time the ping timer of a session fires, whether the client
+/// gets a ping. Policies are called from the session task and must not block.
+pub trait PingPolicy: Send + Sync + fmt::Debug {
+    fn on_ping_due(&self, ctx: &PingContext) -> PingDecision;
+}
+
+/// Ping the client at every interval and close the session once too many
+/// pings are unanswered
+#[derive(Debug, Default)]
+pub struct MissedPingsPolicy;
+
+impl PingPolicy for MissedPingsPolicy {
+    fn on_ping_due(&self, ctx: &PingContext) -> PingDecision {
+        if ctx.pending_pings >= ctx.max_pending_pings {
+            debug!(
+                session_id = ctx.session_id,
+                pending_pings = ctx.pending_pings,
+                "client not replying to pings"
+            );
+            PingDecision::Close
+        } else {
+            PingDecision::Send
+        }
+    }
+}
+
+/// Like [`MissedPingsPolicy`], but skip the ping when the client sent a
+/// message during the last interval: a busy client is alive anyway.
+#[derive(Debug, Default)]
+pub struct SkipWhenActivePolicy;
+
+impl PingPolicy for SkipWhenActivePolicy {
+    fn on_ping_due(&self, ctx: &PingContext) -> PingDecision {
+        if ctx.idle < ctx.ping_interval {
+            PingDecision::Skip
+        } else {
+            MissedPingsPolicy.on_ping_due(ctx)
+        }
+    }
+}
+
+/// Pings sent to the client of a session and not answered yet. A response
+/// clears only the ping it answers, the others stay pending.
+#[derive(Debug, Default)]
+pub struct PingTracker {
+    /// time each pending ping was sent, by id
+    pending: HashMap<RequestId, Instant>,
+}
+
+impl PingTracker {
+    pub fn sent(&mut self, id: RequestId) {
+        self.pending.insert(id, Instant::now());
+    }
+
+    /// Clear the ping answered by a response, and return its round trip.
+    /// `None` if the response answers no pending ping.
+    pub fn answered(&mut self, id: &RequestId) -> Option<Duration> {
+        self.pending.remove(id).map(|sent| sent.elapsed())
+    }
+
+    /// Whether a response with the id would answer a pending ping
+    pub fn is_pending(&self, id: &RequestId) -> bool {
+        self.pending.contains_key(id)
+    }
+
+    /// Forget the pings sent more than `max_age` ago, which are not waited
+    /// for anymore. Returns the number of pings forgotten.
+    pub fn expire(&mut self, max_age: Duration) -> usize {
+        let before = self.pending.len();
+        self.pending.retain(|_, sent| sent.elapsed() <= max_age);
+        before - self.pending.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+/// Health of a session told by its pings
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PingHealth {
+    /// no ping missed
+    Healthy,
+    /// the client missed at least one ping
+    Suspect,
+    /// the session is closed at the next ping if the client does not answer,
+    /// or the proxy asked it to close
+    Closing,
+}
+
+impl PingHealth {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PingHealth::Healthy => "healthy",
+            PingHealth::Suspect => "suspect",
+            PingHealth::Closing => "closing",
+        }
+    }
+}
+
+/// Pings of a session, updated by its handler and read by the admin endpoint
+#[derive(Debug, Default)]
+pub struct PingStats {
+    /// pings sent to the client and not answered yet
+    pending: AtomicUsize,
+    /// limit of unanswered pings of the session
+    max_pending: AtomicUsize,
+    /// round trip of the last answered ping in microseconds, 0 before the
+    /// first answer
+    last_rtt_micros: AtomicU64,
+}
+
+impl PingStats {
+    pub fn set_pending(&self, pending: usize, max_pending: usize) {
+        self.pending.store(pending, Ordering::Relaxed);
+        self.max_pending.store(max_pending, Ordering::Relaxed);
+    }
+
+    pub fn record_rtt(&self, rtt: Duration) {
+        // an answer within a microsecond still counts as answered
+        let micros = (rtt.as_micros() as u64).max(1);
+        self.last_rtt_micros.store(micros, Ordering::Relaxed);
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::Relaxed)
+    }
+
+    pub fn last_rtt(&self) -> Option<Duration> {
+        match self.last_rtt_micros.load(Ordering::Relaxed) {
+            0 => None,
+            micros => Some(Duration::from_micros(micros)),
+        }
+    }
+
+    /// The ping sent at each interval is pending until answered, a second
+    /// pending one means a missed ping
+    pub fn health(&self) -> PingHealth {
+        let pending = self.pending();
+        let max_pending = self.max_pending.load(Ordering::Relaxed);
+        if max_pending > 0 && pending >= max_pending {
+            PingHealth::Closing
+        } else if pending > 1 {
+            PingHealth::Suspect
+        } else {
+            PingHealth::Healthy
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rmcp::model::NumberOrString;
+
+    #[test]
+    fn response_clears_only_the_answered_ping() {
+        let mut pings = PingTracker::default();
+        for id in 1..=3 {
+            pings.sent(NumberOrString::Number(id));
+        }
+        assert!(pings.answered(&NumberOrString::Number(2)).is_some());
+        assert_eq!(pings.len(), 2);
+        assert!(pings.answered(&NumberOrString::Number(2)).is_none());
+        assert!(pings.answered(&NumberOrString::Number(1)).is_some());
+        assert!(pings.answered(&NumberOrString::Number(3)).is_some());
+        assert_eq!(pings.len(), 0);
+    }
+
+    #[test]
+    fn old_pings_expire() {
+        let mut pings = PingTracker::default();
+        pings.sent(NumberOrString::Number(1));
+        std::thread::sleep(Duration::from_millis(20));
+        pings.sent(NumberOrString::Number(2));
+        assert_eq!(pings.expire(Duration::from_secs(60)), 0);
+        assert_eq!(pings.expire(Duration::from_millis(10)), 1);
+        assert!(pings.answered(&NumberOrString::Number(1)).is_none());
+        assert!(pings.answered(&NumberOrString::Number(2)).is_some());
+    }
+
+    #[test]
+    fn missed_pings_close_the_session() {
+        let ctx = |pending_pings| PingContext {
+            session_id: 1,
+            pending_pings,
+            max_pending_pings: 3,
+            ping_interval: Duration::from_secs(20),
+            idle: Duration::from_secs(60),
+        };
+        assert_eq!(MissedPingsPolicy.on_ping_due(&ctx(2)), PingDecision::Send);
+        assert_eq!(MissedPingsPolicy.on_ping_due(&ctx(3)), PingDecision::Close);
+    }
+
+    #[test]
+    fn round_trip_of_the_answered_ping_recorded() {
+        let (mut pings, stats) = (PingTracker::default(), PingStats::default());
+        assert_eq!(stats.last_rtt(), None);
+        pings.sent(NumberOrString::Number(1));
+        let delay = Duration::from_millis(50);
+        std::thread::sleep(delay);
+        stats.record_rtt(pings.answered(&NumberOrString::Number(1)).unwrap());
+        let rtt = stats.last_rtt().unwrap();
+        assert!(rtt >= delay && rtt < delay * 4, "{rtt:?}");
+
+        // an immediate answer still counts as answered
+        stats.record_rtt(Duration::ZERO);
+        assert_eq!(stats.last_rtt(), Some(Duration::from_micros(1)));
+    }
+
+    #[test]
+    fn health_follows_the_pending_pings() {
+        let stats = PingStats::default();
+        assert_eq!(stats.health(), PingHealth::Healthy);
+        for (pending, health) in [
+            (1, PingHealth::Healthy),
+            (2, PingHealth::Suspect),
+            (3, PingHealth::Closing),
+        ] {
+            stats.set_pending(pending, 3);
+            assert_eq!(stats.pending(), pending);
+            assert_eq!(stats.health(), health);
+        }
+        // without a limit, the session is never closing
+        stats.set_pending(5, 0);
+        assert_eq!(stats.health(), PingHealth::Suspect);
+    }
+}
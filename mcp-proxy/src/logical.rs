@@ -0,0 +1,239 @@
+// Copyright AGNTCY Contributors (https://github.com/agntcy)
+// SPDX-License-Identifier: Apache-2.0
+
+use rmcp::model::{
+    ClientJsonRpcMessage, ClientNotification, ClientRequest, JsonRpcError, JsonRpcMessage,
+    JsonRpcNotification, JsonRpcRequest, JsonRpcResponse, NumberOrString, RequestId,
+    ServerJsonRpcMessage,
+};
+use std::{collections::HashMap, str::FromStr};
+use thiserror::Error;
+use tracing::warn;
+
+/// Namespaces of the MCP methods, which a logical server of the same name
+/// would shadow as a method prefix
+const MCP_NAMESPACES: &[&str] = &[
+    "completion",
+    "elicitation",
+    "logging",
+    "notifications",
+    "prompts",
+    "resources",
+    "roots",
+    "sampling",
+    "tasks",
+    "tools",
+];
+
+#[derive(Debug, Error)]
+#[error("expected <name>=<address>, found {0}")]
+pub struct LogicalServerError(String);
+
+/// MCP server exposed under a name to the clients that select it in their
+/// session metadata, e.g. `weather=http://weather:8000/mcp`
+#[derive(Clone, Debug)]
+pub struct LogicalServer {
+    pub name: String,
+    pub url: String,
+}
+
+impl FromStr for LogicalServer {
+    type Err = LogicalServerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, url) = s
+            .split_once('=')
+            .filter(|(name, url)| !name.trim().is_empty() && !url.trim().is_empty())
+            .ok_or_else(|| LogicalServerError(s.to_string()))?;
+        Ok(Self {
+            name: name.trim().to_string(),
+            url: url.trim().to_string(),
+        })
+    }
+}
+
+/// What to do with a session selecting a logical server that does not exist
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum UnknownServerPolicy {
+    /// Connect the session to the MCP server
+    #[default]
+    Default,
+    /// Close the session
+    Reject,
+}
+
+/// Routes of the messages of a session to the logical servers selected by
+/// the prefix of the request methods, e.g. `weather/tools/call` for the
+/// `tools/call` request of `--logical-server weather=...`
+#[derive(Debug, Default)]
+pub struct MethodRoutes {
+    /// client requests forwarded to a logical server and not answered yet
+    requests: HashMap<RequestId, String>,
+    /// requests of the logical servers waiting for the client, by the id
+    /// given to the client, with their own id
+    server_requests: HashMap<RequestId, (String, RequestId)>,
+}
+
+impl MethodRoutes {
+    /// Logical server a client message goes to: the one selected by the
+    /// prefix of the method of a request, which is stripped, or the one of
+    /// the request a cancellation is for
+    pub fn route(
+        &self,
+        msg: &mut ClientJsonRpcMessage,
+        servers: &HashMap<String, String>,
+    ) -> Option<String> {
+        match msg {
+            JsonRpcMessage::Request(JsonRpcRequest {
+                request: ClientRequest::CustomRequest(req),
+                ..
+            }) => {
+                let (server, method) = req.method.split_once('/')?;
+                if MCP_NAMESPACES.contains(&server) || !servers.contains_key(server) {
+                    return None;
+                }
+                let (server, method) = (server.to_string(), method.to_string());
+                // parsed again, as the typed request of the method
+                let mut value = serde_json::to_value(&*msg).ok()?;
+                value["method"] = method.into();
+                match serde_json::from_value(value) {
+                    Ok(stripped) => *msg = stripped,
+                    Err(e) => {
+                        warn!(%server, "invalid request for logical MCP server, forwarded unchanged: {}", e);
+                        return None;
+                    }
+                }
+                Some(server)
+            }
+            JsonRpcMessage::Notification(JsonRpcNotification {
+                notification: ClientNotification::CancelledNotification(cancelled),
+                ..
+            }) => self.requests.get(&cancelled.params.request_id).cloned(),
+            _ => None,
+        }
+    }
+
+    /// Record a client request forwarded to a logical server
+    pub fn forwarded(&mut self, id: RequestId, server: &str) {
+        self.requests.insert(id, server.to_string());
+    }
+
+    /// Record a message of a logical server before it goes to the client. Its
+    /// requests are given an id prefixed with the server name, so that they
+    /// do not collide with the ones of the other servers.
+    pub fn server_message(&mut self, server: &str, msg: &mut ServerJsonRpcMessage) {
+        match msg {
+            JsonRpcMessage::Response(JsonRpcResponse { id, .. })
+            | JsonRpcMessage::Error(JsonRpcError { id, .. }) => {
+                self.requests.remove(id);
+            }
+            JsonRpcMessage::Request(JsonRpcRequest { id, .. }) => {
+                let own = match &*id {
+                    NumberOrString::Number(n) => n.to_string(),
+                    NumberOrString::String(s) => s.to_string(),
+                };
+                let client_id = NumberOrString::String(format!("{server}/{own}").into());
+                let own = std::mem::replace(id, client_id.clone());
+                self.server_requests
+                    .insert(client_id, (server.to_string(), own));
+            }
+            JsonRpcMessage::Notification(_) => {}
+        }
+    }
+
+    /// Logical server and own id of the request a client answer is for
+    pub fn answered(&mut self, id: &RequestId) -> Option<(String, RequestId)> {
+        self.server_requests.remove(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn servers() -> HashMap<String, String> {
+        HashMap::from([
+            ("weather".to_string(), "http://weather:8000/mcp".to_string()),
+            ("tools".to_string(), "http://tools:8000/mcp".to_string()),
+        ])
+    }
+
+    fn client_message(value: serde_json::Value) -> ClientJsonRpcMessage {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn prefixed_request_routed_without_its_prefix() {
+        let mut routes = MethodRoutes::default();
+        let mut msg = client_message(json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "weather/tools/call",
+            "params": {"name": "forecast", "arguments": {"city": "Paris"}}
+        }));
+        assert_eq!(
+            routes.route(&mut msg, &servers()).as_deref(),
+            Some("weather")
+        );
+        assert!(matches!(
+            &msg,
+            JsonRpcMessage::Request(JsonRpcRequest { request: ClientRequest::CallToolRequest(call), .. })
+                if call.params.name == "forecast"
+        ));
+
+        // a cancellation follows the request once it is forwarded
+        let cancel = json!({
+            "jsonrpc": "2.0",
+            "method": "notifications/cancelled",
+            "params": {"requestId": 1}
+        });
+        assert_eq!(
+            routes.route(&mut client_message(cancel.clone()), &servers()),
+            None
+        );
+        routes.forwarded(NumberOrString::Number(1), "weather");
+        assert_eq!(
+            routes
+                .route(&mut client_message(cancel.clone()), &servers())
+                .as_deref(),
+            Some("weather")
+        );
+        let mut response: ServerJsonRpcMessage =
+            serde_json::from_value(json!({"jsonrpc": "2.0", "id": 1, "result": {}})).unwrap();
+        routes.server_message("weather", &mut response);
+        assert_eq!(routes.route(&mut client_message(cancel), &servers()), None);
+    }
+
+    #[test]
+    fn other_requests_not_routed() {
+        let routes = MethodRoutes::default();
+        for method in ["tools/list", "news/tools/list", "tools/call", "weather"] {
+            let mut msg = client_message(json!({"jsonrpc": "2.0", "id": 1, "method": method}));
+            let before = serde_json::to_value(&msg).unwrap();
+            assert_eq!(routes.route(&mut msg, &servers()), None, "{method}");
+            assert_eq!(serde_json::to_value(&msg).unwrap(), before);
+        }
+    }
+
+    #[test]
+    fn server_requests_answered_to_their_server() {
+        let mut routes = MethodRoutes::default();
+        let mut request: ServerJsonRpcMessage =
+            serde_json::from_value(json!({"jsonrpc": "2.0", "id": 0, "method": "roots/list"}))
+                .unwrap();
+        routes.server_message("weather", &mut request);
+        let JsonRpcMessage::Request(JsonRpcRequest { id, .. }) = &request else {
+            panic!("unexpected message {:?}", request);
+        };
+        let client_id = NumberOrString::String("weather/0".into());
+        assert_eq!(id, &client_id);
+
+        assert_eq!(
+            routes.answered(&client_id),
+            Some(("weather".to_string(), NumberOrString::Number(0)))
+        );
+        assert_eq!(routes.answered(&client_id), None);
+        assert_eq!(routes.answered(&NumberOrString::Number(0)), None);
+    }
+}
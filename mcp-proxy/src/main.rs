@@ -4,10 +4,43 @@
 use clap::Parser;
 use slim::config;
 use slim_datapath::messages::Name;
-use std::time::Duration;
-use tracing::{error, info};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
 
+mod admin;
+mod app_message;
+mod authz;
+mod bounded;
+mod chaos;
+mod credits;
+mod discovery;
+mod error;
+mod error_action;
+mod events;
+mod filter;
+mod headers;
+mod ids;
+mod keepalive;
+mod logical;
+mod memory;
+mod message;
+mod metrics;
+mod outbound;
+mod pattern;
+mod pin;
+mod pool;
 mod proxy;
+mod redact;
+mod shadow;
+mod throttle;
+mod tls;
+mod wal;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -21,7 +54,7 @@ pub struct Args {
     svc_name: String,
 
     /// MCP Proxy name in the form org/ns/type
-    #[arg(short, long, value_name = "proxy_name", required = true)]
+    #[arg(short, long, value_name = "proxy_name", required = true, value_parser = proxy_name)]
     name: String,
 
     /// MCP Proxy instance ID
@@ -29,9 +62,47 @@ pub struct Args {
     id: Option<u64>,
 
     /// MCP Server address (e.g http://localhost:8000/sse)
-    #[arg(short, long, value_name = "address", required = true)]
+    #[arg(short, long, value_name = "address", required = true, value_parser = mcp_url)]
     mcp_server: String,
 
+    /// MCP server used by the sessions when the primary one cannot be reached
+    #[arg(long, value_name = "address", required = false, value_parser = mcp_url)]
+    mcp_server_fallback: Option<String>,
+
+    /// MCP server the clients select by name in their session metadata,
+    /// as <name>=<address> (can be repeated)
+    #[arg(long, value_name = "name=address", required = false)]
+    logical_server: Vec<logical::LogicalServer>,
+
+    /// What to do with a session selecting a logical MCP server that does not exist
+    #[arg(long, value_name = "policy", value_enum, default_value_t = logical::UnknownServerPolicy::Default)]
+    unknown_server_policy: logical::UnknownServerPolicy,
+
+    /// Discover the MCP server host and port, e.g. dns-srv:_mcp._tcp.example.com.
+    /// Scheme and path are taken from the MCP server address.
+    #[arg(long, value_name = "source", required = false)]
+    mcp_discovery: Option<discovery::DiscoverySource>,
+
+    /// Resolve the MCP server host before each connection, failing after the
+    /// given time in milliseconds (resolved by the HTTP client by default)
+    #[arg(long, value_name = "milliseconds", required = false, value_parser = positive::<u64>)]
+    dns_timeout: Option<u64>,
+
+    /// Secondary MCP server receiving a copy of the client requests, its
+    /// responses are discarded (e.g http://localhost:8001/mcp)
+    #[arg(long, value_name = "address", required = false, value_parser = mcp_url)]
+    shadow_mcp_server: Option<String>,
+
+    /// Connect to the MCP servers at the normalized form of their addresses: lowercase
+    /// host, no default port, no trailing slash in the path nor fragment
+    #[arg(long, required = false)]
+    normalize_mcp_urls: bool,
+
+    /// HTTP header set on the MCP connection of the clients matching a name
+    /// pattern, e.g. 'org/tenant-a/*=X-Tenant: a' (repeatable)
+    #[arg(long, value_name = "mapping", required = false)]
+    source_header: Vec<headers::SourceHeader>,
+
     /// MCP Proxy shared secret
     #[arg(short = 's', long, value_name = "secret", required = false)]
     secret: Option<String>,
@@ -47,6 +118,415 @@ pub struct Args {
     /// SPIRE JWT audiences (comma-separated)
     #[arg(long, value_name = "audiences", required = false)]
     spire_jwt_audience: Option<String>,
+
+    /// JSON field names whose values are redacted in logged messages (comma-separated)
+    #[arg(long, value_name = "fields", value_delimiter = ',', required = false)]
+    redact_fields: Vec<String>,
+
+    /// How pings sent by the SLIM clients are handled
+    #[arg(long, value_name = "mode", value_enum, default_value_t = proxy::ClientPingMode::Answer)]
+    client_ping_mode: proxy::ClientPingMode,
+
+    /// How roots/list requests of the MCP server are handled
+    #[arg(long, value_name = "mode", value_enum, default_value_t = proxy::RootsMode::Forward)]
+    roots_mode: proxy::RootsMode,
+
+    /// How elicitation/create requests of the MCP server are handled
+    #[arg(long, value_name = "mode", value_enum, default_value_t = proxy::ElicitationMode::Forward)]
+    elicitation_mode: proxy::ElicitationMode,
+
+    /// Interval in seconds between pings sent to the SLIM clients, 0 disables pings
+    #[arg(long, value_name = "seconds", default_value_t = 20)]
+    ping_interval: u64,
+
+    /// Number of unanswered pings after which a session is closed
+    #[arg(long, value_name = "count", required = false)]
+    max_missed_pings: Option<usize>,
+
+    /// Type of the ids of the pings sent to the SLIM clients
+    #[arg(long, value_name = "format", value_enum, default_value_t = proxy::PingIdFormat::SafeNumber)]
+    ping_id_format: proxy::PingIdFormat,
+
+    /// Message of a SLIM client setting the connection the session answers on
+    #[arg(long, value_name = "message", value_enum, default_value_t = proxy::ReturnPath::FirstMessage)]
+    return_path: proxy::ReturnPath,
+
+    /// Do not send the pings before the pending data of a session
+    #[arg(long, required = false)]
+    no_ping_priority: bool,
+
+    /// Do not ping the clients that sent a message during the last ping interval
+    #[arg(long, required = false)]
+    skip_pings_when_active: bool,
+
+    /// Require the MCP server to be reachable over https
+    #[arg(long, required = false)]
+    require_tls: bool,
+
+    /// Lowest TLS version accepted on the MCP server connection, implies --require-tls
+    #[arg(long, value_name = "version", value_enum, required = false)]
+    min_tls_version: Option<proxy::MinTlsVersion>,
+
+    /// HTTP version of the connections to the MCP server
+    #[arg(long, value_name = "version", value_enum, default_value_t = proxy::HttpVersion::Auto)]
+    http_version: proxy::HttpVersion,
+
+    /// SHA-256 fingerprint of a certificate accepted from the MCP server, in
+    /// hex, implies --require-tls (can be repeated)
+    #[arg(long, value_name = "fingerprint", required = false)]
+    pin_sha256: Vec<pin::CertPin>,
+
+    /// PEM file of the certificate chain presented to the MCP server, implies --require-tls
+    #[arg(
+        long,
+        value_name = "path",
+        required = false,
+        requires = "mcp_client_key"
+    )]
+    mcp_client_cert: Option<PathBuf>,
+
+    /// PEM file of the private key of the client certificate
+    #[arg(
+        long,
+        value_name = "path",
+        required = false,
+        requires = "mcp_client_cert"
+    )]
+    mcp_client_key: Option<PathBuf>,
+
+    /// Check the client certificate files every given seconds, the new MCP connections use the
+    /// certificate again when they change (not reloaded by default)
+    #[arg(long, value_name = "seconds", required = false, value_parser = positive::<u64>)]
+    tls_reload_interval: Option<u64>,
+
+    /// Local IP address from which the connections to the MCP server are opened
+    #[arg(long, value_name = "address", required = false)]
+    bind_address: Option<IpAddr>,
+
+    /// Accept client messages declaring another JSON-RPC version than 2.0, or
+    /// none, parsing them as JSON-RPC 2.0
+    #[arg(long, required = false)]
+    lenient_jsonrpc: bool,
+
+    /// Maximum nesting depth of the JSON messages received from the clients
+    #[arg(long, value_name = "depth", default_value_t = message::DEFAULT_MAX_JSON_DEPTH)]
+    max_json_depth: usize,
+
+    /// Name of a `_meta` field set to the SLIM session id in the requests
+    /// forwarded to the MCP server (e.g. _proxy_session_id)
+    #[arg(long, value_name = "field", required = false)]
+    session_label_field: Option<String>,
+
+    /// What to do with messages from the MCP server that cannot be interpreted
+    #[arg(long, value_name = "policy", value_enum, default_value_t = proxy::BadServerMessagePolicy::Forward)]
+    bad_server_message_policy: proxy::BadServerMessagePolicy,
+
+    /// What to do with a client request reusing the id of a request still waiting for a response
+    #[arg(long, value_name = "policy", value_enum, default_value_t = proxy::DuplicateRequestIdPolicy::Allow)]
+    duplicate_request_id_policy: proxy::DuplicateRequestIdPolicy,
+
+    /// What to do with a response of the MCP server to no request of the session
+    #[arg(long, value_name = "policy", value_enum, default_value_t = proxy::UnknownResponsePolicy::Forward)]
+    unknown_response_policy: proxy::UnknownResponsePolicy,
+
+    /// Maximum time in seconds to wait for the SLIM dataplane connections at startup
+    #[arg(long, value_name = "seconds", default_value_t = 60, value_parser = positive::<u64>)]
+    service_run_timeout: u64,
+
+    /// Endpoint of the dataplane client the proxy subscribes on, as configured
+    /// (the first client by default)
+    #[arg(long, value_name = "endpoint", required = false)]
+    subscribe_endpoint: Option<String>,
+
+    /// Maximum random delay in milliseconds before serving, 0 disables it
+    #[arg(long, value_name = "milliseconds", default_value_t = 0)]
+    startup_jitter: u64,
+
+    /// Start draining the proxy when this file exists
+    #[arg(long, value_name = "path", required = false)]
+    drain_file: Option<PathBuf>,
+
+    /// Unix socket publishing the lifecycle and session events of the proxy as JSON lines
+    #[arg(long, value_name = "path", required = false)]
+    event_socket: Option<PathBuf>,
+
+    /// Maximum time in seconds to wait for the active sessions to end when draining
+    #[arg(long, value_name = "seconds", default_value_t = 10, value_parser = positive::<u64>)]
+    drain_timeout: u64,
+
+    /// Drain the proxy on SIGTERM or SIGINT for at most the given seconds, which should be slightly
+    /// under the terminationGracePeriodSeconds of the pod (the proxy stops right away by default)
+    #[arg(long, value_name = "seconds", required = false, value_parser = positive::<u64>)]
+    shutdown_grace_period: Option<u64>,
+
+    /// Address of the admin endpoint serving /readyz and POST /drain (e.g. 0.0.0.0:9091)
+    #[arg(long, value_name = "address", required = false)]
+    admin_addr: Option<SocketAddr>,
+
+    /// Admin endpoint of the instance to take over from once ready, asked to
+    /// drain with POST /handover (e.g. http://10.0.0.5:9091)
+    #[arg(long, value_name = "url", required = false)]
+    handover_from: Option<reqwest::Url>,
+
+    /// Maximum time in seconds to wait for the connections of the admin endpoint to end when the proxy stops
+    #[arg(long, value_name = "seconds", default_value_t = proxy::DEFAULT_ADMIN_SHUTDOWN_TIMEOUT.as_secs(), value_parser = positive::<u64>)]
+    admin_shutdown_timeout: u64,
+
+    /// Consecutive connection failures after which an MCP server is reported down on /backends
+    #[arg(long, value_name = "count", default_value_t = admin::DEFAULT_BACKEND_DOWN_FAILURES, value_parser = positive::<u32>)]
+    backend_down_failures: u32,
+
+    /// Serve CPU profiles on /debug/pprof/profile of the admin endpoint to the requests bearing
+    /// this token, for performance investigations (profiling slows the proxy down)
+    #[arg(long, value_name = "token", required = false)]
+    admin_profiling_token: Option<String>,
+
+    /// Require this token on POST /handover of the admin endpoint, and present it to the
+    /// instance given with --handover-from. Without it the handover takes no successor.
+    #[arg(long, value_name = "token", required = false)]
+    admin_handover_token: Option<String>,
+
+    /// Serve the ping health of every session on /sessions of the admin endpoint
+    #[arg(long, required = false)]
+    admin_sessions: bool,
+
+    /// Directory where the recent messages of every session are recorded. The
+    /// file of a session is kept only if the session ends abnormally.
+    #[arg(long, value_name = "dir", required = false)]
+    session_wal_dir: Option<PathBuf>,
+
+    /// Maximum size in bytes of the file recording a session
+    #[arg(long, value_name = "bytes", default_value_t = wal::DEFAULT_WAL_MAX_BYTES, value_parser = positive::<usize>)]
+    session_wal_max_bytes: usize,
+
+    /// What happens to a session whose messages cannot all be recorded in its file
+    #[arg(long, value_name = "policy", value_enum, default_value_t = wal::WalFailurePolicy::BestEffort)]
+    session_wal_failure_policy: wal::WalFailurePolicy,
+
+    /// Number of times the handshake is retried when the MCP server closes the
+    /// connection before answering `initialize`
+    #[arg(long, value_name = "count", default_value_t = 0)]
+    handshake_retries: usize,
+
+    /// Delay in milliseconds before retrying the handshake
+    #[arg(long, value_name = "milliseconds", default_value_t = 500)]
+    handshake_retry_delay: u64,
+
+    /// Reconnections to the MCP server per second allowed to all the sessions together
+    #[arg(long, value_name = "per-second", required = false, value_parser = positive::<u32>)]
+    reconnect_rate: Option<u32>,
+
+    /// Action on the errors of the MCP server with the given codes, e.g.
+    /// `-32002=reconnect` or `-32099..-32000=close` (can be repeated)
+    #[arg(long, value_name = "codes=action", required = false)]
+    error_action: Vec<error_action::ErrorRule>,
+
+    /// Regular expression looked for in the text of the tool results (can be repeated)
+    #[arg(long, value_name = "regex", required = false)]
+    content_filter: Vec<String>,
+
+    /// What to do with the tool results matching the content filter
+    #[arg(long, value_name = "action", value_enum, default_value_t = filter::FilterAction::Redact)]
+    content_filter_action: filter::FilterAction,
+
+    /// Number of messages queued for a client, published by a task of their
+    /// own, before the overload policy applies (not queued by default)
+    #[arg(long, value_name = "count", required = false, value_parser = positive::<usize>)]
+    outbound_queue_size: Option<usize>,
+
+    /// What to do with the messages from the MCP server when the client queue is full
+    #[arg(long, value_name = "policy", value_enum, default_value_t = proxy::OverloadPolicy::Backpressure)]
+    overload_policy: proxy::OverloadPolicy,
+
+    /// Time in milliseconds during which the messages from the MCP server are
+    /// coalesced into a JSON-RPC batch for the client (disabled by default)
+    #[arg(long, value_name = "milliseconds", required = false, value_parser = positive::<u64>)]
+    batch_window: Option<u64>,
+
+    /// Maximum number of messages in a batch
+    #[arg(long, value_name = "count", default_value_t = outbound::DEFAULT_BATCH_MAX_MESSAGES, value_parser = positive::<usize>)]
+    batch_max_messages: usize,
+
+    /// Messages a session can publish to its client per credit interval (unlimited by default)
+    #[arg(long, value_name = "count", required = false, value_parser = positive::<u64>)]
+    session_credit_messages: Option<u64>,
+
+    /// Bytes a session can publish to its client per credit interval (unlimited by default)
+    #[arg(long, value_name = "bytes", required = false, value_parser = positive::<u64>)]
+    session_credit_bytes: Option<u64>,
+
+    /// Time in milliseconds after which the session credits are granted again
+    #[arg(long, value_name = "milliseconds", default_value_t = credits::DEFAULT_CREDIT_INTERVAL.as_millis() as u64, value_parser = positive::<u64>)]
+    session_credit_interval: u64,
+
+    /// What a session that used its credits does
+    #[arg(long, value_name = "policy", value_enum, default_value_t = credits::CreditPolicy::Strict)]
+    session_credit_policy: credits::CreditPolicy,
+
+    /// Forward the request ids prefixed with the session id, restoring them on the responses
+    #[arg(long, required = false)]
+    namespace_request_ids: bool,
+
+    /// Field removed from the `_meta` of the MCP server messages before they reach the clients (can be repeated)
+    #[arg(long, value_name = "field", required = false)]
+    strip_meta_field: Vec<String>,
+
+    /// Interval in seconds between two checks of the dataplane connection, to subscribe again on a new connection (0 disables)
+    #[arg(long, value_name = "seconds", default_value_t = 5)]
+    subscription_check_interval: u64,
+
+    /// Attempts to subscribe the proxy name at startup before giving up
+    #[arg(long, value_name = "count", default_value_t = proxy::DEFAULT_SUBSCRIBE_ATTEMPTS, value_parser = positive::<u32>)]
+    subscribe_attempts: u32,
+
+    /// What to do when the SLIM app stops notifying new sessions: stop the proxy, or create the app again
+    #[arg(long, value_name = "policy", value_enum, default_value_t = proxy::StreamEndPolicy::Shutdown)]
+    stream_end_policy: proxy::StreamEndPolicy,
+
+    /// Log the messages received outside of a session instead of ignoring them
+    #[arg(long, required = false)]
+    log_app_messages: bool,
+
+    /// Delay every message of the sessions, in both directions, by the given
+    /// milliseconds. For testing the clients only, never in production.
+    #[arg(long, value_name = "milliseconds", required = false, value_parser = positive::<u64>)]
+    chaos_latency_ms: Option<u64>,
+
+    /// Drop the given share, between 0 and 1, of the messages of the sessions,
+    /// in both directions. For testing the clients only, never in production.
+    #[arg(long, value_name = "rate", required = false, value_parser = chaos::drop_rate)]
+    chaos_drop_rate: Option<f64>,
+
+    /// Log the repetitions of the same session error once per window of the
+    /// given seconds, with their count (every occurrence is logged by default)
+    #[arg(long, value_name = "seconds", required = false, value_parser = positive::<u64>)]
+    log_throttle_window: Option<u64>,
+
+    /// Maximum number of unanswered client requests per session, further requests are rejected (unlimited by default)
+    #[arg(long, value_name = "count", required = false, value_parser = positive::<usize>)]
+    max_pending_requests: Option<usize>,
+
+    /// Maximum number of requests forwarded on the MCP connection of a session and not answered
+    /// yet, further requests wait for a response (unlimited by default)
+    #[arg(long, value_name = "count", required = false, value_parser = positive::<usize>)]
+    max_in_flight_requests: Option<usize>,
+
+    /// Reject the client requests whose method is not defined by MCP
+    #[arg(long, required = false)]
+    validate_methods: bool,
+
+    /// Extension method accepted with --validate-methods (can be repeated)
+    #[arg(
+        long,
+        value_name = "method",
+        required = false,
+        requires = "validate_methods"
+    )]
+    allow_method: Vec<String>,
+
+    /// Time in seconds after which a session is closed whatever its activity,
+    /// unless the client sets an earlier deadline
+    #[arg(long, value_name = "seconds", required = false, value_parser = positive::<u64>)]
+    session_deadline: Option<u64>,
+
+    /// Maximum number of sessions in the handshake with the MCP server at the same time (unlimited by default)
+    #[arg(long, value_name = "count", required = false, value_parser = positive::<usize>)]
+    max_concurrent_setups: Option<usize>,
+
+    /// Number of connections to the MCP server kept warmed for the new sessions, for stateless backends only
+    #[arg(long, value_name = "count", required = false, value_parser = positive::<usize>)]
+    mcp_pool_size: Option<usize>,
+
+    /// Time in seconds during which the MCP connection of a client gone is
+    /// kept for its next session, for stateful backends (disabled by default)
+    #[arg(long, value_name = "seconds", required = false, value_parser = positive::<u64>)]
+    session_affinity_window: Option<u64>,
+
+    /// Resident memory in bytes above which the proxy closes sessions, on Linux only
+    #[arg(long, value_name = "bytes", required = false, value_parser = positive::<u64>)]
+    memory_limit_bytes: Option<u64>,
+
+    /// Which sessions are closed first above the memory limit or --max-sessions
+    #[arg(long, value_name = "policy", value_enum, default_value_t = memory::ShedPolicy::LeastActive)]
+    shed_policy: memory::ShedPolicy,
+
+    /// Write the log notifications of the MCP server to the proxy log too
+    #[arg(long, required = false)]
+    mirror_server_logs: bool,
+
+    /// Add the times the proxy received and forwarded each message to its `_meta`, for latency debugging
+    #[arg(long, required = false)]
+    stamp_timings: bool,
+
+    /// Send the clients a last notification with the reason why their session ended
+    #[arg(long, required = false)]
+    send_close_diagnostics: bool,
+
+    /// Answer at the proxy the subscriptions to resources a session is already subscribed to
+    #[arg(long, required = false)]
+    coalesce_subscriptions: bool,
+
+    /// Largest SSE event or JSON response read from the MCP server, larger messages are dropped
+    #[arg(long, value_name = "bytes", default_value_t = bounded::DEFAULT_READ_BUFFER_BYTES, value_parser = positive::<usize>)]
+    read_buffer_bytes: usize,
+
+    /// File listing the SLIM name patterns of the clients allowed to open a session, one per line (e.g. org/tenant-a/*). Advisory: the names are claimed by the clients
+    #[arg(long, value_name = "path", required = false)]
+    source_allowlist: Option<PathBuf>,
+
+    /// Maximum number of concurrent sessions of each client (unlimited by default)
+    #[arg(long, value_name = "count", required = false, value_parser = positive::<usize>)]
+    max_sessions_per_source: Option<usize>,
+
+    /// Session limit of the clients matching a name pattern, overriding --max-sessions-per-source,
+    /// e.g. 'org/tenant-a/*=10' (can be repeated, the last matching one wins)
+    #[arg(long, value_name = "pattern=count", required = false)]
+    source_session_limit: Vec<authz::SourceLimit>,
+
+    /// Maximum number of concurrent sessions of the proxy (unlimited by default)
+    #[arg(long, value_name = "count", required = false, value_parser = positive::<usize>)]
+    max_sessions: Option<usize>,
+
+    /// Priority of the clients matching a name pattern when the proxy reaches --max-sessions,
+    /// e.g. 'org/tenant-a/*=10' (can be repeated, the last matching one wins, 0 by default)
+    #[arg(long, value_name = "pattern=priority", required = false)]
+    source_priority: Vec<authz::SourcePriority>,
+
+    /// Address on which the metrics are served in the Prometheus format (e.g. 0.0.0.0:9090)
+    #[arg(long, value_name = "address", required = false)]
+    metrics_addr: Option<SocketAddr>,
+
+    /// StatsD server to which the metrics are sent (e.g. localhost:8125)
+    #[arg(long, value_name = "address", required = false)]
+    statsd_addr: Option<String>,
+
+    /// Interval in seconds between two StatsD flushes
+    #[arg(long, value_name = "seconds", default_value_t = 10, value_parser = positive::<u64>)]
+    statsd_flush_interval: u64,
+
+    /// StatsD protocol flavor
+    #[arg(long, value_name = "format", value_enum, default_value_t = metrics::StatsdFormat::Statsd)]
+    statsd_format: metrics::StatsdFormat,
+
+    /// Label of the session metrics identifying the client: off keeps the metrics aggregated
+    #[arg(long, value_name = "mode", value_enum, default_value_t = metrics::SourceLabels::Off)]
+    metrics_source_labels: metrics::SourceLabels,
+
+    /// Disable TCP keepalive on the MCP server connection
+    #[arg(long, required = false)]
+    no_tcp_keepalive: bool,
+
+    /// Idle time in seconds before sending TCP keepalive probes to the MCP server
+    #[arg(long, value_name = "seconds", default_value_t = 30, value_parser = positive::<u64>)]
+    tcp_keepalive_idle: u64,
+
+    /// Interval in seconds between TCP keepalive probes
+    #[arg(long, value_name = "seconds", default_value_t = 10, value_parser = positive::<u64>)]
+    tcp_keepalive_interval: u64,
+
+    /// Number of unanswered TCP keepalive probes before dropping the MCP connection
+    #[arg(long, value_name = "count", default_value_t = 3)]
+    tcp_keepalive_retries: u32,
 }
 
 impl Args {
@@ -70,6 +550,38 @@ impl Args {
         &self.mcp_server
     }
 
+    pub fn mcp_server_fallback(&self) -> Option<&String> {
+        self.mcp_server_fallback.as_ref()
+    }
+
+    pub fn logical_servers(&self) -> &[logical::LogicalServer] {
+        &self.logical_server
+    }
+
+    pub fn unknown_server_policy(&self) -> logical::UnknownServerPolicy {
+        self.unknown_server_policy
+    }
+
+    pub fn mcp_discovery(&self) -> Option<&discovery::DiscoverySource> {
+        self.mcp_discovery.as_ref()
+    }
+
+    pub fn dns_timeout(&self) -> Option<Duration> {
+        self.dns_timeout.map(Duration::from_millis)
+    }
+
+    pub fn shadow_mcp_server(&self) -> Option<&String> {
+        self.shadow_mcp_server.as_ref()
+    }
+
+    pub fn normalize_mcp_urls(&self) -> bool {
+        self.normalize_mcp_urls
+    }
+
+    pub fn source_headers(&self) -> &[headers::SourceHeader] {
+        &self.source_header
+    }
+
     pub fn secret(&self) -> Option<&String> {
         self.secret.as_ref()
     }
@@ -85,29 +597,415 @@ impl Args {
     pub fn spire_jwt_audience(&self) -> Option<&String> {
         self.spire_jwt_audience.as_ref()
     }
+
+    pub fn redact_fields(&self) -> &[String] {
+        &self.redact_fields
+    }
+
+    pub fn client_ping_mode(&self) -> proxy::ClientPingMode {
+        self.client_ping_mode
+    }
+
+    pub fn roots_mode(&self) -> proxy::RootsMode {
+        self.roots_mode
+    }
+
+    pub fn elicitation_mode(&self) -> proxy::ElicitationMode {
+        self.elicitation_mode
+    }
+
+    pub fn ping_interval(&self) -> Option<Duration> {
+        (self.ping_interval > 0).then(|| Duration::from_secs(self.ping_interval))
+    }
+
+    pub fn max_missed_pings(&self) -> Option<usize> {
+        self.max_missed_pings
+    }
+
+    pub fn ping_id_format(&self) -> proxy::PingIdFormat {
+        self.ping_id_format
+    }
+
+    pub fn return_path(&self) -> proxy::ReturnPath {
+        self.return_path
+    }
+
+    pub fn ping_priority(&self) -> bool {
+        !self.no_ping_priority
+    }
+
+    pub fn skip_pings_when_active(&self) -> bool {
+        self.skip_pings_when_active
+    }
+
+    pub fn require_tls(&self) -> bool {
+        self.require_tls
+    }
+
+    pub fn min_tls_version(&self) -> Option<proxy::MinTlsVersion> {
+        self.min_tls_version
+    }
+
+    pub fn http_version(&self) -> proxy::HttpVersion {
+        self.http_version
+    }
+
+    pub fn pin_sha256(&self) -> &[pin::CertPin] {
+        &self.pin_sha256
+    }
+
+    pub fn mcp_client_cert(&self) -> Option<tls::ClientCert> {
+        Some(tls::ClientCert {
+            cert: self.mcp_client_cert.clone()?,
+            key: self.mcp_client_key.clone()?,
+        })
+    }
+
+    pub fn tls_reload_interval(&self) -> Option<Duration> {
+        self.tls_reload_interval.map(Duration::from_secs)
+    }
+
+    pub fn bind_address(&self) -> Option<IpAddr> {
+        self.bind_address
+    }
+
+    pub fn lenient_jsonrpc(&self) -> bool {
+        self.lenient_jsonrpc
+    }
+
+    pub fn max_json_depth(&self) -> usize {
+        self.max_json_depth
+    }
+
+    pub fn session_label_field(&self) -> Option<&String> {
+        self.session_label_field.as_ref()
+    }
+
+    pub fn bad_server_message_policy(&self) -> proxy::BadServerMessagePolicy {
+        self.bad_server_message_policy
+    }
+
+    pub fn duplicate_request_id_policy(&self) -> proxy::DuplicateRequestIdPolicy {
+        self.duplicate_request_id_policy
+    }
+
+    pub fn unknown_response_policy(&self) -> proxy::UnknownResponsePolicy {
+        self.unknown_response_policy
+    }
+
+    pub fn service_run_timeout(&self) -> Duration {
+        Duration::from_secs(self.service_run_timeout)
+    }
+
+    pub fn subscribe_endpoint(&self) -> Option<&String> {
+        self.subscribe_endpoint.as_ref()
+    }
+
+    pub fn startup_jitter(&self) -> Duration {
+        Duration::from_millis(self.startup_jitter)
+    }
+
+    pub fn event_socket(&self) -> Option<&PathBuf> {
+        self.event_socket.as_ref()
+    }
+
+    pub fn drain_file(&self) -> Option<&PathBuf> {
+        self.drain_file.as_ref()
+    }
+
+    pub fn admin_addr(&self) -> Option<SocketAddr> {
+        self.admin_addr
+    }
+
+    pub fn handover_from(&self) -> Option<&reqwest::Url> {
+        self.handover_from.as_ref()
+    }
+
+    pub fn backend_down_failures(&self) -> u32 {
+        self.backend_down_failures
+    }
+
+    pub fn admin_profiling_token(&self) -> Option<&String> {
+        self.admin_profiling_token.as_ref()
+    }
+
+    pub fn admin_handover_token(&self) -> Option<&String> {
+        self.admin_handover_token.as_ref()
+    }
+
+    pub fn admin_sessions(&self) -> bool {
+        self.admin_sessions
+    }
+
+    pub fn admin_shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.admin_shutdown_timeout)
+    }
+
+    pub fn shutdown_grace_period(&self) -> Option<Duration> {
+        self.shutdown_grace_period.map(Duration::from_secs)
+    }
+
+    pub fn drain_timeout(&self) -> Duration {
+        Duration::from_secs(self.drain_timeout)
+    }
+
+    pub fn session_wal(&self) -> Option<wal::WalConfig> {
+        self.session_wal_dir.as_ref().map(|dir| wal::WalConfig {
+            dir: dir.clone(),
+            max_bytes: self.session_wal_max_bytes,
+            on_failure: self.session_wal_failure_policy,
+        })
+    }
+
+    pub fn handshake_retries(&self) -> usize {
+        self.handshake_retries
+    }
+
+    pub fn handshake_retry_delay(&self) -> Duration {
+        Duration::from_millis(self.handshake_retry_delay)
+    }
+
+    pub fn reconnect_rate(&self) -> Option<u32> {
+        self.reconnect_rate
+    }
+
+    pub fn error_actions(&self) -> &[error_action::ErrorRule] {
+        &self.error_action
+    }
+
+    pub fn content_filter(&self) -> &[String] {
+        &self.content_filter
+    }
+
+    pub fn content_filter_action(&self) -> filter::FilterAction {
+        self.content_filter_action
+    }
+
+    pub fn outbound_queue_size(&self) -> Option<usize> {
+        self.outbound_queue_size
+    }
+
+    pub fn overload_policy(&self) -> proxy::OverloadPolicy {
+        self.overload_policy
+    }
+
+    pub fn batch_window(&self) -> Option<Duration> {
+        self.batch_window.map(Duration::from_millis)
+    }
+
+    pub fn batch_max_messages(&self) -> usize {
+        self.batch_max_messages
+    }
+
+    pub fn session_credit_messages(&self) -> Option<u64> {
+        self.session_credit_messages
+    }
+
+    pub fn session_credit_bytes(&self) -> Option<u64> {
+        self.session_credit_bytes
+    }
+
+    pub fn session_credit_interval(&self) -> Duration {
+        Duration::from_millis(self.session_credit_interval)
+    }
+
+    pub fn session_credit_policy(&self) -> credits::CreditPolicy {
+        self.session_credit_policy
+    }
+
+    pub fn namespace_request_ids(&self) -> bool {
+        self.namespace_request_ids
+    }
+
+    pub fn strip_meta_fields(&self) -> &[String] {
+        &self.strip_meta_field
+    }
+
+    pub fn subscription_check_interval(&self) -> Option<Duration> {
+        (self.subscription_check_interval > 0)
+            .then(|| Duration::from_secs(self.subscription_check_interval))
+    }
+
+    pub fn subscribe_attempts(&self) -> u32 {
+        self.subscribe_attempts
+    }
+
+    pub fn stream_end_policy(&self) -> proxy::StreamEndPolicy {
+        self.stream_end_policy
+    }
+
+    pub fn log_app_messages(&self) -> bool {
+        self.log_app_messages
+    }
+
+    pub fn max_pending_requests(&self) -> Option<usize> {
+        self.max_pending_requests
+    }
+
+    pub fn max_in_flight_requests(&self) -> Option<usize> {
+        self.max_in_flight_requests
+    }
+
+    pub fn validate_methods(&self) -> bool {
+        self.validate_methods
+    }
+
+    pub fn allow_method(&self) -> &[String] {
+        &self.allow_method
+    }
+
+    pub fn session_deadline(&self) -> Option<Duration> {
+        self.session_deadline.map(Duration::from_secs)
+    }
+
+    pub fn max_concurrent_setups(&self) -> Option<usize> {
+        self.max_concurrent_setups
+    }
+
+    pub fn mcp_pool_size(&self) -> Option<usize> {
+        self.mcp_pool_size
+    }
+
+    pub fn session_affinity_window(&self) -> Option<Duration> {
+        self.session_affinity_window.map(Duration::from_secs)
+    }
+
+    pub fn memory_limit_bytes(&self) -> Option<u64> {
+        self.memory_limit_bytes
+    }
+
+    pub fn shed_policy(&self) -> memory::ShedPolicy {
+        self.shed_policy
+    }
+
+    pub fn mirror_server_logs(&self) -> bool {
+        self.mirror_server_logs
+    }
+
+    pub fn chaos(&self) -> Option<chaos::Chaos> {
+        if self.chaos_latency_ms.is_none() && self.chaos_drop_rate.is_none() {
+            return None;
+        }
+        Some(chaos::Chaos {
+            latency: Duration::from_millis(self.chaos_latency_ms.unwrap_or_default()),
+            drop_rate: self.chaos_drop_rate.unwrap_or_default(),
+        })
+    }
+
+    pub fn log_throttle_window(&self) -> Option<Duration> {
+        self.log_throttle_window.map(Duration::from_secs)
+    }
+
+    pub fn stamp_timings(&self) -> bool {
+        self.stamp_timings
+    }
+
+    pub fn send_close_diagnostics(&self) -> bool {
+        self.send_close_diagnostics
+    }
+
+    pub fn coalesce_subscriptions(&self) -> bool {
+        self.coalesce_subscriptions
+    }
+
+    pub fn read_buffer_bytes(&self) -> usize {
+        self.read_buffer_bytes
+    }
+
+    pub fn source_allowlist(&self) -> Option<&PathBuf> {
+        self.source_allowlist.as_ref()
+    }
+
+    pub fn max_sessions_per_source(&self) -> Option<usize> {
+        self.max_sessions_per_source
+    }
+
+    pub fn source_session_limits(&self) -> &[authz::SourceLimit] {
+        &self.source_session_limit
+    }
+
+    pub fn max_sessions(&self) -> Option<usize> {
+        self.max_sessions
+    }
+
+    pub fn source_priorities(&self) -> &[authz::SourcePriority] {
+        &self.source_priority
+    }
+
+    pub fn metrics_addr(&self) -> Option<SocketAddr> {
+        self.metrics_addr
+    }
+
+    pub fn statsd(&self) -> Option<metrics::StatsdConfig> {
+        self.statsd_addr.as_ref().map(|addr| metrics::StatsdConfig {
+            addr: addr.clone(),
+            flush_interval: Duration::from_secs(self.statsd_flush_interval),
+            format: self.statsd_format,
+        })
+    }
+
+    pub fn metrics_source_labels(&self) -> metrics::SourceLabels {
+        self.metrics_source_labels
+    }
+
+    pub fn tcp_keepalive(&self) -> Option<proxy::TcpKeepalive> {
+        if self.no_tcp_keepalive {
+            return None;
+        }
+        Some(proxy::TcpKeepalive {
+            idle: Duration::from_secs(self.tcp_keepalive_idle),
+            interval: Duration::from_secs(self.tcp_keepalive_interval),
+            retries: self.tcp_keepalive_retries,
+        })
+    }
+}
+
+/// MCP server address reachable by the streamable HTTP transport
+fn mcp_url(s: &str) -> Result<String, String> {
+    proxy::parse_mcp_url(s).map(|_| s.to_string())
+}
+
+/// Proxy name made of three non-empty components
+fn proxy_name(s: &str) -> Result<String, String> {
+    let components: Vec<&str> = s.split('/').collect();
+    if components.len() != 3 || components.iter().any(|c| c.is_empty()) {
+        return Err("expected a name in the form org/ns/type".into());
+    }
+    Ok(s.to_string())
+}
+
+/// Count, size or duration that must be greater than zero
+fn positive<T>(s: &str) -> Result<T, String>
+where
+    T: FromStr + Default + PartialEq,
+    T::Err: fmt::Display,
+{
+    let n: T = s.parse().map_err(|e: T::Err| e.to_string())?;
+    if n == T::default() {
+        return Err("must be greater than zero".into());
+    }
+    Ok(n)
 }
 
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
     // parse command line
     let args = Args::parse();
 
     let config_file = args.config();
     let svc_name = args.svc_name();
     let name = args.name();
-    let _id = args.id();
+    let id = args.id();
     let server = args.mcp_server();
     let secret = args.secret();
     let spire_socket_path = args.spire_socket_path();
     let spire_target_spiffe_id = args.spire_target_spiffe_id();
     let spire_jwt_audience = args.spire_jwt_audience();
 
+    // validated by the parser
     let v_name: Vec<&str> = name.split('/').collect();
-    if v_name.len() != 3 {
-        error!("error processing the MCP proxy name, invalid format");
-        return;
-    }
 
+    let config_load_started = Instant::now();
     let mut config = config::ConfigLoader::new(config_file).expect("failed to load configuration");
     let svc_id = slim_config::component::id::ID::new_with_str(svc_name).unwrap();
     let _guard = config
@@ -115,8 +1013,17 @@ async fn main() {
         .expect("failed to get tracing configuration")
         .setup_tracing_subscriber();
 
+    if let Some(id) = id {
+        // SLIM derives the id of the app from its identity
+        warn!(%id, "--id is ignored, the id of the proxy is derived from its identity");
+    }
+    if let Some(chaos) = args.chaos() {
+        warn!(latency = ?chaos.latency, drop_rate = chaos.drop_rate, "CHAOS MODE ACTIVE: the messages of the sessions are delayed and dropped, for testing only");
+    }
+
     let services = config.services().expect("error loading services");
     let service = services.shift_remove(&svc_id).expect("service not found");
+    let config_load_time = config_load_started.elapsed();
 
     // Create identity configuration based on command line arguments
     let identity_config = if let Some(socket_path) = spire_socket_path {
@@ -135,16 +1042,210 @@ async fn main() {
         proxy::IdentityConfig::SharedSecret(secret_str.clone())
     } else {
         error!("No authentication method provided");
-        return;
+        return ExitCode::FAILURE;
     };
 
-    let mut proxy = proxy::Proxy::new(
+    let mut builder = proxy::Proxy::builder(
         Name::from_strings([v_name[0], v_name[1], v_name[2]]),
         server.clone(),
-    );
+    )
+    .with_mcp_discovery(args.mcp_discovery().cloned())
+    .with_dns_timeout(args.dns_timeout())
+    .with_mcp_server_fallback(args.mcp_server_fallback().cloned())
+    .with_logical_servers(
+        args.logical_servers().to_vec(),
+        args.unknown_server_policy(),
+    )
+    .with_shadow_mcp_server(args.shadow_mcp_server().cloned())
+    .with_normalize_mcp_urls(args.normalize_mcp_urls())
+    .with_source_headers(args.source_headers().to_vec())
+    .with_tcp_keepalive(args.tcp_keepalive())
+    .with_redactor(redact::Redactor::new(args.redact_fields()))
+    .with_client_ping_mode(args.client_ping_mode())
+    .with_roots_mode(args.roots_mode())
+    .with_elicitation_mode(args.elicitation_mode())
+    .with_ping_interval(args.ping_interval())
+    .with_ping_id_format(args.ping_id_format())
+    .with_return_path(args.return_path())
+    .with_ping_priority(args.ping_priority())
+    .with_require_tls(args.require_tls())
+    .with_min_tls_version(args.min_tls_version())
+    .with_http_version(args.http_version())
+    .with_pinned_certificates(args.pin_sha256().to_vec())
+    .with_client_certificate(args.mcp_client_cert())
+    .with_tls_reload_interval(args.tls_reload_interval())
+    .with_bind_address(args.bind_address())
+    .with_lenient_jsonrpc(args.lenient_jsonrpc())
+    .with_max_json_depth(args.max_json_depth())
+    .with_session_label_field(args.session_label_field().cloned())
+    .with_bad_server_message_policy(args.bad_server_message_policy())
+    .with_duplicate_request_id_policy(args.duplicate_request_id_policy())
+    .with_unknown_response_policy(args.unknown_response_policy())
+    .with_drain_file(args.drain_file().cloned())
+    .with_event_socket(args.event_socket().cloned())
+    .with_admin_addr(args.admin_addr())
+    .with_handover_from(args.handover_from().cloned())
+    .with_shutdown_grace_period(args.shutdown_grace_period())
+    .with_admin_shutdown_timeout(args.admin_shutdown_timeout())
+    .with_backend_down_failures(args.backend_down_failures())
+    .with_admin_profiling_token(args.admin_profiling_token().cloned())
+    .with_admin_handover_token(args.admin_handover_token().cloned())
+    .with_admin_sessions(args.admin_sessions())
+    .with_startup_jitter(args.startup_jitter())
+    .with_service_run_timeout(args.service_run_timeout())
+    .with_subscribe_endpoint(args.subscribe_endpoint().cloned())
+    .with_session_wal(args.session_wal())
+    .with_prometheus_addr(args.metrics_addr())
+    .with_statsd(args.statsd())
+    .with_source_labels(args.metrics_source_labels())
+    .with_handshake_retries(args.handshake_retries(), args.handshake_retry_delay())
+    .with_reconnect_rate(args.reconnect_rate())
+    .with_error_actions(args.error_actions().to_vec())
+    .with_content_filter(args.content_filter().to_vec(), args.content_filter_action())
+    .with_outbound_queue(args.outbound_queue_size(), args.overload_policy())
+    .with_batching(args.batch_window(), args.batch_max_messages())
+    .with_session_credits(
+        args.session_credit_messages(),
+        args.session_credit_bytes(),
+        args.session_credit_interval(),
+        args.session_credit_policy(),
+    )
+    .with_namespace_request_ids(args.namespace_request_ids())
+    .with_strip_meta_fields(args.strip_meta_fields().to_vec())
+    .with_subscription_check_interval(args.subscription_check_interval())
+    .with_subscribe_attempts(args.subscribe_attempts())
+    .with_stream_end_policy(args.stream_end_policy())
+    .with_max_pending_requests(args.max_pending_requests())
+    .with_max_in_flight_requests(args.max_in_flight_requests())
+    .with_method_validation(args.validate_methods(), args.allow_method().to_vec())
+    .with_max_concurrent_setups(args.max_concurrent_setups())
+    .with_mcp_pool_size(args.mcp_pool_size())
+    .with_session_affinity(args.session_affinity_window())
+    .with_session_deadline(args.session_deadline())
+    .with_memory_limit(args.memory_limit_bytes(), args.shed_policy())
+    .with_mirror_server_logs(args.mirror_server_logs())
+    .with_log_throttle(args.log_throttle_window())
+    .with_chaos(args.chaos())
+    .with_stamp_timings(args.stamp_timings())
+    .with_send_close_diagnostics(args.send_close_diagnostics())
+    .with_coalesce_subscriptions(args.coalesce_subscriptions())
+    .with_read_buffer_bytes(args.read_buffer_bytes())
+    .with_source_allowlist(args.source_allowlist().cloned())
+    .with_source_session_limits(
+        args.max_sessions_per_source(),
+        args.source_session_limits().to_vec(),
+    )
+    .with_max_sessions(args.max_sessions(), args.source_priorities().to_vec());
+    if args.log_app_messages() {
+        builder = builder.with_app_message_handler(Box::new(app_message::LogAppMessages));
+    }
+    if args.skip_pings_when_active() {
+        builder = builder.with_ping_policy(Arc::new(keepalive::SkipWhenActivePolicy));
+    }
+    if let Some(max_missed_pings) = args.max_missed_pings() {
+        builder = builder.with_max_missed_pings(max_missed_pings);
+    }
+    let mut proxy = match builder.build() {
+        Ok(proxy) => proxy,
+        Err(e) => {
+            error!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    proxy.record_startup_phase("config_load", config_load_time);
 
     info!("starting MCP proxy");
-    proxy
-        .start(service, identity_config, Duration::from_secs(10))
-        .await;
+    if let Err(e) = proxy
+        .start(service, identity_config, args.drain_timeout())
+        .await
+    {
+        error!("{}", e);
+        return ExitCode::FAILURE;
+    }
+
+    ExitCode::SUCCESS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REQUIRED: [&str; 7] = [
+        "slim-mcp-proxy",
+        "--config",
+        "config.yaml",
+        "--svc-name",
+        "slim/0",
+        "--name",
+        "org/ns/mcp-proxy",
+    ];
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        let mut argv = REQUIRED.to_vec();
+        if !args.contains(&"--mcp-server") {
+            argv.extend(["--mcp-server", "http://localhost:8000/mcp"]);
+        }
+        argv.extend(args);
+        Args::try_parse_from(argv).map_err(|e| e.to_string())
+    }
+
+    fn parse_error(args: &[&str]) -> String {
+        parse(args).expect_err("arguments should be rejected")
+    }
+
+    #[test]
+    fn valid_arguments() {
+        let args = parse(&["--dns-timeout", "500"]).unwrap();
+        assert_eq!(args.mcp_server(), "http://localhost:8000/mcp");
+        assert_eq!(args.name(), "org/ns/mcp-proxy");
+    }
+
+    #[test]
+    fn mcp_url_errors() {
+        let error = parse_error(&["--mcp-server", "localhost:8000"]);
+        assert!(
+            error.contains("URL scheme localhost is not supported"),
+            "{error}"
+        );
+        assert!(error.contains("expected http or https"), "{error}");
+
+        let error = parse_error(&["--mcp-server", "http://"]);
+        assert!(error.contains("invalid URL"), "{error}");
+
+        let error = parse_error(&["--shadow-mcp-server", "ftp://localhost/mcp"]);
+        assert!(error.contains("--shadow-mcp-server"), "{error}");
+        assert!(error.contains("URL scheme ftp is not supported"), "{error}");
+    }
+
+    #[test]
+    fn proxy_name_errors() {
+        for name in ["org/ns", "org//mcp-proxy", "org/ns/mcp-proxy/extra"] {
+            let mut argv = REQUIRED.to_vec();
+            argv[6] = name;
+            argv.extend(["--mcp-server", "http://localhost:8000/mcp"]);
+            let error = Args::try_parse_from(argv).unwrap_err().to_string();
+            assert!(
+                error.contains("expected a name in the form org/ns/type"),
+                "{error}"
+            );
+        }
+    }
+
+    #[test]
+    fn positive_value_errors() {
+        let error = parse_error(&["--dns-timeout", "0"]);
+        assert!(error.contains("--dns-timeout"), "{error}");
+        assert!(error.contains("must be greater than zero"), "{error}");
+
+        let error = parse_error(&["--reconnect-rate", "0"]);
+        assert!(error.contains("--reconnect-rate"), "{error}");
+        assert!(error.contains("must be greater than zero"), "{error}");
+
+        let error = parse_error(&["--dns-timeout", "soon"]);
+        assert!(error.contains("invalid digit"), "{error}");
+
+        let error = parse_error(&["--dns-timeout=-1"]);
+        assert!(error.contains("invalid digit"), "{error}");
+    }
 }
@@ -0,0 +1,262 @@
+// Copyright AGNTCY Contributors (https://github.com/agntcy)
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    cmp::Reverse,
+    sync::{
+        OnceLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::Notify;
+
+use crate::keepalive::PingStats;
+
+/// Interval between two checks of the memory of the proxy
+pub const MEMORY_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// share of the sessions shed at each check above the limit
+const SHED_FRACTION: usize = 10;
+
+/// Which sessions are closed first when the proxy is above its memory limit or
+/// full. The sessions of the clients with the lowest priority always go first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ShedPolicy {
+    /// The sessions without messages for the longest time
+    #[default]
+    LeastActive,
+    /// The most recent sessions
+    Newest,
+    /// The oldest sessions
+    Oldest,
+}
+
+/// Why the proxy asks a session to close
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShedCause {
+    /// the proxy is above its memory limit
+    MemoryPressure,
+    /// the proxy is full and a client with a higher priority opened a session
+    Preempted,
+}
+
+/// Activity of a session, updated by its handler and read by the proxy to
+/// choose the sessions to shed
+#[derive(Debug)]
+pub struct SessionActivity {
+    started: Instant,
+    /// priority of the client
+    priority: u32,
+    /// time of the last message, in milliseconds since `started`
+    last_message: AtomicU64,
+    shed: OnceLock<ShedCause>,
+    notify: Notify,
+    pings: PingStats,
+}
+
+impl SessionActivity {
+    pub fn new(priority: u32) -> Self {
+        Self {
+            started: Instant::now(),
+            priority,
+            last_message: AtomicU64::new(0),
+            shed: OnceLock::new(),
+            notify: Notify::new(),
+            pings: PingStats::default(),
+        }
+    }
+
+    /// Record a message of the session
+    pub fn touch(&self) {
+        let elapsed = self.started.elapsed().as_millis() as u64;
+        self.last_message.store(elapsed, Ordering::Relaxed);
+    }
+
+    fn idle(&self) -> Duration {
+        let last = Duration::from_millis(self.last_message.load(Ordering::Relaxed));
+        self.started.elapsed().saturating_sub(last)
+    }
+
+    pub fn pings(&self) -> &PingStats {
+        &self.pings
+    }
+
+    /// The proxy asked the handler to close the session
+    pub fn is_shed(&self) -> bool {
+        self.shed.get().is_some()
+    }
+
+    /// Wait until the proxy asks the handler to close the session
+    pub async fn shed_requested(&self) -> ShedCause {
+        self.notify.notified().await;
+        self.shed
+            .get()
+            .copied()
+            .unwrap_or(ShedCause::MemoryPressure)
+    }
+
+    fn request_shed(&self, cause: ShedCause) {
+        if self.shed.set(cause).is_ok() {
+            self.notify.notify_one();
+        }
+    }
+}
+
+/// Resident memory of the process, known on Linux only
+pub fn resident_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let kb = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kb * 1024)
+}
+
+/// Sessions not asked to close yet, in the order they are shed: by priority,
+/// then with the policy
+fn candidates<'a>(
+    policy: ShedPolicy,
+    sessions: impl Iterator<Item = &'a SessionActivity>,
+) -> Vec<&'a SessionActivity> {
+    let mut candidates: Vec<&SessionActivity> = sessions.filter(|s| !s.is_shed()).collect();
+    match policy {
+        ShedPolicy::LeastActive => candidates.sort_by_key(|s| Reverse(s.idle())),
+        ShedPolicy::Newest => candidates.sort_by_key(|s| Reverse(s.started)),
+        ShedPolicy::Oldest => candidates.sort_by_key(|s| s.started),
+    }
+    // stable, the policy order holds within a priority
+    candidates.sort_by_key(|s| s.priority);
+    candidates
+}
+
+/// Ask a tenth of the sessions, at least one, to close, chosen with the
+/// policy among the sessions not asked yet. Returns the number of sessions
+/// asked.
+pub fn shed<'a>(policy: ShedPolicy, sessions: impl Iterator<Item = &'a SessionActivity>) -> usize {
+    let candidates = candidates(policy, sessions);
+    let count = candidates.len().div_ceil(SHED_FRACTION);
+    for session in candidates.iter().take(count) {
+        session.request_shed(ShedCause::MemoryPressure);
+    }
+    count
+}
+
+/// Shed sessions with the policy if the resident memory of the proxy is above
+/// the limit. Returns the resident memory and the number of sessions asked to
+/// close, if above.
+pub fn enforce_limit<'a>(
+    limit: u64,
+    policy: ShedPolicy,
+    sessions: impl Iterator<Item = &'a SessionActivity>,
+) -> Option<(u64, usize)> {
+    let resident = resident_bytes().filter(|resident| *resident > limit)?;
+    Some((resident, shed(policy, sessions)))
+}
+
+/// Ask a session with a priority lower than the given one to close, chosen
+/// with the policy. Returns whether a session was asked.
+pub fn preempt<'a>(
+    policy: ShedPolicy,
+    sessions: impl Iterator<Item = &'a SessionActivity>,
+    priority: u32,
+) -> bool {
+    match candidates(policy, sessions).first() {
+        Some(session) if session.priority < priority => {
+            session.request_shed(ShedCause::Preempted);
+            true
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sessions(count: usize) -> Vec<SessionActivity> {
+        (0..count).map(|_| SessionActivity::new(0)).collect()
+    }
+
+    #[test]
+    fn sessions_shed_above_a_small_ceiling() {
+        let sessions = sessions(25);
+        let (resident, shed) = enforce_limit(1, ShedPolicy::Newest, sessions.iter()).unwrap();
+        assert!(resident > 1);
+        assert_eq!(shed, 3);
+        assert_eq!(sessions.iter().filter(|s| s.is_shed()).count(), 3);
+
+        // the next check sheds among the sessions left
+        let (_, shed) = enforce_limit(1, ShedPolicy::Newest, sessions.iter()).unwrap();
+        assert_eq!(shed, 3);
+        assert_eq!(sessions.iter().filter(|s| s.is_shed()).count(), 6);
+    }
+
+    #[test]
+    fn nothing_shed_below_the_limit() {
+        let sessions = sessions(5);
+        assert_eq!(
+            enforce_limit(u64::MAX, ShedPolicy::Newest, sessions.iter()),
+            None
+        );
+        assert!(sessions.iter().all(|s| !s.is_shed()));
+    }
+
+    #[test]
+    fn at_least_one_session_shed() {
+        let sessions = sessions(1);
+        assert_eq!(
+            enforce_limit(1, ShedPolicy::Oldest, sessions.iter())
+                .unwrap()
+                .1,
+            1
+        );
+        assert!(sessions[0].is_shed());
+        // a shed session is not counted again
+        assert_eq!(
+            enforce_limit(1, ShedPolicy::Oldest, sessions.iter())
+                .unwrap()
+                .1,
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn high_priority_admitted_by_shedding_low() {
+        let sessions: Vec<SessionActivity> = [1, 5, 1, 3]
+            .into_iter()
+            .map(|priority| {
+                std::thread::sleep(Duration::from_millis(2));
+                SessionActivity::new(priority)
+            })
+            .collect();
+        let shed = || -> Vec<Option<ShedCause>> {
+            sessions.iter().map(|s| s.shed.get().copied()).collect()
+        };
+
+        // the lowest priority goes first, the newest within it
+        assert!(preempt(ShedPolicy::Newest, sessions.iter(), 4));
+        assert_eq!(shed(), [None, None, Some(ShedCause::Preempted), None]);
+        assert_eq!(sessions[2].shed_requested().await, ShedCause::Preempted);
+        assert!(preempt(ShedPolicy::Newest, sessions.iter(), 4));
+        assert!(preempt(ShedPolicy::Newest, sessions.iter(), 4));
+        assert_eq!(
+            shed(),
+            [
+                Some(ShedCause::Preempted),
+                None,
+                Some(ShedCause::Preempted),
+                Some(ShedCause::Preempted)
+            ]
+        );
+
+        // a session of the same or a higher priority is never shed
+        assert!(!preempt(ShedPolicy::Newest, sessions.iter(), 4));
+        assert!(!preempt(ShedPolicy::Newest, sessions.iter(), 5));
+        assert!(!sessions[1].is_shed());
+        assert!(preempt(ShedPolicy::Newest, sessions.iter(), 6));
+    }
+}
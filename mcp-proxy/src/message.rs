@@ -0,0 +1,452 @@
+// Copyright AGNTCY Contributors (https://github.com/agntcy)
+// SPDX-License-Identifier: Apache-2.0
+
+use rmcp::model::{
+    ClientJsonRpcMessage, CustomResult, ErrorData, GetMeta, JsonRpcError, JsonRpcMessage,
+    JsonRpcNotification, JsonRpcRequest, JsonRpcResponse, JsonRpcVersion2_0, RequestId,
+    ServerJsonRpcMessage, ServerResult,
+};
+use serde_json::{Value, json};
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+use tracing::debug;
+
+const JSONRPC_VERSION: &str = "2.0";
+const META: &str = "_meta";
+
+/// `_meta` field with the times the proxy received and forwarded a message,
+/// in microseconds since the Unix epoch:
+/// `{"received": 1760515200000000, "forwarded": 1760515200000250}`
+pub const TIMING_META_FIELD: &str = "io.agntcy.slim/timing";
+
+/// Methods of the requests of the MCP clients in the specification
+const MCP_REQUEST_METHODS: &[&str] = &[
+    "initialize",
+    "ping",
+    "completion/complete",
+    "logging/setLevel",
+    "prompts/get",
+    "prompts/list",
+    "resources/list",
+    "resources/templates/list",
+    "resources/read",
+    "resources/subscribe",
+    "resources/unsubscribe",
+    "tools/call",
+    "tools/list",
+    "tasks/get",
+    "tasks/list",
+    "tasks/result",
+    "tasks/cancel",
+];
+
+/// Whether a client request method is defined by the MCP specification
+pub fn is_mcp_method(method: &str) -> bool {
+    MCP_REQUEST_METHODS.contains(&method)
+}
+
+/// Default maximum nesting depth of a client message
+pub const DEFAULT_MAX_JSON_DEPTH: usize = 64;
+/// Hard limit enforced by serde_json while parsing
+pub const SERDE_MAX_JSON_DEPTH: usize = 128;
+
+/// Options applied when parsing the messages received from the clients
+#[derive(Clone, Copy, Debug)]
+pub struct ParseOptions {
+    /// parse the messages not declaring `"jsonrpc": "2.0"` as JSON-RPC 2.0
+    /// instead of rejecting them
+    pub normalize_jsonrpc: bool,
+    /// maximum nesting depth of objects and arrays
+    pub max_depth: usize,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            normalize_jsonrpc: false,
+            max_depth: DEFAULT_MAX_JSON_DEPTH,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ParseError {
+    #[error("{0}")]
+    Malformed(#[from] serde_json::Error),
+    #[error("message exceeds the maximum nesting depth of {0}")]
+    TooDeep(usize),
+    #[error("unsupported JSON-RPC version {version}")]
+    UnsupportedVersion {
+        id: Option<RequestId>,
+        version: String,
+    },
+}
+
+impl ParseError {
+    /// Error to send back to the client, if the message can be answered
+    pub fn error_response(&self) -> Option<ServerJsonRpcMessage> {
+        match self {
+            ParseError::UnsupportedVersion {
+                id: Some(id),
+                version,
+            } => Some(JsonRpcMessage::Error(JsonRpcError {
+                jsonrpc: JsonRpcVersion2_0,
+                id: id.clone(),
+                error: ErrorData::invalid_request(
+                    format!(
+                        "unsupported JSON-RPC version {}, expected \"{}\"",
+                        version, JSONRPC_VERSION
+                    ),
+                    None,
+                ),
+            })),
+            _ => None,
+        }
+    }
+}
+
+/// Check the nesting depth of a JSON document without parsing it, so that
+/// deeply nested payloads are rejected before reaching the deserializer.
+fn check_depth(payload: &[u8], max_depth: usize) -> Result<(), ParseError> {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    for &b in payload {
+        if in_string {
+            match b {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match b {
+            b'"' => in_string = true,
+            b'{' | b'[' => {
+                depth += 1;
+                if depth > max_depth {
+                    return Err(ParseError::TooDeep(max_depth));
+                }
+            }
+            b'}' | b']' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Parse a message received from a SLIM client.
+///
+/// Messages nested deeper than `max_depth` are rejected. A message that does
+/// not declare `"jsonrpc": "2.0"` is rejected too, unless `normalize_jsonrpc`
+/// is set: the version is then normalized and the message is parsed as
+/// JSON-RPC 2.0.
+pub fn parse_client_message(
+    payload: &[u8],
+    options: ParseOptions,
+) -> Result<ClientJsonRpcMessage, ParseError> {
+    check_depth(payload, options.max_depth)?;
+
+    let err = match serde_json::from_slice(payload) {
+        Ok(msg) => return Ok(msg),
+        Err(e) => e,
+    };
+
+    // slow path: check whether the failure is due to the version field
+    let Ok(Value::Object(mut obj)) = serde_json::from_slice::<Value>(payload) else {
+        return Err(err.into());
+    };
+    let version = match obj.get("jsonrpc") {
+        Some(Value::String(v)) if v == JSONRPC_VERSION => return Err(err.into()),
+        Some(v) => v.to_string(),
+        None => "missing".to_string(),
+    };
+
+    if !options.normalize_jsonrpc {
+        let id = obj
+            .get("id")
+            .and_then(|id| serde_json::from_value(id.clone()).ok());
+        return Err(ParseError::UnsupportedVersion { id, version });
+    }
+
+    debug!(%version, "normalizing JSON-RPC version of client message");
+    obj.insert("jsonrpc".into(), Value::String(JSONRPC_VERSION.into()));
+    Ok(serde_json::from_value(Value::Object(obj))?)
+}
+
+fn timing(received: SystemTime) -> Value {
+    let micros = |t: SystemTime| {
+        t.duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_micros() as u64)
+    };
+    json!({
+        "received": micros(received),
+        "forwarded": micros(SystemTime::now()),
+    })
+}
+
+/// Add the [`TIMING_META_FIELD`] to the `_meta` of a request or notification
+/// forwarded to the MCP server. Responses of the client carry typed results
+/// and are left untouched.
+pub fn stamp_client_message(msg: &mut ClientJsonRpcMessage, received: SystemTime) {
+    let meta = match msg {
+        JsonRpcMessage::Request(JsonRpcRequest { request, .. }) => request.get_meta_mut(),
+        JsonRpcMessage::Notification(JsonRpcNotification { notification, .. }) => {
+            notification.get_meta_mut()
+        }
+        _ => return,
+    };
+    meta.insert(TIMING_META_FIELD.into(), timing(received));
+}
+
+/// Serialize a message received from the MCP server for the client, removing
+/// `fields` from the `_meta` of its result or params, so that a label injected
+/// by the proxy in the requests is never echoed back and backend internals do
+/// not leak to the clients. With `received` the [`TIMING_META_FIELD`] is added
+/// to the `_meta` too.
+///
+/// The HTTP headers of the MCP server never reach the clients: `_meta` is the
+/// only metadata the proxy forwards.
+pub fn to_client_payload(
+    msg: &ServerJsonRpcMessage,
+    strip_meta_fields: &[String],
+    received: Option<SystemTime>,
+) -> serde_json::Result<Vec<u8>> {
+    if strip_meta_fields.is_empty() && received.is_none() {
+        return serde_json::to_vec(msg);
+    }
+
+    let mut value = serde_json::to_value(msg)?;
+    for key in ["result", "params"] {
+        let Some(obj) = value.get_mut(key).and_then(|v| v.as_object_mut()) else {
+            continue;
+        };
+        if let Some(Value::Object(meta)) = obj.get_mut(META) {
+            for field in strip_meta_fields {
+                if meta.remove(field).is_some() {
+                    debug!(%field, "stripped _meta field from MCP server message");
+                }
+            }
+        }
+        if let Some(received) = received
+            && let Value::Object(meta) = obj
+                .entry(META)
+                .or_insert_with(|| Value::Object(Default::default()))
+        {
+            meta.insert(TIMING_META_FIELD.into(), timing(received));
+        }
+    }
+    serde_json::to_vec(&value)
+}
+
+/// Report why a message received from the MCP server cannot be interpreted.
+///
+/// rmcp maps any result it does not know to `CustomResult`, so a response that
+/// reaches the proxy is always valid JSON-RPC (requests and notifications with
+/// non-object params are already discarded by the transport). Yet MCP requires
+/// results to be JSON objects: anything else is a backend bug the client will
+/// not be able to handle.
+pub fn server_message_defect(msg: &ServerJsonRpcMessage) -> Option<&'static str> {
+    match msg {
+        JsonRpcMessage::Response(JsonRpcResponse {
+            result: ServerResult::CustomResult(CustomResult(result)),
+            ..
+        }) if !result.is_object() => Some("result is not a JSON object"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rmcp::model::NumberOrString;
+
+    fn lenient() -> ParseOptions {
+        ParseOptions {
+            normalize_jsonrpc: true,
+            ..ParseOptions::default()
+        }
+    }
+
+    #[test]
+    fn valid_version_accepted() {
+        let payload = br#"{"jsonrpc": "2.0", "id": 1, "method": "tools/list"}"#;
+        assert!(parse_client_message(payload, ParseOptions::default()).is_ok());
+        assert!(parse_client_message(payload, lenient()).is_ok());
+    }
+
+    #[test]
+    fn wrong_version_rejected_by_default() {
+        let payload = br#"{"jsonrpc": "1.0", "id": 1, "method": "tools/list"}"#;
+        let err = parse_client_message(payload, ParseOptions::default()).unwrap_err();
+        let ParseError::UnsupportedVersion { id, version } = &err else {
+            panic!("unexpected error {:?}", err);
+        };
+        assert_eq!(id, &Some(NumberOrString::Number(1)));
+        assert_eq!(version, "\"1.0\"");
+        assert!(matches!(
+            err.error_response(),
+            Some(JsonRpcMessage::Error(JsonRpcError {
+                id: NumberOrString::Number(1),
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn missing_version_rejected_by_default() {
+        let payload = br#"{"id": "a", "method": "tools/list"}"#;
+        assert!(matches!(
+            parse_client_message(payload, ParseOptions::default()),
+            Err(ParseError::UnsupportedVersion { version, .. }) if version == "missing"
+        ));
+    }
+
+    #[test]
+    fn wrong_or_missing_version_normalized_when_lenient() {
+        for payload in [
+            &br#"{"jsonrpc": "1.0", "id": 1, "method": "tools/list"}"#[..],
+            br#"{"jsonrpc": 2, "id": 1, "method": "tools/list"}"#,
+            br#"{"id": 1, "method": "tools/list"}"#,
+        ] {
+            assert!(matches!(
+                parse_client_message(payload, lenient()),
+                Ok(JsonRpcMessage::Request(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn malformed_message_not_answered() {
+        let err = parse_client_message(b"{not json", ParseOptions::default()).unwrap_err();
+        assert!(matches!(err, ParseError::Malformed(_)));
+        assert!(err.error_response().is_none());
+    }
+
+    #[test]
+    fn too_deep_message_rejected() {
+        let options = ParseOptions {
+            max_depth: 2,
+            ..ParseOptions::default()
+        };
+        let payload =
+            br#"{"jsonrpc": "2.0", "id": 1, "method": "tools/call", "params": {"a": {}}}"#;
+        assert!(matches!(
+            parse_client_message(payload, options),
+            Err(ParseError::TooDeep(2))
+        ));
+    }
+
+    #[test]
+    fn mcp_methods_recognized() {
+        for method in [
+            "initialize",
+            "ping",
+            "tools/call",
+            "resources/templates/list",
+        ] {
+            assert!(is_mcp_method(method), "{method}");
+        }
+        for method in [
+            "tools/run",
+            "Tools/list",
+            "tools/list/",
+            "",
+            "x-custom/echo",
+        ] {
+            assert!(!is_mcp_method(method), "{method}");
+        }
+        // notifications are not request methods
+        assert!(!is_mcp_method("notifications/initialized"));
+    }
+
+    fn server_message(value: Value) -> ServerJsonRpcMessage {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn non_object_results_reported() {
+        for result in [
+            json!([1, 2]),
+            json!("done"),
+            json!(42),
+            json!(true),
+            Value::Null,
+        ] {
+            let msg = server_message(json!({"jsonrpc": "2.0", "id": 1, "result": result}));
+            assert_eq!(
+                server_message_defect(&msg),
+                Some("result is not a JSON object"),
+                "{result}"
+            );
+        }
+    }
+
+    #[test]
+    fn valid_server_messages_not_reported() {
+        for msg in [
+            json!({"jsonrpc": "2.0", "id": 1, "result": {}}),
+            json!({"jsonrpc": "2.0", "id": 1, "result": {"custom": [1, 2]}}),
+            json!({"jsonrpc": "2.0", "id": 1, "result": {"tools": []}}),
+            json!({"jsonrpc": "2.0", "id": 1, "error": {"code": -32601, "message": "not found"}}),
+            json!({"jsonrpc": "2.0", "id": "s-1", "method": "ping"}),
+            json!({"jsonrpc": "2.0", "method": "notifications/tools/list_changed"}),
+        ] {
+            assert_eq!(
+                server_message_defect(&server_message(msg.clone())),
+                None,
+                "{msg}"
+            );
+        }
+    }
+
+    fn client_payload(msg: Value, strip: &[&str]) -> Value {
+        let strip: Vec<String> = strip.iter().map(|f| f.to_string()).collect();
+        let payload = to_client_payload(&server_message(msg), &strip, None).unwrap();
+        serde_json::from_slice(&payload).unwrap()
+    }
+
+    #[test]
+    fn meta_fields_stripped() {
+        let meta = json!({"internal/trace": "abc", "internal/host": "db-1", "progressToken": 1});
+        let response = client_payload(
+            json!({"jsonrpc": "2.0", "id": 1, "result": {"data": [1, 2], "_meta": meta}}),
+            &["internal/trace", "internal/host"],
+        );
+        assert_eq!(
+            response,
+            json!({"jsonrpc": "2.0", "id": 1, "result": {"data": [1, 2], "_meta": {"progressToken": 1}}})
+        );
+
+        let notification = client_payload(
+            json!({
+                "jsonrpc": "2.0",
+                "method": "notifications/message",
+                "params": {"level": "info", "data": "done", "_meta": meta}
+            }),
+            &["internal/trace"],
+        );
+        assert_eq!(
+            notification["params"],
+            json!({"level": "info", "data": "done", "_meta": {"internal/host": "db-1", "progressToken": 1}})
+        );
+    }
+
+    #[test]
+    fn payload_unchanged_without_meta_to_strip() {
+        let msg = json!({"jsonrpc": "2.0", "id": 1, "result": {"data": {"_meta": {"internal/trace": "nested"}}}});
+        // only the `_meta` of the result is stripped, and none is added
+        assert_eq!(client_payload(msg.clone(), &["internal/trace"]), msg);
+        assert_eq!(client_payload(msg.clone(), &[]), msg);
+    }
+
+    #[test]
+    fn timing_added_to_the_meta() {
+        let msg = server_message(json!({"jsonrpc": "2.0", "id": 1, "result": {"data": 1}}));
+        let payload = to_client_payload(&msg, &[], Some(SystemTime::now())).unwrap();
+        let value: Value = serde_json::from_slice(&payload).unwrap();
+        assert_eq!(value["result"]["data"], 1);
+        assert!(value["result"][META][TIMING_META_FIELD].is_object());
+    }
+}
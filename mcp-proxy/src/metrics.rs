@@ -0,0 +1,705 @@
+// Copyright AGNTCY Contributors (https://github.com/agntcy)
+// SPDX-License-Identifier: Apache-2.0
+
+use async_trait::async_trait;
+use axum::{Router, extract::State, http::header, response::IntoResponse, routing::get};
+use parking_lot::Mutex;
+use slim_datapath::messages::Name;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    io,
+    net::SocketAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicI64, AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+use tokio::net::{TcpListener, UdpSocket};
+use tracing::{debug, info};
+
+const METRIC_PREFIX: &str = "slim_mcp_proxy_";
+const MAX_LABEL_VALUES: usize = 64;
+const OTHER_LABEL_VALUE: &str = "other";
+/// keep StatsD packets below the usual MTU
+const STATSD_MAX_PACKET_SIZE: usize = 1432;
+/// upper bounds, in seconds, of the buckets of the duration histograms
+const DURATION_BUCKETS: [f64; 12] = [
+    0.001, 0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec(&self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Counter split by the value of a label. Values beyond the first
+/// `MAX_LABEL_VALUES` are counted under `OTHER_LABEL_VALUE`, so that values
+/// chosen by the clients cannot grow the metrics without bounds.
+#[derive(Debug, Default)]
+pub struct LabeledCounter(Mutex<BTreeMap<String, u64>>);
+
+impl LabeledCounter {
+    pub fn inc(&self, label_value: &str) {
+        let mut values = self.0.lock();
+        if let Some(count) = values.get_mut(label_value) {
+            *count += 1;
+        } else if values.len() < MAX_LABEL_VALUES {
+            values.insert(label_value.to_string(), 1);
+        } else {
+            *values.entry(OTHER_LABEL_VALUE.to_string()).or_default() += 1;
+        }
+    }
+
+    pub fn get(&self) -> Vec<(String, u64)> {
+        self.0.lock().iter().map(|(k, v)| (k.clone(), *v)).collect()
+    }
+}
+
+/// Label of the session metrics identifying the client
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SourceLabels {
+    /// No source label, the session metrics are aggregated
+    #[default]
+    Off,
+    /// Label the session metrics with the org/ns/type of the client
+    On,
+    /// Label the session metrics with the org/ns of the client
+    Bucketed,
+}
+
+impl SourceLabels {
+    fn value(self, client: &Name) -> String {
+        let [org, ns, kind] = client.components_strings();
+        match self {
+            SourceLabels::Off => String::new(),
+            SourceLabels::On => format!("{}/{}/{}", org, ns, kind),
+            SourceLabels::Bucketed => format!("{}/{}", org, ns),
+        }
+    }
+}
+
+/// Counter of sessions, split by client with the `source` label unless the
+/// source labels are off. The label values are bounded like the ones of
+/// `LabeledCounter`.
+#[derive(Debug, Default)]
+pub struct SourceCounter {
+    labels: SourceLabels,
+    counts: LabeledCounter,
+}
+
+impl SourceCounter {
+    fn new(labels: SourceLabels) -> Self {
+        Self {
+            labels,
+            counts: LabeledCounter::default(),
+        }
+    }
+
+    pub fn inc(&self, client: &Name) {
+        self.counts.inc(&self.labels.value(client));
+    }
+}
+
+/// Distribution of durations over `DURATION_BUCKETS`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Histogram {
+    /// observations of each bucket, the last one being +Inf (not cumulative)
+    buckets: [u64; DURATION_BUCKETS.len() + 1],
+    count: u64,
+    /// sum of the observations in seconds
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        let bucket = DURATION_BUCKETS
+            .iter()
+            .position(|b| secs <= *b)
+            .unwrap_or(DURATION_BUCKETS.len());
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum += secs;
+    }
+
+    /// Cumulative count of every bucket with its upper bound
+    fn cumulative(&self) -> impl Iterator<Item = (String, u64)> + '_ {
+        let bounds = DURATION_BUCKETS
+            .iter()
+            .map(|b| b.to_string())
+            .chain(["+Inf".to_string()]);
+        bounds.zip(self.buckets.iter().scan(0, |acc, n| {
+            *acc += n;
+            Some(*acc)
+        }))
+    }
+}
+
+/// Duration histograms split by phase
+#[derive(Debug, Default)]
+pub struct PhaseDurations(Mutex<BTreeMap<&'static str, Histogram>>);
+
+impl PhaseDurations {
+    pub fn observe(&self, phase: &'static str, duration: Duration) {
+        self.0.lock().entry(phase).or_default().observe(duration);
+    }
+
+    pub fn get(&self) -> Vec<(&'static str, Histogram)> {
+        self.0.lock().iter().map(|(k, v)| (*k, v.clone())).collect()
+    }
+}
+
+/// Metrics of the proxy, shared by the sessions and read by the exporters
+#[derive(Debug, Default)]
+pub struct Metrics {
+    pub sessions: SourceCounter,
+    pub rejected_sessions: SourceCounter,
+    pub active_sessions: Gauge,
+    pub closed_sessions: LabeledCounter,
+    pub client_messages: Counter,
+    pub client_parse_errors: Counter,
+    pub unknown_client_requests: LabeledCounter,
+    pub rejected_client_requests: Counter,
+    pub server_messages: Counter,
+    pub bad_server_messages: Counter,
+    pub unknown_server_responses: Counter,
+    pub server_error_reconnects: Counter,
+    pub dropped_server_messages: Counter,
+    pub wal_dropped_records: Counter,
+    pub chaos_dropped_messages: Counter,
+    pub diverging_conn_messages: Counter,
+    pub credit_waits: Counter,
+    pub filtered_tool_results: LabeledCounter,
+    /// time spent by the proxy in each startup phase
+    pub startup_phases: PhaseDurations,
+    /// time spent by the sessions to connect to the MCP server
+    pub session_phases: PhaseDurations,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SampleValue {
+    Counter(u64),
+    Gauge(i64),
+    Histogram(Histogram),
+}
+
+/// Value of a metric at a given time
+#[derive(Clone, Debug)]
+pub struct Sample {
+    /// name without the proxy prefix
+    pub name: &'static str,
+    pub help: &'static str,
+    pub labels: Vec<(&'static str, String)>,
+    pub value: SampleValue,
+}
+
+impl Sample {
+    fn counter(name: &'static str, help: &'static str, counter: &Counter) -> Self {
+        Self {
+            name,
+            help,
+            labels: Vec::new(),
+            value: SampleValue::Counter(counter.get()),
+        }
+    }
+
+    fn labeled_counters(
+        name: &'static str,
+        help: &'static str,
+        label: &'static str,
+        counter: &LabeledCounter,
+    ) -> impl Iterator<Item = Self> {
+        counter.get().into_iter().map(move |(value, count)| Self {
+            name,
+            help,
+            labels: vec![(label, value)],
+            value: SampleValue::Counter(count),
+        })
+    }
+
+    fn source_counters(
+        name: &'static str,
+        help: &'static str,
+        counter: &SourceCounter,
+    ) -> Vec<Self> {
+        let counts = counter.counts.get();
+        if counter.labels == SourceLabels::Off {
+            let total = counts.iter().map(|(_, count)| count).sum();
+            return vec![Self {
+                name,
+                help,
+                labels: Vec::new(),
+                value: SampleValue::Counter(total),
+            }];
+        }
+        counts
+            .into_iter()
+            .map(|(value, count)| Self {
+                name,
+                help,
+                labels: vec![("source", value)],
+                value: SampleValue::Counter(count),
+            })
+            .collect()
+    }
+
+    fn gauge(name: &'static str, help: &'static str, gauge: &Gauge) -> Self {
+        Self {
+            name,
+            help,
+            labels: Vec::new(),
+            value: SampleValue::Gauge(gauge.get()),
+        }
+    }
+
+    fn phase_durations(
+        name: &'static str,
+        help: &'static str,
+        durations: &PhaseDurations,
+    ) -> impl Iterator<Item = Self> {
+        durations
+            .get()
+            .into_iter()
+            .map(move |(phase, histogram)| Self {
+                name,
+                help,
+                labels: vec![("phase", phase.to_string())],
+                value: SampleValue::Histogram(histogram),
+            })
+    }
+}
+
+impl Metrics {
+    pub fn new(source_labels: SourceLabels) -> Self {
+        Self {
+            sessions: SourceCounter::new(source_labels),
+            rejected_sessions: SourceCounter::new(source_labels),
+            ..Default::default()
+        }
+    }
+
+    /// Current value of every metric. Samples of the same metric are adjacent.
+    pub fn samples(&self) -> Vec<Sample> {
+        let mut samples =
+            Sample::source_counters("sessions_total", "SLIM sessions accepted", &self.sessions);
+        samples.extend(Sample::source_counters(
+            "rejected_sessions_total",
+            "SLIM sessions rejected",
+            &self.rejected_sessions,
+        ));
+        samples.push(Sample::gauge(
+            "active_sessions",
+            "SLIM sessions currently served",
+            &self.active_sessions,
+        ));
+        samples.extend(Sample::labeled_counters(
+            "closed_sessions_total",
+            "SLIM sessions closed, by reason",
+            "reason",
+            &self.closed_sessions,
+        ));
+        samples.extend([
+            Sample::counter(
+                "client_messages_total",
+                "messages received from the SLIM clients",
+                &self.client_messages,
+            ),
+            Sample::counter(
+                "client_parse_errors_total",
+                "messages from the SLIM clients that could not be parsed",
+                &self.client_parse_errors,
+            ),
+        ]);
+        samples.extend(Sample::labeled_counters(
+            "unknown_client_requests_total",
+            "requests forwarded with a method unknown to the proxy, by method",
+            "method",
+            &self.unknown_client_requests,
+        ));
+        samples.push(Sample::counter(
+            "rejected_client_requests_total",
+            "client requests rejected because too many requests were pending, their id was in use or their method is unknown",
+            &self.rejected_client_requests,
+        ));
+        samples.extend([
+            Sample::counter(
+                "server_messages_total",
+                "messages received from the MCP server",
+                &self.server_messages,
+            ),
+            Sample::counter(
+                "bad_server_messages_total",
+                "messages from the MCP server that could not be interpreted",
+                &self.bad_server_messages,
+            ),
+            Sample::counter(
+                "unknown_server_responses_total",
+                "responses from the MCP server to no request of the session",
+                &self.unknown_server_responses,
+            ),
+            Sample::counter(
+                "server_error_reconnects_total",
+                "reconnections to the MCP server triggered by its errors",
+                &self.server_error_reconnects,
+            ),
+            Sample::counter(
+                "dropped_server_messages_total",
+                "messages from the MCP server dropped because the client queue was full",
+                &self.dropped_server_messages,
+            ),
+            Sample::counter(
+                "wal_dropped_records_total",
+                "messages not recorded in the session write-ahead log",
+                &self.wal_dropped_records,
+            ),
+            Sample::counter(
+                "chaos_dropped_messages_total",
+                "messages of the sessions dropped by the chaos mode",
+                &self.chaos_dropped_messages,
+            ),
+            Sample::counter(
+                "diverging_conn_messages_total",
+                "client messages received on another connection than the one the session answers on",
+                &self.diverging_conn_messages,
+            ),
+            Sample::counter(
+                "credit_waits_total",
+                "publications to the clients delayed by the session credits",
+                &self.credit_waits,
+            ),
+        ]);
+        samples.extend(Sample::labeled_counters(
+            "filtered_tool_results_total",
+            "tool results matching the content filter, by action",
+            "action",
+            &self.filtered_tool_results,
+        ));
+        samples.extend(Sample::phase_durations(
+            "startup_phase_duration_seconds",
+            "time spent in each startup phase",
+            &self.startup_phases,
+        ));
+        samples.extend(Sample::phase_durations(
+            "session_phase_duration_seconds",
+            "time spent by the sessions to connect to the MCP server, by phase",
+            &self.session_phases,
+        ));
+        samples
+    }
+}
+
+/// Backend publishing the metrics outside of the proxy
+#[async_trait]
+pub trait Exporter: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Export the metrics until the proxy stops
+    async fn run(&self, metrics: Arc<Metrics>) -> io::Result<()>;
+}
+
+/// Serve the metrics in the Prometheus text format on `/metrics`
+pub struct PrometheusExporter {
+    addr: SocketAddr,
+}
+
+impl PrometheusExporter {
+    pub fn new(addr: SocketAddr) -> Self {
+        Self { addr }
+    }
+}
+
+#[async_trait]
+impl Exporter for PrometheusExporter {
+    fn name(&self) -> &'static str {
+        "prometheus"
+    }
+
+    async fn run(&self, metrics: Arc<Metrics>) -> io::Result<()> {
+        let listener = TcpListener::bind(self.addr).await?;
+        info!(addr = %self.addr, "serving Prometheus metrics on /metrics");
+        let app = Router::new()
+            .route("/metrics", get(prometheus_handler))
+            .with_state(metrics);
+        axum::serve(listener, app).await
+    }
+}
+
+async fn prometheus_handler(State(metrics): State<Arc<Metrics>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        render_prometheus(&metrics.samples()),
+    )
+}
+
+fn render_prometheus(samples: &[Sample]) -> String {
+    let mut out = String::new();
+    let mut last_name = "";
+    for sample in samples {
+        if sample.name != last_name {
+            let kind = match sample.value {
+                SampleValue::Counter(_) => "counter",
+                SampleValue::Gauge(_) => "gauge",
+                SampleValue::Histogram(_) => "histogram",
+            };
+            let _ = writeln!(
+                out,
+                "# HELP {}{} {}",
+                METRIC_PREFIX, sample.name, sample.help
+            );
+            let _ = writeln!(out, "# TYPE {}{} {}", METRIC_PREFIX, sample.name, kind);
+            last_name = sample.name;
+        }
+        let labels: Vec<String> = sample
+            .labels
+            .iter()
+            .map(|(k, v)| format!("{}=\"{}\"", k, escape_prometheus(v)))
+            .collect();
+        let name = format!("{}{}", METRIC_PREFIX, sample.name);
+        let _ = match &sample.value {
+            SampleValue::Counter(v) => writeln!(out, "{}{} {}", name, braces(&labels), v),
+            SampleValue::Gauge(v) => writeln!(out, "{}{} {}", name, braces(&labels), v),
+            SampleValue::Histogram(h) => {
+                for (le, count) in h.cumulative() {
+                    let mut bucket_labels = labels.clone();
+                    bucket_labels.push(format!("le=\"{}\"", le));
+                    let _ = writeln!(out, "{}_bucket{} {}", name, braces(&bucket_labels), count);
+                }
+                let _ = writeln!(out, "{}_sum{} {}", name, braces(&labels), h.sum);
+                writeln!(out, "{}_count{} {}", name, braces(&labels), h.count)
+            }
+        };
+    }
+    out
+}
+
+fn braces(labels: &[String]) -> String {
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", labels.join(","))
+    }
+}
+
+fn escape_prometheus(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Replace the characters delimiting the fields of a StatsD line
+fn sanitize_statsd(value: &str) -> String {
+    value.replace([':', '|', '#', ',', '@', '\n'], "_")
+}
+
+/// Flavor of the StatsD protocol
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum StatsdFormat {
+    /// Plain StatsD, labels are appended to the metric name
+    #[default]
+    Statsd,
+    /// DogStatsD, labels are sent as tags
+    Dogstatsd,
+}
+
+/// Settings of the StatsD exporter
+#[derive(Clone, Debug)]
+pub struct StatsdConfig {
+    /// address of the StatsD server, as host:port
+    pub addr: String,
+    pub flush_interval: Duration,
+    pub format: StatsdFormat,
+}
+
+/// Send the metrics as StatsD UDP packets at a fixed interval
+pub struct StatsdExporter {
+    config: StatsdConfig,
+}
+
+impl StatsdExporter {
+    pub fn new(config: StatsdConfig) -> Self {
+        Self { config }
+    }
+
+    /// Render a sample as StatsD lines. Counters are sent as the increment
+    /// since the previous flush, nothing is sent if they did not change.
+    /// Histograms are sent as the count and the sum of their observations.
+    fn lines(&self, sample: &Sample, last_counters: &mut HashMap<String, u64>) -> Vec<String> {
+        let mut name = format!("{}{}", METRIC_PREFIX, sample.name);
+        let mut tags = String::new();
+        match self.config.format {
+            StatsdFormat::Statsd => {
+                for (_, v) in &sample.labels {
+                    let _ = write!(name, ".{}", sanitize_statsd(v));
+                }
+            }
+            StatsdFormat::Dogstatsd if !sample.labels.is_empty() => {
+                let joined = sample
+                    .labels
+                    .iter()
+                    .map(|(k, v)| format!("{}:{}", k, sanitize_statsd(v)))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                tags = format!("|#{}", joined);
+            }
+            StatsdFormat::Dogstatsd => {}
+        }
+        let mut counter = |name: &str, v: u64| {
+            let series = format!("{}{}", name, tags);
+            let last = last_counters.insert(series, v).unwrap_or(0);
+            let delta = v.saturating_sub(last);
+            (delta > 0).then(|| format!("{}:{}|c{}", name, delta, tags))
+        };
+        match &sample.value {
+            SampleValue::Counter(v) => counter(&name, *v).into_iter().collect(),
+            SampleValue::Gauge(v) => vec![format!("{}:{}|g{}", name, v, tags)],
+            SampleValue::Histogram(h) => {
+                let count = counter(&format!("{}.count", name), h.count);
+                let sum = format!("{}.sum:{}|g{}", name, h.sum, tags);
+                count.into_iter().chain([sum]).collect()
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Exporter for StatsdExporter {
+    fn name(&self) -> &'static str {
+        "statsd"
+    }
+
+    async fn run(&self, metrics: Arc<Metrics>) -> io::Result<()> {
+        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+        socket.connect(&self.config.addr).await?;
+        info!(addr = %self.config.addr, "sending StatsD metrics");
+
+        let mut last_counters = HashMap::new();
+        let mut interval = tokio::time::interval(self.config.flush_interval);
+        loop {
+            interval.tick().await;
+            let mut packet = String::new();
+            let lines = metrics
+                .samples()
+                .iter()
+                .flat_map(|sample| self.lines(sample, &mut last_counters))
+                .collect::<Vec<_>>();
+            for line in lines {
+                if !packet.is_empty() && packet.len() + 1 + line.len() > STATSD_MAX_PACKET_SIZE {
+                    send_statsd(&socket, &packet).await;
+                    packet.clear();
+                }
+                if !packet.is_empty() {
+                    packet.push('\n');
+                }
+                packet.push_str(&line);
+            }
+            if !packet.is_empty() {
+                send_statsd(&socket, &packet).await;
+            }
+        }
+    }
+}
+
+/// Losing a packet is not an error for StatsD, the failure is only logged
+async fn send_statsd(socket: &UdpSocket, packet: &str) {
+    if let Err(e) = socket.send(packet.as_bytes()).await {
+        debug!("error sending StatsD packet: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(org: &str, ns: &str, kind: &str) -> Name {
+        Name::from_strings([org, ns, kind]).with_id(7)
+    }
+
+    #[test]
+    fn source_label_values() {
+        let name = client("org", "tenant-a", "agent");
+        assert_eq!(SourceLabels::Off.value(&name), "");
+        assert_eq!(SourceLabels::On.value(&name), "org/tenant-a/agent");
+        assert_eq!(SourceLabels::Bucketed.value(&name), "org/tenant-a");
+    }
+
+    #[test]
+    fn source_samples_labeled_or_aggregated() {
+        let clients = [
+            client("org", "tenant-a", "agent"),
+            client("org", "tenant-a", "tool"),
+            client("org", "tenant-b", "agent"),
+        ];
+        let samples = |labels| {
+            let counter = SourceCounter::new(labels);
+            clients.iter().for_each(|c| counter.inc(c));
+            Sample::source_counters("sessions_total", "", &counter)
+                .into_iter()
+                .map(|s| (s.labels, s.value))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            samples(SourceLabels::Off),
+            [(vec![], SampleValue::Counter(3))]
+        );
+        assert_eq!(
+            samples(SourceLabels::Bucketed),
+            [
+                (
+                    vec![("source", "org/tenant-a".to_string())],
+                    SampleValue::Counter(2)
+                ),
+                (
+                    vec![("source", "org/tenant-b".to_string())],
+                    SampleValue::Counter(1)
+                ),
+            ]
+        );
+        assert_eq!(samples(SourceLabels::On).len(), 3);
+    }
+
+    #[test]
+    fn source_labels_beyond_the_limit_counted_as_other() {
+        let counter = SourceCounter::new(SourceLabels::On);
+        for n in 0..MAX_LABEL_VALUES + 2 {
+            counter.inc(&client("org", "ns", &format!("agent-{n}")));
+        }
+        // the values already seen keep their own count
+        counter.inc(&client("org", "ns", "agent-0"));
+
+        let counts = counter.counts.get();
+        assert_eq!(counts.len(), MAX_LABEL_VALUES + 1);
+        assert!(counts.contains(&(OTHER_LABEL_VALUE.to_string(), 2)));
+        assert!(counts.contains(&("org/ns/agent-0".to_string(), 2)));
+        let last = format!("org/ns/agent-{}", MAX_LABEL_VALUES + 1);
+        assert!(!counts.iter().any(|(value, _)| *value == last));
+    }
+}
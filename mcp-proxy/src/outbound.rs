@@ -0,0 +1,409 @@
+// Copyright AGNTCY Contributors (https://github.com/agntcy)
+// SPDX-License-Identifier: Apache-2.0
+
+use async_trait::async_trait;
+use slim_datapath::messages::Name;
+use slim_session::session_controller::SessionController;
+use std::{
+    sync::{Arc, Weak},
+    time::Duration,
+};
+use tokio::{
+    sync::mpsc::{self, error::TrySendError},
+    time::Instant,
+};
+use tracing::{debug, error};
+
+use crate::credits::SessionCredits;
+use crate::proxy::{OverloadPolicy, is_connection_error};
+
+/// messages waiting to be published to the client, when the queue is
+/// required by batching or session credits without a size of its own
+pub const DEFAULT_OUTBOUND_QUEUE_SIZE: usize = 64;
+/// messages published together at most when batching
+pub const DEFAULT_BATCH_MAX_MESSAGES: usize = 16;
+
+/// Coalescing of the messages from the MCP server into JSON-RPC batches
+#[derive(Clone, Copy, Debug)]
+pub struct Batching {
+    /// time the first message of a batch waits for the next ones
+    pub window: Duration,
+    pub max_messages: usize,
+}
+
+impl Batching {
+    /// Wait for the messages following the first one during the window, and
+    /// join them into a JSON-RPC batch. A lone message is published as is.
+    async fn collect(&self, first: Vec<u8>, rx: &mut mpsc::Receiver<Vec<u8>>) -> Vec<u8> {
+        let deadline = Instant::now() + self.window;
+        let mut payloads = vec![first];
+        while payloads.len() < self.max_messages {
+            match tokio::time::timeout_at(deadline, rx.recv()).await {
+                Ok(Some(payload)) => payloads.push(payload),
+                // the window elapsed or the queue closed
+                _ => break,
+            }
+        }
+        if payloads.len() == 1 {
+            return payloads.swap_remove(0);
+        }
+        debug!(
+            messages = payloads.len(),
+            "publishing a batch of MCP messages"
+        );
+        let mut batch = Vec::with_capacity(payloads.iter().map(|p| p.len() + 1).sum::<usize>() + 1);
+        batch.push(b'[');
+        for (i, payload) in payloads.iter().enumerate() {
+            if i > 0 {
+                batch.push(b',');
+            }
+            batch.extend_from_slice(payload);
+        }
+        batch.push(b']');
+        batch
+    }
+}
+
+/// Result of queuing a message for the client
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Queued {
+    Sent,
+    /// the queue was full and the policy dropped the message
+    Dropped,
+    /// the publishing task ended, the SLIM connection is gone
+    Closed,
+}
+
+/// Publication of the messages to the SLIM client of a session
+#[async_trait]
+pub trait Publish: Send + Sync + 'static {
+    /// Publish a message, false once the client cannot be reached anymore
+    async fn publish(&self, payload: Vec<u8>) -> bool;
+}
+
+/// Publishes on the connection of the SLIM client of a session
+pub struct SessionPublisher {
+    pub session: Weak<SessionController>,
+    pub remote_name: Name,
+    pub conn: u64,
+}
+
+#[async_trait]
+impl Publish for SessionPublisher {
+    async fn publish(&self, payload: Vec<u8>) -> bool {
+        let Some(session_arc) = self.session.upgrade() else {
+            debug!("session dropped before sending message to client");
+            return false;
+        };
+        match session_arc
+            .publish_to(&self.remote_name, self.conn, payload, None, None)
+            .await
+        {
+            Err(e) if is_connection_error(&e) => {
+                error!(
+                    "SLIM connection lost ({}), stop sending messages to client",
+                    e
+                );
+                false
+            }
+            Err(e) => {
+                error!("error sending message to client: {}", e);
+                true
+            }
+            Ok(_) => true,
+        }
+    }
+}
+
+enum Sink {
+    /// published by the session handler itself, one message at a time
+    Direct(Arc<dyn Publish>),
+    /// published by a task of its own
+    Queue(mpsc::Sender<Vec<u8>>),
+}
+
+/// Path of every message to the SLIM client of a session, whether it comes
+/// from the MCP server or from the proxy, so that they reach the client in
+/// the order they are sent.
+///
+/// Without a queue the messages are published right away, and a slow client
+/// slows the reading from the MCP server down. With a queue they are
+/// published by a dedicated task, so that the proxy keeps reading from the
+/// MCP server while the SLIM side is slow: when the queue is full the policy
+/// decides between waiting and dropping the message. With batching, the
+/// messages queued within a short window are published together. With
+/// session credits, the task waits for them before each publication.
+pub struct Outbound {
+    sink: Sink,
+    policy: OverloadPolicy,
+}
+
+impl Outbound {
+    /// Publish right away, without queue
+    pub fn direct(publisher: impl Publish) -> Self {
+        Self {
+            sink: Sink::Direct(Arc::new(publisher)),
+            policy: OverloadPolicy::Backpressure,
+        }
+    }
+
+    /// Publish from a queue of the given capacity
+    pub fn spawn(
+        publisher: impl Publish,
+        capacity: usize,
+        policy: OverloadPolicy,
+        batching: Option<Batching>,
+        credits: Option<Arc<SessionCredits>>,
+    ) -> Self {
+        let (tx, mut rx) = mpsc::channel::<Vec<u8>>(capacity);
+        tokio::spawn(async move {
+            while let Some(mut payload) = rx.recv().await {
+                if let Some(batching) = &batching {
+                    payload = batching.collect(payload, &mut rx).await;
+                }
+                if let Some(credits) = &credits {
+                    credits.acquire(payload.len()).await;
+                }
+                if !publisher.publish(payload).await {
+                    break;
+                }
+            }
+        });
+        Self {
+            sink: Sink::Queue(tx),
+            policy,
+        }
+    }
+
+    /// Send a message from the MCP server, queued with the overload policy
+    pub async fn push(&self, payload: Vec<u8>) -> Queued {
+        match (&self.sink, self.policy) {
+            (_, OverloadPolicy::Backpressure) => self.send(payload).await,
+            (Sink::Direct(_), _) => self.send(payload).await,
+            (
+                Sink::Queue(tx),
+                OverloadPolicy::DropNewest
+                | OverloadPolicy::DropNotify
+                | OverloadPolicy::Disconnect,
+            ) => match tx.try_send(payload) {
+                Ok(()) => Queued::Sent,
+                Err(TrySendError::Full(_)) => Queued::Dropped,
+                Err(TrySendError::Closed(_)) => Queued::Closed,
+            },
+        }
+    }
+
+    /// Send a message, waiting for room in the queue whatever the overload
+    /// policy: the few messages of the proxy itself are never dropped
+    pub async fn send(&self, payload: Vec<u8>) -> Queued {
+        match &self.sink {
+            Sink::Direct(publisher) => match publisher.publish(payload).await {
+                true => Queued::Sent,
+                false => Queued::Closed,
+            },
+            Sink::Queue(tx) => match tx.send(payload).await {
+                Ok(()) => Queued::Sent,
+                Err(_) => Queued::Closed,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+    use tokio::sync::Semaphore;
+
+    /// Client publishing one message per permit, recording them
+    #[derive(Clone)]
+    struct SlowClient {
+        published: Arc<Mutex<Vec<Vec<u8>>>>,
+        permits: Arc<Semaphore>,
+    }
+
+    impl Default for SlowClient {
+        fn default() -> Self {
+            Self {
+                published: Arc::default(),
+                permits: Arc::new(Semaphore::new(0)),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Publish for SlowClient {
+        async fn publish(&self, payload: Vec<u8>) -> bool {
+            match self.permits.acquire().await {
+                Ok(permit) => permit.forget(),
+                Err(_) => return false,
+            }
+            self.published.lock().push(payload);
+            true
+        }
+    }
+
+    impl SlowClient {
+        fn published(&self) -> Vec<u8> {
+            self.published.lock().iter().map(|p| p[0]).collect()
+        }
+
+        /// Let the client publish everything and wait for the queue to drain
+        async fn drain(&self, expected: usize) {
+            self.permits.add_permits(Semaphore::MAX_PERMITS / 2);
+            while self.published.lock().len() < expected {
+                tokio::task::yield_now().await;
+            }
+        }
+    }
+
+    /// Push messages 0 to 9 to a client publishing none of them, through a
+    /// queue of 2
+    async fn under_load(policy: OverloadPolicy) -> (SlowClient, Vec<Queued>) {
+        let client = SlowClient::default();
+        let outbound = Outbound::spawn(client.clone(), 2, policy, None, None);
+        let mut queued = Vec::new();
+        for i in 0..10u8 {
+            queued.push(outbound.push(vec![i]).await);
+        }
+        (client, queued)
+    }
+
+    #[tokio::test]
+    async fn backpressure_waits_for_room() {
+        let client = SlowClient::default();
+        let outbound = Arc::new(Outbound::spawn(
+            client.clone(),
+            2,
+            OverloadPolicy::Backpressure,
+            None,
+            None,
+        ));
+        let pushing = tokio::spawn({
+            let outbound = outbound.clone();
+            async move {
+                for i in 0..10u8 {
+                    assert_eq!(outbound.push(vec![i]).await, Queued::Sent);
+                }
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!pushing.is_finished());
+        client.drain(10).await;
+        pushing.await.unwrap();
+        assert_eq!(client.published(), (0..10).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn drop_policies_drop_the_newest() {
+        for policy in [
+            OverloadPolicy::DropNewest,
+            OverloadPolicy::DropNotify,
+            OverloadPolicy::Disconnect,
+        ] {
+            let (client, queued) = under_load(policy).await;
+            let sent = queued.iter().filter(|q| **q == Queued::Sent).count();
+            // the task holds one message, the queue two more
+            assert!((2..=3).contains(&sent), "{:?}: {:?}", policy, queued);
+            assert!(queued[..sent].iter().all(|q| *q == Queued::Sent));
+            assert!(queued[sent..].iter().all(|q| *q == Queued::Dropped));
+            client.drain(sent).await;
+            assert_eq!(client.published(), (0..sent as u8).collect::<Vec<_>>());
+        }
+    }
+
+    #[tokio::test]
+    async fn messages_of_the_proxy_never_dropped() {
+        let client = SlowClient::default();
+        let outbound = Arc::new(Outbound::spawn(
+            client.clone(),
+            1,
+            OverloadPolicy::DropNotify,
+            None,
+            None,
+        ));
+        assert_eq!(outbound.push(vec![0]).await, Queued::Sent);
+        // the task takes the message and waits for the client
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(outbound.push(vec![0]).await, Queued::Sent);
+        assert_eq!(outbound.push(vec![0]).await, Queued::Dropped);
+        let notify = tokio::spawn({
+            let outbound = outbound.clone();
+            async move { outbound.send(vec![1]).await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!notify.is_finished());
+        client.permits.add_permits(Semaphore::MAX_PERMITS / 2);
+        assert_eq!(notify.await.unwrap(), Queued::Sent);
+        client.drain(3).await;
+        // the error follows the queued messages
+        assert_eq!(client.published().last(), Some(&1));
+    }
+
+    #[tokio::test]
+    async fn direct_publishes_right_away() {
+        let client = SlowClient::default();
+        client.permits.add_permits(1);
+        let outbound = Outbound::direct(client.clone());
+        assert_eq!(outbound.push(vec![0]).await, Queued::Sent);
+        assert_eq!(client.published(), [0]);
+        client.permits.close();
+        assert_eq!(outbound.send(vec![1]).await, Queued::Closed);
+    }
+
+    #[tokio::test]
+    async fn closed_once_the_client_is_gone() {
+        let client = SlowClient::default();
+        client.permits.close();
+        let outbound = Outbound::spawn(client, 1, OverloadPolicy::DropNewest, None, None);
+        assert_eq!(outbound.push(vec![0]).await, Queued::Sent);
+        while outbound.push(vec![1]).await != Queued::Closed {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn batch_capped_at_max_messages_in_order() {
+        let batching = Batching {
+            window: Duration::from_secs(5),
+            max_messages: 3,
+        };
+        let (tx, mut rx) = mpsc::channel(8);
+        for id in 2..=5 {
+            tx.send(format!(r#"{{"id":{id}}}"#).into_bytes())
+                .await
+                .unwrap();
+        }
+        let started = Instant::now();
+        let batch = batching.collect(br#"{"id":1}"#.to_vec(), &mut rx).await;
+        // a full batch does not wait for the end of the window
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(batch, br#"[{"id":1},{"id":2},{"id":3}]"#);
+        // the rest goes into the next batch
+        assert_eq!(rx.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn batch_closed_at_the_end_of_the_window() {
+        let batching = Batching {
+            window: Duration::from_millis(50),
+            max_messages: 16,
+        };
+        let (tx, mut rx) = mpsc::channel(8);
+        tx.send(b"2".to_vec()).await.unwrap();
+        let late = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            tx.send(b"3".to_vec()).await.unwrap();
+        });
+        let started = Instant::now();
+        let batch = batching.collect(b"1".to_vec(), &mut rx).await;
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        assert!(started.elapsed() < Duration::from_millis(300));
+        assert_eq!(batch, b"[1,2]");
+
+        // a message alone in its window is published as is
+        late.await.unwrap();
+        let first = rx.recv().await.unwrap();
+        assert_eq!(batching.collect(first, &mut rx).await, b"3");
+    }
+}
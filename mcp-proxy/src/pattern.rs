@@ -0,0 +1,42 @@
+// Copyright AGNTCY Contributors (https://github.com/agntcy)
+// SPDX-License-Identifier: Apache-2.0
+
+use slim_datapath::messages::Name;
+use std::str::FromStr;
+use thiserror::Error;
+
+const WILDCARD: &str = "*";
+
+#[derive(Debug, Error)]
+#[error("invalid name pattern {0}, expected org/ns/type or org/ns/type/id")]
+pub struct NamePatternError(String);
+
+/// Pattern matching SLIM names, e.g. `org/tenant-a/*`
+///
+/// The pattern has the form `org/ns/type` or `org/ns/type/id`, with the id in
+/// hex. Each segment is either matched exactly or `*`, which matches anything.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NamePattern(Vec<String>);
+
+impl FromStr for NamePattern {
+    type Err = NamePatternError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let segments: Vec<String> = s.trim().split('/').map(str::to_string).collect();
+        if !(3..=4).contains(&segments.len()) || segments.iter().any(|p| p.is_empty()) {
+            return Err(NamePatternError(s.trim().to_string()));
+        }
+        Ok(Self(segments))
+    }
+}
+
+impl NamePattern {
+    pub fn matches(&self, name: &Name) -> bool {
+        let id = format!("{:x}", name.id());
+        let segments = name.components_strings().iter().map(String::as_str);
+        self.0
+            .iter()
+            .zip(segments.chain([id.as_str()]))
+            .all(|(p, s)| p == WILDCARD || p == s)
+    }
+}
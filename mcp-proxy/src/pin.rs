@@ -0,0 +1,223 @@
+// Copyright AGNTCY Contributors (https://github.com/agntcy)
+// SPDX-License-Identifier: Apache-2.0
+
+use rustls::{
+    ClientConfig, DigitallySignedStruct, Error, RootCertStore, SignatureScheme,
+    client::{
+        WebPkiServerVerifier,
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    },
+    crypto::ring,
+    pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+};
+use sha2::{Digest, Sha256};
+use std::{fmt, str::FromStr, sync::Arc};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+#[error("invalid SHA-256 fingerprint {0}, expected 64 hex digits, optionally separated by colons")]
+pub struct InvalidPin(String);
+
+/// SHA-256 fingerprint of the certificate expected from the MCP server
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct CertPin([u8; 32]);
+
+impl FromStr for CertPin {
+    type Err = InvalidPin;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits: Vec<u8> = s.bytes().filter(|b| *b != b':').collect();
+        if digits.len() != 64 || !digits.iter().all(u8::is_ascii_hexdigit) {
+            return Err(InvalidPin(s.to_string()));
+        }
+        let mut pin = [0u8; 32];
+        for (byte, pair) in pin.iter_mut().zip(digits.chunks(2)) {
+            let pair = std::str::from_utf8(pair).map_err(|_| InvalidPin(s.to_string()))?;
+            *byte = u8::from_str_radix(pair, 16).map_err(|_| InvalidPin(s.to_string()))?;
+        }
+        Ok(Self(pin))
+    }
+}
+
+impl fmt::Debug for CertPin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ":")?;
+            }
+            write!(f, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// Verifies the certificate of the MCP server as usual, then requires its
+/// fingerprint to be one of the pins
+#[derive(Debug)]
+struct PinnedVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    pins: Vec<CertPin>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+        let fingerprint = CertPin(Sha256::digest(end_entity.as_ref()).into());
+        if !self.pins.contains(&fingerprint) {
+            return Err(Error::General(format!(
+                "certificate of the MCP server {:?} matches no pinned fingerprint",
+                fingerprint
+            )));
+        }
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// TLS configuration of the MCP connections accepting only the certificates
+/// chaining to the web PKI roots and matching one of the pins, if any, and
+/// presenting the client certificate, if any
+pub fn tls_config(
+    pins: Vec<CertPin>,
+    tls13_only: bool,
+    client_cert: Option<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>,
+) -> Result<ClientConfig, Error> {
+    let provider = Arc::new(ring::default_provider());
+    let roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    let inner = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+        .build()
+        .map_err(|e| Error::General(e.to_string()))?;
+    let versions: &[&rustls::SupportedProtocolVersion] = if tls13_only {
+        &[&rustls::version::TLS13]
+    } else {
+        rustls::DEFAULT_VERSIONS
+    };
+    let builder = ClientConfig::builder_with_provider(provider)
+        .with_protocol_versions(versions)?
+        .dangerous();
+    let builder = if pins.is_empty() {
+        builder.with_custom_certificate_verifier(inner)
+    } else {
+        builder.with_custom_certificate_verifier(Arc::new(PinnedVerifier { inner, pins }))
+    };
+    match client_cert {
+        Some((chain, key)) => builder.with_client_auth_cert(chain, key),
+        None => Ok(builder.with_no_client_auth()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::pki_types::pem::PemObject;
+
+    const FINGERPRINT: &str = "ab:CD:01:23:45:67:89:ef:ab:cd:01:23:45:67:89:ef:ab:cd:01:23:45:67:89:ef:ab:cd:01:23:45:67:89:ef";
+
+    #[test]
+    fn pin_parsed_with_or_without_colons() {
+        let pin: CertPin = FINGERPRINT.parse().unwrap();
+        assert_eq!(pin, FINGERPRINT.replace(':', "").parse().unwrap());
+        assert_eq!(format!("{:?}", pin), FINGERPRINT.to_uppercase());
+    }
+
+    #[test]
+    fn invalid_pins_rejected() {
+        for pin in [
+            "",
+            &FINGERPRINT[3..],
+            &format!("{}:00", FINGERPRINT),
+            &FINGERPRINT.replace('e', "g"),
+            &FINGERPRINT.replace(':', " "),
+        ] {
+            let error = pin.parse::<CertPin>().unwrap_err();
+            assert!(error.to_string().contains("invalid SHA-256 fingerprint"));
+        }
+    }
+
+    fn certificate(pem: &[u8]) -> CertificateDer<'static> {
+        CertificateDer::from_pem_slice(pem).unwrap()
+    }
+
+    /// Verifier trusting the test authority, with the given pins
+    fn verifier(pins: Vec<CertPin>) -> PinnedVerifier {
+        let mut roots = RootCertStore::empty();
+        roots
+            .add(certificate(include_bytes!("../testdata/ca.pem")))
+            .unwrap();
+        let inner = WebPkiServerVerifier::builder_with_provider(
+            Arc::new(roots),
+            Arc::new(ring::default_provider()),
+        )
+        .build()
+        .unwrap();
+        PinnedVerifier { inner, pins }
+    }
+
+    fn verify(
+        verifier: &PinnedVerifier,
+        cert: &CertificateDer<'_>,
+    ) -> Result<ServerCertVerified, Error> {
+        let server_name = ServerName::try_from("localhost").unwrap();
+        verifier.verify_server_cert(cert, &[], &server_name, &[], UnixTime::now())
+    }
+
+    #[test]
+    fn matching_pin_accepted() {
+        let cert = certificate(include_bytes!("../testdata/localhost.pem"));
+        let pin = CertPin(Sha256::digest(cert.as_ref()).into());
+        let other: CertPin = FINGERPRINT.parse().unwrap();
+        assert!(verify(&verifier(vec![other, pin]), &cert).is_ok());
+    }
+
+    #[test]
+    fn mismatching_pin_rejected() {
+        let cert = certificate(include_bytes!("../testdata/localhost.pem"));
+        let other: CertPin = FINGERPRINT.parse().unwrap();
+        let error = verify(&verifier(vec![other]), &cert).unwrap_err();
+        assert!(error.to_string().contains("matches no pinned fingerprint"));
+    }
+
+    #[test]
+    fn pinned_certificate_still_verified() {
+        // the authority is not a server certificate of localhost, pinned or not
+        let cert = certificate(include_bytes!("../testdata/ca.pem"));
+        let pin = CertPin(Sha256::digest(cert.as_ref()).into());
+        assert!(verify(&verifier(vec![pin]), &cert).is_err());
+    }
+}
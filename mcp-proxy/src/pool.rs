@@ -0,0 +1,162 @@
+// Copyright AGNTCY Contributors (https://github.com/agntcy)
+// SPDX-License-Identifier: Apache-2.0
+
+use parking_lot::Mutex;
+use rmcp::model::{ClientJsonRpcMessage, ClientRequest, JsonRpcMessage, PingRequest, ServerResult};
+use rmcp::transport::{StreamableHttpClientTransport, Transport};
+use slim_datapath::messages::Name;
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+use tokio::sync::mpsc;
+use tracing::debug;
+
+use crate::bounded::BoundedClient;
+use crate::ids;
+
+/// Interval between two health checks of the idle connections of the pool
+pub const POOL_HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// Time for an idle connection to answer its health check
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Connection to the MCP server whose handshake is already done
+pub struct WarmConnection {
+    pub transport: StreamableHttpClientTransport<BoundedClient>,
+    /// result of the `initialize` request, returned to the client taking the
+    /// connection
+    pub initialize_result: ServerResult,
+    /// the connection is to the fallback MCP server
+    pub on_fallback: bool,
+}
+
+impl WarmConnection {
+    /// Whether the MCP server still answers a ping on the connection. The
+    /// connection is idle, the other messages of the server are dropped.
+    pub async fn is_healthy(&mut self) -> bool {
+        let id = ids::internal_id("pool-check");
+        let check = async {
+            let ping = ClientRequest::PingRequest(PingRequest::default());
+            self.transport
+                .send(ClientJsonRpcMessage::request(ping, id.clone()))
+                .await
+                .ok()?;
+            loop {
+                match self.transport.receive().await? {
+                    JsonRpcMessage::Response(resp) if resp.id == id => return Some(()),
+                    JsonRpcMessage::Error(err) if err.id == id => return Some(()),
+                    _ => {}
+                }
+            }
+        };
+        matches!(
+            tokio::time::timeout(HEALTH_CHECK_TIMEOUT, check).await,
+            Ok(Some(()))
+        )
+    }
+}
+
+/// Idle connections to the MCP server, ready to be taken by new sessions.
+///
+/// The pool is filled by a task of the proxy, which warms a new connection
+/// as soon as one is taken. The clients all get the capabilities negotiated
+/// by the proxy, so the pool only suits stateless backends.
+pub struct McpPool {
+    rx: Mutex<mpsc::Receiver<WarmConnection>>,
+}
+
+impl McpPool {
+    /// Create a pool of `size` connections and the sender filling it
+    pub fn new(size: usize) -> (Self, mpsc::Sender<WarmConnection>) {
+        let (tx, rx) = mpsc::channel(size);
+        (Self { rx: Mutex::new(rx) }, tx)
+    }
+
+    /// Take an idle connection, if any
+    pub fn take(&self) -> Option<WarmConnection> {
+        self.rx.lock().try_recv().ok()
+    }
+
+    /// Number of idle connections
+    pub fn idle(&self) -> usize {
+        self.rx.lock().len()
+    }
+}
+
+impl std::fmt::Debug for McpPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("McpPool")
+            .field("idle", &self.idle())
+            .finish()
+    }
+}
+
+/// MCP connections of the clients that went away, kept for a while so that a
+/// client reconnecting with the same name resumes its MCP session, and the
+/// state the backend holds for it.
+pub struct SessionAffinity {
+    window: Duration,
+    /// parked connection of each client, with the token of its parking
+    parked: Mutex<HashMap<Name, (u64, WarmConnection)>>,
+    next_token: AtomicU64,
+}
+
+impl SessionAffinity {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            parked: Mutex::new(HashMap::new()),
+            next_token: AtomicU64::new(0),
+        }
+    }
+
+    /// Keep the connection of a client for the affinity window, replacing the
+    /// one it may have parked already. The connection is closed if the client
+    /// does not come back in time.
+    pub fn park(self: &Arc<Self>, client: Name, connection: WarmConnection) {
+        let token = self.next_token.fetch_add(1, Ordering::Relaxed);
+        let replaced = self
+            .parked
+            .lock()
+            .insert(client.clone(), (token, connection));
+        let affinity = self.clone();
+        tokio::spawn(async move {
+            if let Some((_, mut replaced)) = replaced {
+                let _ = replaced.transport.close().await;
+            }
+            tokio::time::sleep(affinity.window).await;
+            let expired = {
+                let mut parked = affinity.parked.lock();
+                match parked.get(&client) {
+                    Some((t, _)) if *t == token => parked.remove(&client),
+                    _ => None,
+                }
+            };
+            if let Some((_, mut expired)) = expired {
+                debug!(%client, "client not back within the affinity window, closing its MCP connection");
+                let _ = expired.transport.close().await;
+            }
+        });
+    }
+
+    /// Take the connection parked by a client, if any
+    pub fn take(&self, client: &Name) -> Option<WarmConnection> {
+        self.parked
+            .lock()
+            .remove(client)
+            .map(|(_, connection)| connection)
+    }
+}
+
+impl std::fmt::Debug for SessionAffinity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionAffinity")
+            .field("window", &self.window)
+            .field("parked", &self.parked.lock().len())
+            .finish()
+    }
+}
@@ -1,38 +1,491 @@
 // Copyright AGNTCY Contributors (https://github.com/agntcy)
 // SPDX-License-Identifier: Apache-2.0
 
-use rmcp::model::ClientResult::EmptyResult;
+use rmcp::model::ClientResult::{
+    CreateElicitationResult as Elicitation, EmptyResult, ListRootsResult as ListRoots,
+};
 use rmcp::{
     model::{
-        ClientNotification, ClientRequest, ClientResult, JsonRpcMessage, JsonRpcRequest,
-        PingRequest, PingRequestMethod, ServerJsonRpcMessage,
+        ClientInfo, ClientJsonRpcMessage, ClientNotification, ClientRequest,
+        CreateElicitationResult, CustomNotification, ElicitationAction, ErrorCode, ErrorData,
+        GetMeta, InitializeRequest, InitializeResult, InitializedNotification, JsonRpcError,
+        JsonRpcMessage, JsonRpcNotification, JsonRpcRequest, JsonRpcResponse, JsonRpcVersion2_0,
+        ListRootsResult, LoggingLevel, LoggingMessageNotificationParam, PingRequest,
+        PingRequestMethod, RequestId, ServerJsonRpcMessage, ServerNotification, ServerRequest,
+        ServerResult,
+    },
+    transport::{
+        StreamableHttpClientTransport, Transport,
+        streamable_http_client::StreamableHttpClientTransportConfig,
     },
-    transport::{StreamableHttpClientTransport, Transport},
 };
 
+use crate::admin::{self, AdminServer, AdminState, BackendRole, DEFAULT_BACKEND_DOWN_FAILURES};
+use crate::app_message::{AppMessageHandler, IgnoreAppMessages};
+use crate::authz::{self, Allowlist, SourceLimit, SourcePriority};
+use crate::bounded::{BoundedClient, DEFAULT_READ_BUFFER_BYTES};
+use crate::chaos::Chaos;
+use crate::credits::{
+    CreditConfig, CreditPolicy, CreditScheduler, DEFAULT_CREDIT_INTERVAL, SessionCredits,
+};
+use crate::discovery::{Discovery, DiscoveryError, DiscoverySource, ResolvedHosts};
+use crate::error::ProxyError;
+use crate::error_action::{self, ErrorAction, ErrorRule};
+use crate::events::{EventSocket, Events};
+use crate::filter::{ContentFilter, FilterAction, FilterOutcome};
+use crate::headers::{self, SourceHeader};
+use crate::ids::{self, IdNamespace};
+use crate::keepalive::{MissedPingsPolicy, PingContext, PingDecision, PingPolicy, PingTracker};
+use crate::logical::{LogicalServer, MethodRoutes, UnknownServerPolicy};
+use crate::memory::{self, MEMORY_CHECK_INTERVAL, SessionActivity, ShedCause, ShedPolicy};
+use crate::message::{self, ParseOptions};
+use crate::metrics::{
+    Exporter, Metrics, PrometheusExporter, SourceLabels, StatsdConfig, StatsdExporter,
+};
+use crate::outbound::{
+    Batching, DEFAULT_BATCH_MAX_MESSAGES, DEFAULT_OUTBOUND_QUEUE_SIZE, Outbound, Queued,
+    SessionPublisher,
+};
+use crate::pin::CertPin;
+use crate::pool::{McpPool, POOL_HEALTH_CHECK_INTERVAL, SessionAffinity, WarmConnection};
+use crate::redact::{RedactedUrl, Redactor};
+use crate::shadow::Shadow;
+use crate::throttle::{LogThrottle, ReconnectLimiter};
+use crate::tls::{ClientCert, McpTls};
+use crate::wal::{self, SessionWal, WalConfig, WalFailurePolicy};
 use slim_auth::auth_provider::{AuthProvider, AuthVerifier};
 use slim_auth::shared_secret::SharedSecret;
 use slim_auth::spire::SpireIdentityManager;
+use slim_config::grpc::client::ClientConfig;
 use slim_datapath::messages::Name;
 use slim_session::{
+    SessionError,
     context::SessionContext,
     notification::Notification,
+    session_controller::SessionController,
     timer::{Timer, TimerObserver, TimerType},
 };
 
-use rmcp::model::NumberOrString::Number;
+use rmcp::model::NumberOrString::{self, Number};
 use std::{
-    collections::{HashMap, HashSet},
-    sync::Arc,
-    time::Duration,
+    collections::{HashMap, HashSet, VecDeque},
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::{Arc, Weak},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
-use tokio::sync::mpsc;
-use tracing::{debug, error, info, trace};
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore, mpsc};
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, trace, warn};
 
 use async_trait::async_trait;
 
-const PING_INTERVAL: u64 = 20;
+const PING_INTERVAL: Duration = Duration::from_secs(20);
 const MAX_PENDING_PINGS: usize = 3;
+const DRAIN_FILE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// URL schemes of the streamable HTTP transport
+const STREAMABLE_HTTP_SCHEMES: &[&str] = &["http", "https"];
+const SUBSCRIPTION_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// longest wait between two failed attempts to subscribe again
+const MAX_RESUBSCRIBE_BACKOFF: Duration = Duration::from_secs(60);
+/// name of the id of the initialize request replayed by the proxy after a
+/// reconnection
+const REINITIALIZE: &str = "reinitialize";
+/// name of the id of the initialize request replayed by the proxy to a
+/// logical MCP server selected by the prefix of a method
+const LOGICAL_INITIALIZE: &str = "initialize-logical";
+/// session metadata key of the deadline set by the client
+const SESSION_DEADLINE_KEY: &str = "mcp-proxy-deadline";
+/// session metadata key of the logical MCP server selected by the client
+const LOGICAL_SERVER_KEY: &str = "mcp-proxy-server";
+/// maximum time to warm a connection of the MCP pool
+const WARM_UP_TIMEOUT: Duration = Duration::from_secs(10);
+/// attempts to subscribe the proxy name at startup
+pub const DEFAULT_SUBSCRIBE_ATTEMPTS: u32 = 5;
+/// wait after the first failed attempt to subscribe at startup, doubled at
+/// every further failure
+const SUBSCRIBE_RETRY_BACKOFF: Duration = Duration::from_millis(500);
+/// time given to the admin connections to end when the proxy stops
+pub const DEFAULT_ADMIN_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+/// time given to the consumers of the event socket to receive the last
+/// events when the proxy stops
+const EVENT_SOCKET_STOP_TIMEOUT: Duration = Duration::from_secs(1);
+const HANDSHAKE_RETRY_DELAY: Duration = Duration::from_millis(500);
+const SERVICE_RUN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// TCP keepalive settings for the socket of the MCP connection
+///
+/// The proxy pings are exchanged with the SLIM client only, so they generate
+/// no traffic on the MCP connection. A long lived SSE stream with no events
+/// can therefore stay idle long enough to be reaped by a NAT or firewall
+/// even if the session is still alive. TCP keepalive probes prevent this
+/// without involving the MCP server.
+#[derive(Clone, Debug)]
+pub struct TcpKeepalive {
+    /// idle time before the first probe is sent
+    pub idle: Duration,
+    /// interval between two probes
+    pub interval: Duration,
+    /// number of unanswered probes before the connection is dropped
+    pub retries: u32,
+}
+
+/// Lowest TLS version accepted on the MCP server connections
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum MinTlsVersion {
+    #[value(name = "1.2")]
+    Tls1_2,
+    #[value(name = "1.3")]
+    Tls1_3,
+}
+
+impl MinTlsVersion {
+    fn to_reqwest(self) -> reqwest::tls::Version {
+        match self {
+            MinTlsVersion::Tls1_2 => reqwest::tls::Version::TLS_1_2,
+            MinTlsVersion::Tls1_3 => reqwest::tls::Version::TLS_1_3,
+        }
+    }
+}
+
+/// HTTP version of the connections to the MCP servers
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum HttpVersion {
+    /// HTTP/2 when the server offers it in the TLS handshake, HTTP/1.1
+    /// otherwise and on plain connections
+    #[default]
+    Auto,
+    /// HTTP/1.1 only
+    Http1,
+    /// HTTP/2 only, without upgrade on plain connections (h2c)
+    Http2,
+}
+
+impl HttpVersion {
+    /// Protocols offered in the TLS handshake
+    fn alpn_protocols(self) -> Vec<Vec<u8>> {
+        match self {
+            HttpVersion::Auto => vec![b"h2".to_vec(), b"http/1.1".to_vec()],
+            HttpVersion::Http1 => vec![b"http/1.1".to_vec()],
+            HttpVersion::Http2 => vec![b"h2".to_vec()],
+        }
+    }
+}
+
+impl Default for TcpKeepalive {
+    fn default() -> Self {
+        Self {
+            idle: Duration::from_secs(30),
+            interval: Duration::from_secs(10),
+            retries: 3,
+        }
+    }
+}
+
+/// How the pings sent by the SLIM client are handled
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ClientPingMode {
+    /// Answer the ping at the proxy with an empty result
+    #[default]
+    Answer,
+    /// Forward the ping to the MCP server
+    Forward,
+}
+
+impl ClientPingMode {
+    /// Response of the proxy to a message of the client, if it is a ping the
+    /// proxy answers on its own
+    fn answer(self, msg: &ClientJsonRpcMessage) -> Option<ServerJsonRpcMessage> {
+        match msg {
+            JsonRpcMessage::Request(JsonRpcRequest {
+                id,
+                request: ClientRequest::PingRequest(_),
+                ..
+            }) if self == ClientPingMode::Answer => {
+                Some(ServerJsonRpcMessage::Response(JsonRpcResponse {
+                    jsonrpc: JsonRpcVersion2_0,
+                    id: id.clone(),
+                    result: ServerResult::empty(()),
+                }))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// How the `roots/list` requests of the MCP server are handled
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum RootsMode {
+    /// Forward the request to the client and its response to the MCP server
+    #[default]
+    Forward,
+    /// Answer the request at the proxy with an empty list of roots
+    Answer,
+}
+
+/// How the `elicitation/create` requests of the MCP server are handled
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ElicitationMode {
+    /// Forward the request to the client and its response to the MCP server
+    #[default]
+    Forward,
+    /// Decline the request at the proxy, for clients that cannot ask their user
+    Decline,
+}
+
+/// Type of the ids of the pings sent to the clients
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum PingIdFormat {
+    /// Random 64-bit integer
+    Number,
+    /// Random positive integer below 2^53, kept exact by the clients storing
+    /// the ids as floating point numbers
+    #[default]
+    SafeNumber,
+    /// Random string, for clients that cannot represent every 64-bit integer
+    String,
+}
+
+/// Largest integer exactly represented by a double, 2^53 - 1
+const MAX_SAFE_INTEGER: i64 = (1 << 53) - 1;
+
+impl PingIdFormat {
+    fn new_id(self) -> RequestId {
+        let index = rand::random::<i64>();
+        match self {
+            PingIdFormat::Number => Number(index),
+            PingIdFormat::SafeNumber => Number(rand::random_range(1..=MAX_SAFE_INTEGER)),
+            PingIdFormat::String => NumberOrString::String(format!("slim-ping-{:x}", index).into()),
+        }
+    }
+}
+
+/// Which message of the client sets the SLIM connection the session answers
+/// on
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ReturnPath {
+    /// The first message received
+    #[default]
+    FirstMessage,
+    /// The initialize request: the messages received before it, possibly out
+    /// of order, only set the connection until it arrives
+    Initialize,
+}
+
+/// SLIM connection of the client the session answers on, set by the messages
+/// of the client with the [`ReturnPath`] policy
+#[derive(Debug)]
+struct ReturnRoute {
+    policy: ReturnPath,
+    conn: Option<u64>,
+    /// whether a later message can no longer move the answers to its connection
+    settled: bool,
+}
+
+/// What a message of the client did to the connection the session answers on
+#[derive(Debug, PartialEq, Eq)]
+enum RouteUpdate {
+    /// the first message of the session set it
+    Set,
+    Same,
+    /// the message was received on another connection, the answers stay on
+    /// the expected one
+    Diverging {
+        expected: u64,
+    },
+}
+
+impl ReturnRoute {
+    fn new(policy: ReturnPath) -> Self {
+        Self {
+            policy,
+            conn: None,
+            settled: false,
+        }
+    }
+
+    /// A message of the client received on the connection
+    fn received(&mut self, conn: u64) -> RouteUpdate {
+        match self.conn {
+            None => {
+                self.conn = Some(conn);
+                self.settled = self.policy == ReturnPath::FirstMessage;
+                RouteUpdate::Set
+            }
+            Some(expected) if expected != conn => RouteUpdate::Diverging { expected },
+            Some(_) => RouteUpdate::Same,
+        }
+    }
+
+    /// The initialize request of the client received on the connection, which
+    /// settles the route. Returns the previous connection if the answers move
+    /// to this one.
+    fn initialize_received(&mut self, conn: u64) -> Option<u64> {
+        if std::mem::replace(&mut self.settled, true) {
+            return None;
+        }
+        let previous = self.conn.replace(conn);
+        previous.filter(|previous| *previous != conn)
+    }
+}
+
+/// What to do with a message from the MCP server that cannot be interpreted
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum BadServerMessagePolicy {
+    /// Forward the message to the client as it is
+    #[default]
+    Forward,
+    /// Drop the message and log an error
+    Drop,
+    /// Close the session
+    Close,
+}
+
+/// What to do with a client request reusing the id of a request still
+/// waiting for a response
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum DuplicateRequestIdPolicy {
+    /// Forward the request to the MCP server
+    #[default]
+    Allow,
+    /// Answer the request with an error without forwarding it
+    Reject,
+}
+
+/// What to do when the SLIM app stops notifying new sessions and messages
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum StreamEndPolicy {
+    /// Stop the proxy
+    #[default]
+    Shutdown,
+    /// Create the app again and subscribe, retrying with a backoff
+    Recover,
+}
+
+/// What to do with a response of the MCP server whose id matches no request
+/// forwarded by the session
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum UnknownResponsePolicy {
+    /// Forward the response to the client
+    #[default]
+    Forward,
+    /// Forward the response, logging and counting it as an anomaly
+    Count,
+    /// Drop the response, logging and counting it as an anomaly
+    Drop,
+}
+
+/// What to do with the messages from the MCP server when the queue towards a
+/// congested SLIM client is full
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OverloadPolicy {
+    /// Wait for room in the queue, which stops reading from the MCP server
+    #[default]
+    Backpressure,
+    /// Drop the message
+    DropNewest,
+    /// Drop the message and answer the client request with an error
+    DropNotify,
+    /// Close the session of the slow client
+    Disconnect,
+}
+
+/// Why a session handler ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CloseReason {
+    /// the SLIM client closed the session
+    ClientClosed,
+    /// the SLIM session failed, e.g. the client disappeared
+    SessionError,
+    /// the SLIM session was dropped while in use
+    SessionDropped,
+    /// the MCP server closed the stream
+    ServerClosed,
+    /// the MCP server address could not be discovered
+    DiscoveryFailed,
+    /// the MCP server closed the connection before answering `initialize`
+    HandshakeRejected,
+    /// the MCP server serves the legacy HTTP+SSE transport
+    LegacySseServer,
+    /// the MCP server sent a message that cannot be interpreted
+    BadServerMessage,
+    /// the client did not answer the pings
+    MissedPings,
+    /// the ping timer reported a failure
+    TimerFailed,
+    /// the client did not keep up with the MCP server messages
+    SlowClient,
+    /// the SLIM connection to the client is gone
+    NotConnected,
+    /// the proxy could not serve the session
+    InternalError,
+    /// the session handler panicked
+    Panicked,
+    /// the proxy shed the session to stay below its memory limit
+    MemoryPressure,
+    /// the proxy was full and closed the session for a client with a higher
+    /// priority
+    Preempted,
+    /// the session reached its deadline
+    Deadline,
+    /// the MCP server answered with an error configured to close the session
+    ServerError,
+    /// a message could not be recorded in the strict write-ahead log
+    WalFailed,
+    /// the MCP server could not be reached again after an error configured
+    /// to reconnect
+    ReconnectFailed,
+}
+
+impl CloseReason {
+    fn as_str(self) -> &'static str {
+        match self {
+            CloseReason::ClientClosed => "client_closed",
+            CloseReason::SessionError => "session_error",
+            CloseReason::SessionDropped => "session_dropped",
+            CloseReason::ServerClosed => "server_closed",
+            CloseReason::DiscoveryFailed => "discovery_failed",
+            CloseReason::HandshakeRejected => "handshake_rejected",
+            CloseReason::LegacySseServer => "legacy_sse_server",
+            CloseReason::BadServerMessage => "bad_server_message",
+            CloseReason::MissedPings => "missed_pings",
+            CloseReason::TimerFailed => "timer_failed",
+            CloseReason::SlowClient => "slow_client",
+            CloseReason::NotConnected => "not_connected",
+            CloseReason::InternalError => "internal_error",
+            CloseReason::Panicked => "panicked",
+            CloseReason::MemoryPressure => "memory_pressure",
+            CloseReason::Preempted => "preempted",
+            CloseReason::Deadline => "deadline",
+            CloseReason::ServerError => "server_error",
+            CloseReason::WalFailed => "wal_failed",
+            CloseReason::ReconnectFailed => "reconnect_failed",
+        }
+    }
+
+    /// The client can open a new session right away
+    fn retryable(self) -> bool {
+        matches!(self, CloseReason::MemoryPressure | CloseReason::Preempted)
+    }
+
+    /// The client went away, the MCP session may still be resumed
+    fn client_gone(self) -> bool {
+        matches!(
+            self,
+            CloseReason::ClientClosed
+                | CloseReason::SessionError
+                | CloseReason::NotConnected
+                | CloseReason::MissedPings
+        )
+    }
+
+    /// The client is told about the end of the session even without the
+    /// close diagnostics
+    fn always_notified(self) -> bool {
+        self.retryable() || self == CloseReason::Deadline
+    }
+}
 
 /// Identity configuration for authentication
 pub enum IdentityConfig {
@@ -46,19 +499,35 @@ pub enum IdentityConfig {
     },
 }
 
+/// Event of the ping timer delivered to the session task
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PingTimerEvent {
+    /// time to send a ping
+    Timeout,
+    /// the timer gave up, no more pings will be scheduled
+    Failure,
+}
+
 struct PingTimerObserver {
-    tx_proxy_session: mpsc::Sender<u32>,
+    tx_proxy_session: mpsc::Sender<PingTimerEvent>,
 }
 
 #[async_trait]
 impl TimerObserver for PingTimerObserver {
     async fn on_timeout(&self, timer_id: u32, timeouts: u32) {
         trace!(n_timeouts = %timeouts, %timer_id, "timeout for rtx, retry");
-        let _ = self.tx_proxy_session.send(timer_id).await;
+        // a backlog of pings means the session is stuck: skip this one rather
+        // than block the timer
+        if let Err(mpsc::error::TrySendError::Full(_)) =
+            self.tx_proxy_session.try_send(PingTimerEvent::Timeout)
+        {
+            warn!(%timer_id, "ping timer events not consumed, skipping a ping");
+        }
     }
 
-    async fn on_failure(&self, _timer_id: u32, _timeouts: u32) {
-        panic!("timer on failure, this should never happen");
+    async fn on_failure(&self, timer_id: u32, timeouts: u32) {
+        error!(n_timeouts = %timeouts, %timer_id, "ping timer failure");
+        let _ = self.tx_proxy_session.send(PingTimerEvent::Failure).await;
     }
 
     async fn on_stop(&self, _timer_id: u32) {
@@ -67,7 +536,7 @@ impl TimerObserver for PingTimerObserver {
     }
 }
 
-#[derive(Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 struct SessionId {
     /// name of the source of the packet
     source: Name,
@@ -75,300 +544,7155 @@ struct SessionId {
     id: u32,
 }
 
-pub struct Proxy {
-    name: Name,
+/// Settings used by every session to connect to the MCP server
+#[derive(Clone, Debug)]
+struct SessionConfig {
     mcp_server: String,
-    // retain mapping for active session ids to help with cleanup / debugging
-    connections: HashMap<SessionId, ()>,
+    /// MCP server used when `mcp_server` cannot be reached
+    mcp_server_fallback: Option<String>,
+    /// resolves the host and port of `mcp_server` for every connection
+    discovery: Option<Discovery>,
+    /// bound of the resolution of the MCP server host before each
+    /// connection, left to the HTTP client if `None`
+    dns_timeout: Option<Duration>,
+    /// addresses found by the resolutions bounded by `dns_timeout`
+    resolved_hosts: Arc<ResolvedHosts>,
+    /// MCP server receiving a copy of the client messages
+    shadow_mcp_server: Option<String>,
+    /// headers added to the MCP connection depending on the client name
+    source_headers: Vec<SourceHeader>,
+    tcp_keepalive: Option<TcpKeepalive>,
+    min_tls_version: Option<MinTlsVersion>,
+    http_version: HttpVersion,
+    /// TLS of the MCP connections accepting the pinned certificates only, or
+    /// presenting a client certificate
+    tls: Option<Arc<McpTls>>,
+    /// local address of the connections to the MCP server
+    bind_address: Option<IpAddr>,
+    redactor: Redactor,
+    client_ping_mode: ClientPingMode,
+    roots_mode: RootsMode,
+    elicitation_mode: ElicitationMode,
+    /// interval between pings to the client, `None` if pings are disabled
+    ping_interval: Option<Duration>,
+    max_pending_pings: usize,
+    ping_id_format: PingIdFormat,
+    /// message of the client setting the connection of the answers
+    return_path: ReturnPath,
+    /// decides whether each due ping is sent
+    ping_policy: Arc<dyn PingPolicy>,
+    /// send the pings before any pending data
+    ping_priority: bool,
+    parse_options: ParseOptions,
+    /// `_meta` field carrying the SLIM session id in the forwarded requests
+    session_label_field: Option<String>,
+    /// `_meta` fields removed from the server messages, the label included
+    strip_meta_fields: Vec<String>,
+    bad_server_message_policy: BadServerMessagePolicy,
+    duplicate_request_id_policy: DuplicateRequestIdPolicy,
+    unknown_response_policy: UnknownResponsePolicy,
+    session_wal: Option<WalConfig>,
+    metrics: Arc<Metrics>,
+    /// times the handshake is retried when the MCP server drops it
+    handshake_retries: usize,
+    handshake_retry_delay: Duration,
+    /// paces the reconnections of all the sessions, unlimited if `None`
+    reconnect_limiter: Option<Arc<ReconnectLimiter>>,
+    /// actions on the errors of the MCP server, by error code
+    error_rules: Vec<ErrorRule>,
+    /// patterns looked for in the tool results
+    content_filter: Option<ContentFilter>,
+    /// messages to the client queued for a publishing task, published right
+    /// away by the session handler if `None`
+    outbound_queue_size: Option<usize>,
+    overload_policy: OverloadPolicy,
+    /// coalescing of the messages to the client, disabled if `None`
+    batching: Option<Batching>,
+    /// flow control of the messages to the clients, unlimited if `None`
+    credits: Option<Arc<CreditScheduler>>,
+    /// forward the request ids in a namespace of the session
+    namespace_request_ids: bool,
+    /// unanswered client requests allowed per session, unlimited if `None`
+    max_pending_requests: Option<usize>,
+    /// requests forwarded on the MCP connection of a session and not answered
+    /// yet, beyond which the next ones wait; unlimited if `None`
+    max_in_flight_requests: Option<usize>,
+    /// methods accepted besides the MCP ones, the request methods are not
+    /// validated if `None`
+    allowed_methods: Option<HashSet<String>>,
+    /// time after which a session is closed whatever its activity, unless
+    /// the client sets an earlier deadline
+    session_deadline: Option<Duration>,
+    /// permits of the sessions in the handshake with the MCP server,
+    /// unlimited if `None`
+    setup_permits: Option<Arc<Semaphore>>,
+    /// log the `notifications/message` of the MCP server
+    mirror_server_logs: bool,
+    /// collapses the repetitions of the same session error
+    log_throttle: Arc<LogThrottle>,
+    /// faults injected in the messages, for testing only
+    chaos: Option<Chaos>,
+    /// add the receive and forward times to the `_meta` of the messages
+    stamp_timings: bool,
+    /// tell the client why its session ended
+    send_close_diagnostics: bool,
+    /// answer the subscriptions to resources already subscribed at the proxy
+    coalesce_subscriptions: bool,
+    /// largest message read from the MCP server
+    read_buffer_bytes: usize,
+    /// connections to the MCP server warmed in advance
+    mcp_pool: Option<Arc<McpPool>>,
+    /// MCP connections kept for the clients coming back, disabled if `None`
+    affinity: Option<Arc<SessionAffinity>>,
+    /// addresses of the MCP servers the clients can select, by name
+    logical_servers: HashMap<String, String>,
+    unknown_server_policy: UnknownServerPolicy,
+    /// records the capabilities of the MCP servers for the admin endpoint
+    admin: Arc<AdminState>,
+    /// lifecycle and session events published on the event socket
+    events: Events,
 }
 
-/// Spawn the async task that bridges a SLIM session with the MCP server.
-fn start_proxy_session(ctx: SessionContext, mcp_server: String) {
-    let session_id_val = ctx.session_arc().unwrap().id();
-    ctx.spawn_receiver(move |mut rx, weak| async move {
-        info!(%session_id_val, "Session handler task started");
+impl SessionConfig {
+    /// Build the HTTP client used by the MCP transport of a client. Each
+    /// session has its own cookie store, so that the cookies set by the MCP
+    /// server, e.g. a session cookie, are sent back on the same session only.
+    fn http_client(&self, client_name: &Name) -> reqwest::Result<reqwest::Client> {
+        let headers = headers::headers_for(&self.source_headers, client_name);
+        if !headers.is_empty() {
+            debug!(client = %client_name, headers = ?headers.keys().collect::<Vec<_>>(), "adding headers to MCP connection");
+        }
+        let mut builder = reqwest::Client::builder()
+            .default_headers(headers)
+            .cookie_store(true);
+        if let Some(keepalive) = &self.tcp_keepalive {
+            builder = builder
+                .tcp_keepalive(keepalive.idle)
+                .tcp_keepalive_interval(keepalive.interval)
+                .tcp_keepalive_retries(keepalive.retries);
+        }
+        builder = match self.http_version {
+            HttpVersion::Auto => builder,
+            HttpVersion::Http1 => builder.http1_only(),
+            HttpVersion::Http2 => builder.http2_prior_knowledge(),
+        };
+        if let Some(version) = self.min_tls_version {
+            // a server below the floor fails the TLS handshake of the first request
+            builder = builder
+                .https_only(true)
+                .min_tls_version(version.to_reqwest());
+        }
+        if let Some(tls) = &self.tls {
+            // replaces the TLS settings of the builder, the version floor included
+            builder = builder.https_only(true).use_preconfigured_tls(tls.config());
+        }
+        if let Some(addr) = self.bind_address {
+            builder = builder.local_address(addr);
+        }
+        if self.dns_timeout.is_some() {
+            // the addresses resolved ahead of the connection, within the timeout
+            builder = builder.dns_resolver(self.resolved_hosts.clone());
+        }
+        builder.build()
+    }
 
-        let binding = weak.upgrade();
-        let remote_name = binding.as_ref().unwrap().dst();
+    async fn mcp_transport(
+        &self,
+        client: BoundedClient,
+    ) -> Result<StreamableHttpClientTransport<BoundedClient>, DiscoveryError> {
+        let uri = match &self.discovery {
+            Some(discovery) => {
+                let url = reqwest::Url::parse(&self.mcp_server)
+                    .expect("MCP server URL validated by the builder");
+                discovery.resolve(&url).await?.to_string()
+            }
+            None => self.mcp_server.clone(),
+        };
+        if let Some(timeout) = self.dns_timeout {
+            self.resolve_host(&uri, timeout).await?;
+        }
+        Ok(StreamableHttpClientTransport::with_client(
+            client,
+            StreamableHttpClientTransportConfig::with_uri(uri),
+        ))
+    }
 
-        let mut incoming_conn_id: Option<u64> = None;
+    /// Resolve the host of the MCP server ahead of the connection, for the
+    /// HTTP client to connect to the addresses found
+    async fn resolve_host(&self, uri: &str, timeout: Duration) -> Result<(), DiscoveryError> {
+        let url = reqwest::Url::parse(uri).expect("MCP server URL validated by the builder");
+        // nothing to resolve for an IP address
+        let Some(host) = url.domain() else {
+            return Ok(());
+        };
+        let port = url.port_or_known_default().unwrap_or_default();
+        let started = Instant::now();
+        let addrs: Vec<SocketAddr> =
+            match tokio::time::timeout(timeout, tokio::net::lookup_host((host, port))).await {
+                Ok(Ok(addrs)) => addrs.collect(),
+                Ok(Err(source)) => {
+                    return Err(DiscoveryError::Dns {
+                        host: host.to_string(),
+                        source,
+                    });
+                }
+                Err(_) => {
+                    return Err(DiscoveryError::DnsTimeout {
+                        host: host.to_string(),
+                        timeout,
+                    });
+                }
+            };
+        let elapsed = started.elapsed();
+        self.metrics.session_phases.observe("dns", elapsed);
+        let Some(addr) = addrs.first() else {
+            return Err(DiscoveryError::NoAddress(host.to_string()));
+        };
+        debug!(%host, %addr, addresses = addrs.len(), ?elapsed, "MCP server host resolved");
+        self.resolved_hosts.insert(host, addrs);
+        Ok(())
+    }
 
-        // Connect to MCP server
-        info!("Connecting to MCP server: {}", mcp_server);
-        let mut transport = StreamableHttpClientTransport::from_uri(mcp_server.clone());
+    fn fallback_transport(
+        &self,
+        client: BoundedClient,
+    ) -> Option<StreamableHttpClientTransport<BoundedClient>> {
+        let fallback = self.mcp_server_fallback.as_ref()?;
+        warn!(fallback = %RedactedUrl(fallback), "primary MCP server unavailable, switch to the fallback");
+        Some(StreamableHttpClientTransport::with_client(
+            client,
+            StreamableHttpClientTransportConfig::with_uri(fallback.clone()),
+        ))
+    }
 
-        // Ping timer setup
-        let (tx_timer, mut rx_timer) = mpsc::channel(128);
-        let ping_timer_observer = Arc::new(PingTimerObserver { tx_proxy_session: tx_timer });
-        let mut ping_timer = Timer::new(1, TimerType::Constant, Duration::from_secs(PING_INTERVAL), None, None);
-        ping_timer.start(ping_timer_observer);
-        let mut pending_pings: HashSet<i64> = HashSet::new();
+    /// Connect to the MCP server and complete the handshake on behalf of a
+    /// future session
+    async fn warm_connection(&self, name: &Name) -> Result<WarmConnection, String> {
+        let client = self.http_client(name).map_err(|e| e.to_string())?;
+        let mut transport = self
+            .mcp_transport(BoundedClient::new(client, self.read_buffer_bytes))
+            .await
+            .map_err(|e| e.to_string())?;
+        let id = RequestId::Number(0);
+        let initialize =
+            ClientRequest::InitializeRequest(InitializeRequest::new(ClientInfo::default()));
+        let initialize_result = complete_handshake(
+            &mut transport,
+            ClientJsonRpcMessage::request(initialize, id.clone()),
+            &id,
+        )
+        .await?;
+        if let ServerResult::InitializeResult(result) = &initialize_result {
+            self.record_capabilities(false, result);
+        }
+        self.admin.record_backend_success(&self.backend(false));
+        Ok(WarmConnection {
+            transport,
+            initialize_result,
+            on_fallback: false,
+        })
+    }
+
+    /// Connect a session to the logical MCP server selected by the prefix of
+    /// a method, replaying the initialize request of the client on it
+    async fn logical_connection(
+        &self,
+        client_name: &Name,
+        server: &str,
+        initialize: Option<&ClientJsonRpcMessage>,
+    ) -> Result<StreamableHttpClientTransport<BoundedClient>, String> {
+        let Some(JsonRpcMessage::Request(request)) = initialize else {
+            return Err("session not initialized".into());
+        };
+        let url = &self.logical_servers[server];
+        let backend = RedactedUrl(&normalize_mcp_url(url)).to_string();
+        let client = self.http_client(client_name).map_err(|e| e.to_string())?;
+        let mut transport = StreamableHttpClientTransport::with_client(
+            BoundedClient::new(client, self.read_buffer_bytes),
+            StreamableHttpClientTransportConfig::with_uri(url.clone()),
+        );
+        let mut replay = request.clone();
+        replay.id = ids::internal_id(LOGICAL_INITIALIZE);
+        let id = replay.id.clone();
+        let handshake = complete_handshake(&mut transport, JsonRpcMessage::Request(replay), &id);
+        let result = match tokio::time::timeout(WARM_UP_TIMEOUT, handshake).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(e)) => {
+                self.admin.record_backend_failure(&backend, e.clone());
+                return Err(e);
+            }
+            Err(_) => {
+                self.admin
+                    .record_backend_failure(&backend, "handshake timeout".into());
+                return Err("handshake timeout".into());
+            }
+        };
+        if let ServerResult::InitializeResult(result) = &result {
+            self.admin.record_capabilities(backend.clone(), result);
+        }
+        self.admin.record_backend_success(&backend);
+        Ok(transport)
+    }
 
+    /// Keep the MCP pool full, warming a new connection whenever one is taken
+    /// or fails its health check, run every `check_interval`
+    async fn fill_mcp_pool(
+        self,
+        name: Name,
+        tx: mpsc::Sender<WarmConnection>,
+        check_interval: Duration,
+    ) {
+        let mut backoff = SUBSCRIBE_RETRY_BACKOFF;
+        let mut health_check =
+            tokio::time::interval_at(tokio::time::Instant::now() + check_interval, check_interval);
         loop {
-            tokio::select! {
-                next_from_session = rx.recv() => {
-                    match next_from_session {
-                        None => {
-                            debug!("session channel closed");
-                            ping_timer.stop();
-                            let _ = transport.close().await;
-                            break;
-                        }
-                        Some(Ok(message)) => {
-                            if incoming_conn_id.is_none() {
-                                // derive remote routing info from first message
-                                incoming_conn_id = Some(message.get_incoming_conn());
-                                debug!("Initialized remote routing: name={:?} conn_id={:?}", remote_name, incoming_conn_id);
-                            }
-                            let payload = match message.get_payload() { Some(p) => p.as_application_payload().unwrap().blob.to_vec(), None => { error!("empty payload"); continue; } };
-                            let jsonrpcmsg: JsonRpcMessage<ClientRequest, ClientResult, ClientNotification> = match serde_json::from_slice(&payload) {
-                                Ok(v) => v,
-                                Err(e) => { error!("error parsing message: {}", e); continue; }
-                            };
-                            debug!("Processing message type: {:?}", std::mem::discriminant(&jsonrpcmsg));
-                            match jsonrpcmsg {
-                                JsonRpcMessage::Response(json_rpc_response) => {
-                                    debug!("received response message: {:?}", json_rpc_response);
-                                    match json_rpc_response.result {
-                                        EmptyResult(_) => {
-                                            match json_rpc_response.id {
-                                                Number(index) => {
-                                                    if pending_pings.contains(&index) {
-                                                        debug!("received ping response id {:?}, clearing pending pings", index);
-                                                        pending_pings.clear();
-                                                    } else {
-                                                        debug!("forward response to MCP server {:?}", json_rpc_response);
-                                                        if let Err(e) = transport.send(rmcp::model::JsonRpcMessage::Response(json_rpc_response.clone())).await {
-                                                            error!("failed sending response to MCP server: {:?}, response_id={:?}", e, json_rpc_response.id);
-                                                        }
-                                                    }
-                                                }
-                                                _ => {
-                                                    debug!("forward response to MCP server {:?}", json_rpc_response);
-                                                    if let Err(e) = transport.send(rmcp::model::JsonRpcMessage::Response(json_rpc_response.clone())).await {
-                                                        error!("failed sending response to MCP server: {:?}, response_id={:?}", e, json_rpc_response.id);
-                                                    }
-                                                }
-                                            }
-                                        }
-                                        _ => {
-                                            debug!("forward response to MCP server {:?}", json_rpc_response);
-                                            if let Err(e) = transport.send(rmcp::model::JsonRpcMessage::Response(json_rpc_response.clone())).await {
-                                                error!("failed sending response to MCP server: {:?}, response_id={:?}", e, json_rpc_response.id);
-                                            }
-                                        }
-                                    }
-                                }
-                                _ => {
-                                    debug!("forward message to MCP server {:?}", jsonrpcmsg);
-
-                                    if let Err(e) = transport.send(jsonrpcmsg.clone()).await {
-                                        error!("failed forwarding message to MCP server: {:?}, message_type={}", e, match jsonrpcmsg {
-                                            JsonRpcMessage::Request(_) => "Request",
-                                            JsonRpcMessage::Response(_) => "Response",
-                                            JsonRpcMessage::Notification(_) => "Notification",
-                                            JsonRpcMessage::Error(_) => "Error",
-                                        });
-                                    }
-                                }
-                            }
-                        }
-                        Some(Err(e)) => {
-                            error!("error receiving session message: {:?}", e);
-                            ping_timer.stop();
-                            let _ = transport.close().await;
-                            break;
-                        }
-                    }
+            // wait for room in the pool, the proxy is gone when the pool is closed
+            let permit = tokio::select! {
+                permit = tx.reserve() => match permit {
+                    Ok(permit) => permit,
+                    Err(_) => break,
+                },
+                _ = health_check.tick() => {
+                    self.check_mcp_pool(&tx).await;
+                    continue;
                 }
-                next_from_mcp = transport.receive() => {
-                    match next_from_mcp {
-                        None => {
-                            info!("end of MCP stream");
-                            ping_timer.stop();
-                            let _ = transport.close().await;
-                            break;
-                        }
-                        Some(msg) => {
-                            debug!("Received message from MCP server, message_type={}", match &msg {
-                                JsonRpcMessage::Request(_) => "Request",
-                                JsonRpcMessage::Response(_) => "Response",
-                                JsonRpcMessage::Notification(_) => "Notification",
-                                JsonRpcMessage::Error(_) => "Error",
-                            });
-                            if let Some(conn) = incoming_conn_id {
-                                if let Some(session_arc) = weak.upgrade() {
-                                    let vec = serde_json::to_vec(&msg).unwrap();
-                                    if let Err(e) = session_arc.publish_to(remote_name, conn, vec, None, None).await { error!("error sending MCP->client message: {}", e); }
-                                } else { debug!("session dropped before sending MCP message"); break; }
-                            } else {
-                                debug!("dropping MCP message: remote not initialized yet");
-                            }
-                        }
-                    }
+            };
+            match tokio::time::timeout(WARM_UP_TIMEOUT, self.warm_connection(&name)).await {
+                Ok(Ok(warm)) => {
+                    debug!("MCP connection warmed for the pool");
+                    permit.send(warm);
+                    backoff = SUBSCRIBE_RETRY_BACKOFF;
+                    continue;
                 }
-                timer_ping = rx_timer.recv() => {
-                    match timer_ping {
-                        None => { debug!("timer channel closed"); break; }
-                        Some(_) => {
-                            if pending_pings.len() >= MAX_PENDING_PINGS {
-                                debug!("client not replying to pings, closing");
-                                ping_timer.stop();
-                                let _ = transport.close().await;
-                                break;
-                            }
-                            if let Some(conn) = incoming_conn_id && let Some(session_arc) = weak.upgrade() {
-                                let ping_req = PingRequest { method: PingRequestMethod, extensions: Default::default()  };
-                                let index = rand::random::<i64>();
-                                pending_pings.insert(index);
-                                let req = ServerJsonRpcMessage::Request(JsonRpcRequest { jsonrpc: rmcp::model::JsonRpcVersion2_0, id: Number(index), request: rmcp::model::ServerRequest::PingRequest(ping_req) });
-                                let vec = serde_json::to_vec(&req).unwrap();
-                                if let Err(e) = session_arc.publish_to(remote_name, conn, vec, None, None).await { error!("error sending ping: {}", e); }
-                            }
-                        }
-                    }
+                Ok(Err(e)) => {
+                    warn!(retry_in = ?backoff, "error warming MCP connection: {}", e);
+                    self.admin.record_backend_failure(&self.backend(false), e);
+                }
+                Err(_) => {
+                    warn!(retry_in = ?backoff, "timeout warming MCP connection");
+                    self.admin
+                        .record_backend_failure(&self.backend(false), "handshake timeout".into());
                 }
             }
-        }
-        info!("Session handler task ended (session id={})", session_id_val);
-    });
-}
-
-impl Proxy {
-    pub fn new(name: Name, mcp_server: String) -> Self {
-        Self {
-            name,
-            mcp_server,
-            connections: HashMap::new(),
+            drop(permit);
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_RESUBSCRIBE_BACKOFF);
         }
     }
 
-    pub async fn start(
-        &mut self,
-        service: slim_service::Service,
-        identity_config: IdentityConfig,
-        _drain_timeout: std::time::Duration,
-    ) {
-        let (provider, verifier): (AuthProvider, AuthVerifier) = match identity_config {
-            IdentityConfig::SharedSecret(secret) => {
-                info!("Using shared-secret authentication");
-                let provider =
-                    SharedSecret::new("proxy", &secret).expect("Failed to create SharedSecret");
-                let verifier =
-                    SharedSecret::new("proxy", &secret).expect("Failed to create SharedSecret");
-                (
-                    AuthProvider::shared_secret(provider),
-                    AuthVerifier::shared_secret(verifier),
-                )
+    /// Check the idle connections of the MCP pool one at a time, so that the
+    /// others can still be taken, closing the ones the MCP server no longer
+    /// answers on
+    async fn check_mcp_pool(&self, tx: &mpsc::Sender<WarmConnection>) {
+        let Some(pool) = &self.mcp_pool else {
+            return;
+        };
+        for _ in 0..pool.idle() {
+            let Some(mut warm) = pool.take() else {
+                break;
+            };
+            if !warm.is_healthy().await {
+                warn!("idle MCP connection failed its health check, replacing it");
+                self.admin.record_backend_failure(
+                    &self.backend(warm.on_fallback),
+                    "idle connection failed its health check".into(),
+                );
+                let _ = warm.transport.close().await;
+                continue;
             }
-            IdentityConfig::Spire {
-                socket_path,
-                target_spiffe_id,
-                jwt_audiences,
-            } => {
-                info!("Using SPIRE authentication");
+            if let Err(e) = tx.try_send(warm) {
+                let _ = e.into_inner().transport.close().await;
+            }
+        }
+    }
 
-                // Build provider manager and verifier manager
-                let mut provider_builder = SpireIdentityManager::builder();
-                let mut verifier_builder = SpireIdentityManager::builder();
-                if let Some(path) = &socket_path {
-                    provider_builder = provider_builder.with_socket_path(path.clone());
-                    verifier_builder = verifier_builder.with_socket_path(path.clone());
+    /// Reconnect to the MCP server and send the initialize request again,
+    /// until it is accepted or the retries are exhausted. The fallback server,
+    /// if any, is tried last and kept for the rest of the session. A server
+    /// serving the legacy HTTP+SSE transport is not retried.
+    async fn retry_handshake(
+        &self,
+        client: &BoundedClient,
+        transport: &mut StreamableHttpClientTransport<BoundedClient>,
+        initialize: &ClientJsonRpcMessage,
+        attempts: &mut usize,
+        on_fallback: &mut bool,
+    ) -> bool {
+        while *attempts < self.handshake_retries && !client.legacy_sse() {
+            *attempts += 1;
+            warn!(
+                attempt = *attempts,
+                "MCP server dropped the handshake, retry in {:?}", self.handshake_retry_delay
+            );
+            let _ = transport.close().await;
+            tokio::time::sleep(self.handshake_retry_delay).await;
+            self.reconnect_permit().await;
+            match self.mcp_transport(client.clone()).await {
+                Ok(new_transport) => *transport = new_transport,
+                Err(e) => {
+                    warn!("error discovering MCP server: {}", e);
+                    self.admin
+                        .record_backend_failure(&self.backend(false), e.to_string());
+                    continue;
                 }
-                if let Some(spiffe_id) = &target_spiffe_id {
-                    provider_builder = provider_builder.with_target_spiffe_id(spiffe_id.clone());
-                    verifier_builder = verifier_builder.with_target_spiffe_id(spiffe_id.clone());
+            }
+            match transport.send(initialize.clone()).await {
+                Ok(()) => return true,
+                Err(e) => {
+                    warn!("failed sending initialize request to MCP server: {:?}", e);
+                    self.admin
+                        .record_backend_failure(&self.backend(false), e.to_string());
                 }
-                if !jwt_audiences.is_empty() {
-                    provider_builder = provider_builder.with_jwt_audiences(jwt_audiences.clone());
-                    verifier_builder = verifier_builder.with_jwt_audiences(jwt_audiences.clone());
+            }
+        }
+        if !*on_fallback && let Some(new_transport) = self.fallback_transport(client.clone()) {
+            self.reconnect_permit().await;
+            *on_fallback = true;
+            let _ = transport.close().await;
+            *transport = new_transport;
+            match transport.send(initialize.clone()).await {
+                Ok(()) => return true,
+                Err(e) => {
+                    warn!(
+                        "failed sending initialize request to fallback MCP server: {:?}",
+                        e
+                    );
+                    self.admin
+                        .record_backend_failure(&self.backend(true), e.to_string());
                 }
-
-                let mut provider_manager = provider_builder
-                    .build()
-                    .expect("Failed to build SpireIdentityManager for provider");
-                provider_manager
-                    .initialize()
-                    .await
-                    .expect("Failed to initialize SpireIdentityManager for provider");
-
-                let mut verifier_manager = verifier_builder
-                    .build()
-                    .expect("Failed to build SpireIdentityManager for verifier");
-                verifier_manager
-                    .initialize()
-                    .await
-                    .expect("Failed to initialize SpireIdentityManager for verifier");
-
-                (
-                    AuthProvider::spire(provider_manager),
-                    AuthVerifier::spire(verifier_manager),
-                )
             }
-        };
+        }
+        false
+    }
 
-        let (app, mut slim_rx) = service
-            .create_app(&self.name, provider, verifier)
-            .expect("failed to create app");
+    /// Connect the session to a logical MCP server instead of the MCP
+    /// server. The fallback, discovery, shadow, pool and affinity of the MCP
+    /// server do not apply to it.
+    fn use_logical_server(&mut self, url: String) {
+        self.mcp_server = url;
+        self.mcp_server_fallback = None;
+        self.discovery = None;
+        self.shadow_mcp_server = None;
+        self.mcp_pool = None;
+        self.affinity = None;
+    }
 
-        // run the service - this will create all the connections provided via the config file.
-        service.run().await.unwrap();
+    /// Path of the messages to the client of a session, on the given
+    /// connection
+    fn outbound(
+        &self,
+        session: &Weak<SessionController>,
+        remote_name: &Name,
+        conn: u64,
+        credits: Option<Arc<SessionCredits>>,
+    ) -> Outbound {
+        let publisher = SessionPublisher {
+            session: session.clone(),
+            remote_name: remote_name.clone(),
+            conn,
+        };
+        match self.outbound_queue_size {
+            Some(capacity) => Outbound::spawn(
+                publisher,
+                capacity,
+                self.overload_policy,
+                self.batching,
+                credits,
+            ),
+            None => Outbound::direct(publisher),
+        }
+    }
 
-        // get the connection id
-        let conn_id = service
-            .get_connection_id(&service.config().dataplane_clients()[0].endpoint)
-            .unwrap();
+    /// Count a closed session and publish its end on the event socket
+    fn record_closed(&self, session_id: u32, reason: CloseReason) {
+        self.metrics.closed_sessions.inc(reason.as_str());
+        self.events.emit(
+            "session_closed",
+            serde_json::json!({ "sessionId": session_id, "reason": reason.as_str() }),
+        );
+    }
 
-        // subscribe for local name
-        match app.subscribe(&self.name, Some(conn_id)).await {
-            Ok(_) => {}
+    /// Address of the MCP server, the fallback one or not, on the admin
+    /// endpoint
+    fn backend(&self, on_fallback: bool) -> String {
+        let backend = match &self.mcp_server_fallback {
+            Some(fallback) if on_fallback => fallback,
+            _ => &self.mcp_server,
+        };
+        RedactedUrl(&normalize_mcp_url(backend)).to_string()
+    }
+
+    /// Keep the capabilities of the MCP server, the fallback one or not, for
+    /// the admin endpoint
+    fn record_capabilities(&self, on_fallback: bool, result: &InitializeResult) {
+        self.admin
+            .record_capabilities(self.backend(on_fallback), result);
+    }
+
+    /// Count the session as connected to the MCP server, the fallback one or
+    /// not, in place of the server it was counted on, if any
+    fn record_connection(&self, counted_on: &mut Option<bool>, on_fallback: bool) {
+        if *counted_on == Some(on_fallback) {
+            return;
+        }
+        if let Some(previous) = counted_on.replace(on_fallback) {
+            self.admin
+                .record_backend_connection(&self.backend(previous), false);
+        }
+        self.admin
+            .record_backend_connection(&self.backend(on_fallback), true);
+    }
+
+    /// Wait for the turn of the session to reconnect to the MCP server
+    async fn reconnect_permit(&self) {
+        if let Some(limiter) = &self.reconnect_limiter {
+            let started = Instant::now();
+            limiter.acquire().await;
+            let waited = started.elapsed();
+            if !waited.is_zero() {
+                debug!(?waited, "reconnection paced by the reconnect rate");
+            }
+        }
+    }
+
+    /// Open a new connection to the MCP server, the fallback if the session
+    /// is on it, and replay the initialize request of the client under the
+    /// id of the proxy. The caller closes the old connection and waits out
+    /// the backoff first.
+    async fn reconnect_after_error(
+        &self,
+        client: &BoundedClient,
+        transport: &mut StreamableHttpClientTransport<BoundedClient>,
+        initialize: Option<&ClientJsonRpcMessage>,
+        on_fallback: bool,
+    ) -> bool {
+        self.reconnect_permit().await;
+        let new_transport = if on_fallback {
+            self.fallback_transport(client.clone())
+        } else {
+            match self.mcp_transport(client.clone()).await {
+                Ok(new_transport) => Some(new_transport),
+                Err(e) => {
+                    warn!("error discovering MCP server: {}", e);
+                    self.admin
+                        .record_backend_failure(&self.backend(false), e.to_string());
+                    None
+                }
+            }
+        };
+        let Some(new_transport) = new_transport else {
+            return false;
+        };
+        *transport = new_transport;
+        let Some(JsonRpcMessage::Request(request)) = initialize else {
+            // the client never initialized, nothing to replay
+            return true;
+        };
+        let mut replay = request.clone();
+        replay.id = ids::internal_id(REINITIALIZE);
+        match transport.send(JsonRpcMessage::Request(replay)).await {
+            Ok(()) => true,
             Err(e) => {
-                panic!("an error accoured while adding a subscription {}", e);
+                warn!("failed replaying initialize request to MCP server: {:?}", e);
+                false
             }
         }
+    }
+}
 
-        info!("waiting for incoming messages");
-        loop {
+/// Send the initialize request of the given id on a new MCP connection and
+/// complete the handshake once the MCP server answers it
+async fn complete_handshake(
+    transport: &mut StreamableHttpClientTransport<BoundedClient>,
+    initialize: ClientJsonRpcMessage,
+    id: &RequestId,
+) -> Result<ServerResult, String> {
+    transport
+        .send(initialize)
+        .await
+        .map_err(|e| format!("{:?}", e))?;
+    let initialize_result = loop {
+        match transport.receive().await {
+            Some(JsonRpcMessage::Response(resp)) if resp.id == *id => break resp.result,
+            Some(JsonRpcMessage::Error(err)) if err.id == *id => {
+                return Err(format!("initialize rejected: {}", err.error.message));
+            }
+            Some(_) => {}
+            None => return Err("connection closed during the handshake".into()),
+        }
+    };
+    transport
+        .send(ClientJsonRpcMessage::notification(
+            ClientNotification::InitializedNotification(InitializedNotification::default()),
+        ))
+        .await
+        .map_err(|e| format!("{:?}", e))?;
+    Ok(initialize_result)
+}
+
+/// Log why the handshake with the MCP server failed, telling a server of the
+/// legacy HTTP+SSE transport from a server rejecting the handshake
+fn handshake_failure(client: &BoundedClient, failure: &str) -> CloseReason {
+    if client.legacy_sse() {
+        error!(
+            "MCP server serves the legacy HTTP+SSE transport, only the streamable HTTP transport is supported, closing session"
+        );
+        CloseReason::LegacySseServer
+    } else {
+        error!("{}, closing session", failure);
+        CloseReason::HandshakeRejected
+    }
+}
+
+/// Tell the client that the MCP server rejected the handshake
+fn handshake_rejected(id: RequestId) -> ServerJsonRpcMessage {
+    ServerJsonRpcMessage::Error(JsonRpcError {
+        jsonrpc: JsonRpcVersion2_0,
+        id,
+        error: ErrorData::internal_error("backend rejected handshake", None),
+    })
+}
+
+/// Tell the client that the logical MCP server its request is routed to
+/// cannot be reached
+fn logical_server_unavailable(id: RequestId, server: &str) -> ServerJsonRpcMessage {
+    ServerJsonRpcMessage::Error(JsonRpcError {
+        jsonrpc: JsonRpcVersion2_0,
+        id,
+        error: ErrorData::internal_error(format!("logical MCP server {server} unavailable"), None),
+    })
+}
+
+/// Error sent to the client in place of a tool result blocked by the content
+/// filter
+fn tool_result_blocked(id: RequestId) -> ServerJsonRpcMessage {
+    ServerJsonRpcMessage::Error(JsonRpcError {
+        jsonrpc: JsonRpcVersion2_0,
+        id,
+        error: ErrorData::internal_error("tool result blocked by content filter", None),
+    })
+}
+
+/// Log a `notifications/message` of the MCP server in the proxy log, mapping
+/// the MCP level to the closest tracing level
+fn mirror_server_log(session_id: u32, log: &LoggingMessageNotificationParam) {
+    let logger = log.logger.as_deref().unwrap_or_default();
+    let data = &log.data;
+    match log.level {
+        LoggingLevel::Debug => debug!(%session_id, %logger, "MCP server log: {}", data),
+        LoggingLevel::Info | LoggingLevel::Notice => {
+            info!(%session_id, %logger, "MCP server log: {}", data)
+        }
+        LoggingLevel::Warning => warn!(%session_id, %logger, "MCP server log: {}", data),
+        LoggingLevel::Error
+        | LoggingLevel::Critical
+        | LoggingLevel::Alert
+        | LoggingLevel::Emergency => {
+            error!(%session_id, %logger, "MCP server log: {}", data)
+        }
+    }
+}
+
+/// Method of the notification telling the client why its session ended
+const SESSION_CLOSED_METHOD: &str = "notifications/io.agntcy.slim/session_closed";
+
+/// Deadline of a session: the earliest of the deadline set by the client in
+/// the session metadata, in seconds since the Unix epoch, and the default
+/// time after the start of the session
+fn session_deadline(
+    metadata: &HashMap<String, String>,
+    default: Option<Duration>,
+) -> Option<tokio::time::Instant> {
+    let now = tokio::time::Instant::now();
+    let requested = metadata.get(SESSION_DEADLINE_KEY).and_then(|value| {
+        // a deadline out of the range of the clocks is no deadline of the client
+        let deadline = value
+            .parse::<u64>()
+            .ok()
+            .and_then(|secs| UNIX_EPOCH.checked_add(Duration::from_secs(secs)));
+        let Some(deadline) = deadline else {
+            warn!(%value, "invalid session deadline, expected seconds since the Unix epoch");
+            return None;
+        };
+        let left = deadline
+            .duration_since(SystemTime::now())
+            .unwrap_or_default();
+        let requested = now.checked_add(left);
+        if requested.is_none() {
+            warn!(%value, "session deadline too far away, ignored");
+        }
+        requested
+    });
+    let default = default.map(|d| now + d);
+    match (requested, default) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Queue the notification of the end of a session to its client, returning
+/// whether it was sent
+async fn notify_closed(outbound: &Outbound, notification: ServerJsonRpcMessage) -> bool {
+    match serde_json::to_vec(&notification) {
+        Ok(payload) => outbound.push(payload).await == Queued::Sent,
+        Err(_) => false,
+    }
+}
+
+fn session_closed(
+    reason: CloseReason,
+    duration: Duration,
+    client_messages: u64,
+    server_messages: u64,
+) -> ServerJsonRpcMessage {
+    ServerJsonRpcMessage::Notification(JsonRpcNotification {
+        jsonrpc: JsonRpcVersion2_0,
+        notification: ServerNotification::CustomNotification(CustomNotification::new(
+            SESSION_CLOSED_METHOD,
+            Some(serde_json::json!({
+                "reason": reason.as_str(),
+                "retryable": reason.retryable(),
+                "durationMs": duration.as_millis() as u64,
+                "clientMessages": client_messages,
+                "serverMessages": server_messages,
+            })),
+        )),
+    })
+}
+
+fn duplicate_request_id(id: RequestId) -> ServerJsonRpcMessage {
+    ServerJsonRpcMessage::Error(JsonRpcError {
+        jsonrpc: JsonRpcVersion2_0,
+        id,
+        error: ErrorData::invalid_request("duplicate request id", None),
+    })
+}
+
+fn reserved_request_id(id: RequestId) -> ServerJsonRpcMessage {
+    ServerJsonRpcMessage::Error(JsonRpcError {
+        jsonrpc: JsonRpcVersion2_0,
+        id,
+        error: ErrorData::invalid_request("request id reserved by the proxy", None),
+    })
+}
+
+fn method_not_found(id: RequestId, method: &str) -> ServerJsonRpcMessage {
+    ServerJsonRpcMessage::Error(JsonRpcError {
+        jsonrpc: JsonRpcVersion2_0,
+        id,
+        error: ErrorData::new(
+            ErrorCode::METHOD_NOT_FOUND,
+            format!("method not found: {}", method),
+            None,
+        ),
+    })
+}
+
+/// Client requests forwarded to the MCP server and not answered yet
+struct PendingRequests {
+    ids: HashSet<RequestId>,
+    max: Option<usize>,
+}
+
+impl PendingRequests {
+    fn new(max: Option<usize>) -> Self {
+        Self {
+            ids: HashSet::new(),
+            max,
+        }
+    }
+
+    /// Whether a new request must be rejected, counting the requests held
+    /// back by the session on top of the pending ones
+    fn is_full(&self, held: usize) -> bool {
+        self.max.is_some_and(|max| self.ids.len() + held >= max)
+    }
+
+    fn contains(&self, id: &RequestId) -> bool {
+        self.ids.contains(id)
+    }
+
+    fn insert(&mut self, id: RequestId) {
+        self.ids.insert(id);
+    }
+
+    /// Forget a request, returning whether it was pending
+    fn remove(&mut self, id: &RequestId) -> bool {
+        self.ids.remove(id)
+    }
+
+    fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Forget the request answered by a message of the MCP server, returning
+    /// the id answered when no request of the session has it and the policy
+    /// looks for such responses
+    fn unknown_response<'a>(
+        &mut self,
+        msg: &'a ServerJsonRpcMessage,
+        policy: UnknownResponsePolicy,
+    ) -> Option<&'a RequestId> {
+        match msg {
+            JsonRpcMessage::Response(JsonRpcResponse { id, .. })
+            | JsonRpcMessage::Error(JsonRpcError { id, .. })
+                if !self.remove(id) && policy != UnknownResponsePolicy::Forward =>
+            {
+                Some(id)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Error sent to the client when a session has too many pending requests
+fn too_many_pending_requests(id: RequestId) -> ServerJsonRpcMessage {
+    ServerJsonRpcMessage::Error(JsonRpcError {
+        jsonrpc: JsonRpcVersion2_0,
+        id,
+        error: ErrorData::internal_error("too many pending requests", None),
+    })
+}
+
+/// Error sent to the client in place of a response dropped because the
+/// client queue was full
+fn response_dropped(id: RequestId) -> ServerJsonRpcMessage {
+    ServerJsonRpcMessage::Error(JsonRpcError {
+        jsonrpc: JsonRpcVersion2_0,
+        id,
+        error: ErrorData::internal_error("response dropped, proxy overloaded", None),
+    })
+}
+
+/// Notifies the proxy when a session handler task ends, whatever the exit path
+struct SessionEndGuard {
+    session_id: SessionId,
+    tx_session_end: mpsc::UnboundedSender<SessionId>,
+    metrics: Arc<Metrics>,
+}
+
+impl Drop for SessionEndGuard {
+    fn drop(&mut self) {
+        // the guard is dropped while unwinding when the handler panics: tokio
+        // contains the panic to the task, the other sessions go on
+        if std::thread::panicking() {
+            error!(session_id = self.session_id.id, "session handler panicked");
+            self.metrics
+                .closed_sessions
+                .inc(CloseReason::Panicked.as_str());
+        }
+        let _ = self.tx_session_end.send(self.session_id.clone());
+    }
+}
+
+/// Releases what a session handler holds on every exit path, the early
+/// returns and a panic included: the ping timer is stopped and the MCP
+/// connection closed exactly once, unless kept for the return of the client
+struct SessionTeardown {
+    ping_timer: Timer,
+    transport: Option<StreamableHttpClientTransport<BoundedClient>>,
+    /// connections to the logical MCP servers selected by the prefix of a
+    /// method, opened at the first request to each
+    logical: HashMap<String, StreamableHttpClientTransport<BoundedClient>>,
+}
+
+impl SessionTeardown {
+    fn new(ping_timer: Timer, transport: StreamableHttpClientTransport<BoundedClient>) -> Self {
+        Self {
+            ping_timer,
+            transport: Some(transport),
+            logical: HashMap::new(),
+        }
+    }
+
+    fn transport(&mut self) -> &mut StreamableHttpClientTransport<BoundedClient> {
+        self.transport
+            .as_mut()
+            .expect("MCP connection used after being kept")
+    }
+
+    /// Connection of a message routed to a logical MCP server, or of the MCP
+    /// server
+    fn transport_for(
+        &mut self,
+        server: Option<&str>,
+    ) -> &mut StreamableHttpClientTransport<BoundedClient> {
+        match server {
+            Some(server) => self
+                .logical
+                .get_mut(server)
+                .expect("message routed to a connected logical MCP server"),
+            None => self.transport(),
+        }
+    }
+
+    /// Next message of the MCP server or of a logical MCP server, with the
+    /// name of the latter
+    async fn receive(&mut self) -> (Option<String>, Option<ServerJsonRpcMessage>) {
+        let transport = self
+            .transport
+            .as_mut()
+            .expect("MCP connection used after being kept");
+        if self.logical.is_empty() {
+            return (None, transport.receive().await);
+        }
+        let logical =
+            futures::future::select_all(self.logical.iter_mut().map(|(server, transport)| {
+                Box::pin(async move { (Some(server.clone()), transport.receive().await) })
+            }));
+        tokio::select! {
+            msg = transport.receive() => (None, msg),
+            (received, _, _) = logical => received,
+        }
+    }
+
+    /// Keep the MCP connection open past the end of the session
+    fn keep_transport(&mut self) -> StreamableHttpClientTransport<BoundedClient> {
+        self.transport.take().expect("MCP connection kept twice")
+    }
+
+    /// Use another MCP connection, closing the current one
+    fn replace_transport(&mut self, transport: StreamableHttpClientTransport<BoundedClient>) {
+        self.transport = Some(transport);
+    }
+}
+
+impl Drop for SessionTeardown {
+    fn drop(&mut self) {
+        self.ping_timer.stop();
+        // dropping the transport cancels its worker, which still ends the MCP
+        // session on its own: close() would wait forever on a connection that
+        // never sent its initialize request
+        drop(self.transport.take());
+    }
+}
+
+/// Session served by the proxy
+struct ActiveSession {
+    /// SLIM name of the client
+    client: Name,
+    activity: Arc<SessionActivity>,
+}
+
+pub struct Proxy {
+    name: Name,
+    config: SessionConfig,
+    // retain mapping for active session ids to help with cleanup / debugging
+    connections: HashMap<SessionId, ActiveSession>,
+    /// the proxy starts draining when this file exists
+    drain_file: Option<PathBuf>,
+    /// address of the readiness and drain endpoint
+    admin_addr: Option<SocketAddr>,
+    /// admin endpoint of the instance this one takes over from, asked to
+    /// hand over once this one is ready
+    handover_from: Option<reqwest::Url>,
+    /// token presented to the previous instance when asking it to hand over
+    handover_token: Option<String>,
+    /// time given to the admin connections to end when the proxy stops
+    admin_shutdown_timeout: Duration,
+    /// time given to the sessions to end after a shutdown signal, the proxy
+    /// stops right away if `None`
+    shutdown_grace_period: Option<Duration>,
+    /// each notification counts as a shutdown signal, without raising one
+    /// in the whole process
+    shutdown: Arc<Notify>,
+    /// interval between the checks of the client certificate files, not
+    /// reloaded if `None`
+    tls_reload_interval: Option<Duration>,
+    /// Unix socket publishing the events of the proxy
+    event_socket: Option<PathBuf>,
+    admin: Arc<AdminState>,
+    metrics: Arc<Metrics>,
+    exporters: Vec<Box<dyn Exporter>>,
+    /// maximum random delay before serving
+    startup_jitter: Duration,
+    /// maximum time to wait for the dataplane connections at startup
+    service_run_timeout: Duration,
+    /// endpoint of the dataplane client to subscribe on, the first if `None`
+    subscribe_endpoint: Option<String>,
+    /// interval between two checks of the subscription connection
+    subscription_check_interval: Option<Duration>,
+    subscribe_attempts: u32,
+    stream_end_policy: StreamEndPolicy,
+    /// messages received outside of a session
+    app_message_handler: Box<dyn AppMessageHandler>,
+    /// clients allowed to open a session, anyone if `None`
+    source_allowlist: Option<Allowlist>,
+    /// concurrent sessions allowed to each client, unlimited if `None`
+    max_sessions_per_source: Option<usize>,
+    source_session_limits: Vec<SourceLimit>,
+    /// concurrent sessions of the proxy, unlimited if `None`
+    max_sessions: Option<usize>,
+    /// priorities of the clients when the proxy is full
+    source_priorities: Vec<SourcePriority>,
+    /// sender filling the MCP pool, taken by `start`
+    mcp_pool_filler: Option<mpsc::Sender<WarmConnection>>,
+    /// resident memory above which sessions are shed, unlimited if `None`
+    memory_limit: Option<u64>,
+    shed_policy: ShedPolicy,
+}
+
+/// Whether a publish error means that the SLIM connection of the session is
+/// gone, so that every following message would fail as well
+pub fn is_connection_error(e: &SessionError) -> bool {
+    matches!(
+        e,
+        SessionError::SlimChannelClosed
+            | SessionError::SlimMessageSendFailed
+            | SessionError::SlimSendFailure { .. }
+            | SessionError::SessionSenderShutdown
+            | SessionError::SessionClosed
+            | SessionError::SessionAlreadyClosed
+            | SessionError::SessionControllerSendFailed
+            | SessionError::ParticipantDisconnected(_)
+    )
+}
+
+fn is_initialize_request(msg: &ClientJsonRpcMessage) -> bool {
+    matches!(
+        msg,
+        JsonRpcMessage::Request(JsonRpcRequest {
+            request: ClientRequest::InitializeRequest(_),
+            ..
+        })
+    )
+}
+
+type SlimApp = slim_service::app::App<AuthProvider, AuthVerifier>;
+type SlimRx = mpsc::Receiver<Result<Notification, SessionError>>;
+
+/// Dataplane endpoint the proxy subscribes through: the chosen one, which
+/// must be configured, or the first configured one
+fn subscribe_endpoint(
+    clients: &[ClientConfig],
+    chosen: Option<&String>,
+) -> Result<String, ProxyError> {
+    match chosen {
+        Some(endpoint) if clients.iter().any(|c| &c.endpoint == endpoint) => Ok(endpoint.clone()),
+        Some(endpoint) => Err(ProxyError::UnknownDataplaneEndpoint {
+            endpoint: endpoint.clone(),
+            configured: clients.iter().map(|c| c.endpoint.clone()).collect(),
+        }),
+        None => clients
+            .first()
+            .map(|c| c.endpoint.clone())
+            .ok_or(ProxyError::NoDataplaneClients),
+    }
+}
+
+/// How the start of the SLIM service ended
+#[derive(Debug, PartialEq, Eq)]
+enum ServiceStart {
+    /// every configured connection is established
+    Started,
+    /// the timeout elapsed, the established connections are kept
+    TimedOut,
+    /// the start was cancelled, e.g. by a shutdown signal
+    Cancelled,
+}
+
+/// Run the SLIM service until its connections are established, `cancel` is
+/// cancelled or the timeout elapses, whichever comes first
+async fn run_service(
+    service: &slim_service::Service,
+    timeout: Duration,
+    cancel: &CancellationToken,
+) -> Result<ServiceStart, ProxyError> {
+    // cancels the run at the timeout, not the caller's token
+    let run = cancel.child_token();
+    let deadline = tokio::spawn({
+        let run = run.clone();
+        async move {
+            tokio::time::sleep(timeout).await;
+            run.cancel();
+        }
+    });
+    let result = run.run_until_cancelled(service.run()).await;
+    deadline.abort();
+    match result {
+        Some(result) => result.map(|()| ServiceStart::Started).map_err(Into::into),
+        None if cancel.is_cancelled() => Ok(ServiceStart::Cancelled),
+        None => Ok(ServiceStart::TimedOut),
+    }
+}
+
+/// Create the SLIM app again and subscribe its name on the current dataplane
+/// connection
+async fn recover_app(
+    service: &slim_service::Service,
+    endpoint: &str,
+    name: &Name,
+    provider: &AuthProvider,
+    verifier: &AuthVerifier,
+) -> Result<(SlimApp, SlimRx, u64), ProxyError> {
+    let conn_id = service
+        .get_connection_id(endpoint)
+        .ok_or_else(|| not_connected(service, endpoint))?;
+    let (app, slim_rx) = service
+        .create_app(name, provider.clone(), verifier.clone())
+        .map_err(ProxyError::CreateApp)?;
+    app.subscribe(name, Some(conn_id))
+        .await
+        .map_err(|source| ProxyError::Subscribe {
+            attempts: 1,
+            source,
+        })?;
+    Ok((app, slim_rx, conn_id))
+}
+
+/// Error for an endpoint without connection, listing the endpoints the
+/// service is connected to: the lookup is an exact string match
+fn not_connected(service: &slim_service::Service, endpoint: &str) -> ProxyError {
+    let connected = service
+        .get_all_connections()
+        .into_iter()
+        .filter_map(|c| c.endpoint)
+        .collect();
+    ProxyError::NotConnected {
+        endpoint: endpoint.to_string(),
+        connected,
+    }
+}
+
+/// Subscribe the proxy name at startup, retrying with a growing backoff while
+/// the dataplane connection is still being established
+async fn subscribe_with_retry<F, Fut>(
+    attempts: u32,
+    conn_id: u64,
+    mut subscribe: F,
+) -> Result<(), ProxyError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<(), slim_service::ServiceError>>,
+{
+    let mut backoff = SUBSCRIBE_RETRY_BACKOFF;
+    let mut attempt = 1;
+    while let Err(e) = subscribe().await {
+        if attempt >= attempts {
+            return Err(ProxyError::Subscribe {
+                attempts: attempt,
+                source: e,
+            });
+        }
+        warn!(%conn_id, %attempt, retry_in = ?backoff, "error subscribing the proxy name: {}", e);
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_RESUBSCRIBE_BACKOFF);
+        attempt += 1;
+    }
+    Ok(())
+}
+
+/// Tell how the sessions for the name of the proxy are routed among the
+/// instances sharing it
+fn log_topology(name: &Name, shared_identity: bool) {
+    if !name.has_id() {
+        warn!(%name, "the proxy has no id: the instances subscribed under this name cannot be told apart");
+    } else if shared_identity {
+        warn!(
+            %name,
+            "the id of the proxy is derived from its SPIFFE id: the instances sharing this \
+             workload identity have the same id and cannot be told apart"
+        );
+    } else {
+        info!(
+            %name,
+            "new sessions for this name are load balanced among the proxy instances subscribed \
+             under it, each instance having its own id"
+        );
+    }
+}
+
+fn reject_session(app: &SlimApp, session: &SessionController) {
+    if let Err(e) = app.delete_session(session) {
+        error!("error closing rejected session: {}", e);
+    }
+}
+
+/// Session of a new session notification. A malformed notification, whose
+/// session is already gone, is logged and `None` tells the caller to skip it.
+fn notified_session(ctx: &SessionContext) -> Option<Arc<SessionController>> {
+    let session = ctx.session_arc();
+    if session.is_none() {
+        warn!("new session notification without a session, skipping it");
+    }
+    session
+}
+
+/// Reject the sessions notified but not handled yet when the proxy shuts
+/// down: a handler spawned now would be orphaned by the service shutdown
+fn reject_pending_sessions(app: &SlimApp, slim_rx: &mut SlimRx, metrics: &Metrics) {
+    slim_rx.close();
+    while let Ok(notification) = slim_rx.try_recv() {
+        if let Ok(Notification::NewSession(ctx)) = notification
+            && let Some(session) = ctx.session_arc()
+        {
+            info!(
+                session_id = session.id(),
+                "proxy is shutting down, reject new session"
+            );
+            metrics.rejected_sessions.inc(session.dst());
+            reject_session(app, &session);
+        }
+    }
+}
+
+/// Send a message generated by the proxy to the SLIM client of the session,
+/// behind the messages already sent to it. Transient errors are logged, false
+/// is returned once the client cannot be reached anymore.
+async fn send_to_client(outbound: Option<&Outbound>, msg: &ServerJsonRpcMessage) -> bool {
+    let Some(outbound) = outbound else {
+        debug!("dropping message to client: remote not initialized yet");
+        return true;
+    };
+    let vec = serde_json::to_vec(msg).unwrap();
+    outbound.send(vec).await != Queued::Closed
+}
+
+/// Spawn the async task that bridges a SLIM session with the MCP server.
+fn start_proxy_session(
+    ctx: SessionContext,
+    config: SessionConfig,
+    end_guard: SessionEndGuard,
+    activity: Arc<SessionActivity>,
+) {
+    let Some(session) = ctx.session_arc() else {
+        // the guard reports the end of the session to the proxy
+        error!("session context without a session, session not served");
+        config
+            .metrics
+            .closed_sessions
+            .inc(CloseReason::InternalError.as_str());
+        return;
+    };
+    let session_id_val = session.id();
+    let deadline = session_deadline(&session.metadata(), config.session_deadline);
+    drop(session);
+    ctx.spawn_receiver(move |mut rx, weak| async move {
+        let _end_guard = end_guard;
+        info!(%session_id_val, "Session handler task started");
+        let setup_started = Instant::now();
+
+        let Some(binding) = weak.upgrade() else {
+            debug!("session dropped before its handler started");
+            config.record_closed(session_id_val, CloseReason::SessionDropped);
+            return;
+        };
+        let remote_name = binding.dst();
+
+        let mut return_route = ReturnRoute::new(config.return_path);
+        // messages from the MCP server, started with the first client message
+        let mut outbound: Option<Outbound> = None;
+        // kept across the publishing tasks of the session
+        let credits = config.credits.as_ref().map(|scheduler| Arc::new(scheduler.account()));
+
+        // created before anything the session would have to release when
+        // the log cannot be created
+        let wal = match &config.session_wal {
+            Some(wal_config) => match SessionWal::create(wal_config, session_id_val, config.redactor.clone(), config.metrics.clone()).await {
+                Ok(wal) => Some(wal),
+                Err(e) if wal_config.on_failure == WalFailurePolicy::Strict => {
+                    error!("error creating session write-ahead log, closing the session: {}", e);
+                    config.record_closed(session_id_val, CloseReason::WalFailed);
+                    return;
+                }
+                Err(e) => {
+                    warn!("error creating session write-ahead log, session not recorded: {}", e);
+                    None
+                }
+            },
+            None => None,
+        };
+        // Connect to MCP server
+        info!("Connecting to MCP server: {}", RedactedUrl(&config.mcp_server));
+        let client = match config.http_client(remote_name) {
+            Ok(client) => BoundedClient::new(client, config.read_buffer_bytes),
+            Err(e) => {
+                if config.log_throttle.allow("error creating HTTP client for MCP server") {
+                    error!("error creating HTTP client for MCP server: {}", e);
+                }
+                config.record_closed(session_id_val, CloseReason::InternalError);
+                return;
+            }
+        };
+        // once on the fallback server, the session sticks with it
+        let mut on_fallback = false;
+        let transport = match config.mcp_transport(client.clone()).await {
+            Ok(transport) => transport,
+            Err(e) => {
+                if config.log_throttle.allow("error discovering MCP server") {
+                    error!("error discovering MCP server: {}", e);
+                }
+                config.admin.record_backend_failure(&config.backend(false), e.to_string());
+                let Some(transport) = config.fallback_transport(client.clone()) else {
+                    config.record_closed(session_id_val, CloseReason::DiscoveryFailed);
+                    return;
+                };
+                on_fallback = true;
+                transport
+            }
+        };
+        // MCP connection left by the previous session of the client
+        let mut resumed = config.affinity.as_ref().and_then(|affinity| affinity.take(remote_name));
+        if resumed.is_some() {
+            info!("client back within the affinity window, resuming its MCP session");
+        }
+        // result of the handshake, kept with the connection when the client goes away
+        let mut initialize_result: Option<ServerResult> = None;
+        // MCP server the session is counted on, the fallback one or not, once
+        // the handshake is complete
+        let mut counted_on: Option<bool> = None;
+        let elapsed = setup_started.elapsed();
+        debug!(?elapsed, "connection to MCP server set up");
+        config.metrics.session_phases.observe("setup", elapsed);
+        let shadow = config
+            .shadow_mcp_server
+            .as_ref()
+            .map(|url| Shadow::spawn(client.http().clone(), url.clone(), session_id_val));
+        // initialize request waiting for the MCP server response
+        let mut pending_initialize: Option<(RequestId, ClientJsonRpcMessage)> = None;
+        // last initialize request of the client, replayed after a reconnection
+        let mut initialize_request: Option<ClientJsonRpcMessage> = None;
+        // delay before the next reconnection triggered by an error of the MCP server
+        let mut reconnect_backoff = config.handshake_retry_delay;
+        // held from the initialize request to its response
+        let mut setup_permit: Option<OwnedSemaphorePermit> = None;
+        // the session took a connection of the MCP pool
+        let mut warm_connection = false;
+        let mut handshake_attempts = 0;
+        let mut handshake_started = Instant::now();
+        // tools/call requests whose result goes through the content filter
+        let mut pending_tool_calls: HashSet<RequestId> = HashSet::new();
+        // resources the session is subscribed to, and the subscribe requests
+        // waiting for the MCP server, tracked only to coalesce subscriptions
+        let mut subscriptions: HashSet<String> = HashSet::new();
+        let mut pending_subscribes: HashMap<RequestId, String> = HashMap::new();
+        // client requests not answered yet, tracked only when capped or when
+        // duplicate ids or unknown responses are looked for
+        let mut pending_requests = PendingRequests::new(config.max_pending_requests);
+        let track_pending_requests = config.max_pending_requests.is_some()
+            || config.duplicate_request_id_policy == DuplicateRequestIdPolicy::Reject
+            || config.unknown_response_policy != UnknownResponsePolicy::Forward;
+        // requests forwarded on the MCP connection and not answered yet, and
+        // the client requests waiting for one of them to be answered
+        let mut in_flight: HashSet<RequestId> = HashSet::new();
+        let mut held = VecDeque::new();
+        // messages of the client received while reconnecting to the MCP
+        // server, forwarded once the new connection is open
+        let mut deferred = VecDeque::new();
+        let id_namespace = config.namespace_request_ids.then(|| IdNamespace::new(session_id_val));
+        let reinitialize_id = ids::internal_id(REINITIALIZE);
+        // requests routed to the logical MCP servers by the prefix of their method
+        let mut routes = MethodRoutes::default();
+
+        // Ping timer setup
+        let (tx_timer, mut rx_timer) = mpsc::channel(128);
+        let ping_timer_observer = Arc::new(PingTimerObserver { tx_proxy_session: tx_timer });
+        let ping_timer = Timer::new(1, TimerType::Constant, config.ping_interval.unwrap_or(PING_INTERVAL), None, None);
+        if config.ping_interval.is_some() {
+            ping_timer.start(ping_timer_observer);
+        }
+        let mut teardown = SessionTeardown::new(ping_timer, transport);
+        let mut pending_pings = PingTracker::default();
+        // requests of the MCP server waiting for the response of the client
+        let mut pending_server_requests: HashSet<RequestId> = HashSet::new();
+        let mut last_client_activity = tokio::time::Instant::now();
+        let redactor = &config.redactor;
+        let mut client_messages: u64 = 0;
+        let mut server_messages: u64 = 0;
+
+        let strict_wal = wal.is_some() && config.session_wal.as_ref().is_some_and(|c| c.on_failure == WalFailurePolicy::Strict);
+        let expiry = async {
+            match deadline {
+                Some(deadline) => tokio::time::sleep_until(deadline).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::pin!(expiry);
+        // armed by an error of the MCP server configured to reconnect. The
+        // messages of the client wait from the error until the new connection
+        // completes its handshake.
+        let reconnect_timer = tokio::time::sleep(Duration::ZERO);
+        tokio::pin!(reconnect_timer);
+        let mut reconnect_due = false;
+        let mut reconnecting = false;
+        let close_reason = loop {
+            // a due ping disables the data branches so it is never starved
+            // by a saturated session
+            let ping_due = config.ping_priority && !rx_timer.is_empty();
+            let release_held = !reconnecting && config.max_in_flight_requests.is_some_and(|max| in_flight.len() < max);
             tokio::select! {
-                next_from_slim = slim_rx.recv() => {
-                    match next_from_slim {
+                cause = activity.shed_requested() => match cause {
+                    ShedCause::MemoryPressure => {
+                        warn!("proxy above its memory limit, closing the session");
+                        break CloseReason::MemoryPressure;
+                    }
+                    ShedCause::Preempted => {
+                        warn!("proxy full, closing the session for a client with a higher priority");
+                        break CloseReason::Preempted;
+                    }
+                },
+                _ = &mut expiry => {
+                    info!("session deadline reached, closing the session");
+                    break CloseReason::Deadline;
+                }
+                _ = async { wal.as_ref().unwrap().failed().await }, if strict_wal => {
+                    error!("message not recorded in the write-ahead log, closing the session");
+                    break CloseReason::WalFailed;
+                }
+                _ = &mut reconnect_timer, if reconnect_due => {
+                    reconnect_due = false;
+                    if !config.reconnect_after_error(&client, teardown.transport(), initialize_request.as_ref(), on_fallback).await {
+                        error!("failed reconnecting to MCP server, closing session");
+                        break CloseReason::ReconnectFailed;
+                    }
+                    // a replayed initialize is answered before anything else is sent
+                    reconnecting = initialize_request.is_some();
+                }
+                // the held requests go first, as soon as the MCP connection can take them
+                (next_from_session, replayed) = async {
+                    if release_held && let Some((message, _)) = held.pop_front() {
+                        (Some(Ok(message)), true)
+                    } else if !reconnecting && let Some(message) = deferred.pop_front() {
+                        (Some(Ok(message)), true)
+                    } else {
+                        (rx.recv().await, false)
+                    }
+                }, if !ping_due => {
+                    match next_from_session {
                         None => {
-                            info!("end of the stream, stop the MCP prefix");
-                            break;
+                            info!("session channel closed by the client");
+                            break CloseReason::ClientClosed;
                         }
-                        Some(notification) => {
-                            match notification {
-                                Ok(Notification::NewSession(ctx)) => {
-                                    let session = ctx.session_arc().unwrap();
-                                    let session_id_val = session.id();
-                                    let source_name = session.source().clone();
-                                    let session_key = SessionId { source: source_name, id: session_id_val };
-                                    self.connections.insert(session_key, ());
-                                    debug!("mcp_server {}", self.mcp_server);
-                                    start_proxy_session(ctx, self.mcp_server.clone());
+                        Some(Ok(message)) => {
+                            let received = SystemTime::now();
+                            last_client_activity = tokio::time::Instant::now();
+                            activity.touch();
+                            let conn = message.get_incoming_conn();
+                            match return_route.received(conn) {
+                                RouteUpdate::Set => {
+                                    // derive remote routing info from first message
+                                    outbound = Some(config.outbound(&weak, remote_name, conn, credits.clone()));
+                                    debug!("Initialized remote routing: name={:?} conn_id={:?}", remote_name, conn);
                                 }
-                                Ok(Notification::NewMessage(msg)) => {
-                                    // Unexpected standalone app-level message for proxy use-case
-                                    debug!("received unexpected NewMessage at app level: {:?}", msg.get_session_header().get_session_id());
+                                RouteUpdate::Diverging { expected } => {
+                                    config.metrics.diverging_conn_messages.inc();
+                                    if config.log_throttle.allow("client message received on another connection") {
+                                        warn!(expected, conn, "client message received on another connection than the one the session answers on");
+                                    }
+                                }
+                                RouteUpdate::Same => {}
+                            }
+                            let payload = match message.get_payload() { Some(p) => p.as_application_payload().unwrap().blob.to_vec(), None => { error!("empty payload"); continue; } };
+                            // a held request was already counted and recorded
+                            if !replayed {
+                                if let Some(chaos) = &config.chaos && chaos.drops().await {
+                                    debug!("chaos: dropping message of the client");
+                                    config.metrics.chaos_dropped_messages.inc();
+                                    continue;
                                 }
+                                config.metrics.client_messages.inc();
+                                client_messages += 1;
+                                if let Some(wal) = &wal {
+                                    wal.record(wal::Direction::Client, &payload);
+                                }
+                            }
+                            let mut jsonrpcmsg = match message::parse_client_message(&payload, config.parse_options) {
+                                Ok(v) => v,
                                 Err(e) => {
-                                    error!("error receiving notification: {:?}", e);
+                                    if config.log_throttle.allow("error parsing message") {
+                                        error!("error parsing message: {}", e);
+                                    }
+                                    config.metrics.client_parse_errors.inc();
+                                    if let Some(resp) = e.error_response()
+                                        && !send_to_client(outbound.as_ref(), &resp).await
+                                    {
+                                        error!("SLIM connection lost, closing session");
+                                        break CloseReason::NotConnected;
+                                    }
+                                    continue;
                                 }
+                            };
+                            debug!("Processing message type: {:?}", std::mem::discriminant(&jsonrpcmsg));
+                            if matches!(&jsonrpcmsg, JsonRpcMessage::Request(JsonRpcRequest { request: ClientRequest::InitializeRequest(_), .. }))
+                                && let Some(previous) = return_route.initialize_received(conn)
+                            {
+                                info!(previous, conn, "initialize request received on another connection, answering on it");
+                                outbound = Some(config.outbound(&weak, remote_name, conn, credits.clone()));
                             }
-                        }
-                    }
-                }
-                // shutdown signal
-                _ = slim_signal::shutdown() => {
-                    info!("Received shutdown signal, stop mcp-proxy");
-                    break;
-                }
-            }
-        }
-
-        info!("shutting down proxy server");
-        self.connections.clear();
-
-        service.shutdown().await.unwrap();
+                            // only the answers to the pings are taken while reconnecting
+                            if reconnecting && !matches!(&jsonrpcmsg, JsonRpcMessage::Response(response) if pending_pings.is_pending(&response.id)) {
+                                debug!("reconnecting to MCP server, deferring message of the client");
+                                deferred.push_back(message);
+                                continue;
+                            }
+                            if let JsonRpcMessage::Request(JsonRpcRequest { id, request: ClientRequest::InitializeRequest(_), .. }) = &jsonrpcmsg
+                                && let Some(warm) = resumed.take().or_else(|| config.mcp_pool.as_ref().and_then(|pool| pool.take()))
+                            {
+                                debug!("session takes a warm MCP connection");
+                                teardown.replace_transport(warm.transport);
+                                on_fallback = warm.on_fallback;
+                                config.record_connection(&mut counted_on, on_fallback);
+                                warm_connection = true;
+                                initialize_request = Some(jsonrpcmsg.clone());
+                                initialize_result = Some(warm.initialize_result.clone());
+                                let resp = ServerJsonRpcMessage::Response(JsonRpcResponse { jsonrpc: JsonRpcVersion2_0, id: id.clone(), result: warm.initialize_result });
+                                if !send_to_client(outbound.as_ref(), &resp).await {
+                                    error!("SLIM connection lost, closing session");
+                                    break CloseReason::NotConnected;
+                                }
+                                continue;
+                            }
+                            // the handshake of a warm connection is already complete
+                            if warm_connection && matches!(jsonrpcmsg, JsonRpcMessage::Notification(JsonRpcNotification { notification: ClientNotification::InitializedNotification(_), .. })) {
+                                continue;
+                            }
+                            if let Some(resp) = config.client_ping_mode.answer(&jsonrpcmsg) {
+                                debug!("answering client ping {}", redactor.display(&jsonrpcmsg));
+                                if !send_to_client(outbound.as_ref(), &resp).await {
+                                    error!("SLIM connection lost, closing session");
+                                    break CloseReason::NotConnected;
+                                }
+                                continue;
+                            }
+                            let route = routes.route(&mut jsonrpcmsg, &config.logical_servers);
+                            match jsonrpcmsg {
+                                JsonRpcMessage::Response(mut json_rpc_response) => {
+                                    debug!("received response message: {}", redactor.display(&json_rpc_response));
+                                    // the answer to a request of the MCP server, e.g. roots/list
+                                    // or ping, is never taken for a ping ack even if the ids collide
+                                    let server_request = pending_server_requests.remove(&json_rpc_response.id);
+                                    let answered = routes.answered(&json_rpc_response.id);
+                                    if !server_request
+                                        && answered.is_none()
+                                        && matches!(json_rpc_response.result, EmptyResult(_))
+                                        && let Some(rtt) = pending_pings.answered(&json_rpc_response.id)
+                                    {
+                                        debug!("received ping response id {:?}", json_rpc_response.id);
+                                        activity.pings().record_rtt(rtt);
+                                        activity.pings().set_pending(pending_pings.len(), config.max_pending_pings);
+                                    } else {
+                                        debug!("forward response to MCP server {}", redactor.display(&json_rpc_response));
+                                        let server = answered.map(|(server, id)| {
+                                            json_rpc_response.id = id;
+                                            server
+                                        });
+                                        if let Err(e) = teardown.transport_for(server.as_deref()).send(rmcp::model::JsonRpcMessage::Response(json_rpc_response.clone())).await
+                                            && config.log_throttle.allow("failed sending response to MCP server")
+                                        {
+                                            error!("failed sending response to MCP server: {:?}, response_id={:?}", e, json_rpc_response.id);
+                                        }
+                                    }
+                                }
+                                JsonRpcMessage::Request(JsonRpcRequest { id, request: ClientRequest::SubscribeRequest(req), .. })
+                                    if config.coalesce_subscriptions && subscriptions.contains(&req.params.uri) =>
+                                {
+                                    debug!(uri = %req.params.uri, "already subscribed, answering subscribe request id {:?}", id);
+                                    let resp = ServerJsonRpcMessage::Response(JsonRpcResponse { jsonrpc: JsonRpcVersion2_0, id, result: ServerResult::empty(()) });
+                                    if !send_to_client(outbound.as_ref(), &resp).await {
+                                        error!("SLIM connection lost, closing session");
+                                        break CloseReason::NotConnected;
+                                    }
+                                }
+                                JsonRpcMessage::Request(JsonRpcRequest { id, .. }) if ids::is_internal(&id) => {
+                                    warn!("request id {:?} reserved by the proxy, rejecting request", id);
+                                    config.metrics.rejected_client_requests.inc();
+                                    if !send_to_client(outbound.as_ref(), &reserved_request_id(id)).await {
+                                        error!("SLIM connection lost, closing session");
+                                        break CloseReason::NotConnected;
+                                    }
+                                }
+                                JsonRpcMessage::Request(JsonRpcRequest { id, request, .. })
+                                    if config.allowed_methods.as_ref().is_some_and(|allowed| {
+                                        !message::is_mcp_method(request.method()) && !allowed.contains(request.method())
+                                    }) =>
+                                {
+                                    warn!(method = %request.method(), "unknown method, rejecting request id {:?}", id);
+                                    config.metrics.rejected_client_requests.inc();
+                                    if !send_to_client(outbound.as_ref(), &method_not_found(id, request.method())).await {
+                                        error!("SLIM connection lost, closing session");
+                                        break CloseReason::NotConnected;
+                                    }
+                                }
+                                JsonRpcMessage::Request(JsonRpcRequest { id, .. })
+                                    if config.duplicate_request_id_policy == DuplicateRequestIdPolicy::Reject
+                                        && (pending_requests.contains(&id) || held.iter().any(|(_, held_id)| *held_id == id)) =>
+                                {
+                                    warn!("duplicate request id {:?}, rejecting request", id);
+                                    config.metrics.rejected_client_requests.inc();
+                                    if !send_to_client(outbound.as_ref(), &duplicate_request_id(id)).await {
+                                        error!("SLIM connection lost, closing session");
+                                        break CloseReason::NotConnected;
+                                    }
+                                }
+                                JsonRpcMessage::Request(JsonRpcRequest { id, .. })
+                                    if pending_requests.is_full(held.len()) =>
+                                {
+                                    warn!(pending = pending_requests.len(), "too many pending requests, rejecting request id {:?}", id);
+                                    config.metrics.rejected_client_requests.inc();
+                                    if !send_to_client(outbound.as_ref(), &too_many_pending_requests(id)).await {
+                                        error!("SLIM connection lost, closing session");
+                                        break CloseReason::NotConnected;
+                                    }
+                                }
+                                _ => {
+                                    if let Some(max) = config.max_in_flight_requests {
+                                        match &jsonrpcmsg {
+                                            // the request ends up after the held ones, in order
+                                            JsonRpcMessage::Request(JsonRpcRequest { id, .. })
+                                                if in_flight.len() >= max || (!replayed && !held.is_empty()) =>
+                                            {
+                                                debug!(in_flight = in_flight.len(), "MCP connection busy, holding request id {:?}", id);
+                                                held.push_back((message, id.clone()));
+                                                continue;
+                                            }
+                                            JsonRpcMessage::Request(JsonRpcRequest { id, .. }) => {
+                                                in_flight.insert(id.clone());
+                                            }
+                                            JsonRpcMessage::Notification(JsonRpcNotification { notification: ClientNotification::CancelledNotification(cancelled), .. }) => {
+                                                let cancelled_id = &cancelled.params.request_id;
+                                                if let Some(pos) = held.iter().position(|(_, id)| id == cancelled_id) {
+                                                    debug!("request id {:?} cancelled before being forwarded", cancelled_id);
+                                                    held.remove(pos);
+                                                    continue;
+                                                }
+                                                in_flight.remove(cancelled_id);
+                                            }
+                                            _ => {}
+                                        }
+                                    }
+                                    if let JsonRpcMessage::Error(JsonRpcError { id, .. }) = &jsonrpcmsg {
+                                        pending_server_requests.remove(id);
+                                    }
+                                    if track_pending_requests {
+                                        match &jsonrpcmsg {
+                                            JsonRpcMessage::Request(JsonRpcRequest { id, .. }) => {
+                                                pending_requests.insert(id.clone());
+                                            }
+                                            JsonRpcMessage::Notification(JsonRpcNotification { notification: ClientNotification::CancelledNotification(cancelled), .. }) => {
+                                                pending_requests.remove(&cancelled.params.request_id);
+                                            }
+                                            _ => {}
+                                        }
+                                    }
+                                    if let (Some(field), JsonRpcMessage::Request(req)) = (&config.session_label_field, &mut jsonrpcmsg) {
+                                        req.request.get_meta_mut().insert(field.clone(), session_id_val.into());
+                                    }
+                                    debug!("forward message to MCP server {}", redactor.display(&jsonrpcmsg));
+                                    // responses answer requests of the primary server only
+                                    if route.is_none() && let Some(shadow) = &shadow && matches!(jsonrpcmsg, JsonRpcMessage::Request(_) | JsonRpcMessage::Notification(_)) {
+                                        shadow.mirror(&jsonrpcmsg);
+                                    }
+                                    if let Some(server) = &route && !teardown.logical.contains_key(server) {
+                                        match config.logical_connection(remote_name, server, initialize_request.as_ref()).await {
+                                            Ok(transport) => {
+                                                debug!(%server, "connected to logical MCP server");
+                                                teardown.logical.insert(server.clone(), transport);
+                                            }
+                                            Err(e) => {
+                                                if config.log_throttle.allow("error connecting to logical MCP server") {
+                                                    error!(%server, "error connecting to logical MCP server: {}", e);
+                                                }
+                                                if let JsonRpcMessage::Request(JsonRpcRequest { id, .. }) = &jsonrpcmsg {
+                                                    pending_requests.remove(id);
+                                                    in_flight.remove(id);
+                                                    if !send_to_client(outbound.as_ref(), &logical_server_unavailable(id.clone(), server)).await {
+                                                        error!("SLIM connection lost, closing session");
+                                                        break CloseReason::NotConnected;
+                                                    }
+                                                }
+                                                continue;
+                                            }
+                                        }
+                                    }
+
+                                    let mut forwarded = jsonrpcmsg.clone();
+                                    if let Some(namespace) = &id_namespace {
+                                        namespace.to_server(&mut forwarded);
+                                    }
+                                    if config.stamp_timings {
+                                        message::stamp_client_message(&mut forwarded, received);
+                                    }
+                                    match &jsonrpcmsg {
+                                        JsonRpcMessage::Request(JsonRpcRequest { id, request: ClientRequest::InitializeRequest(_), .. }) => {
+                                            if let Some(permits) = &config.setup_permits {
+                                                // a session initializing again would otherwise wait for itself
+                                                drop(setup_permit.take());
+                                                let wait_started = Instant::now();
+                                                setup_permit = permits.clone().acquire_owned().await.ok();
+                                                config.metrics.session_phases.observe("setup_wait", wait_started.elapsed());
+                                            }
+                                            pending_initialize = Some((id.clone(), forwarded.clone()));
+                                            initialize_request = Some(forwarded.clone());
+                                            handshake_started = Instant::now();
+                                        }
+                                        JsonRpcMessage::Request(JsonRpcRequest { id, request: ClientRequest::CallToolRequest(_), .. }) if config.content_filter.is_some() => {
+                                            pending_tool_calls.insert(id.clone());
+                                        }
+                                        JsonRpcMessage::Request(JsonRpcRequest { id, request: ClientRequest::SubscribeRequest(req), .. }) if config.coalesce_subscriptions => {
+                                            pending_subscribes.insert(id.clone(), req.params.uri.clone());
+                                        }
+                                        JsonRpcMessage::Request(JsonRpcRequest { request: ClientRequest::UnsubscribeRequest(req), .. }) => {
+                                            subscriptions.remove(&req.params.uri);
+                                        }
+                                        JsonRpcMessage::Request(JsonRpcRequest { request: ClientRequest::CustomRequest(req), .. }) => {
+                                            debug!(method = %req.method, "forwarding request with a method unknown to the proxy");
+                                            config.metrics.unknown_client_requests.inc(&req.method);
+                                        }
+                                        _ => {}
+                                    }
+                                    // an error answering a request of a logical server goes back to it
+                                    let mut route = route;
+                                    if let JsonRpcMessage::Error(JsonRpcError { id, .. }) = &mut forwarded
+                                        && let Some((server, own)) = routes.answered(id)
+                                    {
+                                        *id = own;
+                                        route = Some(server);
+                                    }
+                                    let sent = teardown.transport_for(route.as_deref()).send(forwarded).await;
+                                    if sent.is_ok()
+                                        && let Some(server) = &route
+                                        && let JsonRpcMessage::Request(JsonRpcRequest { id, .. }) = &jsonrpcmsg
+                                    {
+                                        routes.forwarded(id.clone(), server);
+                                    }
+                                    if let Err(e) = sent {
+                                        // the request never reached the server, it will not be answered
+                                        if let JsonRpcMessage::Request(JsonRpcRequest { id, .. }) = &jsonrpcmsg {
+                                            pending_requests.remove(id);
+                                            in_flight.remove(id);
+                                        }
+                                        if config.log_throttle.allow("failed forwarding message to MCP server") {
+                                            error!("failed forwarding message to MCP server: {:?}, message_type={}", e, match jsonrpcmsg {
+                                                JsonRpcMessage::Request(_) => "Request",
+                                                JsonRpcMessage::Response(_) => "Response",
+                                                JsonRpcMessage::Notification(_) => "Notification",
+                                                JsonRpcMessage::Error(_) => "Error",
+                                            });
+                                        }
+                                        // only the initialize is retried, a later message of the
+                                        // handshake would be lost on a new connection
+                                        let initialize_failed = is_initialize_request(&jsonrpcmsg);
+                                        if initialize_failed {
+                                            config.admin.record_backend_failure(&config.backend(on_fallback), e.to_string());
+                                        }
+                                        if initialize_failed
+                                            && let Some((id, initialize)) = &pending_initialize
+                                            && !config.retry_handshake(&client, teardown.transport(), initialize, &mut handshake_attempts, &mut on_fallback).await
+                                        {
+                                            let reason = handshake_failure(&client, "MCP server rejected the handshake");
+                                            send_to_client(outbound.as_ref(), &handshake_rejected(id.clone())).await;
+                                            break reason;
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                        Some(Err(SessionError::SessionClosed)) => {
+                            info!("session closed by the client");
+                            break CloseReason::ClientClosed;
+                        }
+                        Some(Err(e)) => {
+                            error!("error receiving session message: {:?}", e);
+                            break CloseReason::SessionError;
+                        }
+                    }
+                }
+                (from, next_from_mcp) = teardown.receive(), if !ping_due && !reconnect_due => {
+                    match next_from_mcp {
+                        None if from.is_some() => {
+                            error!(server = from.as_deref().unwrap_or_default(), "end of the stream of logical MCP server, closing session");
+                            break CloseReason::ServerClosed;
+                        }
+                        None => {
+                            if let Some((id, initialize)) = &pending_initialize {
+                                config.admin.record_backend_failure(&config.backend(on_fallback), "connection closed during the handshake".into());
+                                if config.retry_handshake(&client, teardown.transport(), initialize, &mut handshake_attempts, &mut on_fallback).await {
+                                    continue;
+                                }
+                                let reason = handshake_failure(&client, "MCP server closed the connection during the handshake");
+                                send_to_client(outbound.as_ref(), &handshake_rejected(id.clone())).await;
+                                break reason;
+                            }
+                            info!("end of MCP stream");
+                            break CloseReason::ServerClosed;
+                        }
+                        Some(mut msg) => {
+                            if let Some(chaos) = &config.chaos && chaos.drops().await {
+                                debug!("chaos: dropping message of the MCP server");
+                                config.metrics.chaos_dropped_messages.inc();
+                                continue;
+                            }
+                            let received = SystemTime::now();
+                            debug!("Received message from MCP server, message_type={}", match &msg {
+                                JsonRpcMessage::Request(_) => "Request",
+                                JsonRpcMessage::Response(_) => "Response",
+                                JsonRpcMessage::Notification(_) => "Notification",
+                                JsonRpcMessage::Error(_) => "Error",
+                            });
+                            config.metrics.server_messages.inc();
+                            server_messages += 1;
+                            activity.touch();
+                            // the handshake replayed after a reconnection is the proxy's own
+                            match &msg {
+                                JsonRpcMessage::Response(JsonRpcResponse { id, .. }) if *id == reinitialize_id => {
+                                    info!("reconnected to MCP server");
+                                    config.events.emit("session_reconnected", serde_json::json!({ "sessionId": session_id_val }));
+                                    // the requests in flight on the lost connection are never answered
+                                    in_flight.clear();
+                                    config.admin.record_backend_success(&config.backend(on_fallback));
+                                    reconnect_backoff = config.handshake_retry_delay;
+                                    reconnecting = false;
+                                    let initialized = ClientJsonRpcMessage::notification(ClientNotification::InitializedNotification(InitializedNotification::default()));
+                                    if let Err(e) = teardown.transport().send(initialized).await {
+                                        error!("failed completing the handshake with MCP server: {:?}", e);
+                                    }
+                                    continue;
+                                }
+                                JsonRpcMessage::Error(JsonRpcError { id, error, .. }) if *id == reinitialize_id => {
+                                    error!("MCP server rejected the handshake after a reconnection ({}), closing session", error.message);
+                                    config.admin.record_backend_failure(&config.backend(on_fallback), error.message.to_string());
+                                    break CloseReason::HandshakeRejected;
+                                }
+                                // answers to the other requests of the proxy, none of a client
+                                JsonRpcMessage::Response(JsonRpcResponse { id, .. }) | JsonRpcMessage::Error(JsonRpcError { id, .. }) if ids::is_internal(id) => {
+                                    debug!("response from MCP server to the proxy's own request id {:?}, not forwarded", id);
+                                    continue;
+                                }
+                                _ => {}
+                            }
+                            let error_action = match &msg {
+                                // the error rules apply to the MCP server only
+                                JsonRpcMessage::Error(JsonRpcError { error, .. }) if from.is_none() => error_action::action_for(&config.error_rules, error.code.0),
+                                _ => ErrorAction::Forward,
+                            };
+                            if let Some(namespace) = &id_namespace {
+                                namespace.to_client(&mut msg);
+                            }
+                            if let JsonRpcMessage::Response(JsonRpcResponse { id, .. }) | JsonRpcMessage::Error(JsonRpcError { id, .. }) = &msg {
+                                in_flight.remove(id);
+                            }
+                            if let Some(id) = pending_requests.unknown_response(&msg, config.unknown_response_policy) {
+                                if config.log_throttle.allow("response from MCP server for unknown request id") {
+                                    warn!("response from MCP server for unknown request id {:?}", id);
+                                }
+                                config.metrics.unknown_server_responses.inc();
+                                if config.unknown_response_policy == UnknownResponsePolicy::Drop {
+                                    continue;
+                                }
+                            }
+                            // the subscription holds once the MCP server accepted it
+                            if let JsonRpcMessage::Response(JsonRpcResponse { id, .. }) | JsonRpcMessage::Error(JsonRpcError { id, .. }) = &msg
+                                && let Some(uri) = pending_subscribes.remove(id)
+                                && matches!(msg, JsonRpcMessage::Response(_))
+                            {
+                                subscriptions.insert(uri);
+                            }
+                            // requests of the MCP server answered by the proxy itself
+                            let proxy_answer = match &msg {
+                                JsonRpcMessage::Request(JsonRpcRequest { id, request: ServerRequest::ListRootsRequest(_), .. })
+                                    if config.roots_mode == RootsMode::Answer =>
+                                {
+                                    debug!("answering roots/list request id {:?} with no roots", id);
+                                    Some(ClientJsonRpcMessage::response(ListRoots(ListRootsResult::default()), id.clone()))
+                                }
+                                JsonRpcMessage::Request(JsonRpcRequest { id, request: ServerRequest::CreateElicitationRequest(_), .. })
+                                    if config.elicitation_mode == ElicitationMode::Decline =>
+                                {
+                                    debug!("declining elicitation request id {:?}", id);
+                                    let declined = CreateElicitationResult { action: ElicitationAction::Decline, content: None };
+                                    Some(ClientJsonRpcMessage::response(Elicitation(declined), id.clone()))
+                                }
+                                _ => None,
+                            };
+                            if let Some(resp) = proxy_answer {
+                                if let Err(e) = teardown.transport_for(from.as_deref()).send(resp).await
+                                    && config.log_throttle.allow("failed answering request of MCP server")
+                                {
+                                    error!("failed answering request of MCP server: {:?}", e);
+                                }
+                                continue;
+                            }
+                            if let Some(server) = &from {
+                                routes.server_message(server, &mut msg);
+                            }
+                            if let JsonRpcMessage::Request(JsonRpcRequest { id, .. }) = &msg {
+                                pending_server_requests.insert(id.clone());
+                            }
+                            if config.mirror_server_logs
+                                && let JsonRpcMessage::Notification(JsonRpcNotification { notification: ServerNotification::LoggingMessageNotification(log), .. }) = &msg
+                            {
+                                mirror_server_log(session_id_val, &log.params);
+                            }
+                            if let Some(filter) = &config.content_filter {
+                                let mut blocked = None;
+                                match &mut msg {
+                                    JsonRpcMessage::Response(resp) if pending_tool_calls.remove(&resp.id) => {
+                                        match filter.apply(&mut resp.result) {
+                                            FilterOutcome::Clean => {}
+                                            FilterOutcome::Redacted(matches) => {
+                                                warn!(%matches, "redacted sensitive content in tool result");
+                                                config.metrics.filtered_tool_results.inc(FilterAction::Redact.as_str());
+                                            }
+                                            FilterOutcome::Blocked => blocked = Some(resp.id.clone()),
+                                        }
+                                    }
+                                    JsonRpcMessage::Error(err) => {
+                                        pending_tool_calls.remove(&err.id);
+                                    }
+                                    _ => {}
+                                }
+                                if let Some(id) = blocked {
+                                    warn!("tool result blocked by the content filter");
+                                    config.metrics.filtered_tool_results.inc(FilterAction::Block.as_str());
+                                    msg = tool_result_blocked(id);
+                                }
+                            }
+                            if let Some((id, _)) = &pending_initialize
+                                && matches!(&msg, JsonRpcMessage::Response(JsonRpcResponse { id: resp_id, .. }) | JsonRpcMessage::Error(JsonRpcError { id: resp_id, .. }) if resp_id == id)
+                            {
+                                pending_initialize = None;
+                                if let JsonRpcMessage::Response(resp) = &msg {
+                                    if let ServerResult::InitializeResult(result) = &resp.result {
+                                        config.record_capabilities(on_fallback, result);
+                                    }
+                                    config.admin.record_backend_success(&config.backend(on_fallback));
+                                    config.record_connection(&mut counted_on, on_fallback);
+                                    initialize_result = Some(resp.result.clone());
+                                }
+                                if let JsonRpcMessage::Error(err) = &msg {
+                                    config.admin.record_backend_failure(&config.backend(on_fallback), err.error.message.to_string());
+                                }
+                                drop(setup_permit.take());
+                                let elapsed = handshake_started.elapsed();
+                                debug!(?elapsed, "handshake with MCP server completed");
+                                config.metrics.session_phases.observe("handshake", elapsed);
+                            }
+                            if let Some(wal) = &wal && let Ok(payload) = serde_json::to_vec(&msg) {
+                                wal.record(wal::Direction::Server, &payload);
+                            }
+                            let checked = match message::server_message_defect(&msg) {
+                                Some(defect) if config.bad_server_message_policy != BadServerMessagePolicy::Forward => Err(defect.to_string()),
+                                _ => message::to_client_payload(&msg, &config.strip_meta_fields, config.stamp_timings.then_some(received)).map_err(|e| format!("serialization failed: {}", e)),
+                            };
+                            if checked.is_err() {
+                                config.metrics.bad_server_messages.inc();
+                            }
+                            let vec = match checked {
+                                Ok(vec) => vec,
+                                Err(reason) if config.bad_server_message_policy == BadServerMessagePolicy::Close => {
+                                    error!("bad message from MCP server ({}), closing session", reason);
+                                    break CloseReason::BadServerMessage;
+                                }
+                                Err(reason) => {
+                                    if config.log_throttle.allow("bad message from MCP server") {
+                                        error!("bad message from MCP server ({}), dropping it", reason);
+                                    }
+                                    continue;
+                                }
+                            };
+                            let Some(outbound) = &outbound else {
+                                debug!("dropping MCP message: remote not initialized yet");
+                                continue;
+                            };
+                            match outbound.push(vec).await {
+                                Queued::Sent => {}
+                                Queued::Dropped if config.overload_policy == OverloadPolicy::Disconnect => {
+                                    warn!("client queue full, closing the session of the slow client");
+                                    config.metrics.dropped_server_messages.inc();
+                                    break CloseReason::SlowClient;
+                                }
+                                Queued::Dropped => {
+                                    if config.log_throttle.allow("client queue full, dropping MCP message") {
+                                        warn!("client queue full, dropping MCP message");
+                                    }
+                                    config.metrics.dropped_server_messages.inc();
+                                    let id = match &msg {
+                                        JsonRpcMessage::Response(JsonRpcResponse { id, .. }) | JsonRpcMessage::Error(JsonRpcError { id, .. }) => Some(id.clone()),
+                                        _ => None,
+                                    };
+                                    // the error waits for room in the queue, so that the request is not left waiting
+                                    if config.overload_policy == OverloadPolicy::DropNotify
+                                        && let Some(id) = id
+                                        && !send_to_client(Some(outbound), &response_dropped(id)).await
+                                    {
+                                        error!("SLIM connection lost, closing session");
+                                        break CloseReason::NotConnected;
+                                    }
+                                }
+                                Queued::Closed if weak.upgrade().is_none() => {
+                                    debug!("session dropped before sending MCP message");
+                                    break CloseReason::SessionDropped;
+                                }
+                                Queued::Closed => {
+                                    error!("SLIM connection lost, closing session");
+                                    break CloseReason::NotConnected;
+                                }
+                            }
+                            match error_action {
+                                ErrorAction::Forward => {}
+                                ErrorAction::Close => {
+                                    warn!("error of MCP server configured to close the session");
+                                    break CloseReason::ServerError;
+                                }
+                                ErrorAction::Reconnect => {
+                                    warn!(retry_in = ?reconnect_backoff, "error of MCP server configured to reconnect");
+                                    config.metrics.server_error_reconnects.inc();
+                                    // the pings keep running while the backoff elapses
+                                    let _ = teardown.transport().close().await;
+                                    reconnect_timer.as_mut().reset(tokio::time::Instant::now() + reconnect_backoff);
+                                    reconnect_backoff = (reconnect_backoff * 2).min(MAX_RESUBSCRIBE_BACKOFF);
+                                    reconnect_due = true;
+                                    reconnecting = true;
+                                }
+                            }
+                        }
+                    }
+                }
+                timer_ping = rx_timer.recv() => {
+                    match timer_ping {
+                        None => { debug!("timer channel closed"); break CloseReason::InternalError; }
+                        Some(PingTimerEvent::Failure) => {
+                            error!("ping timer failed, closing session");
+                            break CloseReason::TimerFailed;
+                        }
+                        Some(PingTimerEvent::Timeout) => {
+                            let ping_ctx = PingContext {
+                                session_id: session_id_val,
+                                pending_pings: pending_pings.len(),
+                                max_pending_pings: config.max_pending_pings,
+                                ping_interval: config.ping_interval.unwrap_or(PING_INTERVAL),
+                                idle: last_client_activity.elapsed(),
+                            };
+                            match config.ping_policy.on_ping_due(&ping_ctx) {
+                                PingDecision::Send => {}
+                                PingDecision::Skip => {
+                                    trace!(pending_pings = ping_ctx.pending_pings, "skipping ping");
+                                    continue;
+                                }
+                                PingDecision::Close => {
+                                    debug!("ping policy closed the session");
+                                    break CloseReason::MissedPings;
+                                }
+                            }
+                            if let Some(outbound) = &outbound {
+                                let ping_req = PingRequest { method: PingRequestMethod, extensions: Default::default()  };
+                                let id = config.ping_id_format.new_id();
+                                // a ping unanswered for that long was missed, its
+                                // response is not waited for anymore
+                                let max_age = ping_ctx.ping_interval * config.max_pending_pings as u32;
+                                let expired = pending_pings.expire(max_age);
+                                if expired > 0 {
+                                    debug!(expired, "forgetting unanswered pings");
+                                }
+                                pending_pings.sent(id.clone());
+                                activity.pings().set_pending(pending_pings.len(), config.max_pending_pings);
+                                let req = ServerJsonRpcMessage::Request(JsonRpcRequest { jsonrpc: JsonRpcVersion2_0, id, request: rmcp::model::ServerRequest::PingRequest(ping_req) });
+                                let vec = serde_json::to_vec(&req).unwrap();
+                                // queued behind the messages of the MCP server
+                                if outbound.send(vec).await == Queued::Closed {
+                                    if weak.upgrade().is_none() {
+                                        debug!("session dropped before sending ping");
+                                        break CloseReason::SessionDropped;
+                                    }
+                                    error!("SLIM connection lost, closing session");
+                                    break CloseReason::NotConnected;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        };
+        if let Some(counted_on) = counted_on {
+            config.admin.record_backend_connection(&config.backend(counted_on), false);
+        }
+        if let (Some(affinity), Some(initialize_result)) = (&config.affinity, initialize_result)
+            && close_reason.client_gone()
+        {
+            debug!("keeping the MCP connection for the return of the client");
+            affinity.park(remote_name.clone(), WarmConnection { transport: teardown.keep_transport(), initialize_result, on_fallback });
+        }
+        // a resumed connection the session never initialized waits for the
+        // next return of the client, or is closed with the session
+        if let Some(mut warm) = resumed.take() {
+            match &config.affinity {
+                Some(affinity) if close_reason.client_gone() => {
+                    debug!("client gone before resuming its MCP session, keeping the MCP connection");
+                    affinity.park(remote_name.clone(), warm);
+                }
+                _ => {
+                    let _ = warm.transport.close().await;
+                }
+            }
+        }
+        // stops the ping timer and closes the MCP connection if not kept
+        drop(teardown);
+        config.record_closed(session_id_val, close_reason);
+        // queued behind the pending messages of the client, if it is still
+        // reachable
+        if let Some(outbound) = &outbound
+            && (config.send_close_diagnostics || close_reason.always_notified())
+            && !notify_closed(outbound, session_closed(close_reason, setup_started.elapsed(), client_messages, server_messages)).await
+        {
+            debug!("close diagnostics not sent to the client");
+        }
+        if let Some(wal) = wal {
+            // only a session closed by the client is a clean end
+            wal.finish(close_reason == CloseReason::ClientClosed).await;
+        }
+        info!(reason = close_reason.as_str(), "Session handler task ended (session id={})", session_id_val);
+    });
+}
+
+/// Parse an MCP server address, checking that it can be used by the
+/// streamable HTTP transport, the only transport of the proxy
+pub fn parse_mcp_url(mcp_server: &str) -> Result<reqwest::Url, String> {
+    let url = reqwest::Url::parse(mcp_server).map_err(|e| format!("invalid URL: {}", e))?;
+    if !STREAMABLE_HTTP_SCHEMES.contains(&url.scheme()) {
+        return Err(format!(
+            "URL scheme {} is not supported by the streamable HTTP transport, expected {}",
+            url.scheme(),
+            STREAMABLE_HTTP_SCHEMES.join(" or ")
+        ));
+    }
+    Ok(url)
+}
+
+/// Form of an MCP server address identifying its backend, so that spellings
+/// of the same address are not taken for different backends:
+/// - the scheme and the host are lowercased, the default port of the scheme
+///   is dropped and the percent-encoding is normalized, as by the URL parser;
+/// - the trailing dot of the host and the trailing slashes of the path are
+///   removed, the root path excepted;
+/// - the fragment, never sent to the server, is removed.
+///
+/// The credentials and the query are kept as they are. Addresses that cannot
+/// be parsed are returned unchanged.
+/// A SIGINT or SIGTERM, or a notification standing for one
+async fn shutdown_signal(handle: &Notify) {
+    tokio::select! {
+        _ = slim_signal::shutdown() => {}
+        _ = handle.notified() => {}
+    }
+}
+
+fn normalize_mcp_url(mcp_server: &str) -> String {
+    let Ok(mut url) = reqwest::Url::parse(mcp_server) else {
+        return mcp_server.to_string();
+    };
+    url.set_fragment(None);
+    if let Some(host) = url.host_str().and_then(|h| h.strip_suffix('.'))
+        && !host.is_empty()
+    {
+        let host = host.to_string();
+        let _ = url.set_host(Some(&host));
+    }
+    let path = url.path().trim_end_matches('/').to_string();
+    url.set_path(&path);
+    url.to_string()
+}
+
+fn check_mcp_url(kind: &str, mcp_server: &str, require_tls: bool, conflicts: &mut Vec<String>) {
+    match parse_mcp_url(mcp_server) {
+        Ok(url) if require_tls && url.scheme() != "https" => {
+            conflicts.push(format!("TLS is required but the {} URL is not https", kind))
+        }
+        Ok(_) => {}
+        Err(e) => conflicts.push(format!("{}: {}", kind, e)),
+    }
+}
+
+/// Builder for [`Proxy`], validating the configuration as a whole
+pub struct ProxyBuilder {
+    name: Name,
+    mcp_server: String,
+    mcp_discovery: Option<DiscoverySource>,
+    dns_timeout: Option<Duration>,
+    shadow_mcp_server: Option<String>,
+    source_headers: Vec<SourceHeader>,
+    tcp_keepalive: Option<TcpKeepalive>,
+    redactor: Redactor,
+    client_ping_mode: ClientPingMode,
+    roots_mode: RootsMode,
+    elicitation_mode: ElicitationMode,
+    ping_interval: Option<Duration>,
+    max_pending_pings: Option<usize>,
+    ping_id_format: PingIdFormat,
+    return_path: ReturnPath,
+    ping_priority: bool,
+    require_tls: bool,
+    parse_options: ParseOptions,
+    session_label_field: Option<String>,
+    bad_server_message_policy: BadServerMessagePolicy,
+    duplicate_request_id_policy: DuplicateRequestIdPolicy,
+    unknown_response_policy: UnknownResponsePolicy,
+    drain_file: Option<PathBuf>,
+    event_socket: Option<PathBuf>,
+    admin_addr: Option<SocketAddr>,
+    handover_from: Option<reqwest::Url>,
+    admin_shutdown_timeout: Duration,
+    shutdown_grace_period: Option<Duration>,
+    backend_down_failures: u32,
+    admin_profiling_token: Option<String>,
+    admin_handover_token: Option<String>,
+    admin_sessions: bool,
+    session_wal: Option<WalConfig>,
+    prometheus_addr: Option<SocketAddr>,
+    statsd: Option<StatsdConfig>,
+    handshake_retries: usize,
+    handshake_retry_delay: Duration,
+    reconnect_rate: Option<u32>,
+    error_rules: Vec<ErrorRule>,
+    startup_jitter: Duration,
+    service_run_timeout: Duration,
+    subscribe_endpoint: Option<String>,
+    content_filter_patterns: Vec<String>,
+    content_filter_action: FilterAction,
+    outbound_queue_size: Option<usize>,
+    overload_policy: OverloadPolicy,
+    batch_window: Option<Duration>,
+    batch_max_messages: usize,
+    credit_messages: Option<u64>,
+    credit_bytes: Option<u64>,
+    credit_interval: Duration,
+    credit_policy: CreditPolicy,
+    namespace_request_ids: bool,
+    strip_meta_fields: Vec<String>,
+    subscription_check_interval: Option<Duration>,
+    subscribe_attempts: u32,
+    stream_end_policy: StreamEndPolicy,
+    app_message_handler: Box<dyn AppMessageHandler>,
+    max_pending_requests: Option<usize>,
+    max_in_flight_requests: Option<usize>,
+    validate_methods: bool,
+    extension_methods: Vec<String>,
+    max_concurrent_setups: Option<usize>,
+    mirror_server_logs: bool,
+    log_throttle_window: Option<Duration>,
+    chaos: Option<Chaos>,
+    source_allowlist: Option<PathBuf>,
+    mcp_server_fallback: Option<String>,
+    logical_servers: Vec<LogicalServer>,
+    unknown_server_policy: UnknownServerPolicy,
+    normalize_mcp_urls: bool,
+    min_tls_version: Option<MinTlsVersion>,
+    http_version: HttpVersion,
+    cert_pins: Vec<CertPin>,
+    client_cert: Option<ClientCert>,
+    tls_reload_interval: Option<Duration>,
+    bind_address: Option<IpAddr>,
+    ping_policy: Option<Arc<dyn PingPolicy>>,
+    max_sessions_per_source: Option<usize>,
+    source_session_limits: Vec<SourceLimit>,
+    max_sessions: Option<usize>,
+    source_priorities: Vec<SourcePriority>,
+    stamp_timings: bool,
+    send_close_diagnostics: bool,
+    coalesce_subscriptions: bool,
+    read_buffer_bytes: usize,
+    mcp_pool_size: Option<usize>,
+    session_affinity_window: Option<Duration>,
+    session_deadline: Option<Duration>,
+    source_labels: SourceLabels,
+    memory_limit: Option<u64>,
+    shed_policy: ShedPolicy,
+}
+
+impl ProxyBuilder {
+    /// Look up the host and port of the MCP server every time a session
+    /// connects. Scheme and path are taken from the MCP server URL.
+    pub fn with_mcp_discovery(mut self, discovery: Option<DiscoverySource>) -> Self {
+        self.mcp_discovery = discovery;
+        self
+    }
+
+    /// Resolve the host of the MCP server before each connection, within the
+    /// given time, so that a slow or failing DNS is told apart from a failing
+    /// connection
+    pub fn with_dns_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.dns_timeout = timeout;
+        self
+    }
+
+    /// Mirror the requests and notifications of the clients to a secondary MCP
+    /// server. Its responses are discarded.
+    pub fn with_shadow_mcp_server(mut self, shadow_mcp_server: Option<String>) -> Self {
+        self.shadow_mcp_server = shadow_mcp_server;
+        self
+    }
+
+    /// Add HTTP headers to the MCP connection of the sessions whose client
+    /// name matches the mapping patterns
+    pub fn with_source_headers(mut self, source_headers: Vec<SourceHeader>) -> Self {
+        self.source_headers = source_headers;
+        self
+    }
+
+    /// Set the TCP keepalive used on the MCP connections, `None` disables it
+    pub fn with_tcp_keepalive(mut self, tcp_keepalive: Option<TcpKeepalive>) -> Self {
+        self.tcp_keepalive = tcp_keepalive;
+        self
+    }
+
+    /// Set the redactor applied to the messages written to the logs
+    pub fn with_redactor(mut self, redactor: Redactor) -> Self {
+        self.redactor = redactor;
+        self
+    }
+
+    /// Set how the pings sent by the SLIM clients are handled
+    pub fn with_client_ping_mode(mut self, client_ping_mode: ClientPingMode) -> Self {
+        self.client_ping_mode = client_ping_mode;
+        self
+    }
+
+    /// Set how the `roots/list` requests of the MCP server are handled
+    pub fn with_roots_mode(mut self, roots_mode: RootsMode) -> Self {
+        self.roots_mode = roots_mode;
+        self
+    }
+
+    /// Set how the `elicitation/create` requests of the MCP server are handled
+    pub fn with_elicitation_mode(mut self, elicitation_mode: ElicitationMode) -> Self {
+        self.elicitation_mode = elicitation_mode;
+        self
+    }
+
+    /// Set the interval between pings to the clients, `None` disables pings
+    pub fn with_ping_interval(mut self, ping_interval: Option<Duration>) -> Self {
+        self.ping_interval = ping_interval;
+        self
+    }
+
+    /// Set how many pings can be left unanswered before closing a session
+    pub fn with_max_missed_pings(mut self, max_missed_pings: usize) -> Self {
+        self.max_pending_pings = Some(max_missed_pings);
+        self
+    }
+
+    /// Set the type of the ids of the pings sent to the clients
+    pub fn with_ping_id_format(mut self, ping_id_format: PingIdFormat) -> Self {
+        self.ping_id_format = ping_id_format;
+        self
+    }
+
+    /// Set which message of the client sets the SLIM connection the session
+    /// answers on, for clients whose first messages can arrive out of order
+    pub fn with_return_path(mut self, return_path: ReturnPath) -> Self {
+        self.return_path = return_path;
+        self
+    }
+
+    /// Send the pings to the clients before any pending data, so that a busy
+    /// session is not closed for missing pings
+    pub fn with_ping_priority(mut self, ping_priority: bool) -> Self {
+        self.ping_priority = ping_priority;
+        self
+    }
+
+    /// Only accept an MCP server reachable over https
+    pub fn with_require_tls(mut self, require_tls: bool) -> Self {
+        self.require_tls = require_tls;
+        self
+    }
+
+    /// Refuse to negotiate a TLS version below the given one with the MCP
+    /// servers. Implies [`ProxyBuilder::with_require_tls`].
+    pub fn with_min_tls_version(mut self, version: Option<MinTlsVersion>) -> Self {
+        self.min_tls_version = version;
+        self
+    }
+
+    /// Set the HTTP version of the connections to the MCP servers
+    pub fn with_http_version(mut self, http_version: HttpVersion) -> Self {
+        self.http_version = http_version;
+        self
+    }
+
+    /// Accept only the MCP server certificates whose SHA-256 fingerprint is
+    /// one of the given ones, on top of the usual verification. Several pins
+    /// allow rotating the certificate. Implies
+    /// [`ProxyBuilder::with_require_tls`].
+    pub fn with_pinned_certificates(mut self, pins: Vec<CertPin>) -> Self {
+        self.cert_pins = pins;
+        self
+    }
+
+    /// Present the certificate to the MCP servers asking for one. Implies
+    /// [`ProxyBuilder::with_require_tls`].
+    pub fn with_client_certificate(mut self, client_cert: Option<ClientCert>) -> Self {
+        self.client_cert = client_cert;
+        self
+    }
+
+    /// Check the files of the client certificate at the given interval and
+    /// use the new certificate for the next MCP connections when they change,
+    /// the open connections keep the previous one
+    pub fn with_tls_reload_interval(mut self, interval: Option<Duration>) -> Self {
+        self.tls_reload_interval = interval;
+        self
+    }
+
+    /// Open the connections to the MCP servers from the given local address,
+    /// e.g. when the backend only accepts some source addresses
+    pub fn with_bind_address(mut self, bind_address: Option<IpAddr>) -> Self {
+        self.bind_address = bind_address;
+        self
+    }
+
+    /// Decide with the given policy, every time the ping timer of a session
+    /// fires, whether to ping the client, skip the ping or close the session.
+    /// By default the session is closed after too many missed pings.
+    pub fn with_ping_policy(mut self, policy: Arc<dyn PingPolicy>) -> Self {
+        self.ping_policy = Some(policy);
+        self
+    }
+
+    /// Limit the number of concurrent sessions of each client. The limit of
+    /// the clients matching an override pattern is taken from the last
+    /// matching override.
+    pub fn with_source_session_limits(
+        mut self,
+        max_sessions_per_source: Option<usize>,
+        overrides: Vec<SourceLimit>,
+    ) -> Self {
+        self.max_sessions_per_source = max_sessions_per_source;
+        self.source_session_limits = overrides;
+        self
+    }
+
+    /// Limit the number of concurrent sessions of the proxy. When the proxy
+    /// is full, a new session closes the session of a client with a lower
+    /// priority, chosen with the shed policy, or is rejected.
+    pub fn with_max_sessions(
+        mut self,
+        max_sessions: Option<usize>,
+        priorities: Vec<SourcePriority>,
+    ) -> Self {
+        self.max_sessions = max_sessions;
+        self.source_priorities = priorities;
+        self
+    }
+
+    /// Accept client messages declaring another JSON-RPC version than 2.0, or
+    /// none, parsing them as JSON-RPC 2.0. They are rejected by default.
+    pub fn with_lenient_jsonrpc(mut self, lenient_jsonrpc: bool) -> Self {
+        self.parse_options.normalize_jsonrpc = lenient_jsonrpc;
+        self
+    }
+
+    /// Set the maximum nesting depth of the messages received from the clients
+    pub fn with_max_json_depth(mut self, max_depth: usize) -> Self {
+        self.parse_options.max_depth = max_depth;
+        self
+    }
+
+    /// Add the SLIM session id to the `_meta` of every request forwarded to the
+    /// MCP server under the given field name. The field is removed from the
+    /// messages sent back to the clients.
+    pub fn with_session_label_field(mut self, field: Option<String>) -> Self {
+        self.session_label_field = field;
+        self
+    }
+
+    /// Set what to do with the messages from the MCP server that cannot be
+    /// interpreted. Messages that cannot be serialized are never forwarded.
+    pub fn with_bad_server_message_policy(mut self, policy: BadServerMessagePolicy) -> Self {
+        self.bad_server_message_policy = policy;
+        self
+    }
+
+    /// Set what to do with a client request reusing the id of a request of
+    /// the same session still waiting for a response
+    pub fn with_duplicate_request_id_policy(mut self, policy: DuplicateRequestIdPolicy) -> Self {
+        self.duplicate_request_id_policy = policy;
+        self
+    }
+
+    /// Set what to do with a response of the MCP server whose id matches no
+    /// request forwarded by the session
+    pub fn with_unknown_response_policy(mut self, policy: UnknownResponsePolicy) -> Self {
+        self.unknown_response_policy = policy;
+        self
+    }
+
+    /// Drain the proxy on a shutdown signal, stopping it once the sessions
+    /// end or the grace period elapses. A second signal stops it right away.
+    pub fn with_shutdown_grace_period(mut self, grace_period: Option<Duration>) -> Self {
+        self.shutdown_grace_period = grace_period;
+        self
+    }
+
+    /// Start draining the proxy when the given file appears
+    pub fn with_drain_file(mut self, drain_file: Option<PathBuf>) -> Self {
+        self.drain_file = drain_file;
+        self
+    }
+
+    /// Publish the lifecycle and session events of the proxy as JSON lines
+    /// to the consumers of the Unix socket at the given path
+    pub fn with_event_socket(mut self, event_socket: Option<PathBuf>) -> Self {
+        self.event_socket = event_socket;
+        self
+    }
+
+    /// Serve the readiness of the proxy on `/readyz` and start draining it on
+    /// `POST /drain`, at the given address
+    pub fn with_admin_addr(mut self, admin_addr: Option<SocketAddr>) -> Self {
+        self.admin_addr = admin_addr;
+        self
+    }
+
+    /// Once ready, ask the instance with the admin endpoint at the given
+    /// address to hand over: it stops accepting new sessions and drains the
+    /// ones it serves, leaving the name to this instance
+    pub fn with_handover_from(mut self, previous: Option<reqwest::Url>) -> Self {
+        self.handover_from = previous;
+        self
+    }
+
+    /// Time given to the connections of the admin endpoint to end when the
+    /// proxy stops, after which they are closed
+    pub fn with_admin_shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.admin_shutdown_timeout = timeout;
+        self
+    }
+
+    /// Report an MCP server down on `/backends` after the given number of
+    /// consecutive connection failures, degraded before
+    pub fn with_backend_down_failures(mut self, failures: u32) -> Self {
+        self.backend_down_failures = failures;
+        self
+    }
+
+    /// Serve CPU profiles in the pprof format on `/debug/pprof/profile` of the
+    /// admin endpoint, to the requests bearing the token. Profiling slows the
+    /// proxy down while it lasts.
+    pub fn with_admin_profiling_token(mut self, token: Option<String>) -> Self {
+        self.admin_profiling_token = token;
+        self
+    }
+
+    /// Require the token on `/handover` of the admin endpoint, and present it
+    /// to the previous instance when taking over from it. Only the holders of
+    /// the token can name a successor to check before the handover.
+    pub fn with_admin_handover_token(mut self, token: Option<String>) -> Self {
+        self.admin_handover_token = token;
+        self
+    }
+
+    /// Serve the ping health of every open session on `/sessions` of the
+    /// admin endpoint: pending pings, round trip of the last answered ping
+    /// and whether the session is healthy, suspect or closing
+    pub fn with_admin_sessions(mut self, admin_sessions: bool) -> Self {
+        self.admin_sessions = admin_sessions;
+        self
+    }
+
+    /// Record the recent messages of every session in the given directory. The
+    /// file of a session is removed when the session is closed by the client.
+    pub fn with_session_wal(mut self, session_wal: Option<WalConfig>) -> Self {
+        self.session_wal = session_wal;
+        self
+    }
+
+    /// Serve the metrics in the Prometheus format on the given address
+    pub fn with_prometheus_addr(mut self, addr: Option<SocketAddr>) -> Self {
+        self.prometheus_addr = addr;
+        self
+    }
+
+    /// Send the metrics to a StatsD server
+    pub fn with_statsd(mut self, statsd: Option<StatsdConfig>) -> Self {
+        self.statsd = statsd;
+        self
+    }
+
+    /// Retry the handshake with the MCP server when the connection is closed
+    /// before the initialize response, e.g. while the server is starting up
+    pub fn with_handshake_retries(mut self, retries: usize, delay: Duration) -> Self {
+        self.handshake_retries = retries;
+        self.handshake_retry_delay = delay;
+        self
+    }
+
+    /// Limit the reconnections to the MCP server of all the sessions together
+    /// to the given number per second, so that a backend coming back from an
+    /// outage is not hit by every session at once
+    pub fn with_reconnect_rate(mut self, reconnect_rate: Option<u32>) -> Self {
+        self.reconnect_rate = reconnect_rate;
+        self
+    }
+
+    /// Close the session or reconnect to the MCP server after forwarding the
+    /// errors of the server with the given codes. Errors matching no rule are
+    /// only forwarded.
+    pub fn with_error_actions(mut self, rules: Vec<ErrorRule>) -> Self {
+        self.error_rules = rules;
+        self
+    }
+
+    /// Look for the given regular expressions in the text of the tool results,
+    /// redacting the matches or blocking the whole result
+    pub fn with_content_filter(mut self, patterns: Vec<String>, action: FilterAction) -> Self {
+        self.content_filter_patterns = patterns;
+        self.content_filter_action = action;
+        self
+    }
+
+    /// Queue the messages to a client for a task of their own, up to the
+    /// given number, and decide what to do with the new messages from the MCP
+    /// server when it is full. Without a size the messages are published right
+    /// away, unless batching or session credits require a queue.
+    pub fn with_outbound_queue(mut self, size: Option<usize>, policy: OverloadPolicy) -> Self {
+        self.outbound_queue_size = size;
+        self.overload_policy = policy;
+        self
+    }
+
+    /// Coalesce the messages from the MCP server queued for a client within
+    /// the window, up to the given number, into a single JSON-RPC batch.
+    /// Only for clients that understand batches.
+    pub fn with_batching(mut self, window: Option<Duration>, max_messages: usize) -> Self {
+        self.batch_window = window;
+        self.batch_max_messages = max_messages;
+        self
+    }
+
+    /// Grant every session the given number of messages and bytes to publish
+    /// to its client at each interval. A session that used them waits for the
+    /// next grant, or with the share policy only while other sessions publish.
+    pub fn with_session_credits(
+        mut self,
+        messages: Option<u64>,
+        bytes: Option<u64>,
+        interval: Duration,
+        policy: CreditPolicy,
+    ) -> Self {
+        self.credit_messages = messages;
+        self.credit_bytes = bytes;
+        self.credit_interval = interval;
+        self.credit_policy = policy;
+        self
+    }
+
+    /// Forward the request ids as strings prefixed with the session id, so
+    /// that the MCP server can tell apart the requests of different sessions.
+    /// The clients receive the responses with their original ids.
+    pub fn with_namespace_request_ids(mut self, namespace_request_ids: bool) -> Self {
+        self.namespace_request_ids = namespace_request_ids;
+        self
+    }
+
+    /// Remove the given fields from the `_meta` of the messages of the MCP
+    /// server before they are sent to the clients
+    pub fn with_strip_meta_fields(mut self, fields: Vec<String>) -> Self {
+        self.strip_meta_fields = fields;
+        self
+    }
+
+    /// Check the dataplane connection of the subscription at the given
+    /// interval and subscribe again on a new connection. `None` disables the
+    /// check.
+    pub fn with_subscription_check_interval(mut self, interval: Option<Duration>) -> Self {
+        self.subscription_check_interval = interval;
+        self
+    }
+
+    /// Number of attempts to subscribe the proxy name at startup, while the
+    /// dataplane connection may still be settling
+    pub fn with_subscribe_attempts(mut self, attempts: u32) -> Self {
+        self.subscribe_attempts = attempts;
+        self
+    }
+
+    /// Set what to do when the SLIM app stops notifying new sessions, e.g.
+    /// when its receiver closes during a dataplane reconnection
+    pub fn with_stream_end_policy(mut self, policy: StreamEndPolicy) -> Self {
+        self.stream_end_policy = policy;
+        self
+    }
+
+    /// Handle the messages received by the proxy outside of any session. They
+    /// are ignored by default.
+    pub fn with_app_message_handler(mut self, handler: Box<dyn AppMessageHandler>) -> Self {
+        self.app_message_handler = handler;
+        self
+    }
+
+    /// Reject the client requests beyond the given number of unanswered
+    /// requests in a session
+    pub fn with_max_pending_requests(mut self, max_pending_requests: Option<usize>) -> Self {
+        self.max_pending_requests = max_pending_requests;
+        self
+    }
+
+    /// Hold the client requests while the given number of requests forwarded
+    /// on the MCP connection of the session are not answered, forwarding them
+    /// in order as the responses come back
+    pub fn with_max_in_flight_requests(mut self, max_in_flight_requests: Option<usize>) -> Self {
+        self.max_in_flight_requests = max_in_flight_requests;
+        self
+    }
+
+    /// Answer the client requests whose method is neither an MCP method nor
+    /// one of the extension methods with a "method not found" error, instead
+    /// of forwarding them
+    pub fn with_method_validation(
+        mut self,
+        validate_methods: bool,
+        extension_methods: Vec<String>,
+    ) -> Self {
+        self.validate_methods = validate_methods;
+        self.extension_methods = extension_methods;
+        self
+    }
+
+    /// Limit the number of sessions in the handshake with the MCP server at
+    /// the same time. The initialize requests beyond the limit wait for a
+    /// handshake in progress to complete.
+    pub fn with_max_concurrent_setups(mut self, max_concurrent_setups: Option<usize>) -> Self {
+        self.max_concurrent_setups = max_concurrent_setups;
+        self
+    }
+
+    /// Write the log notifications of the MCP server to the proxy log too,
+    /// besides forwarding them to the clients
+    pub fn with_mirror_server_logs(mut self, mirror_server_logs: bool) -> Self {
+        self.mirror_server_logs = mirror_server_logs;
+        self
+    }
+
+    /// Log the repetitions of the same session error, across all the
+    /// sessions, once per window, with the number of occurrences not logged
+    pub fn with_log_throttle(mut self, window: Option<Duration>) -> Self {
+        self.log_throttle_window = window;
+        self
+    }
+
+    /// Delay and drop the messages of the sessions, in both directions, to
+    /// test the resilience of the clients. Never meant for production.
+    pub fn with_chaos(mut self, chaos: Option<Chaos>) -> Self {
+        self.chaos = chaos;
+        self
+    }
+
+    /// Add the times the proxy received and forwarded each message, in both
+    /// directions, to the `_meta` field `io.agntcy.slim/timing`
+    pub fn with_stamp_timings(mut self, stamp_timings: bool) -> Self {
+        self.stamp_timings = stamp_timings;
+        self
+    }
+
+    /// Send the client a last notification with the reason why its session
+    /// ended, the duration of the session and its message counts
+    pub fn with_send_close_diagnostics(mut self, send_close_diagnostics: bool) -> Self {
+        self.send_close_diagnostics = send_close_diagnostics;
+        self
+    }
+
+    /// Answer at the proxy a `resources/subscribe` request for a resource the
+    /// session is already subscribed to, instead of forwarding it
+    pub fn with_coalesce_subscriptions(mut self, coalesce_subscriptions: bool) -> Self {
+        self.coalesce_subscriptions = coalesce_subscriptions;
+        self
+    }
+
+    /// Close the sessions the given time after they start, whatever their
+    /// activity. A client can set an earlier deadline in the metadata of its
+    /// session.
+    pub fn with_session_deadline(mut self, session_deadline: Option<Duration>) -> Self {
+        self.session_deadline = session_deadline;
+        self
+    }
+
+    /// Keep the MCP connection of a client that went away for the given time.
+    /// A new session of the same client within that time resumes the MCP
+    /// session, with the state the backend keeps for it.
+    pub fn with_session_affinity(mut self, window: Option<Duration>) -> Self {
+        self.session_affinity_window = window;
+        self
+    }
+
+    /// Keep the given number of connections to the MCP server warmed, with
+    /// the handshake done, for the new sessions. The clients get the result
+    /// of the handshake of the proxy, so this suits stateless backends only.
+    pub fn with_mcp_pool_size(mut self, mcp_pool_size: Option<usize>) -> Self {
+        self.mcp_pool_size = mcp_pool_size;
+        self
+    }
+
+    /// Label the session counters with the client of the sessions
+    pub fn with_source_labels(mut self, source_labels: SourceLabels) -> Self {
+        self.source_labels = source_labels;
+        self
+    }
+
+    /// Close sessions, chosen with the given policy, while the resident
+    /// memory of the proxy is above the limit in bytes
+    pub fn with_memory_limit(mut self, memory_limit: Option<u64>, shed_policy: ShedPolicy) -> Self {
+        self.memory_limit = memory_limit;
+        self.shed_policy = shed_policy;
+        self
+    }
+
+    /// Bound the memory taken by a single SSE event or JSON response of the
+    /// MCP server. Larger messages are dropped.
+    pub fn with_read_buffer_bytes(mut self, read_buffer_bytes: usize) -> Self {
+        self.read_buffer_bytes = read_buffer_bytes;
+        self
+    }
+
+    /// Accept the sessions of the clients whose name matches a pattern of the
+    /// given allowlist file only. The names are claimed by the clients, the
+    /// filtering is advisory.
+    pub fn with_source_allowlist(mut self, allowlist: Option<PathBuf>) -> Self {
+        self.source_allowlist = allowlist;
+        self
+    }
+
+    /// Connect the sessions to the given MCP server when the primary one
+    /// cannot be reached, after the handshake retries
+    pub fn with_mcp_server_fallback(mut self, fallback: Option<String>) -> Self {
+        self.mcp_server_fallback = fallback;
+        self
+    }
+
+    /// MCP servers the clients select by name in the metadata of their
+    /// session, and what to do with a session selecting an unknown name. The
+    /// sessions that select none connect to the MCP server.
+    pub fn with_logical_servers(
+        mut self,
+        servers: Vec<LogicalServer>,
+        unknown_server_policy: UnknownServerPolicy,
+    ) -> Self {
+        self.logical_servers = servers;
+        self.unknown_server_policy = unknown_server_policy;
+        self
+    }
+
+    /// Connect to the MCP servers at the normalized form of their addresses,
+    /// see [`normalize_mcp_url`]
+    pub fn with_normalize_mcp_urls(mut self, normalize_mcp_urls: bool) -> Self {
+        self.normalize_mcp_urls = normalize_mcp_urls;
+        self
+    }
+
+    /// Wait a random delay up to the given maximum before serving, to spread
+    /// the load on the MCP server when many proxies start together
+    pub fn with_startup_jitter(mut self, startup_jitter: Duration) -> Self {
+        self.startup_jitter = startup_jitter;
+        self
+    }
+
+    /// Set the maximum time to wait for the SLIM service to establish its
+    /// connections. Once elapsed the proxy goes on with the connections
+    /// established so far.
+    pub fn with_service_run_timeout(mut self, timeout: Duration) -> Self {
+        self.service_run_timeout = timeout;
+        self
+    }
+
+    /// Subscribe on the dataplane client with the given endpoint, as written
+    /// in the configuration, instead of the first one
+    pub fn with_subscribe_endpoint(mut self, endpoint: Option<String>) -> Self {
+        self.subscribe_endpoint = endpoint;
+        self
+    }
+
+    /// Validate the configuration and build the proxy. All the conflicting
+    /// settings are reported at once.
+    pub fn build(mut self) -> Result<Proxy, ProxyError> {
+        let mut conflicts = Vec::new();
+
+        // a TLS version floor makes no sense on a plain connection
+        self.require_tls |= self.min_tls_version.is_some()
+            || !self.cert_pins.is_empty()
+            || self.client_cert.is_some();
+        let tls = if self.cert_pins.is_empty() && self.client_cert.is_none() {
+            None
+        } else {
+            match McpTls::new(
+                self.cert_pins.clone(),
+                self.min_tls_version == Some(MinTlsVersion::Tls1_3),
+                self.http_version.alpn_protocols(),
+                self.client_cert.clone(),
+            ) {
+                Ok(tls) => Some(Arc::new(tls)),
+                Err(e) => {
+                    conflicts.push(format!("TLS of the MCP connections: {}", e));
+                    None
+                }
+            }
+        };
+        if self.tls_reload_interval.is_some() && self.client_cert.is_none() {
+            conflicts.push("TLS reload interval is set but no client certificate".into());
+        }
+        if self
+            .tls_reload_interval
+            .is_some_and(|interval| interval.is_zero())
+        {
+            conflicts.push("TLS reload interval must be greater than zero".into());
+        }
+
+        // an address not assigned to the host would fail every session
+        if let Some(addr) = self.bind_address
+            && let Err(e) = std::net::TcpListener::bind((addr, 0))
+        {
+            conflicts.push(format!("cannot bind to {}: {}", addr, e));
+        }
+
+        check_mcp_url(
+            "MCP server",
+            &self.mcp_server,
+            self.require_tls,
+            &mut conflicts,
+        );
+        if let Some(fallback) = &self.mcp_server_fallback {
+            check_mcp_url(
+                "fallback MCP server",
+                fallback,
+                self.require_tls,
+                &mut conflicts,
+            );
+        }
+        if let Some(shadow) = &self.shadow_mcp_server {
+            check_mcp_url(
+                "shadow MCP server",
+                shadow,
+                self.require_tls,
+                &mut conflicts,
+            );
+        }
+        let mut logical_names = HashSet::new();
+        for server in &self.logical_servers {
+            if !logical_names.insert(server.name.as_str()) {
+                conflicts.push(format!(
+                    "logical MCP server {} is defined twice",
+                    server.name
+                ));
+            }
+            check_mcp_url(
+                &format!("logical MCP server {}", server.name),
+                &server.url,
+                self.require_tls,
+                &mut conflicts,
+            );
+        }
+        // the same backend spelled differently would get redundant connections
+        let backend = normalize_mcp_url(&self.mcp_server);
+        if self.mcp_server_fallback.as_deref().map(normalize_mcp_url) == Some(backend.clone()) {
+            conflicts.push("the fallback MCP server is the MCP server".into());
+        }
+        if self.shadow_mcp_server.as_deref().map(normalize_mcp_url) == Some(backend) {
+            conflicts.push(
+                "the shadow MCP server is the MCP server, it would receive every request twice"
+                    .into(),
+            );
+        }
+        if self.normalize_mcp_urls {
+            self.mcp_server = normalize_mcp_url(&self.mcp_server);
+            self.mcp_server_fallback = self.mcp_server_fallback.as_deref().map(normalize_mcp_url);
+            self.shadow_mcp_server = self.shadow_mcp_server.as_deref().map(normalize_mcp_url);
+            for server in &mut self.logical_servers {
+                server.url = normalize_mcp_url(&server.url);
+            }
+        }
+
+        match (self.ping_interval, self.max_pending_pings) {
+            (Some(interval), _) if interval.is_zero() => {
+                conflicts.push("ping interval must be greater than zero".into())
+            }
+            (Some(_), Some(0)) => conflicts.push(
+                "max missed pings is 0 while pings are enabled, every session would be closed at the first ping".into(),
+            ),
+            (None, Some(_)) => {
+                conflicts.push("max missed pings is set but pings are disabled".into())
+            }
+            _ => {}
+        }
+
+        if let Some(keepalive) = &self.tcp_keepalive
+            && (keepalive.idle.is_zero() || keepalive.interval.is_zero() || keepalive.retries == 0)
+        {
+            conflicts
+                .push("TCP keepalive idle, interval and retries must be greater than zero".into());
+        }
+
+        if self.parse_options.max_depth == 0
+            || self.parse_options.max_depth > message::SERDE_MAX_JSON_DEPTH
+        {
+            conflicts.push(format!(
+                "max JSON depth must be between 1 and {}",
+                message::SERDE_MAX_JSON_DEPTH
+            ));
+        }
+
+        if self
+            .session_label_field
+            .as_ref()
+            .is_some_and(|f| f.trim().is_empty())
+        {
+            conflicts.push("session label field name cannot be empty".into());
+        }
+
+        if self.strip_meta_fields.iter().any(|f| f.trim().is_empty()) {
+            conflicts.push("stripped _meta field name cannot be empty".into());
+        }
+
+        if let Some(session_wal) = &self.session_wal {
+            if session_wal.max_bytes == 0 {
+                conflicts.push("session write-ahead log max size must be greater than zero".into());
+            }
+            if let Err(e) = std::fs::create_dir_all(&session_wal.dir) {
+                conflicts.push(format!(
+                    "cannot create session write-ahead log directory {}: {}",
+                    session_wal.dir.display(),
+                    e
+                ));
+            }
+        }
+
+        if let Some(statsd) = &self.statsd {
+            if statsd.addr.trim().is_empty() {
+                conflicts.push("StatsD address cannot be empty".into());
+            }
+            if statsd.flush_interval.is_zero() {
+                conflicts.push("StatsD flush interval must be greater than zero".into());
+            }
+        }
+
+        let discovery = match self.mcp_discovery.map(Discovery::new).transpose() {
+            Ok(discovery) => discovery,
+            Err(e) => {
+                conflicts.push(format!("cannot set up MCP server discovery: {}", e));
+                None
+            }
+        };
+
+        let content_filter = if self.content_filter_patterns.is_empty() {
+            None
+        } else {
+            match ContentFilter::new(&self.content_filter_patterns, self.content_filter_action) {
+                Ok(filter) => Some(filter),
+                Err(e) => {
+                    conflicts.push(format!("invalid content filter pattern: {}", e));
+                    None
+                }
+            }
+        };
+
+        if self.max_pending_requests == Some(0) {
+            conflicts.push("max pending requests must be greater than zero".into());
+        }
+
+        if self.max_in_flight_requests == Some(0) {
+            conflicts.push("max in-flight requests must be greater than zero".into());
+        }
+        if self.reconnect_rate == Some(0) {
+            conflicts.push("reconnect rate must be greater than zero".into());
+        }
+        if !self.extension_methods.is_empty() && !self.validate_methods {
+            conflicts.push("extension methods are allowed but methods are not validated".into());
+        }
+
+        match self.admin_profiling_token.as_deref() {
+            Some("") => conflicts.push("admin profiling token cannot be empty".into()),
+            Some(_) if self.admin_addr.is_none() => {
+                conflicts.push("admin profiling token is set but the admin endpoint is not".into())
+            }
+            _ => {}
+        }
+        match self.admin_handover_token.as_deref() {
+            Some("") => conflicts.push("admin handover token cannot be empty".into()),
+            Some(_) if self.admin_addr.is_none() && self.handover_from.is_none() => conflicts
+                .push("admin handover token is set but neither the admin endpoint nor the previous instance is".into()),
+            _ => {}
+        }
+        if self.admin_sessions && self.admin_addr.is_none() {
+            conflicts.push("admin sessions are enabled but the admin endpoint is not".into());
+        }
+
+        if self.max_concurrent_setups == Some(0) {
+            conflicts.push("max concurrent setups must be greater than zero".into());
+        }
+
+        if self.max_sessions_per_source == Some(0) {
+            conflicts.push("max sessions per source must be greater than zero".into());
+        }
+
+        if self.max_sessions == Some(0) {
+            conflicts.push("max sessions must be greater than zero".into());
+        }
+
+        if self.read_buffer_bytes == 0 {
+            conflicts.push("read buffer size must be greater than zero".into());
+        }
+
+        if self.subscribe_attempts == 0 {
+            conflicts.push("subscribe attempts must be greater than zero".into());
+        }
+
+        if self.outbound_queue_size == Some(0) {
+            conflicts.push("outbound queue size must be greater than zero".into());
+        }
+        // batching and credits wait in the publishing task, behind a queue
+        let queued = self.batch_window.is_some()
+            || self.credit_messages.is_some()
+            || self.credit_bytes.is_some();
+        let outbound_queue_size = self
+            .outbound_queue_size
+            .or(queued.then_some(DEFAULT_OUTBOUND_QUEUE_SIZE));
+        if outbound_queue_size.is_none() && self.overload_policy != OverloadPolicy::Backpressure {
+            conflicts.push("overload policy is set but the messages to the clients are not queued, set the outbound queue size".into());
+        }
+        if self.batch_window.is_some_and(|w| w.is_zero()) || self.batch_max_messages == 0 {
+            conflicts.push("batch window and size must be greater than zero".into());
+        }
+        if self.credit_interval.is_zero()
+            || self.credit_messages == Some(0)
+            || self.credit_bytes == Some(0)
+        {
+            conflicts.push("session credits and their interval must be greater than zero".into());
+        }
+
+        let source_allowlist = match self
+            .source_allowlist
+            .as_deref()
+            .map(Allowlist::load)
+            .transpose()
+        {
+            Ok(allowlist) => allowlist,
+            Err(e) => {
+                conflicts.push(e.to_string());
+                None
+            }
+        };
+
+        if self.service_run_timeout.is_zero() {
+            conflicts.push("service run timeout must be greater than zero".into());
+        }
+
+        if let Some(size) = self.mcp_pool_size {
+            if size == 0 {
+                conflicts.push("MCP pool size must be greater than zero".into());
+            }
+            // pooled connections are opened before the client is known
+            if !self.source_headers.is_empty() {
+                conflicts.push("the MCP pool cannot be used with source headers".into());
+            }
+        }
+
+        if !conflicts.is_empty() {
+            return Err(ProxyError::InvalidConfig(conflicts));
+        }
+
+        let mut exporters: Vec<Box<dyn Exporter>> = Vec::new();
+        if let Some(addr) = self.prometheus_addr {
+            exporters.push(Box::new(PrometheusExporter::new(addr)));
+        }
+        if let Some(statsd) = self.statsd {
+            exporters.push(Box::new(StatsdExporter::new(statsd)));
+        }
+        let metrics = Arc::new(Metrics::new(self.source_labels));
+        let mut backends: Vec<_> = std::iter::once((&self.mcp_server, BackendRole::Primary))
+            .chain(
+                self.mcp_server_fallback
+                    .iter()
+                    .map(|f| (f, BackendRole::Fallback)),
+            )
+            .chain(
+                self.logical_servers
+                    .iter()
+                    .map(|s| (&s.url, BackendRole::Logical)),
+            )
+            .map(|(url, role)| (RedactedUrl(&normalize_mcp_url(url)).to_string(), role))
+            .collect();
+        // a logical server may also be the MCP server, keep its first role
+        let mut seen = HashSet::new();
+        backends.retain(|(url, _)| seen.insert(url.clone()));
+        let events = Events::default();
+        let admin = Arc::new(AdminState::new(
+            backends,
+            self.backend_down_failures,
+            self.admin_profiling_token.as_deref(),
+            self.admin_handover_token.as_deref(),
+            events.clone(),
+            self.admin_sessions,
+        ));
+        let mut strip_meta_fields = self.strip_meta_fields;
+        strip_meta_fields.extend(self.session_label_field.clone());
+        let (mcp_pool, mcp_pool_filler) = match self.mcp_pool_size {
+            Some(size) => {
+                let (pool, filler) = McpPool::new(size);
+                (Some(Arc::new(pool)), Some(filler))
+            }
+            None => (None, None),
+        };
+
+        Ok(Proxy {
+            name: self.name,
+            config: SessionConfig {
+                mcp_server: self.mcp_server,
+                mcp_server_fallback: self.mcp_server_fallback,
+                discovery,
+                dns_timeout: self.dns_timeout,
+                resolved_hosts: Arc::default(),
+                shadow_mcp_server: self.shadow_mcp_server,
+                source_headers: self.source_headers,
+                tcp_keepalive: self.tcp_keepalive,
+                min_tls_version: self.min_tls_version,
+                http_version: self.http_version,
+                tls,
+                bind_address: self.bind_address,
+                redactor: self.redactor,
+                client_ping_mode: self.client_ping_mode,
+                roots_mode: self.roots_mode,
+                elicitation_mode: self.elicitation_mode,
+                ping_interval: self.ping_interval,
+                max_pending_pings: self.max_pending_pings.unwrap_or(MAX_PENDING_PINGS),
+                ping_id_format: self.ping_id_format,
+                return_path: self.return_path,
+                ping_policy: self
+                    .ping_policy
+                    .unwrap_or_else(|| Arc::new(MissedPingsPolicy)),
+                ping_priority: self.ping_priority,
+                parse_options: self.parse_options,
+                session_label_field: self.session_label_field,
+                strip_meta_fields,
+                bad_server_message_policy: self.bad_server_message_policy,
+                duplicate_request_id_policy: self.duplicate_request_id_policy,
+                unknown_response_policy: self.unknown_response_policy,
+                session_wal: self.session_wal,
+                metrics: metrics.clone(),
+                handshake_retries: self.handshake_retries,
+                handshake_retry_delay: self.handshake_retry_delay,
+                reconnect_limiter: self
+                    .reconnect_rate
+                    .map(|rate| Arc::new(ReconnectLimiter::new(rate))),
+                error_rules: self.error_rules,
+                content_filter,
+                outbound_queue_size,
+                overload_policy: self.overload_policy,
+                batching: self.batch_window.map(|window| Batching {
+                    window,
+                    max_messages: self.batch_max_messages,
+                }),
+                credits: (self.credit_messages.is_some() || self.credit_bytes.is_some()).then(
+                    || {
+                        Arc::new(CreditScheduler::new(
+                            CreditConfig {
+                                messages: self.credit_messages,
+                                bytes: self.credit_bytes,
+                                interval: self.credit_interval,
+                                policy: self.credit_policy,
+                            },
+                            metrics.clone(),
+                        ))
+                    },
+                ),
+                namespace_request_ids: self.namespace_request_ids,
+                max_pending_requests: self.max_pending_requests,
+                max_in_flight_requests: self.max_in_flight_requests,
+                allowed_methods: self
+                    .validate_methods
+                    .then(|| self.extension_methods.into_iter().collect()),
+                session_deadline: self.session_deadline,
+                setup_permits: self
+                    .max_concurrent_setups
+                    .map(|max| Arc::new(Semaphore::new(max))),
+                mirror_server_logs: self.mirror_server_logs,
+                log_throttle: Arc::new(LogThrottle::new(self.log_throttle_window)),
+                chaos: self.chaos,
+                stamp_timings: self.stamp_timings,
+                send_close_diagnostics: self.send_close_diagnostics,
+                coalesce_subscriptions: self.coalesce_subscriptions,
+                read_buffer_bytes: self.read_buffer_bytes,
+                mcp_pool,
+                affinity: self
+                    .session_affinity_window
+                    .map(|window| Arc::new(SessionAffinity::new(window))),
+                logical_servers: self
+                    .logical_servers
+                    .into_iter()
+                    .map(|s| (s.name, s.url))
+                    .collect(),
+                unknown_server_policy: self.unknown_server_policy,
+                admin: admin.clone(),
+                events,
+            },
+            connections: HashMap::new(),
+            drain_file: self.drain_file,
+            event_socket: self.event_socket,
+            admin_addr: self.admin_addr,
+            handover_from: self.handover_from,
+            handover_token: self.admin_handover_token,
+            admin_shutdown_timeout: self.admin_shutdown_timeout,
+            shutdown_grace_period: self.shutdown_grace_period,
+            shutdown: Arc::new(Notify::new()),
+            tls_reload_interval: self.tls_reload_interval,
+            admin,
+            metrics,
+            exporters,
+            startup_jitter: self.startup_jitter,
+            service_run_timeout: self.service_run_timeout,
+            subscribe_endpoint: self.subscribe_endpoint,
+            subscription_check_interval: self.subscription_check_interval,
+            subscribe_attempts: self.subscribe_attempts,
+            stream_end_policy: self.stream_end_policy,
+            app_message_handler: self.app_message_handler,
+            source_allowlist,
+            max_sessions_per_source: self.max_sessions_per_source,
+            source_session_limits: self.source_session_limits,
+            max_sessions: self.max_sessions,
+            source_priorities: self.source_priorities,
+            mcp_pool_filler,
+            memory_limit: self.memory_limit,
+            shed_policy: self.shed_policy,
+        })
+    }
+}
+
+impl Proxy {
+    /// Record the time spent in a startup phase, e.g. loading the configuration
+    pub fn record_startup_phase(&self, phase: &'static str, elapsed: Duration) {
+        debug!(phase, ?elapsed, "startup phase completed");
+        self.metrics.startup_phases.observe(phase, elapsed);
+    }
+
+    pub fn builder(name: Name, mcp_server: String) -> ProxyBuilder {
+        ProxyBuilder {
+            name,
+            mcp_server,
+            mcp_discovery: None,
+            dns_timeout: None,
+            shadow_mcp_server: None,
+            source_headers: Vec::new(),
+            tcp_keepalive: Some(TcpKeepalive::default()),
+            redactor: Redactor::default(),
+            client_ping_mode: ClientPingMode::default(),
+            roots_mode: RootsMode::default(),
+            elicitation_mode: ElicitationMode::default(),
+            ping_interval: Some(PING_INTERVAL),
+            max_pending_pings: None,
+            ping_id_format: PingIdFormat::default(),
+            return_path: ReturnPath::default(),
+            ping_priority: true,
+            require_tls: false,
+            parse_options: ParseOptions::default(),
+            session_label_field: None,
+            bad_server_message_policy: BadServerMessagePolicy::default(),
+            duplicate_request_id_policy: DuplicateRequestIdPolicy::default(),
+            unknown_response_policy: UnknownResponsePolicy::default(),
+            drain_file: None,
+            event_socket: None,
+            admin_addr: None,
+            handover_from: None,
+            admin_shutdown_timeout: DEFAULT_ADMIN_SHUTDOWN_TIMEOUT,
+            shutdown_grace_period: None,
+            backend_down_failures: DEFAULT_BACKEND_DOWN_FAILURES,
+            admin_profiling_token: None,
+            admin_handover_token: None,
+            admin_sessions: false,
+            session_wal: None,
+            prometheus_addr: None,
+            statsd: None,
+            handshake_retries: 0,
+            handshake_retry_delay: HANDSHAKE_RETRY_DELAY,
+            reconnect_rate: None,
+            error_rules: Vec::new(),
+            startup_jitter: Duration::ZERO,
+            service_run_timeout: SERVICE_RUN_TIMEOUT,
+            subscribe_endpoint: None,
+            content_filter_patterns: Vec::new(),
+            content_filter_action: FilterAction::default(),
+            outbound_queue_size: None,
+            overload_policy: OverloadPolicy::default(),
+            batch_window: None,
+            batch_max_messages: DEFAULT_BATCH_MAX_MESSAGES,
+            credit_messages: None,
+            credit_bytes: None,
+            credit_interval: DEFAULT_CREDIT_INTERVAL,
+            credit_policy: CreditPolicy::default(),
+            namespace_request_ids: false,
+            strip_meta_fields: Vec::new(),
+            subscription_check_interval: Some(SUBSCRIPTION_CHECK_INTERVAL),
+            subscribe_attempts: DEFAULT_SUBSCRIBE_ATTEMPTS,
+            stream_end_policy: StreamEndPolicy::default(),
+            app_message_handler: Box::new(IgnoreAppMessages),
+            max_pending_requests: None,
+            max_in_flight_requests: None,
+            validate_methods: false,
+            extension_methods: Vec::new(),
+            max_concurrent_setups: None,
+            mirror_server_logs: false,
+            log_throttle_window: None,
+            chaos: None,
+            stamp_timings: false,
+            send_close_diagnostics: false,
+            coalesce_subscriptions: false,
+            read_buffer_bytes: DEFAULT_READ_BUFFER_BYTES,
+            mcp_pool_size: None,
+            session_affinity_window: None,
+            session_deadline: None,
+            source_labels: SourceLabels::default(),
+            memory_limit: None,
+            shed_policy: ShedPolicy::default(),
+            source_allowlist: None,
+            mcp_server_fallback: None,
+            logical_servers: Vec::new(),
+            unknown_server_policy: UnknownServerPolicy::default(),
+            normalize_mcp_urls: false,
+            min_tls_version: None,
+            http_version: HttpVersion::default(),
+            cert_pins: Vec::new(),
+            client_cert: None,
+            tls_reload_interval: None,
+            bind_address: None,
+            ping_policy: None,
+            max_sessions_per_source: None,
+            source_session_limits: Vec::new(),
+            max_sessions: None,
+            source_priorities: Vec::new(),
+        }
+    }
+
+    pub async fn start(
+        &mut self,
+        service: slim_service::Service,
+        identity_config: IdentityConfig,
+        drain_timeout: std::time::Duration,
+    ) -> Result<(), ProxyError> {
+        // the proxy subscribes through the chosen dataplane client, the
+        // first one by default
+        let endpoint = subscribe_endpoint(
+            service.config().dataplane_clients(),
+            self.subscribe_endpoint.as_ref(),
+        )?;
+
+        // tasks running as long as the proxy, aborted when it stops or fails
+        // to start
+        let mut background = JoinSet::new();
+
+        if let (Some(tls), Some(interval)) = (&self.config.tls, self.tls_reload_interval) {
+            background.spawn(tls.clone().watch(interval));
+        }
+
+        if let Some(filler) = self.mcp_pool_filler.take() {
+            background.spawn(self.config.clone().fill_mcp_pool(
+                self.name.clone(),
+                filler,
+                POOL_HEALTH_CHECK_INTERVAL,
+            ));
+        }
+
+        // stopped with the proxy, or removed if the proxy fails to start
+        let event_socket = match &self.event_socket {
+            Some(path) => Some(
+                EventSocket::bind(path.clone(), self.config.events.clone()).map_err(|source| {
+                    ProxyError::EventSocket {
+                        path: path.clone(),
+                        source,
+                    }
+                })?,
+            ),
+            None => None,
+        };
+
+        // stopped with the proxy, or aborted if the proxy fails to start
+        let admin_server = self
+            .admin_addr
+            .map(|addr| AdminServer::spawn(addr, self.admin.clone()));
+
+        for exporter in self.exporters.drain(..) {
+            let metrics = self.metrics.clone();
+            background.spawn(async move {
+                if let Err(e) = exporter.run(metrics).await {
+                    error!(
+                        exporter = exporter.name(),
+                        "metrics exporter stopped: {}", e
+                    );
+                }
+            });
+        }
+
+        if !self.startup_jitter.is_zero() {
+            let delay = rand::random_range(Duration::ZERO..=self.startup_jitter);
+            info!(?delay, "waiting before serving (startup jitter)");
+            tokio::time::sleep(delay).await;
+        }
+
+        // replicas of a SPIRE workload share the SPIFFE id, and so the app id
+        let shared_identity = matches!(identity_config, IdentityConfig::Spire { .. });
+        let (provider, verifier): (AuthProvider, AuthVerifier) = match identity_config {
+            IdentityConfig::SharedSecret(secret) => {
+                info!("Using shared-secret authentication");
+                let provider =
+                    SharedSecret::new("proxy", &secret).expect("Failed to create SharedSecret");
+                let verifier =
+                    SharedSecret::new("proxy", &secret).expect("Failed to create SharedSecret");
+                (
+                    AuthProvider::shared_secret(provider),
+                    AuthVerifier::shared_secret(verifier),
+                )
+            }
+            IdentityConfig::Spire {
+                socket_path,
+                target_spiffe_id,
+                jwt_audiences,
+            } => {
+                info!("Using SPIRE authentication");
+
+                // Build provider manager and verifier manager
+                let mut provider_builder = SpireIdentityManager::builder();
+                let mut verifier_builder = SpireIdentityManager::builder();
+                if let Some(path) = &socket_path {
+                    provider_builder = provider_builder.with_socket_path(path.clone());
+                    verifier_builder = verifier_builder.with_socket_path(path.clone());
+                }
+                if let Some(spiffe_id) = &target_spiffe_id {
+                    provider_builder = provider_builder.with_target_spiffe_id(spiffe_id.clone());
+                    verifier_builder = verifier_builder.with_target_spiffe_id(spiffe_id.clone());
+                }
+                if !jwt_audiences.is_empty() {
+                    provider_builder = provider_builder.with_jwt_audiences(jwt_audiences.clone());
+                    verifier_builder = verifier_builder.with_jwt_audiences(jwt_audiences.clone());
+                }
+
+                let mut provider_manager = provider_builder
+                    .build()
+                    .expect("Failed to build SpireIdentityManager for provider");
+                provider_manager
+                    .initialize()
+                    .await
+                    .expect("Failed to initialize SpireIdentityManager for provider");
+
+                let mut verifier_manager = verifier_builder
+                    .build()
+                    .expect("Failed to build SpireIdentityManager for verifier");
+                verifier_manager
+                    .initialize()
+                    .await
+                    .expect("Failed to initialize SpireIdentityManager for verifier");
+
+                (
+                    AuthProvider::spire(provider_manager),
+                    AuthVerifier::spire(verifier_manager),
+                )
+            }
+        };
+
+        let (mut app, mut slim_rx) = service
+            .create_app(&self.name, provider.clone(), verifier.clone())
+            .map_err(ProxyError::CreateApp)?;
+        log_topology(app.app_name(), shared_identity);
+
+        // run the service - this will create all the connections provided via the config file.
+        // A shutdown signal received meanwhile stops the proxy right away.
+        let phase_started = Instant::now();
+        let cancel = CancellationToken::new();
+        let signal_watch = tokio::spawn({
+            let cancel = cancel.clone();
+            let shutdown = self.shutdown.clone();
+            async move {
+                shutdown_signal(&shutdown).await;
+                cancel.cancel();
+            }
+        });
+        let started = run_service(&service, self.service_run_timeout, &cancel).await;
+        signal_watch.abort();
+        match started? {
+            ServiceStart::Started => {}
+            ServiceStart::TimedOut => warn!(
+                timeout = ?self.service_run_timeout,
+                "SLIM service not started in time, going on with the established connections"
+            ),
+            ServiceStart::Cancelled => {
+                info!("Received shutdown signal while starting, stop mcp-proxy");
+                if let Err(e) = service.shutdown().await {
+                    warn!("error shutting down the SLIM service: {}", e);
+                }
+                return Ok(());
+            }
+        }
+
+        self.record_startup_phase("service_run", phase_started.elapsed());
+
+        // get the connection id
+        let phase_started = Instant::now();
+        let conn_id = service
+            .get_connection_id(&endpoint)
+            .ok_or_else(|| not_connected(&service, &endpoint))?;
+
+        self.record_startup_phase("get_connection_id", phase_started.elapsed());
+
+        // subscribe for local name
+        let phase_started = Instant::now();
+        subscribe_with_retry(self.subscribe_attempts, conn_id, || {
+            app.subscribe(&self.name, Some(conn_id))
+        })
+        .await?;
+        self.record_startup_phase("subscribe", phase_started.elapsed());
+
+        // the subscription is bound to the dataplane connection: when the
+        // connection is re-established the proxy must subscribe again
+        let mut subscribed_conn = Some(conn_id);
+        let memory_limit = self.memory_limit.filter(|_| {
+            let known = memory::resident_bytes().is_some();
+            if !known {
+                warn!("resident memory unknown on this platform, the memory limit is not enforced");
+            }
+            known
+        });
+        let mut memory_check = tokio::time::interval(MEMORY_CHECK_INTERVAL);
+
+        // the errors suppressed by the log throttle are reported once their
+        // window ends, even if they do not occur again
+        let log_window = self.config.log_throttle.window();
+        // not polled without a window
+        let mut log_flush = tokio::time::interval(log_window.unwrap_or(MEMORY_CHECK_INTERVAL));
+
+        let mut subscription_check = tokio::time::interval(
+            self.subscription_check_interval
+                .unwrap_or(SUBSCRIPTION_CHECK_INTERVAL),
+        );
+        let mut resubscribe_backoff = subscription_check.period();
+        let mut resubscribe_at = tokio::time::Instant::now();
+
+        // sessions notify their end on this channel
+        let (tx_session_end, mut rx_session_end) = mpsc::unbounded_channel();
+
+        // drain state: no new sessions are accepted and the proxy stops once
+        // the existing sessions end or the drain timeout elapses
+        let mut draining = false;
+        let mut drain_file_poll = tokio::time::interval(DRAIN_FILE_POLL_INTERVAL);
+        let drain_deadline = tokio::time::sleep(Duration::ZERO);
+        tokio::pin!(drain_deadline);
+        // the drain was started by a shutdown signal, within the grace period
+        let mut signal_drain = false;
+        // the name was left to a successor, it is not subscribed again
+        let mut handed_over = false;
+
+        // with the recover policy, the app is created again once its
+        // notification stream ends
+        let mut stream_closed = false;
+        let mut recover_backoff = SUBSCRIBE_RETRY_BACKOFF;
+        let recover_at = tokio::time::sleep(Duration::ZERO);
+        tokio::pin!(recover_at);
+
+        let admin = self.admin.clone();
+        let shutdown = self.shutdown.clone();
+        admin.set_ready(true);
+        self.config.events.emit(
+            "proxy_ready",
+            serde_json::json!({ "name": self.name.to_string() }),
+        );
+        if let Some(previous) = self.handover_from.take() {
+            tokio::spawn(admin::request_handover(
+                previous,
+                self.handover_token.clone(),
+            ));
+        }
+        info!("waiting for incoming messages");
+        loop {
+            tokio::select! {
+                next_from_slim = slim_rx.recv(), if !stream_closed => {
+                    match next_from_slim {
+                        None if self.stream_end_policy == StreamEndPolicy::Recover && !handed_over => {
+                            warn!(active_sessions = self.connections.len(), "end of the SLIM stream, create the app again");
+                            stream_closed = true;
+                            admin.set_ready(false);
+                            recover_at.as_mut().reset(tokio::time::Instant::now());
+                        }
+                        None => {
+                            info!("end of the stream, stop the MCP prefix");
+                            break;
+                        }
+                        Some(notification) => {
+                            match notification {
+                                Ok(Notification::NewSession(ctx)) => {
+                                    let Some(session) = notified_session(&ctx) else {
+                                        continue;
+                                    };
+                                    if draining {
+                                        info!(session_id = session.id(), "proxy is draining, reject new session");
+                                        self.metrics.rejected_sessions.inc(session.dst());
+                                        reject_session(&app, &session);
+                                        continue;
+                                    }
+                                    if let Some(allowlist) = &self.source_allowlist && !allowlist.allows(session.dst()) {
+                                        warn!(session_id = session.id(), client = %session.dst(), "client not in the source allowlist, reject new session");
+                                        self.metrics.rejected_sessions.inc(session.dst());
+                                        reject_session(&app, &session);
+                                        continue;
+                                    }
+                                    let mut config = self.config.clone();
+                                    if let Some(selected) = session.metadata().get(LOGICAL_SERVER_KEY) {
+                                        match self.config.logical_servers.get(selected) {
+                                            Some(url) => {
+                                                debug!(session_id = session.id(), server = %selected, "session selects a logical MCP server");
+                                                config.use_logical_server(url.clone());
+                                            }
+                                            None if self.config.unknown_server_policy == UnknownServerPolicy::Reject => {
+                                                warn!(session_id = session.id(), server = %selected, "unknown logical MCP server, reject new session");
+                                                self.metrics.rejected_sessions.inc(session.dst());
+                                                reject_session(&app, &session);
+                                                continue;
+                                            }
+                                            None => {
+                                                warn!(session_id = session.id(), server = %selected, "unknown logical MCP server, session connected to the MCP server");
+                                            }
+                                        }
+                                    }
+                                    let client = session.dst();
+                                    if let Some(limit) = authz::session_limit_for(self.max_sessions_per_source, &self.source_session_limits, client)
+                                        && self.connections.values().filter(|s| s.client == *client).count() >= limit
+                                    {
+                                        warn!(session_id = session.id(), %client, %limit, "client reached its session limit, reject new session");
+                                        self.metrics.rejected_sessions.inc(client);
+                                        reject_session(&app, &session);
+                                        continue;
+                                    }
+                                    let priority = authz::priority_for(&self.source_priorities, client);
+                                    // the sessions asked to close no longer count
+                                    if let Some(max_sessions) = self.max_sessions
+                                        && self.connections.values().filter(|s| !s.activity.is_shed()).count() >= max_sessions
+                                        && !memory::preempt(self.shed_policy, self.connections.values().map(|s| s.activity.as_ref()), priority)
+                                    {
+                                        warn!(session_id = session.id(), %client, priority, max_sessions, "proxy full, reject new session");
+                                        self.metrics.rejected_sessions.inc(client);
+                                        reject_session(&app, &session);
+                                        continue;
+                                    }
+                                    let session_id_val = session.id();
+                                    let source_name = session.source().clone();
+                                    let session_key = SessionId { source: source_name, id: session_id_val };
+                                    let activity = Arc::new(SessionActivity::new(priority));
+                                    self.connections.insert(session_key.clone(), ActiveSession { client: client.clone(), activity: activity.clone() });
+                                    self.admin.register_session(client.to_string(), session_id_val, activity.clone());
+                                    self.metrics.sessions.inc(client);
+                                    self.metrics.active_sessions.inc();
+                                    self.config.events.emit("session_opened", serde_json::json!({ "sessionId": session_id_val, "client": client.to_string(), "priority": priority }));
+                                    debug!("mcp_server {}", RedactedUrl(&config.mcp_server));
+                                    let end_guard = SessionEndGuard { session_id: session_key, tx_session_end: tx_session_end.clone(), metrics: self.metrics.clone() };
+                                    start_proxy_session(ctx, config, end_guard, activity);
+                                }
+                                Ok(Notification::NewMessage(msg)) => {
+                                    self.app_message_handler.on_message(msg).await;
+                                }
+                                Err(e) => {
+                                    error!("error receiving notification: {:?}", e);
+                                }
+                            }
+                        }
+                    }
+                }
+                Some(session_key) = rx_session_end.recv() => {
+                    debug!(session_id = session_key.id, "session ended");
+                    if let Some(ended) = self.connections.remove(&session_key) {
+                        self.metrics.active_sessions.dec();
+                        self.admin.unregister_session(ended.client.to_string(), session_key.id);
+                    }
+                    if draining && self.connections.is_empty() {
+                        info!("all sessions drained, stop mcp-proxy");
+                        break;
+                    }
+                }
+                _ = drain_file_poll.tick(), if !draining && self.drain_file.is_some() => {
+                    let drain_file = self.drain_file.as_ref().unwrap();
+                    if tokio::fs::try_exists(drain_file).await.unwrap_or(false) {
+                        info!(drain_file = %drain_file.display(), active_sessions = self.connections.len(), "drain file found, start draining");
+                        self.config.events.emit("proxy_draining", serde_json::json!({ "trigger": "drain_file", "activeSessions": self.connections.len() }));
+                        admin.set_ready(false);
+                        if self.connections.is_empty() {
+                            break;
+                        }
+                        draining = true;
+                        drain_deadline.as_mut().reset(tokio::time::Instant::now() + drain_timeout);
+                    }
+                }
+                trigger = admin.drain_requested(), if !draining => {
+                    info!(trigger, active_sessions = self.connections.len(), "drain requested, start draining");
+                    self.config.events.emit("proxy_draining", serde_json::json!({ "trigger": trigger, "activeSessions": self.connections.len() }));
+                    if self.connections.is_empty() {
+                        break;
+                    }
+                    if trigger == "handover" {
+                        // the new sessions go to the successor, the open ones
+                        // keep reaching this instance on the routes set up
+                        // when they were established
+                        match app.unsubscribe(app.app_name(), subscribed_conn).await {
+                            Ok(()) => info!("name unsubscribed, the successor takes the new sessions"),
+                            Err(e) => warn!("error unsubscribing the name, the new sessions it receives are rejected: {}", e),
+                        }
+                        handed_over = true;
+                    }
+                    draining = true;
+                    drain_deadline.as_mut().reset(tokio::time::Instant::now() + drain_timeout);
+                }
+                _ = &mut recover_at, if stream_closed => {
+                    match recover_app(&service, &endpoint, &self.name, &provider, &verifier).await {
+                        Ok((new_app, new_rx, conn_id)) => {
+                            info!(%conn_id, "SLIM app created again, waiting for incoming messages");
+                            app = new_app;
+                            slim_rx = new_rx;
+                            subscribed_conn = Some(conn_id);
+                            stream_closed = false;
+                            admin.set_ready(!draining);
+                            recover_backoff = SUBSCRIBE_RETRY_BACKOFF;
+                        }
+                        Err(e) => {
+                            warn!(retry_in = ?recover_backoff, "error creating the SLIM app again: {}", e);
+                            recover_at.as_mut().reset(tokio::time::Instant::now() + recover_backoff);
+                            recover_backoff = (recover_backoff * 2).min(MAX_RESUBSCRIBE_BACKOFF);
+                        }
+                    }
+                }
+                _ = subscription_check.tick(), if self.subscription_check_interval.is_some() && !stream_closed && !handed_over => {
+                    let current = service.get_connection_id(&endpoint);
+                    if current != subscribed_conn {
+                        match current {
+                            None => {
+                                warn!("dataplane connection lost, waiting for the reconnection to subscribe again");
+                                subscribed_conn = None;
+                            }
+                            Some(_) if tokio::time::Instant::now() < resubscribe_at => {}
+                            Some(conn_id) => match app.subscribe(&self.name, Some(conn_id)).await {
+                                Ok(_) => {
+                                    info!(%conn_id, "dataplane reconnected, subscribed again");
+                                    subscribed_conn = Some(conn_id);
+                                    resubscribe_backoff = subscription_check.period();
+                                }
+                                Err(e) => {
+                                    warn!(%conn_id, retry_in = ?resubscribe_backoff, "error subscribing again: {}", e);
+                                    resubscribe_at = tokio::time::Instant::now() + resubscribe_backoff;
+                                    resubscribe_backoff = (resubscribe_backoff * 2).min(MAX_RESUBSCRIBE_BACKOFF);
+                                }
+                            },
+                        }
+                    }
+                }
+                _ = log_flush.tick(), if log_window.is_some() => {
+                    self.config.log_throttle.flush();
+                }
+                Some(limit) = async { memory_check.tick().await; memory_limit }, if memory_limit.is_some() => {
+                    if let Some((resident, shed)) = memory::enforce_limit(limit, self.shed_policy, self.connections.values().map(|s| s.activity.as_ref())) {
+                        warn!(resident, limit, shed, active_sessions = self.connections.len(), "proxy above its memory limit, shedding sessions");
+                    }
+                }
+                _ = &mut drain_deadline, if draining => {
+                    if signal_drain {
+                        warn!(active_sessions = self.connections.len(), grace_period = ?self.shutdown_grace_period, "shutdown grace period elapsed before the sessions ended, stop mcp-proxy; the grace period may be too short");
+                    } else {
+                        warn!(active_sessions = self.connections.len(), "drain timeout elapsed, stop mcp-proxy");
+                    }
+                    break;
+                }
+                // shutdown signal, a second one stops the proxy right away
+                _ = shutdown_signal(&shutdown) => {
+                    match self.shutdown_grace_period {
+                        Some(grace_period) if !signal_drain && !self.connections.is_empty() => {
+                            info!(active_sessions = self.connections.len(), ?grace_period, "Received shutdown signal, draining mcp-proxy");
+                            self.config.events.emit("proxy_draining", serde_json::json!({ "trigger": "signal", "activeSessions": self.connections.len() }));
+                            admin.set_ready(false);
+                            let deadline = tokio::time::Instant::now() + grace_period;
+                            // an ongoing drain only gets shorter
+                            if !draining || deadline < drain_deadline.deadline() {
+                                drain_deadline.as_mut().reset(deadline);
+                            }
+                            draining = true;
+                            signal_drain = true;
+                        }
+                        _ => {
+                            info!("Received shutdown signal, stop mcp-proxy");
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        info!("shutting down proxy server");
+
+        reject_pending_sessions(&app, &mut slim_rx, &self.metrics);
+        self.connections.clear();
+
+        if let Some(admin_server) = admin_server {
+            self.admin.set_ready(false);
+            admin_server.stop(self.admin_shutdown_timeout).await;
+        }
+
+        // the metrics are no longer served, the certificates no longer reloaded
+        background.shutdown().await;
+
+        if let Some(event_socket) = event_socket {
+            self.config
+                .events
+                .emit("proxy_stopped", serde_json::json!({}));
+            event_socket.stop(EVENT_SOCKET_STOP_TIMEOUT).await;
+        }
+
+        service.shutdown().await.unwrap();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn client_message(value: serde_json::Value) -> ClientJsonRpcMessage {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn client_ping_answered_at_the_proxy() {
+        let ping = client_message(json!({ "jsonrpc": "2.0", "id": 7, "method": "ping" }));
+        let Some(ServerJsonRpcMessage::Response(resp)) = ClientPingMode::Answer.answer(&ping)
+        else {
+            panic!("ping not answered");
+        };
+        assert_eq!(resp.id, Number(7));
+        assert!(matches!(resp.result, ServerResult::EmptyResult(_)));
+    }
+
+    #[test]
+    fn client_ping_forwarded() {
+        let ping = client_message(json!({ "jsonrpc": "2.0", "id": 7, "method": "ping" }));
+        assert!(ClientPingMode::Forward.answer(&ping).is_none());
+    }
+
+    #[test]
+    fn only_pings_answered() {
+        let list = client_message(json!({ "jsonrpc": "2.0", "id": 8, "method": "tools/list" }));
+        assert!(ClientPingMode::Answer.answer(&list).is_none());
+    }
+
+    /// Id of the response of a client storing the ids as floating point
+    /// numbers, e.g. a JavaScript one
+    fn echoed_as_double(id: &RequestId) -> RequestId {
+        let echoed = match id {
+            Number(n) => json!(*n as f64 as i64),
+            NumberOrString::String(s) => json!(s),
+        };
+        let response = client_message(json!({ "jsonrpc": "2.0", "id": echoed, "result": {} }));
+        let JsonRpcMessage::Response(response) = response else {
+            panic!("not a response");
+        };
+        response.id
+    }
+
+    #[test]
+    fn safe_ping_ids_recognized_by_default() {
+        assert_eq!(PingIdFormat::default(), PingIdFormat::SafeNumber);
+        let mut pings = PingTracker::default();
+        for _ in 0..1000 {
+            let id = PingIdFormat::default().new_id();
+            assert!(matches!(id, Number(n) if (1..=MAX_SAFE_INTEGER).contains(&n)));
+            pings.sent(id.clone());
+            assert!(pings.answered(&echoed_as_double(&id)).is_some(), "{id:?}");
+        }
+        // the largest safe id survives the round trip
+        pings.sent(Number(MAX_SAFE_INTEGER));
+        assert!(
+            pings
+                .answered(&echoed_as_double(&Number(MAX_SAFE_INTEGER)))
+                .is_some()
+        );
+
+        let id = PingIdFormat::String.new_id();
+        pings.sent(id.clone());
+        assert!(pings.answered(&echoed_as_double(&id)).is_some());
+    }
+
+    #[test]
+    fn rounded_large_ping_ids_not_recognized() {
+        let mut pings = PingTracker::default();
+        // above 2^53 a double keeps only the even integers, then coarser ones
+        for id in [MAX_SAFE_INTEGER + 2, (1 << 62) + 1, i64::MAX - 1024] {
+            let id = Number(id);
+            pings.sent(id.clone());
+            let echoed = echoed_as_double(&id);
+            assert_ne!(echoed, id);
+            assert!(!pings.is_pending(&echoed), "{id:?}");
+        }
+        assert_eq!(pings.len(), 3);
+        // an id the client represents exactly still answers the ping
+        assert!(pings.answered(&Number(MAX_SAFE_INTEGER + 2)).is_some());
+    }
+
+    fn builder(mcp_server: &str) -> ProxyBuilder {
+        Proxy::builder(Name::from_strings(["org", "ns", "mcp"]), mcp_server.into())
+    }
+
+    /// Conflicts reported by the build, which must fail
+    fn conflicts(builder: ProxyBuilder) -> Vec<String> {
+        match builder.build() {
+            Err(ProxyError::InvalidConfig(conflicts)) => conflicts,
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("invalid configuration accepted"),
+        }
+    }
+
+    fn initialize_request() -> ClientJsonRpcMessage {
+        client_message(json!({
+            "jsonrpc": "2.0",
+            "id": 0,
+            "method": "initialize",
+            "params": {
+                "protocolVersion": "2025-06-18",
+                "capabilities": {},
+                "clientInfo": { "name": "test", "version": "0.0.0" },
+            },
+        }))
+    }
+
+    /// Address of an MCP server closing every connection right after
+    /// accepting it
+    async fn closing_mcp_server() -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                drop(stream);
+            }
+        });
+        format!("http://{}/mcp", addr)
+    }
+
+    /// MCP server answering `initialize` and the other requests with an empty
+    /// result unless set for their method, recording the messages it receives
+    /// and their headers. The messages set for a method are sent in an event
+    /// stream before the response to its requests. It fails every request
+    /// once stopped.
+    struct StubMcpServer {
+        url: String,
+        up: Arc<std::sync::atomic::AtomicBool>,
+        received: Arc<parking_lot::Mutex<Vec<serde_json::Value>>>,
+        headers: Arc<parking_lot::Mutex<Vec<axum::http::HeaderMap>>>,
+        cookies: Arc<std::sync::atomic::AtomicBool>,
+        before_response: Arc<parking_lot::Mutex<HashMap<String, Vec<serde_json::Value>>>>,
+        errors: Arc<parking_lot::Mutex<HashMap<String, i32>>>,
+        delays: Arc<parking_lot::Mutex<HashMap<String, Duration>>>,
+        results: Arc<parking_lot::Mutex<HashMap<String, serde_json::Value>>>,
+    }
+
+    impl StubMcpServer {
+        async fn start() -> Self {
+            use axum::{http::StatusCode, http::header, response::IntoResponse, routing::post};
+
+            let up = Arc::new(std::sync::atomic::AtomicBool::new(true));
+            let received = Arc::new(parking_lot::Mutex::new(Vec::new()));
+            let headers = Arc::new(parking_lot::Mutex::new(Vec::new()));
+            let cookies = Arc::new(std::sync::atomic::AtomicBool::new(false));
+            let sessions = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+            let before_response = Arc::new(parking_lot::Mutex::new(HashMap::new()));
+            let errors = Arc::new(parking_lot::Mutex::new(HashMap::new()));
+            let delays = Arc::new(parking_lot::Mutex::new(HashMap::new()));
+            let results = Arc::new(parking_lot::Mutex::new(HashMap::new()));
+            let (up_handler, received_handler, before_handler, errors_handler, delays_handler) = (
+                up.clone(),
+                received.clone(),
+                before_response.clone(),
+                errors.clone(),
+                delays.clone(),
+            );
+            let (results_handler, headers_handler) = (results.clone(), headers.clone());
+            let (cookies_handler, sessions_handler) = (cookies.clone(), sessions.clone());
+            let handler = move |request_headers: axum::http::HeaderMap, body: String| {
+                let (up, received) = (up_handler.clone(), received_handler.clone());
+                let headers = headers_handler.clone();
+                let (cookies, sessions) = (cookies_handler.clone(), sessions_handler.clone());
+                let (before_response, errors) = (before_handler.clone(), errors_handler.clone());
+                let (delays, results) = (delays_handler.clone(), results_handler.clone());
+                async move {
+                    if !up.load(std::sync::atomic::Ordering::Relaxed) {
+                        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+                    }
+                    let msg: serde_json::Value = serde_json::from_str(&body).unwrap();
+                    received.lock().push(msg.clone());
+                    let cookie = match cookies.load(std::sync::atomic::Ordering::Relaxed) {
+                        false => None,
+                        true if msg["method"] == "initialize" => {
+                            let session =
+                                sessions.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                            Some(format!("session={session}"))
+                        }
+                        true if !request_headers.contains_key(header::COOKIE) => {
+                            headers.lock().push(request_headers);
+                            return StatusCode::UNAUTHORIZED.into_response();
+                        }
+                        true => None,
+                    };
+                    headers.lock().push(request_headers);
+                    let delay = msg["method"]
+                        .as_str()
+                        .and_then(|m| delays.lock().get(m).copied());
+                    let (Some(id), Some(method)) = (msg.get("id"), msg.get("method")) else {
+                        if let Some(delay) = delay {
+                            tokio::time::sleep(delay).await;
+                        }
+                        return StatusCode::ACCEPTED.into_response();
+                    };
+                    let result = match method.as_str() {
+                        Some("initialize") => json!({
+                            "protocolVersion": "2025-03-26",
+                            "capabilities": {},
+                            "serverInfo": {"name": "stub", "version": "1.0.0"}
+                        }),
+                        Some(m) => results.lock().get(m).cloned().unwrap_or_else(|| json!({})),
+                        None => json!({}),
+                    };
+                    let error = method.as_str().and_then(|m| errors.lock().remove(m));
+                    let response = match error {
+                        Some(code) => json!({
+                            "jsonrpc": "2.0",
+                            "id": id,
+                            "error": {"code": code, "message": "stub error"}
+                        }),
+                        None => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+                    };
+                    let before: Option<Vec<serde_json::Value>> = method
+                        .as_str()
+                        .and_then(|m| before_response.lock().get(m).cloned());
+                    let mut response = match (before, delay) {
+                        // the stream is opened at once, as a streaming server does
+                        (None, Some(delay)) => {
+                            let event = futures::stream::once(async move {
+                                tokio::time::sleep(delay).await;
+                                Ok::<_, std::convert::Infallible>(format!("data: {response}\n\n"))
+                            });
+                            (
+                                [(header::CONTENT_TYPE, "text/event-stream")],
+                                axum::body::Body::from_stream(event),
+                            )
+                                .into_response()
+                        }
+                        (Some(mut events), _) => {
+                            events.push(response);
+                            let body: String = events
+                                .iter()
+                                .map(|event| format!("data: {event}\n\n"))
+                                .collect();
+                            ([(header::CONTENT_TYPE, "text/event-stream")], body).into_response()
+                        }
+                        (None, None) => (
+                            [(header::CONTENT_TYPE, "application/json")],
+                            response.to_string(),
+                        )
+                            .into_response(),
+                    };
+                    if let Some(cookie) = cookie {
+                        response
+                            .headers_mut()
+                            .insert(header::SET_COOKIE, cookie.parse().unwrap());
+                    }
+                    response
+                }
+            };
+            let app = axum::Router::new().route("/mcp", post(handler));
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(async move { axum::serve(listener, app).await });
+            Self {
+                url: format!("http://{}/mcp", addr),
+                up,
+                received,
+                headers,
+                cookies,
+                before_response,
+                errors,
+                delays,
+                results,
+            }
+        }
+
+        /// Answer the requests of the method with the result
+        fn answer(&self, method: &str, result: serde_json::Value) {
+            self.results.lock().insert(method.into(), result);
+        }
+
+        /// Answer the next request of the method with an error of the code
+        fn fail_next(&self, method: &str, code: i32) {
+            self.errors.lock().insert(method.into(), code);
+        }
+
+        /// Answer the requests of the method after the delay, on a stream
+        /// opened at once, and its notifications after the delay
+        fn delay(&self, method: &str, delay: Duration) {
+            self.delays.lock().insert(method.into(), delay);
+        }
+
+        /// Send the messages before the response to the requests of the method
+        fn before_response(&self, method: &str, messages: Vec<serde_json::Value>) {
+            self.before_response.lock().insert(method.into(), messages);
+        }
+
+        /// Messages received so far with the given id
+        fn with_id(&self, id: serde_json::Value) -> Vec<serde_json::Value> {
+            self.received
+                .lock()
+                .iter()
+                .filter(|msg| msg["id"] == id)
+                .cloned()
+                .collect()
+        }
+
+        /// Set a session cookie on the responses to `initialize`, rejecting the
+        /// other messages without a cookie
+        fn require_cookie(&self) {
+            self.cookies
+                .store(true, std::sync::atomic::Ordering::Relaxed);
+        }
+
+        /// Headers of the messages received so far
+        fn headers(&self) -> Vec<axum::http::HeaderMap> {
+            self.headers.lock().clone()
+        }
+
+        fn stop(&self) {
+            self.up.store(false, std::sync::atomic::Ordering::Relaxed);
+        }
+
+        /// Methods of the messages received so far
+        fn methods(&self) -> Vec<String> {
+            self.received
+                .lock()
+                .iter()
+                .filter_map(|msg| msg["method"].as_str().map(String::from))
+                .collect()
+        }
+    }
+
+    /// Wait for a condition checked every few milliseconds, for at most 5s
+    async fn eventually(condition: impl Fn() -> bool) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !condition() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("condition not met in time");
+    }
+
+    #[tokio::test]
+    async fn session_takes_a_warm_connection_at_once() {
+        let server = StubMcpServer::start().await;
+        let mut proxy = builder(&server.url)
+            .with_mcp_pool_size(Some(2))
+            .build()
+            .unwrap();
+        let filler = proxy.mcp_pool_filler.take().unwrap();
+        let config = proxy.config.clone();
+        let pool = config.mcp_pool.clone().unwrap();
+        tokio::spawn(config.fill_mcp_pool(proxy.name.clone(), filler, Duration::from_secs(60)));
+        eventually(|| pool.idle() == 2).await;
+
+        let started = Instant::now();
+        let warm = pool.take().expect("a warm connection");
+        assert!(started.elapsed() < Duration::from_millis(10));
+        assert!(matches!(
+            warm.initialize_result,
+            ServerResult::InitializeResult(_)
+        ));
+        assert!(!warm.on_fallback);
+
+        // the connection taken is replaced
+        eventually(|| pool.idle() == 2).await;
+        let methods = server.methods();
+        assert_eq!(methods.iter().filter(|m| *m == "initialize").count(), 3);
+    }
+
+    #[tokio::test]
+    async fn unhealthy_pool_connections_dropped() {
+        let server = StubMcpServer::start().await;
+        let mut proxy = builder(&server.url)
+            .with_mcp_pool_size(Some(2))
+            .build()
+            .unwrap();
+        let filler = proxy.mcp_pool_filler.take().unwrap();
+        let config = proxy.config.clone();
+        let pool = config.mcp_pool.clone().unwrap();
+        tokio::spawn(config.fill_mcp_pool(proxy.name.clone(), filler, Duration::from_millis(50)));
+        eventually(|| pool.idle() == 2).await;
+
+        // the idle connections are pinged and kept while the server answers
+        eventually(|| server.methods().iter().filter(|m| *m == "ping").count() >= 4).await;
+        assert_eq!(pool.idle(), 2);
+
+        server.stop();
+        eventually(|| pool.idle() == 0).await;
+    }
+
+    #[test]
+    fn valid_configuration_builds() {
+        assert!(builder("http://localhost:8000/mcp").build().is_ok());
+    }
+
+    #[test]
+    fn tls_required_with_plain_url() {
+        let builder = builder("http://localhost:8000/mcp").with_require_tls(true);
+        assert_eq!(
+            conflicts(builder),
+            ["TLS is required but the MCP server URL is not https"]
+        );
+    }
+
+    #[test]
+    fn invalid_mcp_server_url() {
+        let conflicts = conflicts(builder("not a url"));
+        assert_eq!(conflicts.len(), 1);
+        assert!(conflicts[0].starts_with("MCP server: "), "{:?}", conflicts);
+    }
+
+    #[test]
+    fn zero_reconnect_rate() {
+        let builder = builder("http://localhost:8000/mcp").with_reconnect_rate(Some(0));
+        assert_eq!(
+            conflicts(builder),
+            ["reconnect rate must be greater than zero"]
+        );
+    }
+
+    #[test]
+    fn zero_ping_interval() {
+        let builder = builder("http://localhost:8000/mcp").with_ping_interval(Some(Duration::ZERO));
+        assert_eq!(
+            conflicts(builder),
+            ["ping interval must be greater than zero"]
+        );
+    }
+
+    #[test]
+    fn zero_missed_pings_with_pings_enabled() {
+        let builder = builder("http://localhost:8000/mcp")
+            .with_ping_interval(Some(Duration::from_secs(20)))
+            .with_max_missed_pings(0);
+        let conflicts = conflicts(builder);
+        assert_eq!(conflicts.len(), 1);
+        assert!(
+            conflicts[0].starts_with("max missed pings is 0"),
+            "{:?}",
+            conflicts
+        );
+    }
+
+    #[test]
+    fn missed_pings_with_pings_disabled() {
+        let builder = builder("http://localhost:8000/mcp")
+            .with_ping_interval(None)
+            .with_max_missed_pings(3);
+        assert_eq!(
+            conflicts(builder),
+            ["max missed pings is set but pings are disabled"]
+        );
+    }
+
+    #[test]
+    fn zero_tcp_keepalive() {
+        let builder = builder("http://localhost:8000/mcp").with_tcp_keepalive(Some(TcpKeepalive {
+            retries: 0,
+            ..TcpKeepalive::default()
+        }));
+        assert_eq!(
+            conflicts(builder),
+            ["TCP keepalive idle, interval and retries must be greater than zero"]
+        );
+    }
+
+    #[test]
+    fn all_conflicts_reported_at_once() {
+        let builder = builder("http://localhost:8000/mcp")
+            .with_require_tls(true)
+            .with_ping_interval(Some(Duration::ZERO));
+        assert_eq!(conflicts(builder).len(), 2);
+    }
+
+    #[test]
+    fn only_initialize_retried() {
+        assert!(is_initialize_request(&initialize_request()));
+        let initialized = client_message(json!({
+            "jsonrpc": "2.0",
+            "method": "notifications/initialized",
+        }));
+        assert!(!is_initialize_request(&initialized));
+        let list = client_message(json!({ "jsonrpc": "2.0", "id": 1, "method": "tools/list" }));
+        assert!(!is_initialize_request(&list));
+    }
+
+    #[tokio::test]
+    async fn handshake_retries_bounded_on_closing_server() {
+        let mcp_server = closing_mcp_server().await;
+        let proxy = builder(&mcp_server)
+            .with_handshake_retries(2, Duration::from_millis(10))
+            .build()
+            .unwrap();
+        let config = proxy.config;
+        let client_name = Name::from_strings(["org", "ns", "client"]);
+        let client = BoundedClient::new(
+            config.http_client(&client_name).unwrap(),
+            DEFAULT_READ_BUFFER_BYTES,
+        );
+        let mut transport = config.mcp_transport(client.clone()).await.unwrap();
+        let initialize = initialize_request();
+        assert!(transport.send(initialize.clone()).await.is_err());
+
+        let (mut attempts, mut on_fallback) = (0, false);
+        let retried = config
+            .retry_handshake(
+                &client,
+                &mut transport,
+                &initialize,
+                &mut attempts,
+                &mut on_fallback,
+            )
+            .await;
+        assert!(!retried);
+        assert_eq!(attempts, 2);
+        assert!(!on_fallback);
+    }
+
+    /// SLIM service connecting to a dataplane endpoint which accepts the
+    /// connections and never answers
+    async fn unresponsive_service() -> slim_service::Service {
+        slim_config::tls::provider::initialize_crypto_provider();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                held.push(stream);
+            }
+        });
+        let config = slim_service::ServiceConfiguration::new()
+            .with_dataplane_client(vec![ClientConfig::with_endpoint(&endpoint)]);
+        let id = slim_config::component::id::ID::new_with_str("slim/0").unwrap();
+        slim_service::Service::new_with_config(id, config)
+    }
+
+    /// SLIM node listening on a local port, with its endpoint
+    async fn dataplane() -> (slim_service::Service, String) {
+        slim_config::tls::provider::initialize_crypto_provider();
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let server =
+            slim_config::grpc::server::ServerConfig::with_endpoint(&format!("127.0.0.1:{port}"))
+                .with_tls_settings(
+                    slim_config::tls::server::TlsServerConfig::new().with_insecure(true),
+                );
+        let node = slim_service::ServiceConfiguration::new()
+            .with_dataplane_server(vec![server])
+            .build_server(slim_config::component::id::ID::new_with_str("slim/node").unwrap())
+            .unwrap();
+        node.run().await.unwrap();
+        (node, format!("http://127.0.0.1:{port}"))
+    }
+
+    /// Service connected to the SLIM node at `endpoint`
+    async fn connected_service(endpoint: &str) -> slim_service::Service {
+        let mut client = ClientConfig::with_endpoint(endpoint);
+        client.tls_setting.insecure = true;
+        let config = slim_service::ServiceConfiguration::new().with_dataplane_client(vec![client]);
+        let id = slim_config::component::id::ID::new_with_str("slim/proxy").unwrap();
+        let service = slim_service::Service::new_with_config(id, config);
+        let cancel = CancellationToken::new();
+        assert_eq!(
+            run_service(&service, Duration::from_secs(5), &cancel)
+                .await
+                .unwrap(),
+            ServiceStart::Started
+        );
+        service
+    }
+
+    const TEST_SECRET: &str = "a-shared-secret-long-enough-for-the-tests";
+
+    fn shared_secret() -> (AuthProvider, AuthVerifier) {
+        (
+            AuthProvider::shared_secret(SharedSecret::new("proxy", TEST_SECRET).unwrap()),
+            AuthVerifier::shared_secret(SharedSecret::new("proxy", TEST_SECRET).unwrap()),
+        )
+    }
+
+    #[tokio::test]
+    async fn app_recovered_after_its_stream_ends() {
+        let (_node, endpoint) = dataplane().await;
+        let service = connected_service(&endpoint).await;
+        let name = Name::from_strings(["org", "ns", "mcp-proxy"]);
+        let (provider, verifier) = shared_secret();
+
+        let (app, mut slim_rx, conn_id) =
+            recover_app(&service, &endpoint, &name, &provider, &verifier)
+                .await
+                .unwrap();
+        assert_eq!(service.get_connection_id(&endpoint), Some(conn_id));
+
+        // the stream of an app ends with it
+        drop(app);
+        let ended = tokio::time::timeout(Duration::from_secs(5), async {
+            while slim_rx.recv().await.is_some() {}
+        })
+        .await;
+        assert!(ended.is_ok());
+
+        let (_app, _slim_rx, recovered_conn) =
+            recover_app(&service, &endpoint, &name, &provider, &verifier)
+                .await
+                .unwrap();
+        assert_eq!(recovered_conn, conn_id);
+    }
+
+    #[tokio::test]
+    async fn app_not_recovered_without_connection() {
+        let service = unresponsive_service().await;
+        let name = Name::from_strings(["org", "ns", "mcp-proxy"]);
+        let (provider, verifier) = shared_secret();
+        let result = recover_app(&service, "http://127.0.0.1:1", &name, &provider, &verifier).await;
+        assert!(matches!(result, Err(ProxyError::NotConnected { .. })));
+    }
+
+    #[tokio::test]
+    async fn connected_endpoints_listed_when_not_connected() {
+        let (_node, endpoint) = dataplane().await;
+        let service = connected_service(&endpoint).await;
+        // a trailing slash is enough to miss the connection
+        let misspelled = format!("{endpoint}/");
+        assert!(service.get_connection_id(&misspelled).is_none());
+        let error = not_connected(&service, &misspelled);
+        let ProxyError::NotConnected {
+            endpoint: missing,
+            connected,
+        } = &error
+        else {
+            panic!("unexpected error: {error}");
+        };
+        assert_eq!(*missing, misspelled);
+        assert_eq!(*connected, vec![endpoint.clone()]);
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "no connection to the dataplane endpoint {misspelled}, connected endpoints: {endpoint}"
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn sessions_notified_during_shutdown_rejected() {
+        let (node, endpoint) = dataplane().await;
+        let service = connected_service(&endpoint).await;
+        let (provider, verifier) = shared_secret();
+        let name = Name::from_strings(PROXY_NAME);
+        let (app, mut slim_rx, _) = recover_app(&service, &endpoint, &name, &provider, &verifier)
+            .await
+            .unwrap();
+
+        // a session notified once the proxy stopped handling the notifications
+        let mut client = TestClient::connect(&node, "client").await;
+        eventually(|| !slim_rx.is_empty()).await;
+        let metrics = Metrics::new(SourceLabels::Off);
+        reject_pending_sessions(&app, &mut slim_rx, &metrics);
+
+        let rejected = metrics
+            .samples()
+            .into_iter()
+            .find(|sample| sample.name == "rejected_sessions_total")
+            .unwrap();
+        assert_eq!(rejected.value, crate::metrics::SampleValue::Counter(1));
+        client.closed().await;
+        // the sessions notified later are not handled either
+        assert!(slim_rx.recv().await.is_none());
+    }
+
+    const PROXY_NAME: [&str; 3] = ["org", "ns", "mcp"];
+
+    /// Proxy serving on a SLIM node, stopped when dropped
+    struct RunningProxy {
+        admin: Arc<AdminState>,
+        task: tokio::task::JoinHandle<Result<(), ProxyError>>,
+    }
+
+    impl RunningProxy {
+        /// Start the proxy on the SLIM node at `endpoint` and wait until it is
+        /// ready for new sessions
+        async fn start(mut proxy: Proxy, endpoint: &str, drain_timeout: Duration) -> Self {
+            let mut client = ClientConfig::with_endpoint(endpoint);
+            client.tls_setting.insecure = true;
+            let config =
+                slim_service::ServiceConfiguration::new().with_dataplane_client(vec![client]);
+            let id = slim_config::component::id::ID::new_with_str("slim/proxy").unwrap();
+            let service = slim_service::Service::new_with_config(id, config);
+            let admin = proxy.admin.clone();
+            let identity = IdentityConfig::SharedSecret(TEST_SECRET.into());
+            let task =
+                tokio::spawn(async move { proxy.start(service, identity, drain_timeout).await });
+            eventually(|| admin.is_ready()).await;
+            Self { admin, task }
+        }
+
+        /// Wait for the proxy to stop
+        async fn stopped(&mut self) -> Result<(), ProxyError> {
+            tokio::time::timeout(Duration::from_secs(5), &mut self.task)
+                .await
+                .expect("proxy not stopped in time")
+                .unwrap()
+        }
+    }
+
+    impl Drop for RunningProxy {
+        fn drop(&mut self) {
+            self.task.abort();
+        }
+    }
+
+    /// SLIM client with a session open with the proxy
+    struct TestClient {
+        app: SlimApp,
+        session: Arc<SessionController>,
+        rx: slim_session::AppChannelReceiver,
+    }
+
+    impl TestClient {
+        async fn connect(node: &slim_service::Service, name: &str) -> Self {
+            Self::connect_with(node, name, HashMap::new()).await
+        }
+
+        async fn connect_with(
+            node: &slim_service::Service,
+            name: &str,
+            metadata: HashMap<String, String>,
+        ) -> Self {
+            let (provider, verifier) = shared_secret();
+            let (app, _notifications) = node
+                .create_app(&Name::from_strings(["org", "ns", name]), provider, verifier)
+                .unwrap();
+            Self::open(app, metadata).await
+        }
+
+        /// Open a new session of the app to the proxy
+        async fn open(
+            app: slim_service::app::App<AuthProvider, AuthVerifier>,
+            metadata: HashMap<String, String>,
+        ) -> Self {
+            let (session, rx) = Self::establish(&app, metadata).await;
+            Self { app, session, rx }
+        }
+
+        /// Establish a session of the app with the proxy
+        async fn establish(
+            app: &SlimApp,
+            metadata: HashMap<String, String>,
+        ) -> (Arc<SessionController>, slim_session::AppChannelReceiver) {
+            let config = slim_session::SessionConfig {
+                session_type: slim_datapath::api::ProtoSessionType::PointToPoint,
+                initiator: true,
+                max_retries: Some(5),
+                interval: Some(Duration::from_millis(200)),
+                metadata,
+                ..Default::default()
+            };
+            // the subscription of the proxy reaches the node shortly after
+            // the proxy is ready
+            let ctx = tokio::time::timeout(Duration::from_secs(5), async {
+                loop {
+                    if let Ok((ctx, completion)) = app
+                        .create_session(config.clone(), Name::from_strings(PROXY_NAME), None)
+                        .await
+                        && completion.await.is_ok()
+                    {
+                        break ctx;
+                    }
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                }
+            })
+            .await
+            .expect("session not established in time");
+            let (session, rx) = ctx.into_parts();
+            (session.upgrade().unwrap(), rx)
+        }
+
+        /// Close the session with the proxy
+        /// Close the session, returning the app for a later session
+        async fn close(self) -> slim_service::app::App<AuthProvider, AuthVerifier> {
+            let closed = self.app.delete_session(&self.session).unwrap();
+            let _ = tokio::time::timeout(Duration::from_secs(5), closed).await;
+            self.app
+        }
+
+        /// Wait for the proxy to close the session, failing after 5s
+        async fn closed(&mut self) {
+            Self::wait_closed(&mut self.rx).await;
+        }
+
+        async fn wait_closed(rx: &mut slim_session::AppChannelReceiver) {
+            tokio::time::timeout(Duration::from_secs(5), async {
+                // the client is told the proxy left, then the channel ends
+                while let Some(message) = rx.recv().await {
+                    assert!(message.is_err(), "message received from the proxy");
+                }
+            })
+            .await
+            .expect("session not closed in time");
+        }
+
+        async fn send(&self, msg: serde_json::Value) {
+            self.session
+                .publish(
+                    &Name::from_strings(PROXY_NAME),
+                    msg.to_string().into_bytes(),
+                    None,
+                    None,
+                )
+                .await
+                .unwrap();
+        }
+
+        /// Next message of the proxy, failing after 5s
+        async fn recv(&mut self) -> serde_json::Value {
+            let message = tokio::time::timeout(Duration::from_secs(5), self.rx.recv())
+                .await
+                .expect("no message from the proxy in time")
+                .expect("session closed")
+                .unwrap();
+            let payload = message
+                .get_payload()
+                .unwrap()
+                .as_application_payload()
+                .unwrap();
+            serde_json::from_slice(&payload.blob).unwrap()
+        }
+
+        /// Send the initialize request and the initialized notification
+        async fn initialize(&mut self) -> serde_json::Value {
+            self.send(json!({
+                "jsonrpc": "2.0",
+                "id": 0,
+                "method": "initialize",
+                "params": {
+                    "protocolVersion": "2025-03-26",
+                    "capabilities": {},
+                    "clientInfo": {"name": "test", "version": "1.0.0"}
+                }
+            }))
+            .await;
+            let result = self.recv().await;
+            self.send(json!({"jsonrpc": "2.0", "method": "notifications/initialized"}))
+                .await;
+            result
+        }
+    }
+
+    #[tokio::test]
+    async fn requests_proxied_to_the_mcp_server() {
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        let _proxy = RunningProxy::start(
+            builder(&server.url).build().unwrap(),
+            &endpoint,
+            Duration::from_secs(5),
+        )
+        .await;
+
+        let mut client = TestClient::connect(&node, "client").await;
+        let initialized = client.initialize().await;
+        assert_eq!(initialized["id"], 0);
+        assert_eq!(initialized["result"]["serverInfo"]["name"], "stub");
+
+        client
+            .send(json!({"jsonrpc": "2.0", "id": 1, "method": "tools/list"}))
+            .await;
+        assert_eq!(
+            client.recv().await,
+            json!({"jsonrpc": "2.0", "id": 1, "result": {}})
+        );
+        eventually(|| {
+            server.methods() == ["initialize", "notifications/initialized", "tools/list"]
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn session_label_added_to_requests_and_stripped_from_responses() {
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        // a server echoing the label back
+        server.answer(
+            "tools/list",
+            json!({"tools": [], "_meta": {"x-session": 42, "x-server": "kept"}}),
+        );
+        let _proxy = RunningProxy::start(
+            builder(&server.url)
+                .with_session_label_field(Some("x-session".into()))
+                .build()
+                .unwrap(),
+            &endpoint,
+            Duration::from_secs(5),
+        )
+        .await;
+        let mut client = TestClient::connect(&node, "client").await;
+        client.initialize().await;
+
+        client
+            .send(json!({"jsonrpc": "2.0", "id": 1, "method": "tools/list", "params": {"_meta": {"progressToken": 7}}}))
+            .await;
+        assert_eq!(
+            client.recv().await,
+            json!({"jsonrpc": "2.0", "id": 1, "result": {"tools": [], "_meta": {"x-server": "kept"}}})
+        );
+        let forwarded = server.with_id(json!(1));
+        assert_eq!(
+            forwarded[0]["params"]["_meta"],
+            json!({"progressToken": 7, "x-session": client.session.id()})
+        );
+        // the notifications are not labelled
+        let initialized = server.received.lock()[1].clone();
+        assert_eq!(initialized["method"], "notifications/initialized");
+        assert!(initialized.get("params").is_none(), "{initialized}");
+    }
+
+    fn elicitation_request() -> serde_json::Value {
+        json!({
+            "jsonrpc": "2.0",
+            "id": "elicit-1",
+            "method": "elicitation/create",
+            "params": {
+                "message": "which city?",
+                "requestedSchema": {"type": "object", "properties": {"city": {"type": "string"}}}
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn elicitation_round_trip() {
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        server.before_response("tools/call", vec![elicitation_request()]);
+        let _proxy = RunningProxy::start(
+            builder(&server.url).build().unwrap(),
+            &endpoint,
+            Duration::from_secs(5),
+        )
+        .await;
+        let mut client = TestClient::connect(&node, "client").await;
+        client.initialize().await;
+
+        let call = json!({"jsonrpc": "2.0", "id": 1, "method": "tools/call", "params": {"name": "weather"}});
+        client.send(call).await;
+        let elicitation = client.recv().await;
+        assert_eq!(elicitation["method"], "elicitation/create");
+        assert_eq!(elicitation["id"], "elicit-1");
+        assert_eq!(client.recv().await["id"], 1);
+
+        let accepted = json!({
+            "jsonrpc": "2.0",
+            "id": "elicit-1",
+            "result": {"action": "accept", "content": {"city": "Paris"}}
+        });
+        client.send(accepted.clone()).await;
+        eventually(|| server.with_id(json!("elicit-1")).len() == 1).await;
+        assert_eq!(
+            server.with_id(json!("elicit-1"))[0]["result"],
+            accepted["result"]
+        );
+    }
+
+    #[tokio::test]
+    async fn elicitation_declined_at_the_proxy() {
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        server.before_response("tools/call", vec![elicitation_request()]);
+        let proxy = builder(&server.url)
+            .with_elicitation_mode(ElicitationMode::Decline)
+            .build()
+            .unwrap();
+        let _proxy = RunningProxy::start(proxy, &endpoint, Duration::from_secs(5)).await;
+        let mut client = TestClient::connect(&node, "client").await;
+        client.initialize().await;
+
+        let call = json!({"jsonrpc": "2.0", "id": 1, "method": "tools/call", "params": {"name": "weather"}});
+        client.send(call).await;
+        // the client only gets the response to its call
+        assert_eq!(client.recv().await["id"], 1);
+        eventually(|| server.with_id(json!("elicit-1")).len() == 1).await;
+        assert_eq!(
+            server.with_id(json!("elicit-1"))[0]["result"],
+            json!({"action": "decline"})
+        );
+    }
+
+    fn roots_request() -> serde_json::Value {
+        json!({"jsonrpc": "2.0", "id": 1, "method": "roots/list"})
+    }
+
+    #[tokio::test]
+    async fn roots_round_trip() {
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        server.before_response("tools/list", vec![roots_request()]);
+        let _proxy = RunningProxy::start(
+            builder(&server.url).build().unwrap(),
+            &endpoint,
+            Duration::from_secs(5),
+        )
+        .await;
+        let mut client = TestClient::connect(&node, "client").await;
+        client.initialize().await;
+
+        client.send(tools_list(1)).await;
+        let (roots, _) = client.recv_answering_pings().await;
+        assert_eq!(roots, roots_request());
+        assert_eq!(client.recv_answering_pings().await.0["id"], 1);
+
+        // the id of the request of the server may be the one of a proxy ping
+        let answer = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "result": {"roots": [{"uri": "file:///workspace", "name": "workspace"}]}
+        });
+        client.send(answer.clone()).await;
+        eventually(|| {
+            server
+                .with_id(json!(1))
+                .iter()
+                .any(|m| m.get("result").is_some())
+        })
+        .await;
+        let forwarded = server.with_id(json!(1));
+        let forwarded = forwarded.iter().find(|m| m.get("result").is_some());
+        assert_eq!(forwarded.unwrap()["result"], answer["result"]);
+    }
+
+    #[tokio::test]
+    async fn roots_answered_at_the_proxy() {
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        server.before_response("tools/list", vec![roots_request()]);
+        let proxy = builder(&server.url)
+            .with_roots_mode(RootsMode::Answer)
+            .build()
+            .unwrap();
+        let _proxy = RunningProxy::start(proxy, &endpoint, Duration::from_secs(5)).await;
+        let mut client = TestClient::connect(&node, "client").await;
+        client.initialize().await;
+
+        client.send(tools_list(2)).await;
+        // the client only gets the response to its request
+        assert_eq!(client.recv_answering_pings().await.0["id"], 2);
+        eventually(|| !server.with_id(json!(1)).is_empty()).await;
+        assert_eq!(server.with_id(json!(1))[0]["result"], json!({"roots": []}));
+    }
+
+    /// Proxy answering the error -32002 of the stub server with the action
+    async fn error_action_proxy(
+        endpoint: &str,
+        server: &StubMcpServer,
+        action: &str,
+    ) -> RunningProxy {
+        let rule = format!("-32002={action}").parse().unwrap();
+        RunningProxy::start(
+            builder(&server.url)
+                .with_error_actions(vec![rule])
+                .with_handshake_retries(0, Duration::from_millis(300))
+                .with_ping_interval(Some(Duration::from_millis(50)))
+                .with_send_close_diagnostics(true)
+                .build()
+                .unwrap(),
+            endpoint,
+            Duration::from_secs(5),
+        )
+        .await
+    }
+
+    impl TestClient {
+        /// Receive the next message other than a ping, answering the pings
+        async fn recv_answering_pings(&mut self) -> (serde_json::Value, usize) {
+            let mut pings = 0;
+            loop {
+                let message = self.recv().await;
+                if message["method"] != "ping" {
+                    return (message, pings);
+                }
+                pings += 1;
+                let id = message["id"].clone();
+                self.send(json!({"jsonrpc": "2.0", "id": id, "result": {}}))
+                    .await;
+            }
+        }
+    }
+
+    fn tools_list(id: u64) -> serde_json::Value {
+        json!({"jsonrpc": "2.0", "id": id, "method": "tools/list"})
+    }
+
+    #[tokio::test]
+    async fn error_forwarded() {
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        let _proxy = error_action_proxy(&endpoint, &server, "forward").await;
+        let mut client = TestClient::connect(&node, "client").await;
+        client.initialize().await;
+
+        server.fail_next("tools/list", -32002);
+        client.send(tools_list(1)).await;
+        let (error, _) = client.recv_answering_pings().await;
+        assert_eq!(error["error"]["code"], -32002);
+        client.send(tools_list(2)).await;
+        let (response, _) = client.recv_answering_pings().await;
+        assert_eq!(response, json!({"jsonrpc": "2.0", "id": 2, "result": {}}));
+        // the connection was kept
+        assert_eq!(
+            server
+                .methods()
+                .iter()
+                .filter(|m| *m == "initialize")
+                .count(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn error_closes_the_session() {
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        let _proxy = error_action_proxy(&endpoint, &server, "close").await;
+        let mut client = TestClient::connect(&node, "client").await;
+        client.initialize().await;
+
+        server.fail_next("tools/list", -32002);
+        client.send(tools_list(1)).await;
+        let (error, _) = client.recv_answering_pings().await;
+        assert_eq!(error["error"]["code"], -32002);
+        let (closed, _) = client.recv_answering_pings().await;
+        assert_eq!(closed["method"], SESSION_CLOSED_METHOD);
+        assert_eq!(closed["params"]["reason"], "server_error");
+    }
+
+    #[tokio::test]
+    async fn error_reconnects_while_pinging() {
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        let _proxy = error_action_proxy(&endpoint, &server, "reconnect").await;
+        let mut client = TestClient::connect(&node, "client").await;
+        client.initialize().await;
+
+        server.fail_next("tools/list", -32002);
+        client.send(tools_list(1)).await;
+        let (error, _) = client.recv_answering_pings().await;
+        assert_eq!(error["error"]["code"], -32002);
+        // the request sent meanwhile waits for the new connection, and the
+        // client is pinged while the reconnection waits out its delay
+        let started = Instant::now();
+        client.send(tools_list(2)).await;
+        let (response, pings) = client.recv_answering_pings().await;
+        assert_eq!(response, json!({"jsonrpc": "2.0", "id": 2, "result": {}}));
+        assert!(started.elapsed() >= Duration::from_millis(250));
+        assert!(pings > 0);
+        assert_eq!(
+            server.methods()[3..],
+            ["initialize", "notifications/initialized", "tools/list"]
+        );
+        // the initialize request of the client was replayed under an internal id
+        let replayed = server.received.lock()[3].clone();
+        assert_eq!(replayed["method"], "initialize");
+        assert!(
+            replayed["id"]
+                .as_str()
+                .is_some_and(|id| id.contains(REINITIALIZE))
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_reconnection_closes_the_session() {
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        let _proxy = error_action_proxy(&endpoint, &server, "reconnect").await;
+        let mut client = TestClient::connect(&node, "client").await;
+        client.initialize().await;
+
+        server.fail_next("tools/list", -32002);
+        client.send(tools_list(1)).await;
+        let (error, _) = client.recv_answering_pings().await;
+        assert_eq!(error["error"]["code"], -32002);
+        server.stop();
+        let (closed, _) = client.recv_answering_pings().await;
+        assert_eq!(closed["method"], SESSION_CLOSED_METHOD);
+        assert_eq!(closed["params"]["reason"], "reconnect_failed");
+    }
+
+    #[tokio::test]
+    async fn sessions_kept_while_draining() {
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        let mut proxy = RunningProxy::start(
+            builder(&server.url).build().unwrap(),
+            &endpoint,
+            Duration::from_secs(30),
+        )
+        .await;
+        let mut client = TestClient::connect(&node, "client").await;
+        client.initialize().await;
+
+        proxy.admin.request_drain();
+        assert!(!proxy.admin.is_ready());
+
+        // the open session is still served, and keeps the proxy running
+        client
+            .send(json!({"jsonrpc": "2.0", "id": 1, "method": "tools/list"}))
+            .await;
+        assert_eq!(client.recv().await["id"], 1);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!proxy.task.is_finished());
+        assert!(!proxy.admin.is_ready());
+
+        // the proxy stops once the last session ends
+        client.close().await;
+        proxy.stopped().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn service_start_times_out_on_unresponsive_endpoint() {
+        let service = unresponsive_service().await;
+        let cancel = CancellationToken::new();
+        let started = Instant::now();
+        let start = run_service(&service, Duration::from_millis(200), &cancel).await;
+        assert_eq!(start.unwrap(), ServiceStart::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(!cancel.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn service_start_cancelled() {
+        let service = unresponsive_service().await;
+        let cancel = CancellationToken::new();
+        let stop = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            stop.cancel();
+        });
+        let start = run_service(&service, Duration::from_secs(60), &cancel).await;
+        assert_eq!(start.unwrap(), ServiceStart::Cancelled);
+    }
+
+    #[tokio::test]
+    #[tracing_test::traced_test]
+    async fn session_context_without_session_skipped() {
+        let server = StubMcpServer::start().await;
+        let config = builder(&server.url).build().unwrap().config;
+        let metrics = config.metrics.clone();
+        let (_tx, rx) = mpsc::unbounded_channel();
+        let ctx = SessionContext {
+            session: std::sync::Weak::new(),
+            rx,
+        };
+        assert!(notified_session(&ctx).is_none());
+        assert!(logs_contain("new session notification without a session"));
+
+        // the handler gives up without panicking and reports the end
+        let (tx_session_end, mut rx_session_end) = mpsc::unbounded_channel();
+        let end_guard = SessionEndGuard {
+            session_id: SessionId {
+                source: Name::from_strings(["org", "ns", "client"]),
+                id: 1,
+            },
+            tx_session_end,
+            metrics: metrics.clone(),
+        };
+        start_proxy_session(ctx, config, end_guard, Arc::new(SessionActivity::new(0)));
+        assert_eq!(rx_session_end.recv().await.unwrap().id, 1);
+        assert_eq!(
+            metrics.closed_sessions.get(),
+            [(CloseReason::InternalError.as_str().to_string(), 1)]
+        );
+        assert!(logs_contain("session context without a session"));
+        assert!(server.methods().is_empty());
+    }
+
+    #[tokio::test]
+    async fn panicking_session_cleaned_up() {
+        let metrics = Arc::new(Metrics::new(SourceLabels::Off));
+        let (tx_session_end, mut rx_session_end) = mpsc::unbounded_channel();
+        let guard = |id| SessionEndGuard {
+            session_id: SessionId {
+                source: Name::from_strings(["org", "ns", "client"]),
+                id,
+            },
+            tx_session_end: tx_session_end.clone(),
+            metrics: metrics.clone(),
+        };
+
+        let panicking = guard(1);
+        let handler = tokio::spawn(async move {
+            let _end_guard = panicking;
+            panic!("handler bug");
+        });
+        assert!(handler.await.unwrap_err().is_panic());
+        assert_eq!(rx_session_end.recv().await.unwrap().id, 1);
+        assert_eq!(metrics.closed_sessions.get(), [("panicked".to_string(), 1)]);
+
+        // the other sessions go on and end normally
+        let ending = guard(2);
+        tokio::spawn(async move {
+            let _end_guard = ending;
+        })
+        .await
+        .unwrap();
+        assert_eq!(rx_session_end.recv().await.unwrap().id, 2);
+        assert_eq!(metrics.closed_sessions.get(), [("panicked".to_string(), 1)]);
+    }
+
+    #[test]
+    fn no_dataplane_clients() {
+        assert!(matches!(
+            subscribe_endpoint(&[], None),
+            Err(ProxyError::NoDataplaneClients)
+        ));
+    }
+
+    #[test]
+    fn subscribe_endpoint_chosen_or_first() {
+        let clients = [
+            ClientConfig::with_endpoint("http://a:46357"),
+            ClientConfig::with_endpoint("http://b:46357"),
+        ];
+        assert_eq!(
+            subscribe_endpoint(&clients, None).unwrap(),
+            "http://a:46357"
+        );
+        let chosen = "http://b:46357".to_string();
+        assert_eq!(
+            subscribe_endpoint(&clients, Some(&chosen)).unwrap(),
+            "http://b:46357"
+        );
+        let unknown = "http://c:46357".to_string();
+        assert!(matches!(
+            subscribe_endpoint(&clients, Some(&unknown)),
+            Err(ProxyError::UnknownDataplaneEndpoint { configured, .. }) if configured.len() == 2
+        ));
+    }
+
+    #[test]
+    fn flood_rejected_past_the_cap() {
+        let mut pending = PendingRequests::new(Some(3));
+        let mut admitted = 0;
+        for n in 0..100 {
+            if !pending.is_full(0) {
+                pending.insert(RequestId::Number(n));
+                admitted += 1;
+            }
+        }
+        assert_eq!(admitted, 3);
+        assert_eq!(pending.len(), 3);
+
+        // held requests count against the cap
+        assert!(pending.remove(&RequestId::Number(0)));
+        assert!(!pending.is_full(0));
+        assert!(pending.is_full(1));
+
+        // an answered request frees its slot, an unknown one does not
+        assert!(!pending.remove(&RequestId::Number(50)));
+        pending.insert(RequestId::Number(100));
+        assert!(pending.is_full(0));
+    }
+
+    fn server_response(id: i64) -> ServerJsonRpcMessage {
+        serde_json::from_value(json!({"jsonrpc": "2.0", "id": id, "result": {}})).unwrap()
+    }
+
+    fn server_error(id: i64) -> ServerJsonRpcMessage {
+        serde_json::from_value(json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": {"code": -32603, "message": "failed"}
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn unknown_responses_per_policy() {
+        for policy in [
+            UnknownResponsePolicy::Forward,
+            UnknownResponsePolicy::Count,
+            UnknownResponsePolicy::Drop,
+        ] {
+            let mut pending = PendingRequests::new(None);
+            pending.insert(RequestId::Number(1));
+            pending.insert(RequestId::Number(2));
+
+            // answered requests are known once, whatever the policy
+            assert_eq!(pending.unknown_response(&server_response(1), policy), None);
+            assert_eq!(pending.unknown_response(&server_error(2), policy), None);
+            assert_eq!(pending.len(), 0);
+
+            let expected =
+                (policy != UnknownResponsePolicy::Forward).then_some(&RequestId::Number(1));
+            assert_eq!(
+                pending.unknown_response(&server_response(1), policy),
+                expected
+            );
+            let expected =
+                (policy != UnknownResponsePolicy::Forward).then_some(&RequestId::Number(3));
+            assert_eq!(pending.unknown_response(&server_error(3), policy), expected);
+        }
+    }
+
+    #[test]
+    fn notifications_never_unknown_responses() {
+        let mut pending = PendingRequests::new(None);
+        let progress: ServerJsonRpcMessage = serde_json::from_value(json!({
+            "jsonrpc": "2.0",
+            "method": "notifications/progress",
+            "params": {"progressToken": 1, "progress": 1}
+        }))
+        .unwrap();
+        assert_eq!(
+            pending.unknown_response(&progress, UnknownResponsePolicy::Drop),
+            None
+        );
+    }
+
+    const CLOSE_REASONS: [CloseReason; 20] = [
+        CloseReason::ClientClosed,
+        CloseReason::SessionError,
+        CloseReason::SessionDropped,
+        CloseReason::ServerClosed,
+        CloseReason::DiscoveryFailed,
+        CloseReason::HandshakeRejected,
+        CloseReason::LegacySseServer,
+        CloseReason::BadServerMessage,
+        CloseReason::MissedPings,
+        CloseReason::TimerFailed,
+        CloseReason::SlowClient,
+        CloseReason::NotConnected,
+        CloseReason::InternalError,
+        CloseReason::Panicked,
+        CloseReason::MemoryPressure,
+        CloseReason::Preempted,
+        CloseReason::Deadline,
+        CloseReason::ServerError,
+        CloseReason::WalFailed,
+        CloseReason::ReconnectFailed,
+    ];
+
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<parking_lot::Mutex<Vec<serde_json::Value>>>);
+
+    #[async_trait]
+    impl crate::outbound::Publish for Recorder {
+        async fn publish(&self, payload: Vec<u8>) -> bool {
+            self.0
+                .lock()
+                .push(serde_json::from_slice(&payload).unwrap());
+            true
+        }
+    }
+
+    #[tokio::test]
+    async fn close_notified_for_each_reason() {
+        let recorder = Recorder::default();
+        let outbound = Outbound::direct(recorder.clone());
+        for reason in CLOSE_REASONS {
+            let notification = session_closed(reason, Duration::from_millis(1500), 3, 4);
+            assert!(notify_closed(&outbound, notification).await);
+        }
+
+        let sent = recorder.0.lock().clone();
+        assert_eq!(sent.len(), CLOSE_REASONS.len());
+        for (reason, sent) in CLOSE_REASONS.into_iter().zip(sent) {
+            assert_eq!(sent["method"], SESSION_CLOSED_METHOD);
+            assert_eq!(
+                sent["params"],
+                json!({
+                    "reason": reason.as_str(),
+                    "retryable": reason.retryable(),
+                    "durationMs": 1500,
+                    "clientMessages": 3,
+                    "serverMessages": 4,
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn close_reasons_distinct_and_notified_when_retryable() {
+        let names: HashSet<_> = CLOSE_REASONS.iter().map(|r| r.as_str()).collect();
+        assert_eq!(names.len(), CLOSE_REASONS.len());
+        for reason in CLOSE_REASONS {
+            // a client that may retry right away is always told so
+            if reason.retryable() {
+                assert!(reason.always_notified(), "{}", reason.as_str());
+            }
+        }
+        assert!(CloseReason::Deadline.always_notified());
+        assert!(!CloseReason::ServerClosed.always_notified());
+    }
+
+    #[tokio::test]
+    async fn close_not_notified_to_a_gone_client() {
+        let outbound = Outbound::direct(SessionGone);
+        let notification = session_closed(CloseReason::NotConnected, Duration::ZERO, 0, 0);
+        assert!(!notify_closed(&outbound, notification).await);
+    }
+
+    struct SessionGone;
+
+    #[async_trait]
+    impl crate::outbound::Publish for SessionGone {
+        async fn publish(&self, _payload: Vec<u8>) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn uncapped_never_full() {
+        let mut pending = PendingRequests::new(None);
+        for n in 0..1000 {
+            pending.insert(RequestId::Number(n));
+        }
+        assert!(!pending.is_full(1000));
+        assert!(pending.contains(&RequestId::Number(999)));
+    }
+
+    /// Teardown of a session whose timer ticks every 10ms, with the channel
+    /// of the timer events
+    async fn ticking_teardown(
+        server: &StubMcpServer,
+    ) -> (SessionTeardown, mpsc::Receiver<PingTimerEvent>) {
+        let config = builder(&server.url).build().unwrap().config;
+        let client = BoundedClient::new(
+            config
+                .http_client(&Name::from_strings(["org", "ns", "client"]))
+                .unwrap(),
+            DEFAULT_READ_BUFFER_BYTES,
+        );
+        let transport = config.mcp_transport(client).await.unwrap();
+        let (tx_timer, mut rx_timer) = mpsc::channel(128);
+        let timer = Timer::new(
+            1,
+            TimerType::Constant,
+            Duration::from_millis(10),
+            None,
+            None,
+        );
+        timer.start(Arc::new(PingTimerObserver {
+            tx_proxy_session: tx_timer,
+        }));
+        assert!(rx_timer.recv().await.is_some());
+        (SessionTeardown::new(timer, transport), rx_timer)
+    }
+
+    /// The timer is stopped once its channel closes
+    async fn timer_stopped(mut rx_timer: mpsc::Receiver<PingTimerEvent>) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while rx_timer.recv().await.is_some() {}
+        })
+        .await
+        .expect("timer still running");
+    }
+
+    #[tokio::test]
+    async fn teardown_on_every_exit_path() {
+        let server = StubMcpServer::start().await;
+
+        // the end of the session loop, or an early return
+        let (teardown, rx_timer) = ticking_teardown(&server).await;
+        drop(teardown);
+        timer_stopped(rx_timer).await;
+
+        // a connection replaced, then dropped
+        let (mut teardown, rx_timer) = ticking_teardown(&server).await;
+        let (mut other, _) = ticking_teardown(&server).await;
+        teardown.replace_transport(other.keep_transport());
+        drop(teardown);
+        timer_stopped(rx_timer).await;
+
+        // a panic of the handler
+        let (teardown, rx_timer) = ticking_teardown(&server).await;
+        let handler = tokio::spawn(async move {
+            let _teardown = teardown;
+            panic!("handler failure");
+        });
+        assert!(handler.await.unwrap_err().is_panic());
+        timer_stopped(rx_timer).await;
+    }
+
+    #[tokio::test]
+    async fn kept_transport_outlives_the_teardown() {
+        let server = StubMcpServer::start().await;
+        let (mut teardown, rx_timer) = ticking_teardown(&server).await;
+        let mut transport = teardown.keep_transport();
+        drop(teardown);
+        timer_stopped(rx_timer).await;
+
+        transport.send(initialize_request()).await.unwrap();
+        let response = tokio::time::timeout(Duration::from_secs(5), transport.receive())
+            .await
+            .unwrap();
+        assert!(matches!(response, Some(JsonRpcMessage::Response(_))));
+    }
+
+    fn deadline_metadata(value: &str) -> HashMap<String, String> {
+        HashMap::from([(SESSION_DEADLINE_KEY.to_string(), value.to_string())])
+    }
+
+    #[test]
+    fn session_deadline_of_the_client() {
+        let in_a_minute = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 60;
+        let metadata = deadline_metadata(&in_a_minute.to_string());
+        let deadline = session_deadline(&metadata, None).unwrap();
+        let left = deadline - tokio::time::Instant::now();
+        assert!(left > Duration::from_secs(55) && left <= Duration::from_secs(60));
+
+        // the earlier of the client's and the configured deadline
+        let deadline = session_deadline(&metadata, Some(Duration::from_secs(10))).unwrap();
+        assert!(deadline - tokio::time::Instant::now() <= Duration::from_secs(10));
+        let deadline = session_deadline(&metadata, Some(Duration::from_secs(3600))).unwrap();
+        assert!(deadline - tokio::time::Instant::now() <= Duration::from_secs(60));
+
+        // a past deadline is due at once
+        let deadline = session_deadline(&deadline_metadata("1"), None).unwrap();
+        assert!(deadline <= tokio::time::Instant::now());
+    }
+
+    #[test]
+    fn invalid_session_deadline_ignored() {
+        for value in [
+            u64::MAX.to_string(),
+            "tomorrow".to_string(),
+            "-1".to_string(),
+        ] {
+            let metadata = deadline_metadata(&value);
+            assert_eq!(session_deadline(&metadata, None), None, "{value}");
+            // the configured deadline still applies
+            let deadline = session_deadline(&metadata, Some(Duration::from_secs(10))).unwrap();
+            let left = deadline - tokio::time::Instant::now();
+            assert!(left > Duration::from_secs(9) && left <= Duration::from_secs(10));
+        }
+        assert_eq!(session_deadline(&HashMap::new(), None), None);
+    }
+
+    #[tokio::test]
+    async fn ping_timer_never_blocked_by_a_full_channel() {
+        let (tx_timer, mut rx_timer) = mpsc::channel(2);
+        let observer = PingTimerObserver {
+            tx_proxy_session: tx_timer,
+        };
+        for timeouts in 0..2 {
+            observer.on_timeout(1, timeouts).await;
+        }
+        // the session does not consume the events: the next ones are skipped
+        tokio::time::timeout(Duration::from_secs(1), async {
+            for timeouts in 2..10 {
+                observer.on_timeout(1, timeouts).await;
+            }
+        })
+        .await
+        .expect("ping timer blocked by a full channel");
+        assert_eq!(rx_timer.len(), 2);
+        assert!(matches!(
+            rx_timer.recv().await,
+            Some(PingTimerEvent::Timeout)
+        ));
+        // a consumed event makes room for the next ping
+        observer.on_timeout(1, 10).await;
+        assert_eq!(rx_timer.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn ping_timer_failure_reported_to_the_session() {
+        let (tx_timer, mut rx_timer) = mpsc::channel(128);
+        // gives up after two pings
+        let timer = Timer::new(
+            1,
+            TimerType::Constant,
+            Duration::from_millis(10),
+            None,
+            Some(2),
+        );
+        timer.start(Arc::new(PingTimerObserver {
+            tx_proxy_session: tx_timer,
+        }));
+        let mut events = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            // the timer ends after the failure, closing the channel
+            while let Some(event) = rx_timer.recv().await {
+                events.push(event);
+            }
+        })
+        .await
+        .expect("timer still running");
+        assert_eq!(
+            events,
+            [
+                PingTimerEvent::Timeout,
+                PingTimerEvent::Timeout,
+                PingTimerEvent::Failure
+            ]
+        );
+    }
+
+    /// Close the session of the client, wait for the proxy to end it, and
+    /// open a new session of the same client. The proxy must expose its
+    /// sessions.
+    async fn reconnect(client: TestClient, proxy: &RunningProxy) -> TestClient {
+        eventually(|| proxy.admin.active_sessions().len() == 1).await;
+        let app = client.close().await;
+        eventually(|| proxy.admin.active_sessions().is_empty()).await;
+        TestClient::open(app, HashMap::new()).await
+    }
+
+    #[tokio::test]
+    async fn client_back_within_the_affinity_window_resumes() {
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        let proxy = RunningProxy::start(
+            builder(&server.url)
+                .with_session_affinity(Some(Duration::from_secs(30)))
+                .with_admin_addr(Some("127.0.0.1:0".parse().unwrap()))
+                .with_admin_sessions(true)
+                .build()
+                .unwrap(),
+            &endpoint,
+            Duration::from_secs(5),
+        )
+        .await;
+        let mut client = TestClient::connect(&node, "client").await;
+        client.initialize().await;
+        client.send(tools_list(1)).await;
+        assert_eq!(client.recv().await["id"], 1);
+        let client = reconnect(client, &proxy).await;
+
+        // back and gone again without initializing: the connection is kept
+        let mut client = reconnect(client, &proxy).await;
+        let initialized = client.initialize().await;
+        assert_eq!(initialized["result"]["serverInfo"]["name"], "stub");
+        client.send(tools_list(2)).await;
+        assert_eq!(client.recv().await["id"], 2);
+        // a single MCP session served the three sessions of the client
+        eventually(|| {
+            server.methods()
+                == [
+                    "initialize",
+                    "notifications/initialized",
+                    "tools/list",
+                    "tools/list",
+                ]
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn ping_round_trip_served() {
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        let proxy = RunningProxy::start(
+            builder(&server.url)
+                .with_ping_interval(Some(Duration::from_millis(300)))
+                .with_admin_addr(Some("127.0.0.1:0".parse().unwrap()))
+                .with_admin_sessions(true)
+                .build()
+                .unwrap(),
+            &endpoint,
+            Duration::from_secs(5),
+        )
+        .await;
+        let mut client = TestClient::connect(&node, "client").await;
+        client.initialize().await;
+
+        let ping = client.recv().await;
+        assert_eq!(ping["method"], "ping");
+        let delay = Duration::from_millis(150);
+        tokio::time::sleep(delay).await;
+        client
+            .send(json!({"jsonrpc": "2.0", "id": ping["id"], "result": {}}))
+            .await;
+        eventually(|| {
+            proxy
+                .admin
+                .active_sessions()
+                .first()
+                .is_some_and(|session| session.last_ping_rtt.is_some())
+        })
+        .await;
+        let session = &proxy.admin.active_sessions()[0];
+        let rtt = session.last_ping_rtt.unwrap();
+        assert!(rtt >= delay && rtt < delay * 4, "{rtt:?}");
+        assert_eq!(session.health, crate::keepalive::PingHealth::Healthy);
+    }
+
+    #[tokio::test]
+    async fn pings_not_delayed_by_a_busy_session() {
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        let _proxy = RunningProxy::start(
+            builder(&server.url)
+                .with_ping_interval(Some(Duration::from_millis(100)))
+                .with_ping_priority(true)
+                // the answers of the client queue behind its backlog
+                .with_max_missed_pings(50)
+                .build()
+                .unwrap(),
+            &endpoint,
+            Duration::from_secs(5),
+        )
+        .await;
+        let mut client = TestClient::connect(&node, "client").await;
+        client.initialize().await;
+
+        // forwarding the backlog of the client takes a second
+        server.delay("notifications/progress", Duration::from_millis(50));
+        for progress in 0..20 {
+            client
+                .send(json!({
+                    "jsonrpc": "2.0",
+                    "method": "notifications/progress",
+                    "params": {"progressToken": "backlog", "progress": progress}
+                }))
+                .await;
+        }
+        let progress = || {
+            server
+                .methods()
+                .iter()
+                .filter(|m| *m == "notifications/progress")
+                .count()
+        };
+
+        // the pings go out every interval while the backlog is forwarded
+        let mut pings = 0;
+        while progress() < 20 {
+            let ping = client.recv().await;
+            assert_eq!(ping["method"], "ping");
+            pings += 1;
+            client
+                .send(json!({"jsonrpc": "2.0", "id": ping["id"], "result": {}}))
+                .await;
+        }
+        assert!(pings >= 7, "{pings} pings while forwarding the backlog");
+    }
+
+    #[tokio::test]
+    async fn shadow_receives_copies_without_affecting_replies() {
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        let shadow = StubMcpServer::start().await;
+        let _proxy = RunningProxy::start(
+            builder(&server.url)
+                .with_shadow_mcp_server(Some(shadow.url.clone()))
+                .build()
+                .unwrap(),
+            &endpoint,
+            Duration::from_secs(5),
+        )
+        .await;
+        let mut client = TestClient::connect(&node, "client").await;
+        client.initialize().await;
+
+        // a slow shadow server does not delay the reply
+        shadow.delay("tools/list", Duration::from_secs(3));
+        let started = Instant::now();
+        client.send(tools_list(2)).await;
+        assert_eq!(client.recv().await["id"], 2);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        eventually(|| shadow.with_id(json!(2)).len() == 1).await;
+        assert_eq!(shadow.with_id(json!(2))[0]["method"], "tools/list");
+
+        // nor does a failing one
+        shadow.stop();
+        client.send(tools_list(3)).await;
+        let reply = client.recv().await;
+        assert_eq!(reply["id"], 3);
+        assert!(reply.get("error").is_none(), "{reply}");
+        assert_eq!(server.with_id(json!(3)).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn graceful_and_abrupt_client_closes_recorded() {
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        let proxy = builder(&server.url)
+            .with_ping_interval(Some(Duration::from_millis(100)))
+            .build()
+            .unwrap();
+        let metrics = proxy.config.metrics.clone();
+        let _proxy = RunningProxy::start(proxy, &endpoint, Duration::from_secs(5)).await;
+
+        // the client closes its session
+        let mut client = TestClient::connect(&node, "client").await;
+        client.initialize().await;
+        let app = client.close().await;
+        eventually(|| metrics.closed_sessions.get() == [("client_closed".to_string(), 1)]).await;
+
+        // the client disappears without closing it, which only the pings tell
+        let mut client = TestClient::connect(&node, "other").await;
+        client.initialize().await;
+        drop(client);
+        drop(app);
+        eventually(|| {
+            metrics.closed_sessions.get()
+                == [
+                    ("client_closed".to_string(), 1),
+                    ("missed_pings".to_string(), 1),
+                ]
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn source_headers_sent_to_the_mcp_server() {
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        let _proxy = RunningProxy::start(
+            builder(&server.url)
+                .with_source_headers(vec!["org/ns/tenant-a=X-Tenant: a".parse().unwrap()])
+                .build()
+                .unwrap(),
+            &endpoint,
+            Duration::from_secs(5),
+        )
+        .await;
+
+        let mut client = TestClient::connect(&node, "tenant-a").await;
+        client.initialize().await;
+        client.send(tools_list(1)).await;
+        client.recv().await;
+        let headers = server.headers();
+        assert_eq!(headers.len(), 3);
+        assert!(headers.iter().all(|h| h["x-tenant"] == "a"));
+        client.close().await;
+
+        // not added for the other clients
+        let mut client = TestClient::connect(&node, "tenant-b").await;
+        client.initialize().await;
+        eventually(|| server.headers().len() == 5).await;
+        assert!(
+            server.headers()[3..]
+                .iter()
+                .all(|h| !h.contains_key("x-tenant"))
+        );
+    }
+
+    /// TCP relay to the SLIM node whose connections can be cut, e.g. to
+    /// simulate the loss of the dataplane connection of the proxy
+    struct Relay {
+        endpoint: String,
+        connections: Arc<parking_lot::Mutex<tokio::task::JoinSet<()>>>,
+        task: tokio::task::JoinHandle<()>,
+    }
+
+    impl Relay {
+        async fn start(node_endpoint: &str) -> Self {
+            let node_addr = node_endpoint.trim_start_matches("http://").to_string();
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let endpoint = format!("http://{}", listener.local_addr().unwrap());
+            let connections = Arc::new(parking_lot::Mutex::new(tokio::task::JoinSet::new()));
+            let relayed = connections.clone();
+            let task = tokio::spawn(async move {
+                while let Ok((mut inbound, _)) = listener.accept().await {
+                    let node_addr = node_addr.clone();
+                    relayed.lock().spawn(async move {
+                        let mut outbound = tokio::net::TcpStream::connect(node_addr).await.unwrap();
+                        let _ = tokio::io::copy_bidirectional(&mut inbound, &mut outbound).await;
+                    });
+                }
+            });
+            Self {
+                endpoint,
+                connections,
+                task,
+            }
+        }
+
+        /// Cut the open connections, the new ones are still relayed
+        fn cut(&self) {
+            self.connections.lock().abort_all();
+        }
+    }
+
+    impl Drop for Relay {
+        fn drop(&mut self) {
+            self.task.abort();
+            self.cut();
+        }
+    }
+
+    #[tokio::test]
+    async fn session_closed_when_the_dataplane_connection_drops() {
+        let (node, endpoint) = dataplane().await;
+        // the proxy reaches the node through a relay cut mid-session
+        let relay = Relay::start(&endpoint).await;
+        let server = StubMcpServer::start().await;
+        let proxy = builder(&server.url)
+            .with_ping_interval(Some(Duration::from_millis(100)))
+            .build()
+            .unwrap();
+        let metrics = proxy.config.metrics.clone();
+        let _proxy = RunningProxy::start(proxy, &relay.endpoint, Duration::from_secs(5)).await;
+        let mut client = TestClient::connect(&node, "client").await;
+        client.initialize().await;
+
+        drop(relay);
+        eventually(|| metrics.closed_sessions.get().len() == 1).await;
+    }
+
+    #[tokio::test]
+    async fn publisher_stops_once_the_session_is_closed() {
+        use crate::outbound::Publish;
+
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        let _proxy = RunningProxy::start(
+            builder(&server.url).build().unwrap(),
+            &endpoint,
+            Duration::from_secs(5),
+        )
+        .await;
+        let mut client = TestClient::connect(&node, "client").await;
+        client.initialize().await;
+        let publisher = SessionPublisher {
+            session: Arc::downgrade(&client.session),
+            remote_name: Name::from_strings(PROXY_NAME),
+            conn: 0,
+        };
+        assert!(publisher.publish(b"{}".to_vec()).await);
+
+        // every later message would fail as well
+        let closed = client.app.delete_session(&client.session).unwrap();
+        let _ = tokio::time::timeout(Duration::from_secs(5), closed).await;
+        assert!(!publisher.publish(b"{}".to_vec()).await);
+    }
+
+    #[tokio::test]
+    async fn session_cookie_sent_back_to_the_mcp_server() {
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        server.require_cookie();
+        let _proxy = RunningProxy::start(
+            builder(&server.url).build().unwrap(),
+            &endpoint,
+            Duration::from_secs(5),
+        )
+        .await;
+
+        let mut client = TestClient::connect(&node, "client").await;
+        client.initialize().await;
+        client.send(tools_list(1)).await;
+        let reply = client.recv().await;
+        assert!(reply["result"].is_object(), "{reply}");
+
+        // each session sends back its own cookie
+        let mut other = TestClient::connect(&node, "other").await;
+        other.initialize().await;
+        other.send(tools_list(2)).await;
+        let reply = other.recv().await;
+        assert!(reply["result"].is_object(), "{reply}");
+        let cookies: Vec<_> = server
+            .headers()
+            .iter()
+            .map(|h| h.get("cookie").map(|c| c.to_str().unwrap().to_string()))
+            .collect();
+        assert_eq!(
+            cookies
+                .iter()
+                .filter(|c| c.as_deref() == Some("session=0"))
+                .count(),
+            2
+        );
+        assert_eq!(
+            cookies
+                .iter()
+                .filter(|c| c.as_deref() == Some("session=1"))
+                .count(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn blocked_tool_results_replaced_with_an_error() {
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        let proxy = builder(&server.url)
+            .with_content_filter(vec![r"\d{4}-\d{4}-\d{4}-\d{4}".into()], FilterAction::Block)
+            .build()
+            .unwrap();
+        let metrics = proxy.config.metrics.clone();
+        let _proxy = RunningProxy::start(proxy, &endpoint, Duration::from_secs(5)).await;
+        let mut client = TestClient::connect(&node, "client").await;
+        client.initialize().await;
+        let call = |id| {
+            json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "method": "tools/call",
+                "params": {"name": "lookup", "arguments": {}}
+            })
+        };
+
+        server.answer(
+            "tools/call",
+            json!({"content": [{"type": "text", "text": "order shipped"}]}),
+        );
+        client.send(call(1)).await;
+        let reply = client.recv().await;
+        assert_eq!(reply["result"]["content"][0]["text"], "order shipped");
+
+        server.answer(
+            "tools/call",
+            json!({"content": [{"type": "text", "text": "card 1234-5678-9012-3456"}]}),
+        );
+        client.send(call(2)).await;
+        let reply = client.recv().await;
+        assert_eq!(reply["id"], 2);
+        assert_eq!(reply["error"]["code"], -32603);
+        assert_eq!(
+            reply["error"]["message"],
+            "tool result blocked by content filter"
+        );
+        assert_eq!(
+            metrics.filtered_tool_results.get(),
+            [("block".to_string(), 1)]
+        );
+    }
+
+    #[tokio::test]
+    async fn sessions_accepted_after_a_dataplane_reconnection() {
+        let (node, endpoint) = dataplane().await;
+        let relay = Relay::start(&endpoint).await;
+        let server = StubMcpServer::start().await;
+        let _proxy = RunningProxy::start(
+            builder(&server.url)
+                .with_subscription_check_interval(Some(Duration::from_millis(50)))
+                .build()
+                .unwrap(),
+            &relay.endpoint,
+            Duration::from_secs(5),
+        )
+        .await;
+        let mut client = TestClient::connect(&node, "client").await;
+        client.initialize().await;
+        client.close().await;
+
+        relay.cut();
+        // the proxy accepts new sessions once reconnected
+        let mut client = TestClient::connect(&node, "other").await;
+        client.initialize().await;
+    }
+
+    #[tokio::test]
+    #[tracing_test::traced_test]
+    async fn server_logs_forwarded_and_mirrored() {
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        let _proxy = RunningProxy::start(
+            builder(&server.url)
+                .with_mirror_server_logs(true)
+                .build()
+                .unwrap(),
+            &endpoint,
+            Duration::from_secs(5),
+        )
+        .await;
+        let log = json!({
+            "jsonrpc": "2.0",
+            "method": "notifications/message",
+            "params": {"level": "warning", "logger": "db", "data": "slow query"}
+        });
+        server.before_response("tools/list", vec![log.clone()]);
+        let mut client = TestClient::connect(&node, "client").await;
+        client.initialize().await;
+
+        client.send(tools_list(1)).await;
+        assert_eq!(client.recv().await, log);
+        assert_eq!(client.recv().await["id"], 1);
+        assert!(logs_contain("MCP server log: \"slow query\""));
+        assert!(logs_contain("logger=db"));
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn server_log_levels_mapped() {
+        for (level, expected) in [
+            (LoggingLevel::Debug, "DEBUG"),
+            (LoggingLevel::Notice, "INFO"),
+            (LoggingLevel::Warning, "WARN"),
+            (LoggingLevel::Critical, "ERROR"),
+        ] {
+            let data = format!("{level:?} entry");
+            mirror_server_log(
+                1,
+                &LoggingMessageNotificationParam {
+                    level,
+                    logger: None,
+                    data: json!(data),
+                },
+            );
+            logs_assert(|lines: &[&str]| {
+                match lines
+                    .iter()
+                    .any(|line| line.contains(expected) && line.contains(&data))
+                {
+                    true => Ok(()),
+                    false => Err(format!("{data} not logged at {expected}")),
+                }
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn sessions_of_other_sources_rejected() {
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        let allowlist =
+            std::env::temp_dir().join(format!("source-allowlist-{}", std::process::id()));
+        std::fs::write(&allowlist, "org/ns/allowed\n").unwrap();
+        let proxy = builder(&server.url)
+            .with_source_allowlist(Some(allowlist.clone()))
+            .build()
+            .unwrap();
+        std::fs::remove_file(&allowlist).unwrap();
+        let metrics = proxy.config.metrics.clone();
+        let _proxy = RunningProxy::start(proxy, &endpoint, Duration::from_secs(5)).await;
+
+        let mut client = TestClient::connect(&node, "allowed").await;
+        assert_eq!(client.initialize().await["id"], 0);
+
+        let mut other = TestClient::connect(&node, "other").await;
+        other.closed().await;
+        let rejected = metrics
+            .samples()
+            .into_iter()
+            .find(|sample| sample.name == "rejected_sessions_total")
+            .unwrap();
+        assert_eq!(rejected.value, crate::metrics::SampleValue::Counter(1));
+        // only the allowed client reached the MCP server
+        eventually(|| server.methods().len() == 2).await;
+        assert_eq!(
+            server.methods(),
+            ["initialize", "notifications/initialized"]
+        );
+    }
+
+    #[tokio::test]
+    async fn fallback_used_when_the_primary_is_down() {
+        let (node, endpoint) = dataplane().await;
+        // nothing listens on the primary address
+        let primary = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}/mcp", listener.local_addr().unwrap())
+        };
+        let fallback = StubMcpServer::start().await;
+        let _proxy = RunningProxy::start(
+            builder(&primary)
+                .with_mcp_server_fallback(Some(fallback.url.clone()))
+                .build()
+                .unwrap(),
+            &endpoint,
+            Duration::from_secs(5),
+        )
+        .await;
+        let mut client = TestClient::connect(&node, "client").await;
+        assert_eq!(
+            client.initialize().await["result"]["serverInfo"]["name"],
+            "stub"
+        );
+
+        // the session stays on the fallback
+        client.send(tools_list(1)).await;
+        assert_eq!(client.recv().await["id"], 1);
+        assert_eq!(
+            fallback.methods(),
+            ["initialize", "notifications/initialized", "tools/list"]
+        );
+    }
+
+    /// Proxy publishing two messages every 100ms to its clients, whose MCP
+    /// server sends ten progress notifications before answering `tools/list`
+    async fn slow_client_proxy(
+        endpoint: &str,
+        server: &StubMcpServer,
+        queue_size: usize,
+        policy: OverloadPolicy,
+    ) -> (RunningProxy, Arc<Metrics>) {
+        let progress = (0..10)
+            .map(|progress: u32| {
+                json!({
+                    "jsonrpc": "2.0",
+                    "method": "notifications/progress",
+                    "params": {"progressToken": "burst", "progress": progress}
+                })
+            })
+            .collect();
+        server.before_response("tools/list", progress);
+        let proxy = builder(&server.url)
+            .with_outbound_queue(Some(queue_size), policy)
+            .with_session_credits(
+                Some(2),
+                None,
+                Duration::from_millis(100),
+                CreditPolicy::Strict,
+            )
+            .build()
+            .unwrap();
+        let metrics = proxy.config.metrics.clone();
+        (
+            RunningProxy::start(proxy, endpoint, Duration::from_secs(5)).await,
+            metrics,
+        )
+    }
+
+    #[tokio::test]
+    async fn burst_buffered_for_a_slow_client() {
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        let (_proxy, _) =
+            slow_client_proxy(&endpoint, &server, 16, OverloadPolicy::Backpressure).await;
+        let mut client = TestClient::connect(&node, "client").await;
+        client.initialize().await;
+
+        client.send(tools_list(1)).await;
+        for progress in 0..10 {
+            assert_eq!(client.recv().await["params"]["progress"], progress as f64);
+        }
+        assert_eq!(client.recv().await["id"], 1);
+    }
+
+    #[tokio::test]
+    async fn slow_client_disconnected() {
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        let (_proxy, metrics) =
+            slow_client_proxy(&endpoint, &server, 2, OverloadPolicy::Disconnect).await;
+        let mut client = TestClient::connect(&node, "client").await;
+        client.initialize().await;
+
+        client.send(tools_list(1)).await;
+        eventually(|| metrics.closed_sessions.get() == [("slow_client".to_string(), 1)]).await;
+    }
+
+    /// Policy sending the first ping and closing the session at the next one,
+    /// keeping the contexts it is called with
+    #[derive(Debug, Default)]
+    struct PingOnce(parking_lot::Mutex<Vec<PingContext>>);
+
+    impl PingPolicy for PingOnce {
+        fn on_ping_due(&self, ctx: &PingContext) -> PingDecision {
+            let mut calls = self.0.lock();
+            calls.push(*ctx);
+            match calls.len() {
+                1 => PingDecision::Send,
+                _ => PingDecision::Close,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn custom_ping_policy_closes_the_session() {
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        let policy = Arc::new(PingOnce::default());
+        let proxy = builder(&server.url)
+            .with_ping_interval(Some(Duration::from_millis(100)))
+            .with_ping_policy(policy.clone())
+            .build()
+            .unwrap();
+        let metrics = proxy.config.metrics.clone();
+        let _proxy = RunningProxy::start(proxy, &endpoint, Duration::from_secs(5)).await;
+        let mut client = TestClient::connect(&node, "client").await;
+        client.initialize().await;
+
+        assert_eq!(client.recv().await["method"], "ping");
+        eventually(|| metrics.closed_sessions.get() == [("missed_pings".to_string(), 1)]).await;
+        let calls = policy.0.lock();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].session_id, client.session.id());
+        assert_eq!(calls[0].ping_interval, Duration::from_millis(100));
+        // the first ping is not answered
+        assert_eq!(calls[0].pending_pings, 0);
+        assert_eq!(calls[1].pending_pings, 1);
+    }
+
+    #[tokio::test]
+    async fn startup_and_session_phases_timed() {
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        let proxy = builder(&server.url).build().unwrap();
+        let metrics = proxy.config.metrics.clone();
+        let _proxy = RunningProxy::start(proxy, &endpoint, Duration::from_secs(5)).await;
+        let observed = |phases: &crate::metrics::PhaseDurations| {
+            phases
+                .get()
+                .into_iter()
+                .filter(|(_, histogram)| *histogram != crate::metrics::Histogram::default())
+                .map(|(phase, _)| phase)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            observed(&metrics.startup_phases),
+            ["get_connection_id", "service_run", "subscribe"]
+        );
+
+        let mut client = TestClient::connect(&node, "client").await;
+        client.initialize().await;
+        eventually(|| observed(&metrics.session_phases) == ["handshake", "setup"]).await;
+    }
+
+    #[tokio::test]
+    async fn sessions_rejected_at_the_limit_of_the_client() {
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        let proxy = builder(&server.url)
+            .with_source_session_limits(Some(2), vec!["org/ns/limited=1".parse().unwrap()])
+            .build()
+            .unwrap();
+        let metrics = proxy.config.metrics.clone();
+        let _proxy = RunningProxy::start(proxy, &endpoint, Duration::from_secs(5)).await;
+        let rejected = || {
+            metrics
+                .samples()
+                .into_iter()
+                .find(|sample| sample.name == "rejected_sessions_total")
+                .map(|sample| sample.value)
+        };
+
+        // the limit of the matching pattern
+        let mut limited = TestClient::connect(&node, "limited").await;
+        limited.initialize().await;
+        // the limit counts the sessions of the same client instance
+        let (_second, mut rx) = TestClient::establish(&limited.app, HashMap::new()).await;
+        TestClient::wait_closed(&mut rx).await;
+        assert_eq!(rejected(), Some(crate::metrics::SampleValue::Counter(1)));
+
+        // the default limit of the other clients
+        let mut other = TestClient::connect(&node, "other").await;
+        other.initialize().await;
+        let (_second, _second_rx) = TestClient::establish(&other.app, HashMap::new()).await;
+        let (_third, mut rx) = TestClient::establish(&other.app, HashMap::new()).await;
+        TestClient::wait_closed(&mut rx).await;
+        assert_eq!(rejected(), Some(crate::metrics::SampleValue::Counter(2)));
+    }
+
+    #[tokio::test]
+    async fn subscribe_retried_until_it_succeeds() {
+        let calls = std::sync::atomic::AtomicU32::new(0);
+        let started = Instant::now();
+        subscribe_with_retry(5, 1, || async {
+            if calls.fetch_add(1, std::sync::atomic::Ordering::Relaxed) < 2 {
+                Err(slim_service::ServiceError::ConnectionError(
+                    "not ready".into(),
+                ))
+            } else {
+                Ok(())
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(calls.load(std::sync::atomic::Ordering::Relaxed), 3);
+        // 500ms after the first failure, then twice as long
+        assert!(started.elapsed() >= SUBSCRIBE_RETRY_BACKOFF * 3);
+    }
+
+    #[tokio::test]
+    async fn subscribe_fails_after_the_last_attempt() {
+        let calls = std::sync::atomic::AtomicU32::new(0);
+        let error = subscribe_with_retry(2, 1, || async {
+            calls.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            Err(slim_service::ServiceError::ConnectionError(
+                "not ready".into(),
+            ))
+        })
+        .await
+        .unwrap_err();
+        assert!(
+            matches!(error, ProxyError::Subscribe { attempts: 2, .. }),
+            "{error}"
+        );
+        assert_eq!(calls.load(std::sync::atomic::Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn duplicate_request_id_rejected() {
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        server.answer("tools/list", json!({"tools": []}));
+        // the first request is still in flight when the duplicate arrives
+        server.delay("tools/list", Duration::from_millis(500));
+        let _proxy = RunningProxy::start(
+            builder(&server.url)
+                .with_duplicate_request_id_policy(DuplicateRequestIdPolicy::Reject)
+                .build()
+                .unwrap(),
+            &endpoint,
+            Duration::from_secs(5),
+        )
+        .await;
+        let mut client = TestClient::connect(&node, "client").await;
+        client.initialize().await;
+
+        client.send(tools_list(1)).await;
+        client.send(tools_list(1)).await;
+        let rejected = client.recv().await;
+        assert_eq!(rejected["id"], 1);
+        assert_eq!(rejected["error"]["code"], -32600);
+        assert_eq!(rejected["error"]["message"], "duplicate request id");
+        let answered = client.recv().await;
+        assert_eq!(answered["id"], 1);
+        assert_eq!(answered["result"], json!({"tools": []}));
+        assert_eq!(server.with_id(json!(1)).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn burst_of_setups_throttled() {
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        server.delay("initialize", Duration::from_millis(500));
+        let proxy = builder(&server.url)
+            .with_max_concurrent_setups(Some(1))
+            .build()
+            .unwrap();
+        let metrics = proxy.config.metrics.clone();
+        let _proxy = RunningProxy::start(proxy, &endpoint, Duration::from_secs(5)).await;
+        let mut clients = Vec::new();
+        for name in ["first", "second", "third"] {
+            clients.push(TestClient::connect(&node, name).await);
+        }
+        let initializes = || {
+            server
+                .methods()
+                .iter()
+                .filter(|m| *m == "initialize")
+                .count()
+        };
+
+        for client in &clients {
+            client
+                .send(json!({
+                    "jsonrpc": "2.0",
+                    "id": 0,
+                    "method": "initialize",
+                    "params": {
+                        "protocolVersion": "2025-03-26",
+                        "capabilities": {},
+                        "clientInfo": {"name": "test", "version": "1.0.0"}
+                    }
+                }))
+                .await;
+        }
+        // the other handshakes wait for the first one to complete
+        eventually(|| initializes() == 1).await;
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert_eq!(initializes(), 1);
+
+        for client in &mut clients {
+            assert!(client.recv().await["result"].is_object());
+        }
+        assert_eq!(initializes(), 3);
+        assert!(
+            metrics
+                .session_phases
+                .get()
+                .iter()
+                .any(|(phase, _)| *phase == "setup_wait")
+        );
+    }
+
+    #[tokio::test]
+    async fn duplicate_subscriptions_coalesced() {
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        let _proxy = RunningProxy::start(
+            builder(&server.url)
+                .with_coalesce_subscriptions(true)
+                .build()
+                .unwrap(),
+            &endpoint,
+            Duration::from_secs(5),
+        )
+        .await;
+        let mut client = TestClient::connect(&node, "client").await;
+        client.initialize().await;
+        let forwarded = || {
+            server
+                .methods()
+                .iter()
+                .filter(|m| *m == "resources/subscribe")
+                .count()
+        };
+        async fn request(
+            client: &mut TestClient,
+            id: u64,
+            method: &str,
+            uri: &str,
+        ) -> serde_json::Value {
+            client
+                .send(json!({"jsonrpc": "2.0", "id": id, "method": method, "params": {"uri": uri}}))
+                .await;
+            client.recv().await
+        }
+
+        // a duplicate is answered by the proxy
+        assert_eq!(
+            request(&mut client, 1, "resources/subscribe", "file:///a").await["id"],
+            1
+        );
+        let duplicate = request(&mut client, 2, "resources/subscribe", "file:///a").await;
+        assert_eq!(duplicate, json!({"jsonrpc": "2.0", "id": 2, "result": {}}));
+        assert_eq!(forwarded(), 1);
+
+        // overlapping and empty URIs are resources of their own
+        request(&mut client, 3, "resources/subscribe", "file:///a/b").await;
+        request(&mut client, 4, "resources/subscribe", "").await;
+        assert_eq!(forwarded(), 3);
+        request(&mut client, 5, "resources/subscribe", "").await;
+        assert_eq!(forwarded(), 3);
+
+        // the subscription ends with the unsubscribe
+        request(&mut client, 6, "resources/unsubscribe", "file:///a").await;
+        request(&mut client, 7, "resources/subscribe", "file:///a").await;
+        assert_eq!(forwarded(), 4);
+
+        // a subscription refused by the MCP server is not remembered
+        server.fail_next("resources/subscribe", -32602);
+        assert!(
+            request(&mut client, 8, "resources/subscribe", "file:///c").await["error"].is_object()
+        );
+        request(&mut client, 9, "resources/subscribe", "file:///c").await;
+        assert_eq!(forwarded(), 6);
+    }
+
+    #[tokio::test]
+    async fn other_client_not_resumed() {
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        let proxy = RunningProxy::start(
+            builder(&server.url)
+                .with_session_affinity(Some(Duration::from_secs(30)))
+                .with_admin_addr(Some("127.0.0.1:0".parse().unwrap()))
+                .with_admin_sessions(true)
+                .build()
+                .unwrap(),
+            &endpoint,
+            Duration::from_secs(5),
+        )
+        .await;
+        let mut client = TestClient::connect(&node, "client").await;
+        client.initialize().await;
+        eventually(|| proxy.admin.active_sessions().len() == 1).await;
+        client.close().await;
+        eventually(|| proxy.admin.active_sessions().is_empty()).await;
+
+        let mut other = TestClient::connect(&node, "other").await;
+        other.initialize().await;
+        eventually(|| {
+            server
+                .methods()
+                .iter()
+                .filter(|m| *m == "initialize")
+                .count()
+                == 2
+        })
+        .await;
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn colliding_ids_warned() {
+        log_topology(&Name::from_strings(PROXY_NAME), false);
+        assert!(logs_contain("the proxy has no id"));
+
+        let name = Name::from_strings(PROXY_NAME).with_id(7);
+        log_topology(&name, true);
+        assert!(logs_contain(
+            "instances sharing this workload identity have the same id"
+        ));
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn distinct_ids_not_warned() {
+        log_topology(&Name::from_strings(PROXY_NAME).with_id(7), false);
+        assert!(logs_contain("each instance having its own id"));
+        assert!(!logs_contain("WARN"));
+    }
+
+    #[tokio::test]
+    async fn capabilities_of_the_handshake_served() {
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        let admin_addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let _proxy = RunningProxy::start(
+            builder(&server.url)
+                .with_admin_addr(Some(admin_addr))
+                .build()
+                .unwrap(),
+            &endpoint,
+            Duration::from_secs(5),
+        )
+        .await;
+        let mut client = TestClient::connect(&node, "client").await;
+        client.initialize().await;
+
+        let capabilities: serde_json::Value =
+            reqwest::get(format!("http://{admin_addr}/capabilities"))
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+        assert_eq!(
+            capabilities,
+            json!({
+                normalize_mcp_url(&server.url): {
+                    "protocolVersion": "2025-03-26",
+                    "capabilities": {},
+                    "serverInfo": {"name": "stub", "version": "1.0.0"},
+                }
+            })
+        );
+    }
+
+    fn dns_config(mcp_server: &str) -> SessionConfig {
+        builder(mcp_server)
+            .with_dns_timeout(Some(Duration::from_secs(5)))
+            .build()
+            .unwrap()
+            .config
+    }
+
+    fn bounded_client(config: &SessionConfig) -> BoundedClient {
+        BoundedClient::new(
+            config
+                .http_client(&Name::from_strings(["org", "ns", "client"]))
+                .unwrap(),
+            DEFAULT_READ_BUFFER_BYTES,
+        )
+    }
+
+    #[tokio::test]
+    async fn client_connects_to_the_address_resolved_ahead() {
+        let server = StubMcpServer::start().await;
+        let config = dns_config(&server.url);
+        let mut transport = config.mcp_transport(bounded_client(&config)).await.unwrap();
+        assert!(
+            config
+                .resolved_hosts
+                .get("localhost")
+                .is_none_or(|addrs| !addrs.is_empty())
+        );
+        transport.send(initialize_request()).await.unwrap();
+        assert!(matches!(
+            transport.receive().await,
+            Some(JsonRpcMessage::Response(_))
+        ));
+
+        // a host the system cannot resolve is reached at the address given
+        let port = reqwest::Url::parse(&server.url).unwrap().port().unwrap();
+        let config = dns_config(&format!("http://mcp.invalid:{port}/mcp"));
+        config
+            .resolved_hosts
+            .insert("MCP.invalid", vec!["127.0.0.1:0".parse().unwrap()]);
+        let response = config
+            .http_client(&Name::from_strings(["org", "ns", "client"]))
+            .unwrap()
+            .post(format!("http://mcp.invalid:{port}/mcp"))
+            .body(r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::ACCEPTED);
+    }
+
+    #[tokio::test]
+    async fn failed_resolution_reported() {
+        let config = dns_config("http://mcp.invalid/mcp");
+        let error = config
+            .mcp_transport(bounded_client(&config))
+            .await
+            .err()
+            .unwrap();
+        assert!(
+            matches!(
+                error,
+                DiscoveryError::Dns { .. } | DiscoveryError::NoAddress(_)
+            ),
+            "{error}"
+        );
+        assert!(config.resolved_hosts.get("mcp.invalid").is_none());
+
+        // nothing to resolve for an address
+        let config = dns_config("http://127.0.0.1:8000/mcp");
+        config
+            .resolve_host("http://127.0.0.1:8000/mcp", Duration::ZERO)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn uncreated_log_closes_only_strict_sessions() {
+        let (node, endpoint) = dataplane().await;
+        for on_failure in [WalFailurePolicy::Strict, WalFailurePolicy::BestEffort] {
+            let server = StubMcpServer::start().await;
+            let dir = std::env::temp_dir().join(format!(
+                "wal-proxy-{:?}-{}",
+                on_failure,
+                std::process::id()
+            ));
+            let proxy = builder(&server.url)
+                .with_session_wal(Some(WalConfig {
+                    dir: dir.clone(),
+                    max_bytes: wal::DEFAULT_WAL_MAX_BYTES,
+                    on_failure,
+                }))
+                .build()
+                .unwrap();
+            // the session logs cannot be created anymore
+            std::fs::remove_dir_all(&dir).unwrap();
+            let metrics = proxy.config.metrics.clone();
+            let _proxy = RunningProxy::start(proxy, &endpoint, Duration::from_secs(5)).await;
+            let mut client = TestClient::connect(&node, "client").await;
+
+            if on_failure == WalFailurePolicy::Strict {
+                client
+                    .send(serde_json::to_value(initialize_request()).unwrap())
+                    .await;
+                eventually(|| metrics.closed_sessions.get() == [("wal_failed".to_string(), 1)])
+                    .await;
+                // closed before connecting to the MCP server
+                assert!(server.methods().is_empty());
+            } else {
+                assert!(client.initialize().await["result"].is_object());
+                assert_eq!(server.methods(), ["initialize"]);
+            }
+        }
+    }
+
+    #[test]
+    fn url_variants_share_one_key() {
+        for variant in [
+            "http://mcp.example.com/mcp",
+            "HTTP://MCP.Example.COM/mcp",
+            "http://mcp.example.com:80/mcp",
+            "http://mcp.example.com./mcp",
+            "http://mcp.example.com/mcp/",
+            "http://mcp.example.com/mcp#tools",
+        ] {
+            assert_eq!(
+                normalize_mcp_url(variant),
+                "http://mcp.example.com/mcp",
+                "{variant}"
+            );
+        }
+        // different servers keep different keys
+        for other in [
+            "https://mcp.example.com/mcp",
+            "http://mcp.example.com:8080/mcp",
+            "http://mcp.example.com/MCP",
+            "http://mcp.example.com/mcp?tenant=a",
+        ] {
+            assert_ne!(
+                normalize_mcp_url(other),
+                "http://mcp.example.com/mcp",
+                "{other}"
+            );
+        }
+        assert_eq!(normalize_mcp_url("not a url"), "not a url");
+    }
+
+    #[tokio::test]
+    #[tracing_test::traced_test]
+    async fn drain_bounded_by_the_grace_period() {
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        let grace_period = Duration::from_millis(300);
+        let proxy = builder(&server.url)
+            .with_shutdown_grace_period(Some(grace_period))
+            .build()
+            .unwrap();
+        let signal = proxy.shutdown.clone();
+        let mut proxy = RunningProxy::start(proxy, &endpoint, Duration::from_secs(30)).await;
+        let mut client = TestClient::connect(&node, "client").await;
+        client.initialize().await;
+
+        // a drain already running for longer only gets shorter
+        proxy.admin.request_drain();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let started = Instant::now();
+        signal.notify_one();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!proxy.task.is_finished());
+
+        // the session never ends, the proxy stops at the end of the grace period
+        proxy.stopped().await.unwrap();
+        let elapsed = started.elapsed();
+        assert!(elapsed >= grace_period, "{elapsed:?}");
+        assert!(elapsed < Duration::from_secs(3), "{elapsed:?}");
+        assert!(logs_contain(
+            "shutdown grace period elapsed before the sessions ended"
+        ));
+        assert!(logs_contain("active_sessions=1"));
+    }
+
+    #[test]
+    fn first_message_sets_the_return_path() {
+        let mut route = ReturnRoute::new(ReturnPath::FirstMessage);
+        assert_eq!(route.received(1), RouteUpdate::Set);
+        // the initialize request arrived later on another connection
+        assert_eq!(route.received(2), RouteUpdate::Diverging { expected: 1 });
+        assert_eq!(route.initialize_received(2), None);
+        assert_eq!(route.received(1), RouteUpdate::Same);
+    }
+
+    #[test]
+    fn reordered_early_messages_follow_the_initialize_request() {
+        let mut route = ReturnRoute::new(ReturnPath::Initialize);
+        // a notification overtook the initialize request on another connection
+        assert_eq!(route.received(1), RouteUpdate::Set);
+        assert_eq!(route.received(2), RouteUpdate::Diverging { expected: 1 });
+        assert_eq!(route.initialize_received(2), Some(1));
+        assert_eq!(route.received(2), RouteUpdate::Same);
+        assert_eq!(route.received(1), RouteUpdate::Diverging { expected: 2 });
+        // settled by the first initialize request
+        assert_eq!(route.received(3), RouteUpdate::Diverging { expected: 2 });
+        assert_eq!(route.initialize_received(3), None);
+        assert_eq!(route.received(2), RouteUpdate::Same);
+
+        // in order, the initialize request keeps the connection
+        let mut route = ReturnRoute::new(ReturnPath::Initialize);
+        assert_eq!(route.received(5), RouteUpdate::Set);
+        assert_eq!(route.initialize_received(5), None);
+        assert_eq!(route.received(6), RouteUpdate::Diverging { expected: 5 });
+        assert_eq!(route.initialize_received(6), None);
+    }
+
+    #[tokio::test]
+    async fn internal_responses_not_forwarded() {
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        let _proxy = RunningProxy::start(
+            builder(&server.url)
+                .with_ping_interval(None)
+                .build()
+                .unwrap(),
+            &endpoint,
+            Duration::from_secs(5),
+        )
+        .await;
+        let mut client = TestClient::connect(&node, "client").await;
+        client.initialize().await;
+
+        // an answer to a request of the proxy comes before the one of the
+        // client; rmcp ends the stream at the first response, so an error
+        let internal = ids::internal_id("probe");
+        server.before_response(
+            "tools/list",
+            vec![
+                json!({"jsonrpc": "2.0", "id": internal, "error": {"code": -32603, "message": "failed"}}),
+            ],
+        );
+        client.send(tools_list(1)).await;
+        assert_eq!(
+            client.recv().await,
+            json!({"jsonrpc": "2.0", "id": 1, "result": {}})
+        );
+
+        // the clients cannot use the ids of the proxy
+        client
+            .send(json!({"jsonrpc": "2.0", "id": internal, "method": "tools/list"}))
+            .await;
+        let rejected = client.recv().await;
+        assert_eq!(rejected["id"], json!(internal));
+        assert_eq!(rejected["error"]["code"], -32600);
+        assert!(server.with_id(json!(internal)).is_empty());
+    }
+
+    #[tokio::test]
+    async fn one_request_in_flight_at_limit_1() {
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        let proxy = builder(&server.url)
+            .with_ping_interval(None)
+            .with_max_in_flight_requests(Some(1))
+            .build()
+            .unwrap();
+        let metrics = proxy.config.metrics.clone();
+        let _proxy = RunningProxy::start(proxy, &endpoint, Duration::from_secs(5)).await;
+        let mut client = TestClient::connect(&node, "client").await;
+        client.initialize().await;
+
+        let delay = Duration::from_millis(300);
+        server.delay("tools/list", delay);
+        let started = Instant::now();
+        for id in 1..=4 {
+            client.send(tools_list(id)).await;
+        }
+        // the initialize request, the initialized notification and the 4
+        // requests; SLIM may deliver the cancellation below first otherwise
+        eventually(|| metrics.client_messages.get() == 6).await;
+        assert_eq!(server.with_id(json!(1)).len(), 1);
+        assert!(server.with_id(json!(2)).is_empty());
+        // a held request cancelled by the client is never forwarded
+        client
+            .send(json!({"jsonrpc": "2.0", "method": "notifications/cancelled", "params": {"requestId": 3}}))
+            .await;
+
+        // each request waits for the response to the previous one
+        for id in [1, 2, 4] {
+            assert_eq!(client.recv().await["id"], id);
+        }
+        assert!(started.elapsed() >= delay * 3);
+        assert!(server.with_id(json!(3)).is_empty());
+        let forwarded: Vec<_> = server
+            .received
+            .lock()
+            .iter()
+            .filter(|msg| msg["method"] == "tools/list")
+            .map(|msg| msg["id"].clone())
+            .collect();
+        assert_eq!(forwarded, [1, 2, 4]);
+    }
+
+    #[tokio::test]
+    async fn exporter_stopped_with_the_proxy() {
+        let (_node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let proxy = builder(&server.url)
+            .with_prometheus_addr(Some(addr))
+            .build()
+            .unwrap();
+        let signal = proxy.shutdown.clone();
+        let mut proxy = RunningProxy::start(proxy, &endpoint, Duration::from_secs(5)).await;
+        let metrics = format!("http://{addr}/metrics");
+        eventually(|| std::net::TcpStream::connect(addr).is_ok()).await;
+        assert!(reqwest::get(&metrics).await.unwrap().status().is_success());
+
+        // no session to drain, the proxy stops at once
+        signal.notify_one();
+        proxy.stopped().await.unwrap();
+        std::net::TcpListener::bind(addr).unwrap();
+    }
+
+    #[tokio::test]
+    async fn http_version_negotiated() {
+        // answers with the version of the request, over HTTP/1.1 or h2c
+        let app = axum::Router::new().route(
+            "/mcp",
+            axum::routing::get(|request: axum::extract::Request| async move {
+                format!("{:?}", request.version())
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/mcp", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        for (version, negotiated) in [
+            // no ALPN on a plain connection, so no HTTP/2 without prior knowledge
+            (HttpVersion::Auto, reqwest::Version::HTTP_11),
+            (HttpVersion::Http1, reqwest::Version::HTTP_11),
+            (HttpVersion::Http2, reqwest::Version::HTTP_2),
+        ] {
+            let config = builder(&url)
+                .with_http_version(version)
+                .build()
+                .unwrap()
+                .config;
+            let response = config
+                .http_client(&Name::from_strings(["org", "ns", "client"]))
+                .unwrap()
+                .get(&url)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.version(), negotiated, "{version:?}");
+            assert_eq!(response.text().await.unwrap(), format!("{negotiated:?}"));
+        }
+
+        // over TLS, the protocols offered decide
+        assert_eq!(
+            HttpVersion::Auto.alpn_protocols(),
+            [&b"h2"[..], b"http/1.1"]
+        );
+        assert_eq!(HttpVersion::Http1.alpn_protocols(), [b"http/1.1"]);
+        assert_eq!(HttpVersion::Http2.alpn_protocols(), [b"h2"]);
+    }
+
+    #[tokio::test]
+    async fn unknown_methods_rejected_when_validated() {
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        let _proxy = RunningProxy::start(
+            builder(&server.url)
+                .with_ping_interval(None)
+                .with_method_validation(true, vec!["x-custom/echo".into()])
+                .build()
+                .unwrap(),
+            &endpoint,
+            Duration::from_secs(5),
+        )
+        .await;
+        let mut client = TestClient::connect(&node, "client").await;
+        client.initialize().await;
+
+        client
+            .send(json!({"jsonrpc": "2.0", "id": 1, "method": "tools/run"}))
+            .await;
+        let rejected = client.recv().await;
+        assert_eq!(rejected["id"], 1);
+        assert_eq!(rejected["error"]["code"], -32601);
+        assert!(server.with_id(json!(1)).is_empty());
+
+        // the MCP methods and the extension methods are forwarded
+        client.send(tools_list(2)).await;
+        assert_eq!(client.recv().await["result"], json!({}));
+        client
+            .send(json!({"jsonrpc": "2.0", "id": 3, "method": "x-custom/echo"}))
+            .await;
+        assert_eq!(client.recv().await["result"], json!({}));
+        assert_eq!(server.with_id(json!(3))[0]["method"], "x-custom/echo");
+    }
+
+    #[tokio::test]
+    async fn requests_routed_to_logical_servers_by_method_prefix() {
+        let (node, endpoint) = dataplane().await;
+        let (server, weather, news) = (
+            StubMcpServer::start().await,
+            StubMcpServer::start().await,
+            StubMcpServer::start().await,
+        );
+        let _proxy = RunningProxy::start(
+            builder(&server.url)
+                .with_ping_interval(None)
+                .with_logical_servers(
+                    vec![
+                        format!("weather={}", weather.url).parse().unwrap(),
+                        format!("news={}", news.url).parse().unwrap(),
+                    ],
+                    UnknownServerPolicy::Default,
+                )
+                .build()
+                .unwrap(),
+            &endpoint,
+            Duration::from_secs(5),
+        )
+        .await;
+        let mut client = TestClient::connect(&node, "client").await;
+        client.initialize().await;
+
+        // a logical server asks the client before answering
+        weather.before_response(
+            "tools/call",
+            vec![json!({"jsonrpc": "2.0", "id": 0, "method": "ping"})],
+        );
+        client
+            .send(json!({"jsonrpc": "2.0", "id": 1, "method": "weather/tools/call", "params": {"name": "forecast"}}))
+            .await;
+        let ping = client.recv().await;
+        assert_eq!(ping["method"], "ping");
+        assert_eq!(ping["id"], "weather/0");
+        client
+            .send(json!({"jsonrpc": "2.0", "id": "weather/0", "result": {}}))
+            .await;
+        assert_eq!(client.recv().await["id"], 1);
+        client
+            .send(json!({"jsonrpc": "2.0", "id": 2, "method": "news/tools/list"}))
+            .await;
+        assert_eq!(client.recv().await["id"], 2);
+        client.send(tools_list(3)).await;
+        assert_eq!(client.recv().await["id"], 3);
+
+        // each server got the requests of its prefix without it, the logical
+        // ones after the initialize request of the client
+        let weather_call = weather.with_id(json!(1));
+        assert_eq!(weather_call[0]["method"], "tools/call");
+        assert_eq!(weather_call[0]["params"]["name"], "forecast");
+        eventually(|| {
+            weather
+                .with_id(json!(0))
+                .iter()
+                .any(|msg| msg.get("result").is_some())
+        })
+        .await;
+        assert_eq!(news.with_id(json!(2))[0]["method"], "tools/list");
+        for logical in [&weather, &news] {
+            assert_eq!(
+                logical.methods()[..2],
+                ["initialize", "notifications/initialized"]
+            );
+        }
+        assert_eq!(server.with_id(json!(3))[0]["method"], "tools/list");
+        assert!(server.with_id(json!(1)).is_empty());
+        assert!(server.with_id(json!(2)).is_empty());
+    }
+
+    #[tokio::test]
+    async fn sessions_kept_through_a_handover() {
+        let (node, endpoint) = dataplane().await;
+        let server = StubMcpServer::start().await;
+        let old_admin = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let instance = |admin_addr, handover_from: Option<reqwest::Url>| {
+            builder(&server.url)
+                .with_admin_addr(Some(admin_addr))
+                .with_admin_sessions(true)
+                .with_admin_handover_token(Some("secret".into()))
+                .with_handover_from(handover_from)
+                .build()
+                .unwrap()
+        };
+        let mut old = RunningProxy::start(
+            instance(old_admin, None),
+            &endpoint,
+            Duration::from_secs(30),
+        )
+        .await;
+        let mut open = TestClient::connect(&node, "open").await;
+        open.initialize().await;
+
+        // the new instance takes over the name once ready
+        let previous = format!("http://{old_admin}/").parse().unwrap();
+        let new = RunningProxy::start(
+            instance("127.0.0.1:0".parse().unwrap(), Some(previous)),
+            &endpoint,
+            Duration::from_secs(30),
+        )
+        .await;
+        eventually(|| !old.admin.is_ready()).await;
+        // leave the old instance the time to unsubscribe the name
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        // the new sessions all go to the new instance, none is rejected
+        let mut clients = Vec::new();
+        for i in 0..5 {
+            let mut client = TestClient::connect(&node, &format!("client{i}")).await;
+            client.initialize().await;
+            clients.push(client);
+        }
+        assert_eq!(new.admin.active_sessions().len(), 5);
+
+        // while the open session is still served by the old instance
+        assert_eq!(old.admin.active_sessions().len(), 1);
+        open.send(tools_list(1)).await;
+        assert_eq!(open.recv().await["id"], 1);
+        assert!(!old.task.is_finished());
+
+        open.close().await;
+        old.stopped().await.unwrap();
+        let client = &mut clients[0];
+        client.send(tools_list(2)).await;
+        assert_eq!(client.recv().await["id"], 2);
     }
 }
@@ -0,0 +1,67 @@
+// Copyright AGNTCY Contributors (https://github.com/agntcy)
+// SPDX-License-Identifier: Apache-2.0
+
+use rmcp::{
+    model::ClientJsonRpcMessage,
+    transport::{
+        StreamableHttpClientTransport, Transport,
+        streamable_http_client::StreamableHttpClientTransportConfig,
+    },
+};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::{debug, trace};
+
+/// messages waiting to be mirrored before new ones are dropped
+const SHADOW_QUEUE_SIZE: usize = 128;
+
+/// Mirrors the client messages of a session to a secondary MCP server.
+///
+/// The shadow connection runs in its own task and its responses are
+/// discarded: a slow or failing shadow server never delays nor affects the
+/// primary session. The task ends when the `Shadow` is dropped.
+pub struct Shadow {
+    tx: mpsc::Sender<ClientJsonRpcMessage>,
+}
+
+impl Shadow {
+    pub fn spawn(client: reqwest::Client, mcp_server: String, session_id: u32) -> Self {
+        let (tx, mut rx) = mpsc::channel(SHADOW_QUEUE_SIZE);
+        tokio::spawn(async move {
+            let mut transport = StreamableHttpClientTransport::with_client(
+                client,
+                StreamableHttpClientTransportConfig::with_uri(mcp_server),
+            );
+            loop {
+                tokio::select! {
+                    next = rx.recv() => {
+                        let Some(msg) = next else {
+                            break;
+                        };
+                        if let Err(e) = transport.send(msg).await {
+                            debug!(%session_id, "failed mirroring message to shadow MCP server: {:?}", e);
+                        }
+                    }
+                    next = transport.receive() => {
+                        if next.is_none() {
+                            debug!(%session_id, "end of shadow MCP stream, stop mirroring");
+                            break;
+                        }
+                        trace!(%session_id, "discarding message from shadow MCP server");
+                    }
+                }
+            }
+            let _ = transport.close().await;
+        });
+        Self { tx }
+    }
+
+    /// Queue a copy of a message for the shadow server. The message is dropped
+    /// if the queue is full or the shadow connection ended.
+    pub fn mirror(&self, msg: &ClientJsonRpcMessage) {
+        match self.tx.try_send(msg.clone()) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => debug!("shadow queue full, message not mirrored"),
+            Err(TrySendError::Closed(_)) => trace!("shadow connection ended, message not mirrored"),
+        }
+    }
+}
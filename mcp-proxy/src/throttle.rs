@@ -0,0 +1,183 @@
+// Copyright AGNTCY Contributors (https://github.com/agntcy)
+// SPDX-License-Identifier: Apache-2.0
+
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Token bucket shared by the sessions, pacing their reconnections to the MCP
+/// server. The bucket holds one second of tokens, so a burst of reconnections
+/// after an outage is cut down to the rate within a second.
+#[derive(Debug)]
+pub struct ReconnectLimiter {
+    /// reconnections per second, also the size of the bucket
+    rate: f64,
+    /// tokens left and time of the last refill
+    bucket: Mutex<(f64, Instant)>,
+}
+
+impl ReconnectLimiter {
+    pub fn new(rate: u32) -> Self {
+        let rate = f64::from(rate);
+        Self {
+            rate,
+            bucket: Mutex::new((rate, Instant::now())),
+        }
+    }
+
+    /// Wait until a reconnection is allowed
+    pub async fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock();
+                let (tokens, last) = &mut *bucket;
+                let now = Instant::now();
+                *tokens =
+                    (*tokens + now.duration_since(*last).as_secs_f64() * self.rate).min(self.rate);
+                *last = now;
+                if *tokens >= 1.0 {
+                    *tokens -= 1.0;
+                    return;
+                }
+                Duration::from_secs_f64((1.0 - *tokens) / self.rate)
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Collapses the repetitions of the same error log of all the sessions: the
+/// first occurrence in a window is logged, the next ones are only counted and
+/// reported with the first occurrence of a later window, or by [`Self::flush`]
+/// once the window ends if the error does not occur again.
+#[derive(Debug, Default)]
+pub struct LogThrottle {
+    /// every occurrence is logged if `None`
+    window: Option<Duration>,
+    /// time of the last logged occurrence of each error, and the occurrences
+    /// suppressed since
+    seen: Mutex<HashMap<&'static str, (Instant, u64)>>,
+}
+
+impl LogThrottle {
+    pub fn new(window: Option<Duration>) -> Self {
+        Self {
+            window,
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether this occurrence of the error is logged
+    pub fn allow(&self, error: &'static str) -> bool {
+        let Some(window) = self.window else {
+            return true;
+        };
+        let now = Instant::now();
+        let mut seen = self.seen.lock();
+        let Some((logged_at, suppressed)) = seen.get_mut(error) else {
+            seen.insert(error, (now, 0));
+            return true;
+        };
+        let elapsed = now.duration_since(*logged_at);
+        if elapsed < window {
+            *suppressed += 1;
+            return false;
+        }
+        report(error, *suppressed, elapsed);
+        *logged_at = now;
+        *suppressed = 0;
+        true
+    }
+
+    /// Report the occurrences suppressed in the windows that ended, called
+    /// periodically by the proxy. Returns the number of errors reported.
+    pub fn flush(&self) -> usize {
+        let Some(window) = self.window else {
+            return 0;
+        };
+        let now = Instant::now();
+        let mut reported = 0;
+        for (error, (logged_at, suppressed)) in self.seen.lock().iter_mut() {
+            let elapsed = now.duration_since(*logged_at);
+            if elapsed >= window && *suppressed > 0 {
+                report(error, *suppressed, elapsed);
+                // the next occurrence starts a new window
+                *logged_at = now - window;
+                *suppressed = 0;
+                reported += 1;
+            }
+        }
+        reported
+    }
+
+    pub fn window(&self) -> Option<Duration> {
+        self.window
+    }
+}
+
+fn report(error: &str, suppressed: u64, elapsed: Duration) {
+    if suppressed > 0 {
+        warn!(
+            occurrences = suppressed,
+            ?elapsed,
+            "{}: repeated occurrences not logged",
+            error
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reconnections_paced_after_a_burst() {
+        let limiter = ReconnectLimiter::new(100);
+        let started = Instant::now();
+        for _ in 0..100 {
+            limiter.acquire().await;
+        }
+        assert!(started.elapsed() < Duration::from_millis(50));
+
+        // the bucket is empty, a token comes back every 10ms
+        let started = Instant::now();
+        for _ in 0..10 {
+            limiter.acquire().await;
+        }
+        assert!(started.elapsed() >= Duration::from_millis(90));
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn repetitions_collapsed_and_flushed() {
+        let throttle = LogThrottle::new(Some(Duration::from_millis(50)));
+        assert!(throttle.allow("bad message"));
+        for _ in 0..9 {
+            assert!(!throttle.allow("bad message"));
+        }
+        assert!(throttle.allow("other error"));
+        // nothing to report before the window ends
+        assert_eq!(throttle.flush(), 0);
+        assert!(!logs_contain("repeated occurrences not logged"));
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(throttle.flush(), 1);
+        assert!(logs_contain(
+            "bad message: repeated occurrences not logged occurrences=9"
+        ));
+        assert!(!logs_contain("other error: repeated"));
+        // reported once, and the next occurrence is logged
+        assert_eq!(throttle.flush(), 0);
+        assert!(throttle.allow("bad message"));
+    }
+
+    #[test]
+    fn every_occurrence_logged_without_window() {
+        let throttle = LogThrottle::new(None);
+        for _ in 0..3 {
+            assert!(throttle.allow("bad message"));
+        }
+        assert_eq!(throttle.flush(), 0);
+    }
+}
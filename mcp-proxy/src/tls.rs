@@ -0,0 +1,215 @@
+// Copyright AGNTCY Contributors (https://github.com/agntcy)
+// SPDX-License-Identifier: Apache-2.0
+
+use parking_lot::RwLock;
+use rustls::{
+    ClientConfig,
+    pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use thiserror::Error;
+use tracing::{info, warn};
+
+use crate::pin::{self, CertPin};
+
+#[derive(Debug, Error)]
+pub enum TlsError {
+    #[error("cannot read {0}: {1}")]
+    Read(PathBuf, rustls::pki_types::pem::Error),
+    #[error("no certificate in {0}")]
+    NoCertificate(PathBuf),
+    #[error(transparent)]
+    Rustls(#[from] rustls::Error),
+}
+
+/// Certificate chain and private key presented to the MCP servers, in PEM
+/// files
+#[derive(Clone, Debug)]
+pub struct ClientCert {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+impl ClientCert {
+    fn load(&self) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>), TlsError> {
+        let chain = CertificateDer::pem_file_iter(&self.cert)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| TlsError::Read(self.cert.clone(), e))?;
+        if chain.is_empty() {
+            return Err(TlsError::NoCertificate(self.cert.clone()));
+        }
+        let key = PrivateKeyDer::from_pem_file(&self.key)
+            .map_err(|e| TlsError::Read(self.key.clone(), e))?;
+        Ok((chain, key))
+    }
+
+    /// Last modification of the files, `None` if one cannot be read
+    fn modified(&self) -> Option<(SystemTime, SystemTime)> {
+        let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        Some((modified(&self.cert)?, modified(&self.key)?))
+    }
+}
+
+/// TLS configuration of the new MCP connections, for the pinned certificates
+/// and the client certificate. The open connections keep the configuration
+/// they were made with when it is rebuilt after a rotation of the client
+/// certificate.
+#[derive(Debug)]
+pub struct McpTls {
+    pins: Vec<CertPin>,
+    tls13_only: bool,
+    /// protocols offered in the TLS handshake
+    alpn_protocols: Vec<Vec<u8>>,
+    client_cert: Option<ClientCert>,
+    /// configuration of the new connections, and modification times of the
+    /// client certificate files it was built from
+    current: RwLock<(ClientConfig, Option<(SystemTime, SystemTime)>)>,
+}
+
+impl McpTls {
+    pub fn new(
+        pins: Vec<CertPin>,
+        tls13_only: bool,
+        alpn_protocols: Vec<Vec<u8>>,
+        client_cert: Option<ClientCert>,
+    ) -> Result<Self, TlsError> {
+        let modified = client_cert.as_ref().and_then(ClientCert::modified);
+        let config = build(&pins, tls13_only, &alpn_protocols, client_cert.as_ref())?;
+        Ok(Self {
+            pins,
+            tls13_only,
+            alpn_protocols,
+            client_cert,
+            current: RwLock::new((config, modified)),
+        })
+    }
+
+    /// Configuration of a new MCP connection
+    pub fn config(&self) -> ClientConfig {
+        self.current.read().0.clone()
+    }
+
+    /// Check the client certificate files every interval, and rebuild the
+    /// configuration when they change. A certificate that cannot be loaded,
+    /// e.g. while its files are being replaced, is tried again at the next
+    /// check and the previous one stays in use meanwhile.
+    pub async fn watch(self: Arc<Self>, interval: Duration) {
+        let Some(client_cert) = &self.client_cert else {
+            return;
+        };
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let modified = client_cert.modified();
+            if modified.is_none() || modified == self.current.read().1 {
+                continue;
+            }
+            match build(
+                &self.pins,
+                self.tls13_only,
+                &self.alpn_protocols,
+                Some(client_cert),
+            ) {
+                Ok(config) => {
+                    *self.current.write() = (config, modified);
+                    info!(cert = %client_cert.cert.display(), "client certificate reloaded, used by the new MCP connections");
+                }
+                Err(e) => warn!(
+                    "error reloading the client certificate, keeping the previous one: {}",
+                    e
+                ),
+            }
+        }
+    }
+}
+
+fn build(
+    pins: &[CertPin],
+    tls13_only: bool,
+    alpn_protocols: &[Vec<u8>],
+    client_cert: Option<&ClientCert>,
+) -> Result<ClientConfig, TlsError> {
+    let client_cert = client_cert.map(ClientCert::load).transpose()?;
+    let mut config = pin::tls_config(pins.to_vec(), tls13_only, client_cert)?;
+    config.alpn_protocols = alpn_protocols.to_vec();
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::SignatureScheme;
+
+    const TESTDATA: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata");
+
+    /// Copy a certificate and its key of the testdata over the files
+    fn install(name: &str, cert: &ClientCert) {
+        std::fs::copy(format!("{TESTDATA}/{name}.pem"), &cert.cert).unwrap();
+        std::fs::copy(format!("{TESTDATA}/{name}.key"), &cert.key).unwrap();
+    }
+
+    fn leaf(name: &str) -> CertificateDer<'static> {
+        CertificateDer::from_pem_file(format!("{TESTDATA}/{name}.pem")).unwrap()
+    }
+
+    /// Certificate a connection made with the configuration presents
+    fn presented(config: &ClientConfig) -> CertificateDer<'static> {
+        let key = config
+            .client_auth_cert_resolver
+            .resolve(&[], &[SignatureScheme::ECDSA_NISTP256_SHA256])
+            .expect("no client certificate");
+        key.cert[0].clone()
+    }
+
+    #[tokio::test]
+    async fn new_connections_use_the_swapped_certificate() {
+        let dir = std::env::temp_dir().join(format!("tls-swap-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cert = ClientCert {
+            cert: dir.join("client.pem"),
+            key: dir.join("client.key"),
+        };
+        install("localhost", &cert);
+        let tls = Arc::new(McpTls::new(Vec::new(), false, Vec::new(), Some(cert.clone())).unwrap());
+        let watcher = tokio::spawn(tls.clone().watch(Duration::from_millis(20)));
+        let before = tls.config();
+        assert_eq!(presented(&before), leaf("localhost"));
+
+        // a modification time in the future, whatever the resolution of the
+        // file system
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        install("client", &cert);
+        let later = SystemTime::now() + Duration::from_secs(60);
+        for path in [&cert.cert, &cert.key] {
+            std::fs::File::options()
+                .append(true)
+                .open(path)
+                .unwrap()
+                .set_modified(later)
+                .unwrap();
+        }
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while presented(&tls.config()) != leaf("client") {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("certificate not reloaded");
+        // an open connection keeps the certificate it was made with
+        assert_eq!(presented(&before), leaf("localhost"));
+
+        // a certificate that cannot be loaded keeps the previous one
+        std::fs::write(&cert.cert, "not a certificate").unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(presented(&tls.config()), leaf("client"));
+
+        watcher.abort();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
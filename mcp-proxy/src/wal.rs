@@ -0,0 +1,304 @@
+// Copyright AGNTCY Contributors (https://github.com/agntcy)
+// SPDX-License-Identifier: Apache-2.0
+
+use serde_json::{Value, json};
+use std::{
+    collections::VecDeque,
+    io,
+    path::PathBuf,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    fs::{self, File},
+    io::{AsyncSeekExt, AsyncWriteExt},
+    sync::{Notify, mpsc},
+    task::JoinHandle,
+};
+use tracing::{debug, error, warn};
+
+use crate::metrics::Metrics;
+use crate::redact::Redactor;
+
+pub const DEFAULT_WAL_MAX_BYTES: usize = 1024 * 1024;
+/// entries waiting for the writer of a session log, further entries are not
+/// recorded
+const WAL_QUEUE_CAPACITY: usize = 1024;
+
+/// What happens to a session whose messages cannot all be recorded, because
+/// writing the log failed or the writer fell behind
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum WalFailurePolicy {
+    /// Go on without recording the messages, which are counted
+    #[default]
+    BestEffort,
+    /// Close the session, for compliance
+    Strict,
+}
+
+/// Where and how much of the session history is recorded
+#[derive(Clone, Debug)]
+pub struct WalConfig {
+    /// directory containing one file per session
+    pub dir: PathBuf,
+    /// maximum size of a session file
+    pub max_bytes: usize,
+    pub on_failure: WalFailurePolicy,
+}
+
+/// Origin of a recorded message
+#[derive(Clone, Copy, Debug)]
+pub enum Direction {
+    /// received from the SLIM client
+    Client,
+    /// received from the MCP server
+    Server,
+}
+
+impl Direction {
+    fn as_str(self) -> &'static str {
+        match self {
+            Direction::Client => "client",
+            Direction::Server => "server",
+        }
+    }
+}
+
+/// Write-ahead log of the messages exchanged in a session.
+///
+/// Every message is appended to the session file as a JSON line as soon as it
+/// is received, so the history survives an abnormal end of the session. The
+/// file is written by a task of its own, so that a slow or failing disk never
+/// holds the session up.
+pub struct SessionWal {
+    path: PathBuf,
+    tx: mpsc::Sender<String>,
+    writer: JoinHandle<()>,
+    /// notified when an entry is not recorded
+    failed: Arc<Notify>,
+    redactor: Redactor,
+    metrics: Arc<Metrics>,
+}
+
+impl SessionWal {
+    pub async fn create(
+        config: &WalConfig,
+        session_id: u32,
+        redactor: Redactor,
+        metrics: Arc<Metrics>,
+    ) -> io::Result<Self> {
+        let path = config.dir.join(format!("session-{}.jsonl", session_id));
+        let file = File::create(&path).await?;
+        debug!(path = %path.display(), "session write-ahead log created");
+        let (tx, rx) = mpsc::channel(WAL_QUEUE_CAPACITY);
+        let failed = Arc::new(Notify::new());
+        let writer = WalWriter {
+            path: path.clone(),
+            file,
+            max_bytes: config.max_bytes,
+            file_bytes: 0,
+            recent: VecDeque::new(),
+            recent_bytes: 0,
+        };
+        let writer = tokio::spawn(writer.run(rx, failed.clone(), metrics.clone()));
+        Ok(Self {
+            path,
+            tx,
+            writer,
+            failed,
+            redactor,
+            metrics,
+        })
+    }
+
+    /// Wait until an entry is not recorded, because the file could not be
+    /// written or the writer fell behind
+    pub async fn failed(&self) {
+        self.failed.notified().await
+    }
+
+    /// Queue a message for the log. Payloads that are not JSON are recorded as
+    /// strings. A message that cannot be queued is counted and not recorded.
+    pub fn record(&self, direction: Direction, payload: &[u8]) {
+        let mut message = serde_json::from_slice(payload)
+            .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(payload).into_owned()));
+        self.redactor.redact(&mut message);
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let mut line =
+            json!({ "ts": ts, "from": direction.as_str(), "message": message }).to_string();
+        line.push('\n');
+
+        if self.tx.try_send(line).is_err() {
+            warn!(path = %self.path.display(), "session write-ahead log behind, message not recorded");
+            self.metrics.wal_dropped_records.inc();
+            self.failed.notify_one();
+        }
+    }
+
+    /// Close the log once the queued messages are written. The file is
+    /// removed if the session ended cleanly and kept for inspection otherwise.
+    pub async fn finish(self, clean: bool) {
+        drop(self.tx);
+        if let Err(e) = self.writer.await {
+            error!(path = %self.path.display(), "session write-ahead log writer failed: {}", e);
+        }
+        if clean {
+            if let Err(e) = fs::remove_file(&self.path).await {
+                warn!(path = %self.path.display(), "error removing session write-ahead log: {}", e);
+            }
+        } else {
+            warn!(path = %self.path.display(), "session ended abnormally, write-ahead log kept");
+        }
+    }
+}
+
+/// Writer of a session log. When the file grows beyond the maximum size it is
+/// rewritten with the most recent entries only, which are kept in memory up to
+/// half of the maximum size.
+struct WalWriter {
+    path: PathBuf,
+    file: File,
+    max_bytes: usize,
+    /// bytes currently in the file
+    file_bytes: usize,
+    /// most recent entries, at most `max_bytes / 2` bytes
+    recent: VecDeque<String>,
+    recent_bytes: usize,
+}
+
+impl WalWriter {
+    async fn run(
+        mut self,
+        mut rx: mpsc::Receiver<String>,
+        failed: Arc<Notify>,
+        metrics: Arc<Metrics>,
+    ) {
+        while let Some(line) = rx.recv().await {
+            if let Err(e) = self.append(line).await {
+                warn!(path = %self.path.display(), "error writing session write-ahead log: {}", e);
+                metrics.wal_dropped_records.inc();
+                failed.notify_one();
+            }
+        }
+    }
+
+    async fn append(&mut self, line: String) -> io::Result<()> {
+        self.recent_bytes += line.len();
+        self.recent.push_back(line);
+        while self.recent_bytes > self.max_bytes / 2 && self.recent.len() > 1 {
+            if let Some(old) = self.recent.pop_front() {
+                self.recent_bytes -= old.len();
+            }
+        }
+
+        let line = self.recent.back().map(String::as_bytes).unwrap_or_default();
+        if self.file_bytes + line.len() <= self.max_bytes {
+            self.file.write_all(line).await?;
+            self.file_bytes += line.len();
+        } else {
+            // ring buffer full: keep only the recent entries
+            self.file.set_len(0).await?;
+            self.file.rewind().await?;
+            let content = self.recent.iter().map(String::as_str).collect::<String>();
+            self.file.write_all(content.as_bytes()).await?;
+            self.file_bytes = content.len();
+        }
+        self.file.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::SourceLabels;
+    use std::time::Duration;
+
+    /// Empty directory of its own for a test
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("wal-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn wal_config(dir: PathBuf, on_failure: WalFailurePolicy) -> WalConfig {
+        WalConfig {
+            dir,
+            max_bytes: DEFAULT_WAL_MAX_BYTES,
+            on_failure,
+        }
+    }
+
+    #[tokio::test]
+    async fn messages_recorded_and_kept_on_abnormal_end() {
+        let dir = test_dir("recorded");
+        let config = wal_config(dir.clone(), WalFailurePolicy::Strict);
+        let metrics = Arc::new(Metrics::new(SourceLabels::Off));
+        let wal = SessionWal::create(&config, 1, Redactor::default(), metrics.clone())
+            .await
+            .unwrap();
+        wal.record(
+            Direction::Client,
+            br#"{"jsonrpc":"2.0","id":1,"method":"ping"}"#,
+        );
+        wal.record(Direction::Server, b"not json");
+        wal.finish(false).await;
+
+        let content = std::fs::read_to_string(dir.join("session-1.jsonl")).unwrap();
+        let entries: Vec<Value> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries[0]["from"], "client");
+        assert_eq!(entries[0]["message"]["method"], "ping");
+        assert_eq!(entries[1]["from"], "server");
+        assert_eq!(entries[1]["message"], "not json");
+        assert_eq!(metrics.wal_dropped_records.get(), 0);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Under both policies the log reports the entries it cannot write and
+    /// goes on; the strict policy closes the session on the report
+    #[tokio::test]
+    async fn failing_writer_reported_under_both_policies() {
+        for on_failure in [WalFailurePolicy::BestEffort, WalFailurePolicy::Strict] {
+            let dir = test_dir("failing");
+            // every write to the device fails with no space left
+            std::os::unix::fs::symlink("/dev/full", dir.join("session-1.jsonl")).unwrap();
+            let config = wal_config(dir.clone(), on_failure);
+            let metrics = Arc::new(Metrics::new(SourceLabels::Off));
+            let wal = SessionWal::create(&config, 1, Redactor::default(), metrics.clone())
+                .await
+                .unwrap();
+
+            wal.record(Direction::Client, b"{}");
+            tokio::time::timeout(Duration::from_secs(5), wal.failed())
+                .await
+                .expect("failed write not reported");
+            wal.record(Direction::Server, b"{}");
+            tokio::time::timeout(Duration::from_secs(5), wal.failed())
+                .await
+                .expect("failed write not reported");
+            wal.finish(false).await;
+            assert_eq!(metrics.wal_dropped_records.get(), 2);
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_directory_fails_the_creation() {
+        let config = wal_config(
+            test_dir("missing").join("missing"),
+            WalFailurePolicy::BestEffort,
+        );
+        let metrics = Arc::new(Metrics::new(SourceLabels::Off));
+        assert!(
+            SessionWal::create(&config, 1, Redactor::default(), metrics)
+                .await
+                .is_err()
+        );
+    }
+}